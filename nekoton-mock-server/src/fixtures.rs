@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nekoton::transport::emulated::EmulatedTransport;
+use nekoton::transport::models::{
+    BlockSummary, ExistingContract, LatestMasterchainBlock, RawContractState, RawTransaction,
+};
+use nekoton::transport::Transport;
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
+use nekoton_utils::ConstClock;
+use ton_block::{Account, Block, Deserializable, MsgAddressInt, Serializable};
+use ton_types::UInt256;
+
+/// Blockchain state served by the [`MockServer`](crate::MockServer).
+///
+/// The fixture directory has the following layout (all files are raw BOCs):
+/// ```text
+/// accounts/*.boc       - account states, the address is taken from the state
+/// transactions/*.boc   - transactions of the accounts above
+/// blocks/*.boc         - masterchain and shard blocks, a single chain per shard
+/// key_block.boc        - latest key block (optional)
+/// ```
+#[derive(Default, Clone)]
+pub struct Fixtures {
+    accounts: HashMap<MsgAddressInt, Account>,
+    transactions: HashMap<UInt256, FixtureTransaction>,
+    account_transactions: HashMap<MsgAddressInt, BTreeMap<u64, UInt256>>,
+    dst_transactions: HashMap<UInt256, UInt256>,
+    blocks: HashMap<UInt256, FixtureBlock>,
+    key_block: Option<Block>,
+}
+
+impl Fixtures {
+    /// Loads fixtures from the directory
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut fixtures = Self::default();
+
+        for (path, data) in read_bocs(&dir.join(ACCOUNTS_DIR))? {
+            let account = Account::construct_from_bytes(&data)
+                .with_context(|| format!("invalid account state: {}", path.display()))?;
+            fixtures.add_account(account)?;
+        }
+
+        // NOTE: transactions are loaded after all accounts to resolve their workchains
+        for (path, data) in read_bocs(&dir.join(TRANSACTIONS_DIR))? {
+            let transaction = ton_block::Transaction::construct_from_bytes(&data)
+                .with_context(|| format!("invalid transaction: {}", path.display()))?;
+            fixtures.add_transaction(transaction)?;
+        }
+
+        for (path, data) in read_bocs(&dir.join(BLOCKS_DIR))? {
+            let block = Block::construct_from_bytes(&data)
+                .with_context(|| format!("invalid block: {}", path.display()))?;
+            fixtures.add_block(block)?;
+        }
+
+        let key_block = dir.join(KEY_BLOCK_FILE);
+        if key_block.exists() {
+            let block = Block::construct_from_bytes(&std::fs::read(&key_block)?)
+                .context("invalid key block")?;
+            fixtures.set_key_block(block);
+        }
+
+        Ok(fixtures)
+    }
+
+    /// Saves fixtures into the directory, e.g. to record a state from the emulated transport
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+
+        let accounts_dir = dir.join(ACCOUNTS_DIR);
+        std::fs::create_dir_all(&accounts_dir)?;
+        for (address, account) in &self.accounts {
+            let name = format!(
+                "{}_{}.boc",
+                address.workchain_id(),
+                address.address().to_hex_string()
+            );
+            std::fs::write(accounts_dir.join(name), account.write_to_bytes()?)?;
+        }
+
+        let transactions_dir = dir.join(TRANSACTIONS_DIR);
+        std::fs::create_dir_all(&transactions_dir)?;
+        for (hash, transaction) in &self.transactions {
+            let name = format!("{}.boc", hash.to_hex_string());
+            std::fs::write(transactions_dir.join(name), &transaction.boc)?;
+        }
+
+        let blocks_dir = dir.join(BLOCKS_DIR);
+        std::fs::create_dir_all(&blocks_dir)?;
+        for block in self.blocks.values() {
+            let summary = &block.summary;
+            let name = format!(
+                "{}_{:016x}_{}.boc",
+                summary.workchain_id, summary.shard, summary.seqno
+            );
+            std::fs::write(blocks_dir.join(name), block.block.write_to_bytes()?)?;
+        }
+
+        if let Some(block) = &self.key_block {
+            std::fs::write(dir.join(KEY_BLOCK_FILE), block.write_to_bytes()?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the current state and the full history of the accounts,
+    /// e.g. from the emulated transport or from the real network
+    pub async fn record(transport: &dyn Transport, addresses: &[MsgAddressInt]) -> Result<Self> {
+        let batch_size = transport.info().max_transactions_per_fetch;
+
+        let mut fixtures = Self::default();
+        for address in addresses {
+            match transport.get_contract_state(address).await? {
+                RawContractState::Exists(contract) => {
+                    fixtures.add_account(Account::Account(contract.account))?;
+                }
+                RawContractState::NotExists => continue,
+            }
+
+            let mut from_lt = u64::MAX;
+            loop {
+                let transactions = transport
+                    .get_transactions(address, from_lt, batch_size)
+                    .await?;
+                let last = match transactions.last() {
+                    Some(last) => last.data.prev_trans_lt,
+                    None => break,
+                };
+                for transaction in transactions {
+                    fixtures.add_transaction(transaction.data)?;
+                }
+                if last == 0 {
+                    break;
+                }
+                from_lt = last;
+            }
+        }
+
+        Ok(fixtures)
+    }
+
+    /// Account with three incoming transfers, executed by the emulated transport
+    pub async fn sample() -> Result<(Self, MsgAddressInt)> {
+        let transport = EmulatedTransport::new(
+            Arc::new(ConstClock::from_secs(1650000000)),
+            Default::default(),
+        );
+        let src = MsgAddressInt::from_str(
+            "-1:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+        let dst = MsgAddressInt::from_str(
+            "0:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+
+        for i in 1..=3 {
+            let message = ton_block::Message::with_int_header(ton_block::InternalMessageHeader {
+                src: ton_block::MsgAddressIntOrNone::Some(src.clone()),
+                dst: dst.clone(),
+                value: ton_block::CurrencyCollection::with_grams(i * 1_000_000_000),
+                bounce: false,
+                ..Default::default()
+            });
+            transport.send_message(&message).await?;
+        }
+
+        let fixtures = Self::record(&transport, std::slice::from_ref(&dst)).await?;
+        Ok((fixtures, dst))
+    }
+
+    /// Inserts or replaces an account state
+    pub fn add_account(&mut self, account: Account) -> Result<MsgAddressInt> {
+        let address = account
+            .get_addr()
+            .cloned()
+            .ok_or(FixturesError::AccountNotExists)?;
+        self.accounts.insert(address.clone(), account);
+        Ok(address)
+    }
+
+    /// Inserts a transaction.
+    ///
+    /// The account is resolved from the destination of the inbound message,
+    /// or from the known account states for transactions without it
+    pub fn add_transaction(&mut self, transaction: ton_block::Transaction) -> Result<()> {
+        let cell = transaction.serialize()?;
+        let hash = cell.repr_hash();
+
+        let in_msg = transaction.read_in_msg()?;
+        let address = match in_msg.as_ref().and_then(|message| message.dst()) {
+            Some(address) => address,
+            None => self
+                .accounts
+                .keys()
+                .find(|address| address.address() == transaction.account_addr)
+                .cloned()
+                .ok_or(FixturesError::UnknownAccount)?,
+        };
+
+        if let Some(in_msg) = in_msg {
+            self.dst_transactions
+                .insert(in_msg.serialize()?.repr_hash(), hash);
+        }
+        self.account_transactions
+            .entry(address)
+            .or_default()
+            .insert(transaction.lt, hash);
+        self.transactions.insert(
+            hash,
+            FixtureTransaction {
+                boc: ton_types::serialize_toc(&cell)?,
+                raw: RawTransaction {
+                    hash,
+                    data: transaction,
+                },
+            },
+        );
+        Ok(())
+    }
+
+    /// Inserts a masterchain or shard block, returns its id
+    pub fn add_block(&mut self, block: Block) -> Result<UInt256> {
+        let info = block.info.read_struct()?;
+        let summary = BlockSummary {
+            workchain_id: info.shard().workchain_id(),
+            shard: info.shard().shard_prefix_with_tag(),
+            seqno: info.seq_no(),
+            id: block.serialize()?.repr_hash(),
+            end_lt: info.end_lt(),
+            gen_utime: info.gen_utime().0,
+        };
+        let id = summary.id;
+        self.blocks.insert(id, FixtureBlock { summary, block });
+        Ok(id)
+    }
+
+    pub fn set_key_block(&mut self, block: Block) {
+        self.key_block = Some(block);
+    }
+
+    pub(crate) fn account(&self, address: &MsgAddressInt) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    pub(crate) fn contract_state(&self, address: &MsgAddressInt) -> RawContractState {
+        let account = match self.accounts.get(address) {
+            Some(Account::Account(account)) => account.clone(),
+            _ => return RawContractState::NotExists,
+        };
+
+        let latest_lt = account.storage.last_trans_lt;
+        let last_transaction = self
+            .account_transactions
+            .get(address)
+            .and_then(|transactions| transactions.iter().next_back())
+            .filter(|(lt, _)| **lt == latest_lt);
+
+        let last_transaction_id = match last_transaction {
+            Some((lt, hash)) => LastTransactionId::Exact(TransactionId {
+                lt: *lt,
+                hash: *hash,
+            }),
+            None => LastTransactionId::Inexact { latest_lt },
+        };
+
+        RawContractState::Exists(ExistingContract {
+            account,
+            timings: GenTimings::Unknown,
+            last_transaction_id,
+        })
+    }
+
+    /// Returns account transactions with `lt <= from_lt` in descending order
+    pub(crate) fn account_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        limit: usize,
+    ) -> Vec<&FixtureTransaction> {
+        let transactions = match self.account_transactions.get(address) {
+            Some(transactions) => transactions,
+            None => return Vec::new(),
+        };
+        transactions
+            .range(..=from_lt)
+            .rev()
+            .filter_map(|(_, hash)| self.transactions.get(hash))
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns all transactions ordered by `lt`
+    pub(crate) fn all_transactions(&self) -> Vec<(&MsgAddressInt, &FixtureTransaction)> {
+        let mut transactions = self
+            .account_transactions
+            .iter()
+            .flat_map(|(address, transactions)| {
+                transactions
+                    .values()
+                    .filter_map(move |hash| Some((address, self.transactions.get(hash)?)))
+            })
+            .collect::<Vec<_>>();
+        transactions.sort_by_key(|(_, transaction)| transaction.raw.data.lt);
+        transactions
+    }
+
+    pub(crate) fn transaction(&self, hash: &UInt256) -> Option<&FixtureTransaction> {
+        self.transactions.get(hash)
+    }
+
+    pub(crate) fn dst_transaction(&self, message_hash: &UInt256) -> Option<&FixtureTransaction> {
+        self.dst_transactions
+            .get(message_hash)
+            .and_then(|hash| self.transactions.get(hash))
+    }
+
+    /// Returns addresses with the specified code hash, ordered by their string representation
+    pub(crate) fn accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        continuation: Option<&MsgAddressInt>,
+        limit: usize,
+    ) -> Vec<MsgAddressInt> {
+        let continuation = continuation.map(ToString::to_string);
+
+        let mut accounts = self
+            .accounts
+            .iter()
+            .filter(
+                |(_, account)| matches!(code_hash_of(account), Some(hash) if &hash == code_hash),
+            )
+            .map(|(address, _)| (address.to_string(), address))
+            .filter(|(id, _)| matches!(&continuation, Some(c) if id > c) || continuation.is_none())
+            .collect::<Vec<_>>();
+        accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        accounts
+            .into_iter()
+            .take(limit)
+            .map(|(_, address)| address.clone())
+            .collect()
+    }
+
+    pub(crate) fn block(&self, id: &UInt256) -> Option<&Block> {
+        self.blocks.get(id).map(|block| &block.block)
+    }
+
+    pub(crate) fn block_by_seqno(
+        &self,
+        workchain_id: i32,
+        shard: u64,
+        seqno: u32,
+    ) -> Option<&Block> {
+        self.blocks
+            .values()
+            .find(|block| {
+                let summary = &block.summary;
+                summary.workchain_id == workchain_id
+                    && summary.shard == shard
+                    && summary.seqno == seqno
+            })
+            .map(|block| &block.block)
+    }
+
+    /// Returns the latest masterchain block with the latest blocks of all shards
+    pub(crate) fn latest_masterchain_block(&self) -> Option<LatestMasterchainBlock> {
+        let mut latest = HashMap::<(i32, u64), &BlockSummary>::new();
+        for FixtureBlock { summary, .. } in self.blocks.values() {
+            let entry = latest
+                .entry((summary.workchain_id, summary.shard))
+                .or_insert(summary);
+            if summary.seqno > entry.seqno {
+                *entry = summary;
+            }
+        }
+
+        let block = latest
+            .remove(&(ton_block::MASTERCHAIN_ID, ton_block::SHARD_FULL))?
+            .clone();
+        let mut shards = latest.into_values().cloned().collect::<Vec<_>>();
+        shards.sort_by_key(|shard| (shard.workchain_id, shard.shard));
+        Some(LatestMasterchainBlock { block, shards })
+    }
+
+    /// Returns the block after the current one in the shard of the account
+    pub(crate) fn next_block(&self, current: &UInt256, address: &MsgAddressInt) -> Option<UInt256> {
+        let current = &self.blocks.get(current)?.summary;
+        self.blocks
+            .values()
+            .map(|block| &block.summary)
+            .find(|summary| {
+                summary.workchain_id == current.workchain_id
+                    && summary.seqno == current.seqno + 1
+                    && summary.contains_account(address)
+            })
+            .map(|summary| summary.id)
+    }
+
+    pub(crate) fn key_block(&self) -> Option<&Block> {
+        self.key_block.as_ref()
+    }
+}
+
+#[derive(Clone)]
+struct FixtureBlock {
+    summary: BlockSummary,
+    block: Block,
+}
+
+#[derive(Clone)]
+pub(crate) struct FixtureTransaction {
+    pub boc: Vec<u8>,
+    pub raw: RawTransaction,
+}
+
+fn code_hash_of(account: &Account) -> Option<UInt256> {
+    match account {
+        Account::Account(account) => match &account.storage.state {
+            ton_block::AccountState::AccountActive { state_init, .. } => {
+                state_init.code.as_ref().map(ton_types::Cell::repr_hash)
+            }
+            _ => None,
+        },
+        Account::AccountNone => None,
+    }
+}
+
+fn read_bocs(dir: &Path) -> Result<Vec<(std::path::PathBuf, Vec<u8>)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| matches!(path.extension(), Some(ext) if ext == "boc"));
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let data = std::fs::read(&path)?;
+            Ok((path, data))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_address(address: &str) -> Result<MsgAddressInt> {
+    MsgAddressInt::from_str(address).map_err(|_| FixturesError::InvalidAddress.into())
+}
+
+const ACCOUNTS_DIR: &str = "accounts";
+const TRANSACTIONS_DIR: &str = "transactions";
+const BLOCKS_DIR: &str = "blocks";
+const KEY_BLOCK_FILE: &str = "key_block.boc";
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+enum FixturesError {
+    #[error("account not exists")]
+    AccountNotExists,
+    #[error("unknown transaction account")]
+    UnknownAccount,
+    #[error("invalid address")]
+    InvalidAddress,
+}
@@ -0,0 +1,279 @@
+use anyhow::Result;
+use nekoton::transport::gql::queries::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use ton_block::{Deserializable, MsgAddressInt, Serializable};
+use ton_types::UInt256;
+
+use crate::fixtures::{parse_address, FixtureTransaction, Fixtures};
+use crate::Shared;
+
+pub const PATH: &str = "/graphql";
+
+/// Response to the `info` query which is used for the endpoint selection
+pub fn status(shared: &Shared) -> Value {
+    json!({
+        "data": {
+            "info": {
+                "version": "0.0.0",
+                "time": nekoton_utils::now_ms_u64(),
+                "latency": shared.sync_lag().as_millis() as u64,
+            }
+        }
+    })
+}
+
+/// Executes the query and returns either `data` or `errors`.
+/// Fails only on malformed requests
+pub fn handle(shared: &Shared, body: &[u8]) -> Result<Value> {
+    let request: GqlRequest = serde_json::from_slice(body)?;
+
+    let result = match request.query.as_str() {
+        query_account_state::QUERY => account_state(shared, request.variables),
+        query_account_transactions::QUERY => account_transactions(shared, request.variables),
+        query_transactions::QUERY => transactions(shared, request.variables),
+        query_transaction::QUERY => transaction(shared, request.variables, Fixtures::transaction),
+        query_dst_transaction::QUERY => {
+            transaction(shared, request.variables, Fixtures::dst_transaction)
+        }
+        query_accounts_by_code_hash::QUERY => accounts_by_code_hash(shared, request.variables),
+        query_latest_key_block::QUERY => latest_key_block(shared),
+        mutation_send_message::QUERY => send_message(shared, request.variables),
+        _ => Err(GqlError::UnsupportedQuery.into()),
+    };
+
+    Ok(match result {
+        Ok(data) => json!({ "data": data }),
+        Err(e) => json!({ "errors": [{ "message": e.to_string() }] }),
+    })
+}
+
+fn account_state(shared: &Shared, variables: Value) -> Result<Value> {
+    #[derive(Deserialize)]
+    struct Variables {
+        a: String,
+    }
+
+    let Variables { a } = serde_json::from_value(variables)?;
+    let address = parse_address(&a)?;
+
+    let fixtures = shared.fixtures.read();
+    let accounts = match fixtures.account(&address) {
+        Some(account) => vec![json!({ "boc": base64::encode(account.write_to_bytes()?) })],
+        None => Vec::new(),
+    };
+    Ok(json!({ "accounts": accounts }))
+}
+
+fn account_transactions(shared: &Shared, variables: Value) -> Result<Value> {
+    #[derive(Deserialize)]
+    struct Variables {
+        a: String,
+        lt: String,
+        l: u8,
+    }
+
+    let Variables { a, lt, l } = serde_json::from_value(variables)?;
+    let address = parse_address(&a)?;
+    let lt = parse_lt(&lt)?;
+
+    let fixtures = shared.fixtures.read();
+    let transactions = fixtures
+        .account_transactions(&address, lt, l as usize)
+        .into_iter()
+        .map(|transaction| json!({ "boc": base64::encode(&transaction.boc) }))
+        .collect::<Vec<_>>();
+    Ok(json!({ "transactions": transactions }))
+}
+
+fn transactions(shared: &Shared, variables: Value) -> Result<Value> {
+    #[derive(Deserialize)]
+    struct Variables {
+        #[serde(default)]
+        f: TransactionFilter,
+        #[serde(default)]
+        o: Vec<OrderBy>,
+        l: u8,
+    }
+
+    #[derive(Deserialize)]
+    struct OrderBy {
+        path: String,
+        direction: String,
+    }
+
+    let Variables { f, o, l } =
+        serde_json::from_value(variables).map_err(|_| GqlError::UnsupportedFilter)?;
+
+    let descending = match o.as_slice() {
+        [] => false,
+        [order] if order.path == "chain_order" => order.direction == "DESC",
+        _ => return Err(GqlError::UnsupportedOrder.into()),
+    };
+
+    let fixtures = shared.fixtures.read();
+    let mut transactions = fixtures.all_transactions();
+    if descending {
+        transactions.reverse();
+    }
+
+    let mut result = Vec::new();
+    for (address, transaction) in transactions {
+        if result.len() >= l as usize {
+            break;
+        }
+        if f.matches(address, transaction)? {
+            result.push(json!({
+                "boc": base64::encode(&transaction.boc),
+                "chain_order": chain_order(transaction),
+            }));
+        }
+    }
+    Ok(json!({ "transactions": result }))
+}
+
+fn transaction<F>(shared: &Shared, variables: Value, f: F) -> Result<Value>
+where
+    F: for<'a> Fn(&'a Fixtures, &UInt256) -> Option<&'a FixtureTransaction>,
+{
+    #[derive(Deserialize)]
+    struct Variables {
+        h: String,
+    }
+
+    let Variables { h } = serde_json::from_value(variables)?;
+    let hash = parse_hash(&h)?;
+
+    let fixtures = shared.fixtures.read();
+    let transactions = match f(&fixtures, &hash) {
+        Some(transaction) => vec![json!({ "boc": base64::encode(&transaction.boc) })],
+        None => Vec::new(),
+    };
+    Ok(json!({ "transactions": transactions }))
+}
+
+fn accounts_by_code_hash(shared: &Shared, variables: Value) -> Result<Value> {
+    #[derive(Deserialize)]
+    struct Variables {
+        h: String,
+        c: Option<String>,
+        l: u8,
+    }
+
+    let Variables { h, c, l } = serde_json::from_value(variables)?;
+    let code_hash = parse_hash(&h)?;
+    let continuation = c.as_deref().map(parse_address).transpose()?;
+
+    let accounts = shared
+        .fixtures
+        .read()
+        .accounts_by_code_hash(&code_hash, continuation.as_ref(), l as usize)
+        .into_iter()
+        .map(|address| json!({ "id": address.to_string() }))
+        .collect::<Vec<_>>();
+    Ok(json!({ "accounts": accounts }))
+}
+
+fn latest_key_block(shared: &Shared) -> Result<Value> {
+    let fixtures = shared.fixtures.read();
+    let blocks = match fixtures.key_block() {
+        Some(block) => vec![json!({ "boc": base64::encode(block.write_to_bytes()?) })],
+        None => Vec::new(),
+    };
+    Ok(json!({ "blocks": blocks }))
+}
+
+fn send_message(shared: &Shared, variables: Value) -> Result<Value> {
+    #[derive(Deserialize)]
+    struct Variables {
+        id: String,
+        boc: String,
+    }
+
+    let Variables { id, boc } = serde_json::from_value(variables)?;
+    let message = ton_block::Message::construct_from_base64(&boc)?;
+    shared.messages.lock().push(message);
+    Ok(json!({ "postRequests": [id] }))
+}
+
+/// Subset of the transaction filter which is supported by the mock server
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransactionFilter {
+    account_addr: Option<ScalarFilter<String>>,
+    now: Option<ScalarFilter<u32>>,
+    aborted: Option<ScalarFilter<bool>>,
+    chain_order: Option<ScalarFilter<String>>,
+}
+
+impl TransactionFilter {
+    fn matches(&self, address: &MsgAddressInt, transaction: &FixtureTransaction) -> Result<bool> {
+        let data = &transaction.raw.data;
+        Ok(matches_opt(&self.account_addr, &address.to_string())
+            && matches_opt(&self.now, &data.now)
+            && matches_opt(&self.chain_order, &chain_order(transaction))
+            && (self.aborted.is_none()
+                || matches_opt(&self.aborted, &data.read_description()?.is_aborted())))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScalarFilter<T> {
+    eq: Option<T>,
+    gt: Option<T>,
+    lt: Option<T>,
+    ge: Option<T>,
+    le: Option<T>,
+}
+
+impl<T: PartialOrd> ScalarFilter<T> {
+    fn matches(&self, value: &T) -> bool {
+        !matches!(&self.eq, Some(eq) if value != eq)
+            && !matches!(&self.gt, Some(gt) if value <= gt)
+            && !matches!(&self.lt, Some(lt) if value >= lt)
+            && !matches!(&self.ge, Some(ge) if value < ge)
+            && !matches!(&self.le, Some(le) if value > le)
+    }
+}
+
+fn matches_opt<T: PartialOrd>(filter: &Option<ScalarFilter<T>>, value: &T) -> bool {
+    filter.as_ref().map_or(true, |filter| filter.matches(value))
+}
+
+/// Fixtures contain only one chain, so the logical time is enough for ordering
+fn chain_order(transaction: &FixtureTransaction) -> String {
+    format!("{:016x}", transaction.raw.data.lt)
+}
+
+fn parse_lt(lt: &str) -> Result<u64> {
+    let lt = match lt.strip_prefix("0x") {
+        Some(lt) => u64::from_str_radix(lt, 16),
+        None => lt.parse(),
+    };
+    lt.map_err(|_| GqlError::InvalidVariables.into())
+}
+
+fn parse_hash(hash: &str) -> Result<UInt256> {
+    hash.parse::<UInt256>()
+        .map_err(|_| GqlError::InvalidVariables.into())
+}
+
+#[derive(Deserialize)]
+struct GqlRequest {
+    query: String,
+    #[serde(default)]
+    variables: Value,
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+enum GqlError {
+    #[error("unsupported query")]
+    UnsupportedQuery,
+    #[error("unsupported transactions filter")]
+    UnsupportedFilter,
+    #[error("unsupported transactions order")]
+    UnsupportedOrder,
+    #[error("invalid variables")]
+    InvalidVariables,
+}
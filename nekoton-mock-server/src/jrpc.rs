@@ -0,0 +1,248 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use ton_block::{Deserializable, Serializable};
+use ton_types::UInt256;
+
+use crate::fixtures::parse_address;
+use crate::Shared;
+
+pub const PATH: &str = "/rpc";
+
+/// Handles a single request or a batch.
+/// Fails only on malformed requests
+pub fn handle(shared: &Shared, body: &[u8]) -> Result<Value> {
+    Ok(match serde_json::from_slice(body)? {
+        Value::Array(requests) => Value::Array(
+            requests
+                .into_iter()
+                .map(|request| handle_request(shared, request))
+                .collect::<Result<_>>()?,
+        ),
+        request => handle_request(shared, request)?,
+    })
+}
+
+fn handle_request(shared: &Shared, request: Value) -> Result<Value> {
+    let JrpcRequest { id, method, params } = serde_json::from_value(request)?;
+
+    Ok(match call(shared, &method, params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => {
+            let code = match e.downcast_ref::<JrpcError>() {
+                Some(JrpcError::MethodNotFound) => -32601,
+                Some(JrpcError::InvalidParams) => -32602,
+                _ => -32603,
+            };
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": e.to_string() },
+            })
+        }
+    })
+}
+
+fn call(shared: &Shared, method: &str, params: Value) -> Result<Value> {
+    match method {
+        "getTimings" => {
+            let last_mc_utime = shared.last_mc_utime();
+            Ok(json!({
+                "lastMcBlockSeqno": 0,
+                "lastShardClientMcBlockSeqno": 0,
+                "lastMcUtime": last_mc_utime,
+                "mcTimeDiff": shared.sync_lag().as_secs(),
+                "smallestKnownLt": "0",
+            }))
+        }
+        "getContractState" => {
+            #[derive(Deserialize)]
+            struct Params {
+                address: String,
+            }
+
+            let Params { address } = parse_params(params)?;
+            let address = parse_address(&address)?;
+            let state = shared.fixtures.read().contract_state(&address);
+            Ok(serde_json::to_value(state)?)
+        }
+        "getTransactionsList" => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Params {
+                limit: u64,
+                last_transaction_lt: Option<String>,
+                account: String,
+            }
+
+            let params: Params = parse_params(params)?;
+            let address = parse_address(&params.account)?;
+            let from_lt = match params.last_transaction_lt {
+                Some(lt) => lt.parse().map_err(|_| JrpcError::InvalidParams)?,
+                None => u64::MAX,
+            };
+
+            let fixtures = shared.fixtures.read();
+            let transactions = fixtures
+                .account_transactions(&address, from_lt, params.limit as usize)
+                .into_iter()
+                .map(|transaction| base64::encode(&transaction.boc))
+                .collect::<Vec<_>>();
+            Ok(json!(transactions))
+        }
+        "getTransaction" => {
+            #[derive(Deserialize)]
+            struct Params {
+                id: String,
+            }
+
+            let Params { id } = parse_params(params)?;
+            let fixtures = shared.fixtures.read();
+            let transaction = fixtures.transaction(&parse_hash(&id)?);
+            Ok(json!(
+                transaction.map(|transaction| base64::encode(&transaction.boc))
+            ))
+        }
+        "getDstTransaction" => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Params {
+                message_hash: String,
+            }
+
+            let Params { message_hash } = parse_params(params)?;
+            let fixtures = shared.fixtures.read();
+            let transaction = fixtures.dst_transaction(&parse_hash(&message_hash)?);
+            Ok(json!(
+                transaction.map(|transaction| base64::encode(&transaction.boc))
+            ))
+        }
+        "getAccountsByCodeHash" => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Params {
+                limit: u32,
+                continuation: Option<String>,
+                code_hash: String,
+            }
+
+            let params: Params = parse_params(params)?;
+            let code_hash = parse_hash(&params.code_hash)?;
+            let continuation = params
+                .continuation
+                .as_deref()
+                .map(parse_address)
+                .transpose()?;
+
+            let accounts = shared
+                .fixtures
+                .read()
+                .accounts_by_code_hash(&code_hash, continuation.as_ref(), params.limit as usize)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            Ok(json!(accounts))
+        }
+        "getLatestKeyBlock" => {
+            let fixtures = shared.fixtures.read();
+            let block = fixtures.key_block().ok_or(JrpcError::BlockNotFound)?;
+            Ok(json!({ "block": base64::encode(block.write_to_bytes()?) }))
+        }
+        "getBlock" => {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Params {
+                Id {
+                    id: String,
+                },
+                #[serde(rename_all = "camelCase")]
+                Seqno {
+                    workchain_id: i32,
+                    shard: String,
+                    seqno: u32,
+                },
+            }
+
+            let fixtures = shared.fixtures.read();
+            let block = match parse_params(params)? {
+                Params::Id { id } => fixtures.block(&parse_hash(&id)?),
+                Params::Seqno {
+                    workchain_id,
+                    shard,
+                    seqno,
+                } => {
+                    let shard =
+                        u64::from_str_radix(&shard, 16).map_err(|_| JrpcError::InvalidParams)?;
+                    fixtures.block_by_seqno(workchain_id, shard, seqno)
+                }
+            };
+            Ok(match block {
+                Some(block) => json!({ "block": base64::encode(block.write_to_bytes()?) }),
+                None => Value::Null,
+            })
+        }
+        "getLatestMasterchainBlock" => {
+            let block = shared
+                .fixtures
+                .read()
+                .latest_masterchain_block()
+                .ok_or(JrpcError::BlockNotFound)?;
+            Ok(serde_json::to_value(block)?)
+        }
+        "waitForNextBlock" => {
+            // NOTE: responds immediately instead of waiting for the timeout
+            #[derive(Deserialize)]
+            struct Params {
+                current: String,
+                account: String,
+            }
+
+            let Params { current, account } = parse_params(params)?;
+            let current = parse_hash(&current)?;
+            let account = parse_address(&account)?;
+            let next = shared.fixtures.read().next_block(&current, &account);
+            Ok(json!(next.map(|id| json!({ "id": id.to_hex_string() }))))
+        }
+        "sendMessage" => {
+            #[derive(Deserialize)]
+            struct Params {
+                message: String,
+            }
+
+            let Params { message } = parse_params(params)?;
+            let message = ton_block::Message::construct_from_base64(&message)
+                .map_err(|_| JrpcError::InvalidParams)?;
+            shared.messages.lock().push(message);
+            Ok(Value::Null)
+        }
+        _ => Err(JrpcError::MethodNotFound.into()),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T> {
+    serde_json::from_value(params).map_err(|_| JrpcError::InvalidParams.into())
+}
+
+fn parse_hash(hash: &str) -> Result<UInt256> {
+    hash.parse::<UInt256>()
+        .map_err(|_| JrpcError::InvalidParams.into())
+}
+
+#[derive(Deserialize)]
+struct JrpcRequest {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+enum JrpcError {
+    #[error("method not found")]
+    MethodNotFound,
+    #[error("invalid params")]
+    InvalidParams,
+    #[error("block not found")]
+    BlockNotFound,
+}
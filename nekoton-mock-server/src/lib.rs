@@ -0,0 +1,304 @@
+//! Local HTTP server which speaks the GQL, JRPC and protobuf APIs used by nekoton transports.
+//!
+//! It serves the state from [`Fixtures`] and allows tests to inject latency,
+//! failed requests and lagging nodes, so that endpoint selection, failover
+//! and response parsing can be tested end-to-end without network access.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::oneshot;
+
+pub use self::fixtures::Fixtures;
+
+mod fixtures;
+mod gql;
+mod jrpc;
+mod proto;
+
+pub struct MockServer {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Starts the server on a random local port
+    pub async fn start(fixtures: Fixtures) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            fixtures: RwLock::new(fixtures),
+            faults: Default::default(),
+            request_count: Default::default(),
+            messages: Default::default(),
+        });
+
+        let make_service = make_service_fn({
+            let shared = shared.clone();
+            move |_| {
+                let shared = shared.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(shared.clone(), req))) }
+            }
+        });
+
+        let server =
+            hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_service);
+        let address = server.local_addr();
+
+        let (shutdown, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let server = server.with_graceful_shutdown(async {
+                rx.await.ok();
+            });
+            if let Err(e) = server.await {
+                log::error!("Mock server error: {e:?}");
+            }
+        });
+
+        Ok(Self {
+            address,
+            shared,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Base url for the GQL client, `/graphql` is appended by the client itself
+    pub fn gql_endpoint(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn jrpc_endpoint(&self) -> String {
+        format!("http://{}/rpc", self.address)
+    }
+
+    pub fn proto_endpoint(&self) -> String {
+        format!("http://{}/proto", self.address)
+    }
+
+    /// Delays all responses by the specified duration
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.faults.lock().latency = latency;
+    }
+
+    /// Makes the node report that it is behind the network by the specified duration.
+    /// Such nodes are skipped during endpoint selection when the lag exceeds `max_latency`
+    pub fn set_sync_lag(&self, lag: Duration) {
+        self.shared.faults.lock().sync_lag = lag;
+    }
+
+    /// Responds to the next `count` requests with the specified HTTP status
+    pub fn fail_next_requests(&self, count: usize, status: u16) {
+        self.shared.faults.lock().failures = Some(Failures {
+            status,
+            remaining: count,
+        });
+    }
+
+    /// Modifies the served state
+    pub fn update_fixtures<F: FnOnce(&mut Fixtures)>(&self, f: F) {
+        f(&mut self.shared.fixtures.write());
+    }
+
+    /// Number of received requests, including the failed ones
+    pub fn request_count(&self) -> usize {
+        self.shared.request_count.load(Ordering::Acquire)
+    }
+
+    /// Messages received via `sendMessage`, `postRequests` and protobuf `send_message`
+    pub fn sent_messages(&self) -> Vec<ton_block::Message> {
+        self.shared.messages.lock().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+pub(crate) struct Shared {
+    pub fixtures: RwLock<Fixtures>,
+    faults: Mutex<Faults>,
+    request_count: AtomicUsize,
+    pub messages: Mutex<Vec<ton_block::Message>>,
+}
+
+impl Shared {
+    /// Latest masterchain block time as seen by this node
+    pub fn last_mc_utime(&self) -> u32 {
+        let lag = self.sync_lag();
+        (nekoton_utils::now_sec_u64().saturating_sub(lag.as_secs())) as u32
+    }
+
+    pub fn sync_lag(&self) -> Duration {
+        self.faults.lock().sync_lag
+    }
+}
+
+#[derive(Default)]
+struct Faults {
+    latency: Duration,
+    sync_lag: Duration,
+    failures: Option<Failures>,
+}
+
+impl Faults {
+    /// Returns the response delay and the status of the injected failure
+    fn next_request(&mut self) -> (Duration, Option<u16>) {
+        let status = match &mut self.failures {
+            Some(failures) if failures.remaining > 0 => {
+                failures.remaining -= 1;
+                Some(failures.status)
+            }
+            _ => None,
+        };
+        (self.latency, status)
+    }
+}
+
+struct Failures {
+    status: u16,
+    remaining: usize,
+}
+
+async fn handle(shared: Arc<Shared>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    shared.request_count.fetch_add(1, Ordering::AcqRel);
+
+    let (latency, failure) = shared.faults.lock().next_request();
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+    if let Some(status) = failure {
+        return Ok(empty_response(
+            StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        ));
+    }
+
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, gql::PATH) => Ok(gql::status(&shared)),
+        (&Method::POST, gql::PATH) => match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => gql::handle(&shared, &body),
+            Err(_) => return Ok(empty_response(StatusCode::BAD_REQUEST)),
+        },
+        (&Method::POST, jrpc::PATH) => match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => jrpc::handle(&shared, &body),
+            Err(_) => return Ok(empty_response(StatusCode::BAD_REQUEST)),
+        },
+        (&Method::POST, proto::PATH) => {
+            let content_type = req.headers().get(hyper::header::CONTENT_TYPE);
+            if !matches!(content_type, Some(value) if value == proto::CONTENT_TYPE) {
+                return Ok(empty_response(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+            }
+            return Ok(match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => proto::handle(&shared, &body),
+                Err(_) => empty_response(StatusCode::BAD_REQUEST),
+            });
+        }
+        _ => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+
+    Ok(match response {
+        Ok(response) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response.to_string()))
+            .unwrap_or_default(),
+        Err(e) => {
+            log::debug!("Invalid mock server request: {e:?}");
+            empty_response(StatusCode::BAD_REQUEST)
+        }
+    })
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fixtures_roundtrip() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+        assert_eq!(
+            fixtures.account_transactions(&address, u64::MAX, 10).len(),
+            3
+        );
+
+        let dir = std::env::temp_dir().join(format!("nekoton-fixtures-{}", std::process::id()));
+        fixtures.save(&dir)?;
+        let loaded = Fixtures::load(&dir);
+        std::fs::remove_dir_all(&dir)?;
+        let loaded = loaded?;
+
+        let hashes = |fixtures: &Fixtures| {
+            fixtures
+                .account_transactions(&address, u64::MAX, 10)
+                .into_iter()
+                .map(|transaction| transaction.raw.hash)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hashes(&loaded), hashes(&fixtures));
+        assert!(matches!(
+            loaded.contract_state(&address),
+            nekoton::transport::models::RawContractState::Exists(_)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn faults_are_injected() -> Result<()> {
+        const QUERY: &str = r#"{"jsonrpc":"2.0","id":1,"method":"getTimings","params":{}}"#;
+
+        let server = MockServer::start(Fixtures::default()).await?;
+        server.fail_next_requests(1, 503);
+        server.set_sync_lag(Duration::from_secs(600));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(server.jrpc_endpoint())
+            .body(QUERY)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 503);
+
+        let response: serde_json::Value = client
+            .post(server.jrpc_endpoint())
+            .body(QUERY)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let last_mc_utime = response["result"]["lastMcUtime"]
+            .as_u64()
+            .unwrap_or_default();
+        assert!(last_mc_utime + 600 <= nekoton_utils::now_sec_u64());
+
+        let response: serde_json::Value = client
+            .post(server.jrpc_endpoint())
+            .body(r#"{"jsonrpc":"2.0","id":1,"method":"unknown","params":{}}"#)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(response["error"]["code"], -32601);
+
+        assert_eq!(server.request_count(), 3);
+        Ok(())
+    }
+}
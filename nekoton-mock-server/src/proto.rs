@@ -0,0 +1,192 @@
+use anyhow::Result;
+use hyper::{Body, Response, StatusCode};
+use nekoton::transport::models::RawContractState;
+use nekoton::transport::proto::rpc::request::Call;
+use nekoton::transport::proto::rpc::response::get_contract_state::{exists, Exists, NotExist};
+use nekoton::transport::proto::rpc::response::{self, Result as CallResult};
+use nekoton::transport::proto::{decode_address, encode_address, rpc};
+use nekoton_abi::{GenTimings, LastTransactionId};
+use prost::Message;
+use ton_block::{Deserializable, Serializable};
+use ton_types::UInt256;
+
+use crate::Shared;
+
+pub const PATH: &str = "/proto";
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Handles a single request.
+/// Failed calls are answered with `rpc::Error` and the `400` status
+pub fn handle(shared: &Shared, body: &[u8]) -> Response<Body> {
+    let (status, body) = match call(shared, body) {
+        Ok(result) => (
+            StatusCode::OK,
+            rpc::Response {
+                result: Some(result),
+            }
+            .encode_to_vec(),
+        ),
+        Err(e) => {
+            let code = match e.downcast_ref::<ProtoError>() {
+                Some(ProtoError::MethodNotFound) => -32601,
+                Some(ProtoError::InvalidParams) => -32602,
+                _ => -32603,
+            };
+            let error = rpc::Error {
+                code,
+                message: e.to_string(),
+            };
+            (StatusCode::BAD_REQUEST, error.encode_to_vec())
+        }
+    };
+
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, CONTENT_TYPE)
+        .body(Body::from(body))
+        .unwrap_or_default()
+}
+
+fn call(shared: &Shared, body: &[u8]) -> Result<CallResult> {
+    let call = rpc::Request::decode(body)
+        .map_err(|_| ProtoError::InvalidParams)?
+        .call
+        .ok_or(ProtoError::MethodNotFound)?;
+
+    match call {
+        Call::GetTimings(()) => Ok(CallResult::GetTimings(response::GetTimings {
+            last_mc_block_seqno: 0,
+            last_shard_client_mc_block_seqno: 0,
+            last_mc_utime: shared.last_mc_utime(),
+            mc_time_diff: shared.sync_lag().as_secs() as i64,
+            shard_client_time_diff: 0,
+        })),
+        Call::GetContractState(call) => {
+            let address = parse_address(&call.address)?;
+            let fixtures = shared.fixtures.read();
+            let state = match (
+                fixtures.contract_state(&address),
+                fixtures.account(&address),
+            ) {
+                (RawContractState::Exists(state), Some(account)) => {
+                    response::get_contract_state::State::Exists(Exists {
+                        account: account.write_to_bytes()?,
+                        gen_timings: match state.timings {
+                            GenTimings::Known { gen_lt, gen_utime } => {
+                                Some(exists::GenTimings { gen_lt, gen_utime })
+                            }
+                            GenTimings::Unknown => None,
+                        },
+                        last_transaction_id: Some(encode_last_transaction_id(
+                            state.last_transaction_id,
+                        )),
+                    })
+                }
+                _ => response::get_contract_state::State::NotExists(NotExist {}),
+            };
+            Ok(CallResult::GetContractState(response::GetContractState {
+                state: Some(state),
+            }))
+        }
+        Call::GetTransactionsList(call) => {
+            let address = parse_address(&call.account)?;
+            let fixtures = shared.fixtures.read();
+            let transactions = fixtures
+                .account_transactions(
+                    &address,
+                    call.last_transaction_lt.unwrap_or(u64::MAX),
+                    call.limit as usize,
+                )
+                .into_iter()
+                .map(|transaction| transaction.boc.clone())
+                .collect();
+            Ok(CallResult::GetTransactionsList(
+                response::GetTransactionsList { transactions },
+            ))
+        }
+        Call::GetTransaction(call) => {
+            let fixtures = shared.fixtures.read();
+            let transaction = fixtures.transaction(&parse_hash(&call.id)?);
+            Ok(CallResult::GetRawTransaction(response::GetRawTransaction {
+                transaction: transaction.map(|transaction| transaction.boc.clone()),
+            }))
+        }
+        Call::GetDstTransaction(call) => {
+            let fixtures = shared.fixtures.read();
+            let transaction = fixtures.dst_transaction(&parse_hash(&call.id)?);
+            Ok(CallResult::GetRawTransaction(response::GetRawTransaction {
+                transaction: transaction.map(|transaction| transaction.boc.clone()),
+            }))
+        }
+        Call::GetAccountsByCodeHash(call) => {
+            let code_hash = parse_hash(&call.code_hash)?;
+            let continuation = call
+                .continuation
+                .as_deref()
+                .map(parse_address)
+                .transpose()?;
+
+            let account = shared
+                .fixtures
+                .read()
+                .accounts_by_code_hash(&code_hash, continuation.as_ref(), call.limit as usize)
+                .iter()
+                .map(encode_address)
+                .collect::<Result<_>>()?;
+            Ok(CallResult::GetAccounts(response::GetAccountsByCodeHash {
+                account,
+            }))
+        }
+        Call::GetLatestKeyBlock(()) => {
+            let fixtures = shared.fixtures.read();
+            let block = fixtures.key_block().ok_or(ProtoError::BlockNotFound)?;
+            Ok(CallResult::GetLatestKeyBlock(response::GetLatestKeyBlock {
+                block: block.write_to_bytes()?,
+            }))
+        }
+        Call::SendMessage(call) => {
+            let message = ton_block::Message::construct_from_bytes(&call.message)
+                .map_err(|_| ProtoError::InvalidParams)?;
+            shared.messages.lock().push(message);
+            Ok(CallResult::SendMessage(()))
+        }
+        _ => Err(ProtoError::MethodNotFound.into()),
+    }
+}
+
+fn encode_last_transaction_id(id: LastTransactionId) -> exists::LastTransactionId {
+    use exists::last_transaction_id::{Exact, Inexact, LastTransactionId as Id};
+
+    let id = match id {
+        LastTransactionId::Exact(id) => Id::Exact(Exact {
+            lt: id.lt,
+            hash: id.hash.as_slice().to_vec(),
+        }),
+        LastTransactionId::Inexact { latest_lt } => Id::Inexact(Inexact { latest_lt }),
+    };
+    exists::LastTransactionId {
+        last_transaction_id: Some(id),
+    }
+}
+
+fn parse_address(address: &[u8]) -> Result<ton_block::MsgAddressInt> {
+    decode_address(address).map_err(|_| ProtoError::InvalidParams.into())
+}
+
+fn parse_hash(hash: &[u8]) -> Result<UInt256> {
+    match hash.len() {
+        32 => Ok(UInt256::from_slice(hash)),
+        _ => Err(ProtoError::InvalidParams.into()),
+    }
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+enum ProtoError {
+    #[error("method not found")]
+    MethodNotFound,
+    #[error("invalid params")]
+    InvalidParams,
+    #[error("block not found")]
+    BlockNotFound,
+}
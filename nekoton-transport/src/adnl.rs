@@ -0,0 +1,396 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use anyhow::{Context, Result};
+use curve25519_dalek_ng::constants::ED25519_BASEPOINT_TABLE;
+use curve25519_dalek_ng::edwards::CompressedEdwardsY;
+use curve25519_dalek_ng::scalar::Scalar;
+use nekoton::transport::adnl::tl;
+use nekoton_utils::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdnlNetworkSettings {
+    /// Liteserver address, e.g. `127.0.0.1:3031`
+    pub server_address: SocketAddr,
+    /// Liteserver ed25519 public key, base64 encoded
+    #[serde(with = "serde_base64_array")]
+    pub server_pubkey: [u8; 32],
+    /// Timeout of a single query, including the connection. Default: `30000`
+    #[serde(with = "serde_duration_ms", default = "default_query_timeout")]
+    pub query_timeout: Duration,
+}
+
+fn default_query_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Liteserver client over ADNL TCP.
+///
+/// Queries are sent one at a time over a single connection,
+/// which is reestablished after any error
+pub struct AdnlClient {
+    settings: AdnlNetworkSettings,
+    connection: tokio::sync::Mutex<Option<AdnlConnection>>,
+}
+
+impl AdnlClient {
+    pub fn new(settings: AdnlNetworkSettings) -> Arc<Self> {
+        Arc::new(Self {
+            settings,
+            connection: Default::default(),
+        })
+    }
+
+    async fn query_impl(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let mut connection = self.connection.lock().await;
+
+        let result = async {
+            if connection.is_none() {
+                *connection = Some(
+                    AdnlConnection::connect(
+                        self.settings.server_address,
+                        &self.settings.server_pubkey,
+                    )
+                    .await?,
+                );
+            }
+            // SAFETY: connection was initialized above
+            let connection = connection.as_mut().trust_me();
+            connection.query(request).await
+        };
+
+        let result = match tokio::time::timeout(self.settings.query_timeout, result).await {
+            Ok(result) => result,
+            Err(_) => Err(AdnlClientError::Timeout.into()),
+        };
+
+        if result.is_err() {
+            // Partially read packets make the connection unusable
+            *connection = None;
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl nekoton::external::AdnlConnection for AdnlClient {
+    async fn query(&self, request: Vec<u8>) -> Result<Vec<u8>> {
+        self.query_impl(&request).await
+    }
+}
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+struct AdnlConnection {
+    stream: TcpStream,
+    rx: Aes256Ctr,
+    tx: Aes256Ctr,
+}
+
+impl AdnlConnection {
+    async fn connect(address: SocketAddr, server_pubkey: &[u8; 32]) -> Result<Self> {
+        let mut stream = TcpStream::connect(address)
+            .await
+            .context("failed to connect to liteserver")?;
+
+        let params: [u8; 160] = {
+            let mut params = [0; 160];
+            rand::thread_rng().fill(&mut params[..]);
+            params
+        };
+
+        let secret = Scalar::from_bits(clamp(rand::thread_rng().gen()));
+        let public = (&secret * &ED25519_BASEPOINT_TABLE).compress();
+        let shared_secret = compute_shared_secret(&secret, server_pubkey)?;
+
+        let mut handshake = Vec::with_capacity(256);
+        handshake.extend_from_slice(&compute_key_id(server_pubkey));
+        handshake.extend_from_slice(public.as_bytes());
+        handshake.extend_from_slice(&handshake_cipher_params(&params, &shared_secret, true));
+        stream.write_all(&handshake).await?;
+
+        let mut connection = Self {
+            stream,
+            rx: Aes256Ctr::new(params[0..32].into(), params[64..80].into()),
+            tx: Aes256Ctr::new(params[32..64].into(), params[80..96].into()),
+        };
+
+        // Server confirms the handshake with an empty packet
+        let packet = connection.read_packet().await?;
+        if !packet.is_empty() {
+            return Err(AdnlClientError::InvalidHandshake.into());
+        }
+
+        Ok(connection)
+    }
+
+    async fn query(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+        let query_id: [u8; 32] = rand::thread_rng().gen();
+        self.write_packet(&make_query(&query_id, request)).await?;
+
+        loop {
+            let packet = self.read_packet().await?;
+            if let Some(answer) = parse_answer(&query_id, &packet)? {
+                return Ok(answer);
+            }
+        }
+    }
+
+    async fn write_packet(&mut self, payload: &[u8]) -> Result<()> {
+        let mut packet = encode_packet(payload);
+        self.tx.apply_keystream(&mut packet);
+        self.stream.write_all(&packet).await?;
+        Ok(())
+    }
+
+    async fn read_packet(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.stream.read_exact(&mut len).await?;
+        self.rx.apply_keystream(&mut len);
+
+        let len = u32::from_le_bytes(len) as usize;
+        if !(64..=MAX_PACKET_LEN).contains(&len) {
+            return Err(AdnlClientError::InvalidPacket.into());
+        }
+
+        let mut data = vec![0; len];
+        self.stream.read_exact(&mut data).await?;
+        self.rx.apply_keystream(&mut data);
+        decode_packet(&data)
+    }
+}
+
+/// Makes `nonce | payload | sha256(nonce | payload)` with the length prefix
+fn encode_packet(payload: &[u8]) -> Vec<u8> {
+    let nonce: [u8; 32] = rand::thread_rng().gen();
+
+    let mut packet = Vec::with_capacity(4 + 64 + payload.len());
+    packet.extend_from_slice(&((64 + payload.len()) as u32).to_le_bytes());
+    packet.extend_from_slice(&nonce);
+    packet.extend_from_slice(payload);
+    let checksum = Sha256::digest(&packet[4..]);
+    packet.extend_from_slice(&checksum);
+    packet
+}
+
+/// Checks the decrypted packet without the length prefix and returns its payload
+fn decode_packet(data: &[u8]) -> Result<Vec<u8>> {
+    let checksum_offset = data.len() - 32;
+    if Sha256::digest(&data[..checksum_offset]).as_slice() != &data[checksum_offset..] {
+        return Err(AdnlClientError::InvalidPacket.into());
+    }
+    Ok(data[32..checksum_offset].to_vec())
+}
+
+/// `adnl.message.query query_id:int256 query:bytes` with `liteServer.query data:bytes`
+fn make_query(query_id: &[u8; 32], request: &[u8]) -> Vec<u8> {
+    let mut query = Vec::with_capacity(request.len() + 8);
+    tl::write_u32(&mut query, tl::id::LITE_SERVER_QUERY);
+    tl::write_bytes(&mut query, request);
+
+    let mut packet = Vec::with_capacity(query.len() + 40);
+    tl::write_u32(&mut packet, ADNL_MESSAGE_QUERY);
+    packet.extend_from_slice(query_id);
+    tl::write_bytes(&mut packet, &query);
+    packet
+}
+
+/// Returns the answer to the specified query or `None` for other packets
+fn parse_answer(query_id: &[u8; 32], packet: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut reader = tl::TlReader::new(packet);
+    match reader.read_u32() {
+        Ok(ADNL_MESSAGE_ANSWER) => {
+            if reader.read_int256()?.as_slice() != query_id {
+                return Ok(None);
+            }
+            Ok(Some(reader.read_bytes()?))
+        }
+        // Empty packets are used as keepalive
+        _ => Ok(None),
+    }
+}
+
+/// `sha256(pub.ed25519 key:int256)`
+fn compute_key_id(pubkey: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(PUB_ED25519.to_le_bytes());
+    hasher.update(pubkey);
+    hasher.finalize().into()
+}
+
+fn compute_shared_secret(secret: &Scalar, pubkey: &[u8; 32]) -> Result<[u8; 32]> {
+    let point = CompressedEdwardsY(*pubkey)
+        .decompress()
+        .ok_or(AdnlClientError::InvalidPublicKey)?
+        .to_montgomery();
+    Ok((secret * point).to_bytes())
+}
+
+/// Returns `sha256(params) | aes_ctr(params)`, with the cipher derived from
+/// the shared secret and the checksum. Decrypts the params if `encrypt` is false
+fn handshake_cipher_params(params: &[u8], shared_secret: &[u8; 32], encrypt: bool) -> Vec<u8> {
+    let checksum: [u8; 32] = if encrypt {
+        Sha256::digest(params).into()
+    } else {
+        let mut checksum = [0; 32];
+        checksum.copy_from_slice(&params[..32]);
+        checksum
+    };
+
+    let mut key = [0; 32];
+    key[..16].copy_from_slice(&shared_secret[..16]);
+    key[16..].copy_from_slice(&checksum[16..]);
+
+    let mut iv = [0; 16];
+    iv[..4].copy_from_slice(&checksum[..4]);
+    iv[4..].copy_from_slice(&shared_secret[20..]);
+
+    let mut data = if encrypt {
+        params.to_vec()
+    } else {
+        params[32..].to_vec()
+    };
+    Aes256Ctr::new(&key.into(), &iv.into()).apply_keystream(&mut data);
+
+    if encrypt {
+        let mut result = checksum.to_vec();
+        result.extend_from_slice(&data);
+        result
+    } else {
+        data
+    }
+}
+
+fn clamp(mut bytes: [u8; 32]) -> [u8; 32] {
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    bytes
+}
+
+const PUB_ED25519: u32 = 0x4813b4c6;
+const ADNL_MESSAGE_QUERY: u32 = 0xb48bf97a;
+const ADNL_MESSAGE_ANSWER: u32 = 0x0fac8416;
+
+const MAX_PACKET_LEN: usize = 1 << 24;
+
+#[derive(thiserror::Error, Debug)]
+enum AdnlClientError {
+    #[error("invalid server public key")]
+    InvalidPublicKey,
+    #[error("invalid handshake response")]
+    InvalidHandshake,
+    #[error("invalid packet")]
+    InvalidPacket,
+    #[error("query timeout")]
+    Timeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use nekoton::transport::adnl::AdnlTransport;
+    use nekoton::transport::Transport;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accepts a single connection and answers `liteServer.sendMessage` queries
+    async fn serve(
+        listener: TcpListener,
+        secret: Scalar,
+        pubkey: [u8; 32],
+    ) -> Result<Vec<Vec<u8>>> {
+        let (mut stream, _) = listener.accept().await?;
+
+        let mut handshake = [0; 256];
+        stream.read_exact(&mut handshake).await?;
+        anyhow::ensure!(handshake[..32] == compute_key_id(&pubkey), "invalid key id");
+
+        let mut client_pubkey = [0; 32];
+        client_pubkey.copy_from_slice(&handshake[32..64]);
+        let shared_secret = compute_shared_secret(&secret, &client_pubkey)?;
+        let params = handshake_cipher_params(&handshake[64..], &shared_secret, false);
+        anyhow::ensure!(
+            Sha256::digest(&params).as_slice() == &handshake[64..96],
+            "invalid params"
+        );
+
+        // Server ciphers are swapped
+        let mut connection = AdnlConnection {
+            stream,
+            rx: Aes256Ctr::new(params[32..64].into(), params[80..96].into()),
+            tx: Aes256Ctr::new(params[0..32].into(), params[64..80].into()),
+        };
+        connection.write_packet(&[]).await?;
+
+        let mut messages = Vec::new();
+        loop {
+            let packet = match connection.read_packet().await {
+                Ok(packet) => packet,
+                Err(_) => break,
+            };
+
+            let mut reader = tl::TlReader::new(&packet);
+            anyhow::ensure!(reader.read_u32()? == ADNL_MESSAGE_QUERY);
+            let query_id = reader.read_int256()?;
+            let query = reader.read_bytes()?;
+
+            let mut reader = tl::TlReader::new(&query);
+            anyhow::ensure!(reader.read_u32()? == tl::id::LITE_SERVER_QUERY);
+            let request = reader.read_bytes()?;
+
+            let mut reader = tl::TlReader::new(&request);
+            anyhow::ensure!(reader.read_u32()? == tl::id::SEND_MESSAGE);
+            messages.push(reader.read_bytes()?);
+
+            let mut response = Vec::new();
+            tl::write_u32(&mut response, tl::id::SEND_MSG_STATUS);
+            tl::write_i32(&mut response, 1);
+
+            let mut answer = Vec::new();
+            tl::write_u32(&mut answer, ADNL_MESSAGE_ANSWER);
+            answer.extend_from_slice(query_id.as_slice());
+            tl::write_bytes(&mut answer, &response);
+            connection.write_packet(&answer).await?;
+        }
+
+        Ok(messages)
+    }
+
+    #[tokio::test]
+    async fn adnl_client_works() -> Result<()> {
+        let secret = Scalar::from_bits(clamp(rand::thread_rng().gen()));
+        let pubkey = (&secret * &ED25519_BASEPOINT_TABLE).compress().to_bytes();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let server_address = listener.local_addr()?;
+        let server = tokio::spawn(serve(listener, secret, pubkey));
+
+        let client = AdnlClient::new(AdnlNetworkSettings {
+            server_address,
+            server_pubkey: pubkey,
+            query_timeout: Duration::from_secs(5),
+        });
+        let transport = AdnlTransport::new(client.clone());
+
+        for _ in 0..2 {
+            transport
+                .send_message(&ton_block::Message::default())
+                .await?;
+        }
+
+        // Close the connection to stop the server
+        *client.connection.lock().await = None;
+        let messages = server.await??;
+        assert_eq!(messages.len(), 2);
+
+        Ok(())
+    }
+}
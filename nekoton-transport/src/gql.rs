@@ -1,5 +1,4 @@
 use std::convert::TryInto;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,8 +8,9 @@ use futures_util::StreamExt;
 use nekoton_utils::*;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use tokio::sync::futures::Notified;
-use tokio::sync::Notify;
+
+use crate::policy::{read_response, RequestKind, RequestPolicy, RequestRunner};
+use crate::selection::EndpointSelection;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GqlNetworkSettings {
@@ -26,6 +26,9 @@ pub struct GqlNetworkSettings {
     pub endpoint_selection_retry_count: usize,
     /// Gql node type
     pub local: bool,
+    /// Retry, timeout and rate limit settings
+    #[serde(default)]
+    pub policy: RequestPolicy,
 }
 
 impl Default for GqlNetworkSettings {
@@ -36,6 +39,7 @@ impl Default for GqlNetworkSettings {
             max_latency: Duration::from_secs(60),
             endpoint_selection_retry_count: 5,
             local: false,
+            policy: Default::default(),
         }
     }
 }
@@ -43,12 +47,11 @@ impl Default for GqlNetworkSettings {
 pub struct GqlClient {
     client: reqwest::Client,
     endpoints: Vec<Endpoint>,
-    latency_detection_interval: u64,
     max_latency: u32,
     endpoint_selection_retry_count: usize,
     local: bool,
-    flags: AtomicU64,
-    notify: Notify,
+    selection: EndpointSelection,
+    runner: RequestRunner,
 }
 
 impl GqlClient {
@@ -79,106 +82,22 @@ impl GqlClient {
         Ok(Arc::new(Self {
             client,
             endpoints,
-            latency_detection_interval: settings.latency_detection_interval.as_secs(),
             max_latency: settings.max_latency.as_millis() as u32,
             endpoint_selection_retry_count: settings.endpoint_selection_retry_count,
             local: settings.local,
-            flags: Default::default(),
-            notify: Default::default(),
+            selection: EndpointSelection::new(settings.latency_detection_interval),
+            runner: RequestRunner::new(settings.policy),
         }))
     }
 
     async fn select_querying_endpoint(&self) -> Result<&'_ Endpoint> {
-        // Low 4 bytes which are used as endpoint index
-        const INDEX_MASK: u64 = 0x0000_0000_ffff_ffff;
-
-        const INTERMEDIATE: u64 = 0x0000_0000_ffff_fffe;
-        const IN_PROCESS: u64 = 0x0000_0000_ffff_ffff;
-
-        struct Guard<'a> {
-            client: &'a GqlClient,
-            result: Option<u64>,
-        }
-
-        impl<'a> Guard<'a> {
-            fn new(client: &'a GqlClient) -> Self {
-                Self {
-                    client,
-                    result: None,
-                }
-            }
-
-            fn set_result(&mut self, index: usize) {
-                self.result = Some((index as u64) & INDEX_MASK);
-            }
-        }
-
-        impl Drop for Guard<'_> {
-            fn drop(&mut self) {
-                let state = match self.result {
-                    Some(result) => {
-                        let detection_time = now_sec_u64() + self.client.latency_detection_interval;
-                        (detection_time << 32) | result
-                    }
-                    None => 0,
-                };
-
-                // Lock the loop
-                self.client.flags.store(INTERMEDIATE, Ordering::Release);
-                // Notify all `notify_fut`
-                self.client.notify.notify_waiters();
-                // Update state
-                self.client.flags.store(state, Ordering::Release);
-            }
-        }
-
-        let now = now_sec_u64();
-
-        let mut notify_fut: Option<Notified<'_>> = None;
-        loop {
-            let state = self.flags.load(Ordering::Acquire);
-            match state >> 32 {
-                // Not detecting yet
-                detection_time if now < detection_time => {
-                    break self
-                        .endpoints
-                        .get((state & INDEX_MASK) as usize)
-                        .ok_or_else(|| GqlClientError::EndpointNotFound.into())
-                }
-
-                // Waiting flags change
-                INTERMEDIATE => continue,
-
-                // Already searching endpoint
-                IN_PROCESS => match notify_fut.take() {
-                    Some(notify_fut) => notify_fut.await,
-                    None => notify_fut = Some(self.notify.notified()),
-                },
-
-                _ => {
-                    match self.flags.compare_exchange(
-                        state,
-                        IN_PROCESS,
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    ) {
-                        // Start searching best endpoint
-                        Ok(_) => {
-                            // This guard will reset the state back in case of error
-                            // or unlock other waiters on success
-                            let mut guard = Guard::new(self);
-
-                            let (index, endpoint) = self.find_best_endpoint().await?;
-                            guard.set_result(index);
-
-                            break Ok(endpoint);
-                        }
-                        // State has already been changed
-                        Err(_) => continue,
-                    }
-                }
-            }
-        }
+        let index = self
+            .selection
+            .select(|| async move { self.find_best_endpoint().await.map(|(i, _)| i) })
+            .await?;
+        self.endpoints
+            .get(index)
+            .ok_or_else(|| GqlClientError::EndpointNotFound.into())
     }
 
     async fn find_best_endpoint(&'_ self) -> Result<(usize, &'_ Endpoint)> {
@@ -235,6 +154,11 @@ impl GqlClient {
         let response: GqlResponse = response.json().await?;
         Ok(response.data.info.latency)
     }
+
+    async fn send(&self, url: &Url, data: String) -> Result<String> {
+        let response = self.client.post(url.clone()).body(data).send().await?;
+        read_response(response).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -244,14 +168,29 @@ impl nekoton::external::GqlConnection for GqlClient {
     }
 
     async fn post(&self, req: nekoton::external::GqlRequest) -> Result<String> {
-        let endpoint = self.select_querying_endpoint().await?;
-        let response = self
-            .client
-            .post(endpoint.gql.clone())
-            .body(req.data)
-            .send()
-            .await?;
-        Ok(response.text().await?)
+        let kind = if req.broadcast {
+            RequestKind::Broadcast
+        } else if req.long_query {
+            RequestKind::LongRead
+        } else {
+            RequestKind::Read
+        };
+
+        let data = &req.data;
+        self.runner
+            .run(kind, move || async move {
+                let endpoint = self.select_querying_endpoint().await?;
+                let result = self.send(&endpoint.gql, data.clone()).await;
+                if let Err(e) = &result {
+                    if self.endpoints.len() > 1 {
+                        // Select another endpoint for the next attempt
+                        log::debug!("GQL request failed, resetting endpoint: {:?}", e);
+                        self.selection.reset();
+                    }
+                }
+                result
+            })
+            .await
     }
 }
 
@@ -271,7 +210,7 @@ impl Endpoint {
     }
 }
 
-fn expand_address(base_url: &str) -> String {
+pub(crate) fn expand_address(base_url: &str) -> String {
     match base_url.trim_end_matches('/') {
         url if base_url.starts_with("http://") || base_url.starts_with("https://") => {
             format!("{}/graphql", url)
@@ -294,6 +233,10 @@ enum GqlClientError {
 #[cfg(test)]
 mod tests {
     use nekoton::external::{GqlConnection, GqlRequest};
+    use nekoton::transport::gql::GqlTransport;
+    use nekoton::transport::models::{RawContractState, TransactionRef};
+    use nekoton::transport::Transport;
+    use nekoton_mock_server::{Fixtures, MockServer};
 
     use super::*;
 
@@ -311,12 +254,70 @@ mod tests {
         }"#;
 
         let response = client
-            .post(GqlRequest {
-                data: QUERY.to_string(),
-                long_query: false,
-            })
+            .post(GqlRequest::new(QUERY.to_string(), false))
             .await
             .unwrap();
         println!("{}", response);
     }
+
+    #[tokio::test]
+    async fn gql_client_retries_failed_requests() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+        let server = MockServer::start(fixtures).await?;
+
+        let client = GqlClient::new(GqlNetworkSettings {
+            endpoints: vec![server.gql_endpoint()],
+            ..Default::default()
+        })?;
+        let transport = GqlTransport::new(client);
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 10).await?;
+        assert_eq!(transactions.len(), 3);
+
+        // Both failures must be retried with the default policy
+        server.fail_next_requests(2, 503);
+        let transaction = transport
+            .get_transaction(&transactions[0].hash)
+            .await?
+            .map(|transaction| transaction.hash);
+        assert_eq!(transaction, Some(transactions[0].hash));
+
+        server.fail_next_requests(3, 503);
+        assert!(transport
+            .get_transaction(&transactions[0].hash)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gql_historical_state_falls_back_to_replay() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+        let server = MockServer::start(fixtures).await?;
+
+        // NOTE: the mock server has no accounts history, so the query fails.
+        // Local mode replays transactions with the default config instead of a key block
+        let client = GqlClient::new(GqlNetworkSettings {
+            endpoints: vec![server.gql_endpoint()],
+            local: true,
+            ..Default::default()
+        })?;
+        let transport = GqlTransport::new(client);
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 10).await?;
+        let target = &transactions[1];
+
+        let state = transport
+            .get_contract_state_at(&SimpleClock, &address, &TransactionRef::Hash(target.hash))
+            .await?;
+        match state {
+            RawContractState::Exists(state) => {
+                assert_eq!(state.last_transaction_id.lt(), target.data.lt);
+            }
+            RawContractState::NotExists => panic!("account not found"),
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use nekoton::external::{GqlRequest, GqlSubscriptionStream};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::gql::expand_address;
+
+/// Subscriptions client which uses `graphql-ws` protocol
+pub struct GqlWsClient {
+    endpoint: String,
+}
+
+impl GqlWsClient {
+    pub fn new(endpoint: &str) -> Result<Arc<Self>> {
+        let endpoint = expand_ws_address(endpoint);
+        // Check url
+        endpoint.as_str().into_client_request()?;
+
+        Ok(Arc::new(Self { endpoint }))
+    }
+
+    async fn connect(&self) -> Result<WsStream> {
+        let mut request = self.endpoint.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(PROTOCOL_HEADER, HeaderValue::from_static(PROTOCOL));
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(request).await?;
+
+        send(&mut ws, &ClientMessage::ConnectionInit).await?;
+        loop {
+            match read(&mut ws).await? {
+                ServerMessage::ConnectionAck => break Ok(ws),
+                ServerMessage::Ping => send(&mut ws, &ClientMessage::Pong).await?,
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl nekoton::external::GqlSubscriptionConnection for GqlWsClient {
+    async fn subscribe(&self, req: GqlRequest) -> Result<GqlSubscriptionStream> {
+        let mut ws = tokio::time::timeout(CONNECTION_TIMEOUT, self.connect())
+            .await
+            .map_err(|_| GqlWsClientError::ConnectionTimeout)??;
+
+        let payload = serde_json::from_str::<serde_json::Value>(&req.data)?;
+        send(
+            &mut ws,
+            &ClientMessage::Subscribe {
+                id: SUBSCRIPTION_ID,
+                payload,
+            },
+        )
+        .await?;
+
+        Ok(futures_util::stream::unfold(Some(ws), |ws| async move {
+            let mut ws = ws?;
+            loop {
+                match read(&mut ws).await {
+                    Ok(ServerMessage::Next { payload }) => {
+                        break Some((Ok(payload.to_string()), Some(ws)))
+                    }
+                    Ok(ServerMessage::Error { payload }) => {
+                        let reason = payload.to_string();
+                        break Some((
+                            Err(GqlWsClientError::SubscriptionFailed(reason).into()),
+                            None,
+                        ));
+                    }
+                    Ok(ServerMessage::Complete) => break None,
+                    Ok(ServerMessage::Ping) => {
+                        if let Err(e) = send(&mut ws, &ClientMessage::Pong).await {
+                            break Some((Err(e), None));
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(e) => break Some((Err(e), None)),
+                }
+            }
+        })
+        .boxed())
+    }
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn send(ws: &mut WsStream, message: &ClientMessage<'_>) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    ws.send(Message::Text(text)).await?;
+    Ok(())
+}
+
+async fn read(ws: &mut WsStream) -> Result<ServerMessage> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => return Ok(serde_json::from_str(&text)?),
+            Some(Ok(Message::Close(_))) | None => {
+                return Err(GqlWsClientError::ConnectionClosed.into())
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+fn expand_ws_address(base_url: &str) -> String {
+    let url = base_url.trim_end_matches('/');
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        return format!("{}/graphql", url);
+    }
+
+    let url = expand_address(url);
+    match url.strip_prefix("https://") {
+        Some(url) => format!("wss://{}", url),
+        None => format!("ws://{}", url.trim_start_matches("http://")),
+    }
+}
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const PROTOCOL_HEADER: &str = "Sec-WebSocket-Protocol";
+const PROTOCOL: &str = "graphql-transport-ws";
+const SUBSCRIPTION_ID: &str = "1";
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a> {
+    ConnectionInit,
+    Pong,
+    Subscribe {
+        id: &'a str,
+        payload: serde_json::Value,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    ConnectionAck,
+    Ping,
+    Pong,
+    Next { payload: serde_json::Value },
+    Error { payload: serde_json::Value },
+    Complete,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum GqlWsClientError {
+    #[error("connection timeout")]
+    ConnectionTimeout,
+    #[error("connection closed")]
+    ConnectionClosed,
+    #[error("subscription failed: {0}")]
+    SubscriptionFailed(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use nekoton::external::GqlSubscriptionConnection;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    use super::*;
+
+    async fn run_server(listener: tokio::net::TcpListener) -> Result<()> {
+        let (stream, _) = listener.accept().await?;
+        let mut ws =
+            tokio_tungstenite::accept_hdr_async(stream, |_: &Request, mut response: Response| {
+                response
+                    .headers_mut()
+                    .insert(PROTOCOL_HEADER, HeaderValue::from_static(PROTOCOL));
+                Ok(response)
+            })
+            .await?;
+
+        // connection_init
+        ws.next().await;
+        ws.send(Message::Text(r#"{"type":"connection_ack"}"#.to_owned()))
+            .await?;
+
+        // subscribe
+        ws.next().await;
+        ws.send(Message::Text(r#"{"type":"ping"}"#.to_owned()))
+            .await?;
+        for i in 0..2 {
+            let text = format!(
+                r#"{{"id":"1","type":"next","payload":{{"data":{{"blocks":{{"boc":"{}"}}}}}}}}"#,
+                i
+            );
+            ws.send(Message::Text(text)).await?;
+        }
+        ws.send(Message::Text(r#"{"id":"1","type":"complete"}"#.to_owned()))
+            .await?;
+
+        // pong
+        ws.next().await;
+        Ok(())
+    }
+
+    #[test]
+    fn ws_address_expansion() {
+        assert_eq!(
+            expand_ws_address("mainnet.evercloud.dev/123"),
+            "wss://mainnet.evercloud.dev/123/graphql"
+        );
+        assert_eq!(expand_ws_address("localhost"), "ws://localhost/graphql");
+        assert_eq!(
+            expand_ws_address("ws://127.0.0.1:8080/"),
+            "ws://127.0.0.1:8080/graphql"
+        );
+    }
+
+    #[tokio::test]
+    async fn gql_ws_client_works() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(run_server(listener));
+
+        let client = GqlWsClient::new(&format!("ws://{}", address))?;
+        let items = client
+            .subscribe(GqlRequest::new(
+                r#"{"query":"subscription{blocks{boc}}","variables":{}}"#.to_owned(),
+                false,
+            ))
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(items.len(), 2);
+        for (i, item) in items.into_iter().enumerate() {
+            let payload = serde_json::from_str::<serde_json::Value>(&item?)?;
+            assert_eq!(payload["data"]["blocks"]["boc"], i.to_string());
+        }
+
+        server.await??;
+        Ok(())
+    }
+}
@@ -1,17 +1,84 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use nekoton_utils::*;
 use reqwest::{IntoUrl, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::policy::{read_response, RequestKind, RequestPolicy, RequestRunner};
+use crate::selection::EndpointSelection;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JrpcNetworkSettings {
+    /// Paths to jrpc api endpoints, e.g. `https://jrpc.everwallet.net/rpc`
+    pub endpoints: Vec<String>,
+    /// Frequency of sync latency detection. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub latency_detection_interval: Duration,
+    /// Maximum value for the endpoint's blockchain data sync latency. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub max_latency: Duration,
+    /// Maximum amount of retries during endpoint selection
+    pub endpoint_selection_retry_count: usize,
+    /// Retry, timeout and rate limit settings
+    #[serde(default)]
+    pub policy: RequestPolicy,
+    /// Allow sending JSON-RPC 2.0 batch requests. Default: `false`
+    #[serde(default)]
+    pub batch_requests: bool,
+}
+
+impl Default for JrpcNetworkSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            latency_detection_interval: Duration::from_secs(60),
+            max_latency: Duration::from_secs(60),
+            endpoint_selection_retry_count: 5,
+            policy: Default::default(),
+            batch_requests: false,
+        }
+    }
+}
 
 pub struct JrpcClient {
     client: reqwest::Client,
-    base_url: Url,
+    endpoints: Vec<Url>,
     alternative_url: Option<Url>,
+    batch_requests: bool,
+    max_latency: u32,
+    endpoint_selection_retry_count: usize,
+    selection: EndpointSelection,
+    runner: RequestRunner,
 }
 
 impl JrpcClient {
     pub fn new<U: IntoUrl>(endpoint: U) -> Result<Arc<Self>> {
         let url = endpoint.into_url()?;
+        Self::with_endpoints(vec![url], Default::default())
+    }
+
+    pub fn with_settings(settings: JrpcNetworkSettings) -> Result<Arc<Self>> {
+        let endpoints = settings
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                endpoint
+                    .as_str()
+                    .into_url()
+                    .with_context(|| format!("failed to parse endpoint: {}", endpoint))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::with_endpoints(endpoints, settings)
+    }
+
+    fn with_endpoints(endpoints: Vec<Url>, settings: JrpcNetworkSettings) -> Result<Arc<Self>> {
+        if endpoints.is_empty() {
+            return Err(JrpcClientError::NoEndpointsSpecified.into());
+        }
 
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
@@ -26,8 +93,13 @@ impl JrpcClient {
 
         Ok(Arc::new(Self {
             client,
-            base_url: url,
+            endpoints,
             alternative_url: None,
+            batch_requests: settings.batch_requests,
+            max_latency: settings.max_latency.as_millis() as u32,
+            endpoint_selection_retry_count: settings.endpoint_selection_retry_count,
+            selection: EndpointSelection::new(settings.latency_detection_interval),
+            runner: RequestRunner::new(settings.policy),
         }))
     }
 
@@ -36,24 +108,151 @@ impl JrpcClient {
         self.alternative_url = Some(endpoint.into_url()?);
         Ok(())
     }
+
+    async fn select_querying_endpoint(&self) -> Result<&'_ Url> {
+        // Skip latency detection when there is nothing to choose from
+        let index = if self.endpoints.len() == 1 {
+            0
+        } else {
+            self.selection.select(|| self.find_best_endpoint()).await?
+        };
+
+        self.endpoints
+            .get(index)
+            .ok_or_else(|| JrpcClientError::EndpointNotFound.into())
+    }
+
+    async fn find_best_endpoint(&self) -> Result<usize> {
+        for i in 1..=self.endpoint_selection_retry_count {
+            let mut requests = FuturesUnordered::new();
+
+            for (i, endpoint) in self.endpoints.iter().enumerate() {
+                requests.push(async move { (i, self.check_latency(endpoint).await) });
+            }
+
+            let mut best_latency: Option<(usize, u32)> = None;
+            while let Some((i, response)) = requests.next().await {
+                match response {
+                    Ok(latency) if latency <= self.max_latency => return Ok(i),
+                    Ok(latency) => {
+                        if !matches!(best_latency, Some((_, l)) if l <= latency) {
+                            best_latency = Some((i, latency));
+                        }
+                    }
+                    Err(e) => {
+                        log::debug!("JRPC endpoint selection error: {:?}", e);
+                    }
+                }
+            }
+
+            if let Some((i, _)) = best_latency {
+                return Ok(i);
+            }
+
+            let interval = std::cmp::min(i * 100, 5000);
+            tokio::time::sleep(Duration::from_millis(interval as u64)).await;
+        }
+
+        Err(JrpcClientError::NoEndpointFound.into())
+    }
+
+    async fn check_latency(&self, endpoint: &Url) -> Result<u32> {
+        const QUERY: &str = r#"{"jsonrpc":"2.0","id":1,"method":"getTimings","params":{}}"#;
+
+        #[derive(Deserialize)]
+        struct JrpcResponse {
+            result: JrpcResponseTimings,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct JrpcResponseTimings {
+            last_mc_utime: u32,
+        }
+
+        let response = self
+            .client
+            .post(endpoint.clone())
+            .body(QUERY)
+            .send()
+            .await?
+            .error_for_status()?;
+        let response: JrpcResponse = response.json().await?;
+
+        // Compare the latest masterchain block time with the local time
+        let latency_ms = now_sec_u64()
+            .saturating_sub(response.result.last_mc_utime as u64)
+            .saturating_mul(1000);
+        Ok(std::cmp::min(latency_ms, u32::MAX as u64) as u32)
+    }
+
+    async fn send(&self, url: &Url, data: String) -> Result<String> {
+        let response = self.client.post(url.clone()).body(data).send().await?;
+        read_response(response).await
+    }
 }
 
 #[async_trait::async_trait]
 impl nekoton::external::JrpcConnection for JrpcClient {
     async fn post(&self, req: nekoton::external::JrpcRequest) -> Result<String> {
-        let url = if req.requires_db {
-            self.alternative_url.as_ref().unwrap_or(&self.base_url)
+        let kind = if req.broadcast {
+            RequestKind::Broadcast
         } else {
-            &self.base_url
+            RequestKind::Read
+        };
+
+        let alternative_url = match &self.alternative_url {
+            Some(url) if req.requires_db => Some(url),
+            _ => None,
         };
-        let response = self.client.post(url.clone()).body(req.data).send().await?;
-        Ok(response.text().await?)
+
+        let data = &req.data;
+        self.runner
+            .run(kind, move || async move {
+                if let Some(url) = alternative_url {
+                    return self.send(url, data.clone()).await;
+                }
+
+                let endpoint = self.select_querying_endpoint().await?;
+                let result = self.send(endpoint, data.clone()).await;
+                if let Err(e) = &result {
+                    if self.endpoints.len() > 1 {
+                        // Select another endpoint for the next attempt
+                        log::debug!("JRPC request failed, resetting endpoint: {:?}", e);
+                        self.selection.reset();
+                    }
+                }
+                result
+            })
+            .await
     }
+
+    fn supports_batch(&self) -> bool {
+        self.batch_requests
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+enum JrpcClientError {
+    #[error("no endpoints specified")]
+    NoEndpointsSpecified,
+    #[error("no valid JRPC endpoint found")]
+    NoEndpointFound,
+    #[error("endpoint not found")]
+    EndpointNotFound,
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use nekoton::external::{JrpcConnection, JrpcRequest};
+    use nekoton::transport::emulated::EmulatedTransport;
+    use nekoton::transport::jrpc::JrpcTransport;
+    use nekoton::transport::models::{BlockRef, RawContractState};
+    use nekoton::transport::Transport;
+    use nekoton_mock_server::{Fixtures, MockServer};
+    use ton_block::Serializable;
 
     use super::*;
 
@@ -71,12 +270,142 @@ mod tests {
         }"#;
 
         let response = client
-            .post(JrpcRequest {
-                data: QUERY.to_owned(),
-                requires_db: true,
-            })
+            .post(JrpcRequest::new(QUERY.to_owned(), true))
             .await
             .unwrap();
         println!("{}", response);
     }
+
+    #[tokio::test]
+    async fn jrpc_client_failover() -> Result<()> {
+        let failing = MockServer::start(Fixtures::default()).await?;
+        failing.fail_next_requests(usize::MAX, 503);
+        let healthy = MockServer::start(Fixtures::default()).await?;
+
+        let client = JrpcClient::with_settings(JrpcNetworkSettings {
+            endpoints: vec![failing.jrpc_endpoint(), healthy.jrpc_endpoint()],
+            endpoint_selection_retry_count: 1,
+            ..Default::default()
+        })?;
+
+        let response = client
+            .post(JrpcRequest::new(
+                r#"{"jsonrpc":"2.0","id":1,"method":"getTimings","params":{}}"#.to_owned(),
+                false,
+            ))
+            .await?;
+        assert!(response.contains("lastMcUtime"));
+        assert!(failing.request_count() > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jrpc_client_skips_lagging_endpoints() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+
+        let lagging = MockServer::start(Fixtures::default()).await?;
+        lagging.set_sync_lag(Duration::from_secs(600));
+        let fresh = MockServer::start(fixtures).await?;
+
+        let client = JrpcClient::with_settings(JrpcNetworkSettings {
+            endpoints: vec![lagging.jrpc_endpoint(), fresh.jrpc_endpoint()],
+            endpoint_selection_retry_count: 1,
+            ..Default::default()
+        })?;
+        let transport = JrpcTransport::new(client);
+
+        let state = transport.get_contract_state(&address).await?;
+        assert!(matches!(state, RawContractState::Exists(_)));
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 10).await?;
+        assert_eq!(transactions.len(), 3);
+        assert!(transactions.windows(2).all(|w| w[0].data.lt > w[1].data.lt));
+
+        // Only the latency probe could reach the lagging node
+        assert!(lagging.request_count() <= 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jrpc_client_batches_requests() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+        let server = MockServer::start(fixtures).await?;
+
+        let client = JrpcClient::with_settings(JrpcNetworkSettings {
+            endpoints: vec![server.jrpc_endpoint()],
+            batch_requests: true,
+            ..Default::default()
+        })?;
+        assert!(client.supports_batch());
+        let transport = JrpcTransport::new(client);
+
+        let states = transport
+            .get_contract_states(&[address.clone(), address])
+            .await?;
+        assert_eq!(states.len(), 2);
+        assert!(states
+            .iter()
+            .all(|state| matches!(state, RawContractState::Exists(_))));
+        assert_eq!(server.request_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jrpc_transport_walks_blocks() -> Result<()> {
+        let emulated = EmulatedTransport::new(
+            Arc::new(ConstClock::from_secs(1650000000)),
+            Default::default(),
+        )
+        .with_blocks(tokio::time::sleep);
+        let address = ton_block::MsgAddressInt::from_str(
+            "0:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+
+        let initial = emulated.get_latest_masterchain_block().await?;
+        emulated.produce_blocks()?;
+        let latest = emulated.get_latest_masterchain_block().await?;
+
+        let mut fixtures = Fixtures::default();
+        for summary in [&initial, &latest]
+            .into_iter()
+            .flat_map(|block| std::iter::once(&block.block).chain(&block.shards))
+        {
+            let block = emulated.get_block(&BlockRef::Id(summary.id)).await?;
+            fixtures.add_block(block)?;
+        }
+
+        let server = MockServer::start(fixtures).await?;
+        let transport = JrpcTransport::new(JrpcClient::new(server.jrpc_endpoint())?);
+        assert!(transport.info().has_blocks);
+        assert_eq!(transport.get_latest_masterchain_block().await?, latest);
+
+        let current = initial.find_block(&address).expect("shard must exist");
+        let next = latest.find_block(&address).expect("shard must exist");
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            transport
+                .wait_for_next_block(&current.id, &address, timeout)
+                .await?,
+            Some(next.id)
+        );
+        assert_eq!(
+            transport
+                .wait_for_next_block(&next.id, &address, timeout)
+                .await?,
+            None
+        );
+
+        let block = transport.get_block(&BlockRef::Id(next.id)).await?;
+        assert_eq!(block.serialize()?.repr_hash(), next.id);
+        let block = transport
+            .get_block(&BlockRef::Seqno {
+                workchain_id: next.workchain_id,
+                shard: next.shard,
+                seqno: next.seqno,
+            })
+            .await?;
+        assert_eq!(block.serialize()?.repr_hash(), next.id);
+
+        Ok(())
+    }
 }
@@ -52,7 +52,25 @@
     clippy::dbg_macro
 )]
 
+#[cfg(feature = "adnl_transport")]
+pub mod adnl;
 #[cfg(feature = "gql_transport")]
 pub mod gql;
+#[cfg(feature = "gql_subscriptions")]
+pub mod gql_ws;
 #[cfg(feature = "jrpc_transport")]
 pub mod jrpc;
+#[cfg(any(
+    feature = "gql_transport",
+    feature = "jrpc_transport",
+    feature = "proto_transport"
+))]
+pub mod policy;
+#[cfg(feature = "proto_transport")]
+pub mod proto;
+#[cfg(any(
+    feature = "gql_transport",
+    feature = "jrpc_transport",
+    feature = "proto_transport"
+))]
+mod selection;
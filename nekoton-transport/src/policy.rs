@@ -0,0 +1,301 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use nekoton_utils::*;
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Retry, timeout and rate limit settings shared by transport clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestPolicy {
+    /// Rules for the read requests
+    pub reads: RetryRules,
+    /// Rules for the requests which broadcast external messages
+    pub broadcasts: RetryRules,
+    /// Timeout of a single GQL request with `long_query` set. Default: `120000`
+    #[serde(with = "serde_duration_ms")]
+    pub long_query_timeout: Duration,
+    /// Max sustained number of requests per second. `0` disables the rate limit.
+    /// Default: `0`
+    pub rate_limit: u32,
+    /// Max number of requests which can be sent at once within the rate limit.
+    /// Default: `10`
+    pub rate_limit_burst: u32,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            reads: RetryRules::default(),
+            broadcasts: RetryRules {
+                max_retries: 1,
+                ..Default::default()
+            },
+            long_query_timeout: Duration::from_secs(120),
+            rate_limit: 0,
+            rate_limit_burst: 10,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryRules {
+    /// Max number of retries after the first attempt. Default: `2`
+    pub max_retries: u32,
+    /// Delay before the first retry. Default: `100`
+    #[serde(with = "serde_duration_ms")]
+    pub min_backoff: Duration,
+    /// Max delay between retries. Default: `5000`
+    #[serde(with = "serde_duration_ms")]
+    pub max_backoff: Duration,
+    /// Timeout of a single attempt. Default: `30000`
+    #[serde(with = "serde_duration_ms")]
+    pub timeout: Duration,
+}
+
+impl Default for RetryRules {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryRules {
+    /// Exponential backoff with jitter
+    fn backoff(&self, attempt: u32) -> Duration {
+        let multiplier = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        let max = std::cmp::min(
+            self.min_backoff.saturating_mul(multiplier),
+            self.max_backoff,
+        );
+        max.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum RequestKind {
+    Read,
+    LongRead,
+    Broadcast,
+}
+
+pub(crate) struct RequestRunner {
+    policy: RequestPolicy,
+    rate_limiter: Option<Mutex<TokenBucket>>,
+}
+
+impl RequestRunner {
+    pub fn new(policy: RequestPolicy) -> Self {
+        let rate_limiter = (policy.rate_limit > 0).then(|| {
+            Mutex::new(TokenBucket::new(
+                policy.rate_limit,
+                policy.rate_limit_burst,
+                now_ms_f64(),
+            ))
+        });
+
+        Self {
+            policy,
+            rate_limiter,
+        }
+    }
+
+    /// Executes the request according to the policy, retrying it on
+    /// timeouts, connection errors, 429 and 5xx responses
+    pub async fn run<F, R, T>(&self, kind: RequestKind, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T>>,
+    {
+        let rules = match kind {
+            RequestKind::Read | RequestKind::LongRead => &self.policy.reads,
+            RequestKind::Broadcast => &self.policy.broadcasts,
+        };
+        let timeout = match kind {
+            RequestKind::LongRead => self.policy.long_query_timeout,
+            RequestKind::Read | RequestKind::Broadcast => rules.timeout,
+        };
+
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                let delay = rate_limiter.lock().take(now_ms_f64());
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+
+            let result = match tokio::time::timeout(timeout, request()).await {
+                Ok(result) => result,
+                Err(_) => Err(RequestError::Timeout.into()),
+            };
+
+            match result {
+                Ok(result) => break Ok(result),
+                Err(e) if attempt < rules.max_retries && is_retriable(&e) => {
+                    let delay = rules.backoff(attempt);
+                    log::debug!("Request failed, retrying in {:?}: {:?}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => break Err(e),
+            }
+        }
+    }
+}
+
+/// Reads the response body, converting 429 and 5xx responses into errors
+pub(crate) async fn read_response(response: reqwest::Response) -> Result<String> {
+    check_status(&response)?;
+    Ok(response.text().await?)
+}
+
+/// Same as [`read_response`], but for binary responses
+#[cfg(feature = "proto_transport")]
+pub(crate) async fn read_response_bytes(response: reqwest::Response) -> Result<Vec<u8>> {
+    check_status(&response)?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn check_status(response: &reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        response.error_for_status_ref()?;
+    }
+    Ok(())
+}
+
+fn is_retriable(e: &anyhow::Error) -> bool {
+    if let Some(RequestError::Timeout) = e.downcast_ref::<RequestError>() {
+        return true;
+    }
+
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) => match e.status() {
+            Some(status) => {
+                status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            None => e.is_timeout() || e.is_connect(),
+        },
+        None => false,
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens_per_ms: f64,
+    tokens: f64,
+    updated_at: f64,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, burst: u32, now_ms: f64) -> Self {
+        let capacity = std::cmp::max(burst, 1) as f64;
+        Self {
+            capacity,
+            tokens_per_ms: rate as f64 / 1000.0,
+            tokens: capacity,
+            updated_at: now_ms,
+        }
+    }
+
+    /// Reserves one token and returns how long to wait until it becomes available
+    fn take(&mut self, now_ms: f64) -> Duration {
+        let elapsed = (now_ms - self.updated_at).max(0.0);
+        self.tokens = elapsed
+            .mul_add(self.tokens_per_ms, self.tokens)
+            .min(self.capacity);
+        self.updated_at = now_ms;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.tokens_per_ms / 1000.0)
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum RequestError {
+    #[error("request timeout")]
+    Timeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn token_bucket_limits_rate() {
+        let mut bucket = TokenBucket::new(10, 2, 0.0);
+        assert_eq!(bucket.take(0.0), Duration::ZERO);
+        assert_eq!(bucket.take(0.0), Duration::ZERO);
+        assert_eq!(bucket.take(0.0).as_millis(), 100);
+        assert_eq!(bucket.take(0.0).as_millis(), 200);
+
+        // Tokens are refilled over time
+        let mut bucket = TokenBucket::new(10, 2, 0.0);
+        bucket.take(0.0);
+        bucket.take(0.0);
+        assert_eq!(bucket.take(1000.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let rules = RetryRules::default();
+        for attempt in 0..40 {
+            let backoff = rules.backoff(attempt);
+            assert!(backoff <= rules.max_backoff);
+            assert!(backoff >= rules.min_backoff / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried() -> Result<()> {
+        let runner = RequestRunner::new(RequestPolicy {
+            reads: RetryRules {
+                max_retries: 2,
+                min_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                timeout: Duration::from_millis(10),
+            },
+            ..Default::default()
+        });
+
+        let attempts = &AtomicU32::new(0);
+        let result = runner
+            .run(RequestKind::Read, move || async move {
+                if attempts.fetch_add(1, Ordering::Relaxed) < 2 {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        // Broadcasts use their own rules
+        attempts.store(0, Ordering::Relaxed);
+        let result = runner
+            .run(RequestKind::Broadcast, move || async move {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err::<(), _>(anyhow::anyhow!("invalid message"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use nekoton::transport::proto::rpc::request::Call;
+use nekoton::transport::proto::rpc::response::Result as CallResult;
+use nekoton::transport::proto::{make_proto_request, parse_proto_response};
+use nekoton_utils::*;
+use reqwest::{IntoUrl, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::policy::{read_response_bytes, RequestKind, RequestPolicy, RequestRunner};
+use crate::selection::EndpointSelection;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtoNetworkSettings {
+    /// Paths to protobuf api endpoints, e.g. `https://jrpc.everwallet.net/proto`
+    pub endpoints: Vec<String>,
+    /// Frequency of sync latency detection. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub latency_detection_interval: Duration,
+    /// Maximum value for the endpoint's blockchain data sync latency. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub max_latency: Duration,
+    /// Maximum amount of retries during endpoint selection
+    pub endpoint_selection_retry_count: usize,
+    /// Retry, timeout and rate limit settings
+    #[serde(default)]
+    pub policy: RequestPolicy,
+}
+
+impl Default for ProtoNetworkSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            latency_detection_interval: Duration::from_secs(60),
+            max_latency: Duration::from_secs(60),
+            endpoint_selection_retry_count: 5,
+            policy: Default::default(),
+        }
+    }
+}
+
+pub struct ProtoClient {
+    client: reqwest::Client,
+    endpoints: Vec<Url>,
+    max_latency: u32,
+    endpoint_selection_retry_count: usize,
+    selection: EndpointSelection,
+    runner: RequestRunner,
+}
+
+impl ProtoClient {
+    pub fn new<U: IntoUrl>(endpoint: U) -> Result<Arc<Self>> {
+        let url = endpoint.into_url()?;
+        Self::with_endpoints(vec![url], Default::default())
+    }
+
+    pub fn with_settings(settings: ProtoNetworkSettings) -> Result<Arc<Self>> {
+        let endpoints = settings
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                endpoint
+                    .as_str()
+                    .into_url()
+                    .with_context(|| format!("failed to parse endpoint: {}", endpoint))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::with_endpoints(endpoints, settings)
+    }
+
+    fn with_endpoints(endpoints: Vec<Url>, settings: ProtoNetworkSettings) -> Result<Arc<Self>> {
+        if endpoints.is_empty() {
+            return Err(ProtoClientError::NoEndpointsSpecified.into());
+        }
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/x-protobuf"),
+        );
+
+        let client = reqwest::ClientBuilder::new()
+            .default_headers(headers)
+            .build()
+            .context("failed to build http client")?;
+
+        Ok(Arc::new(Self {
+            client,
+            endpoints,
+            max_latency: settings.max_latency.as_millis() as u32,
+            endpoint_selection_retry_count: settings.endpoint_selection_retry_count,
+            selection: EndpointSelection::new(settings.latency_detection_interval),
+            runner: RequestRunner::new(settings.policy),
+        }))
+    }
+
+    async fn select_querying_endpoint(&self) -> Result<&'_ Url> {
+        // Skip latency detection when there is nothing to choose from
+        let index = if self.endpoints.len() == 1 {
+            0
+        } else {
+            self.selection.select(|| self.find_best_endpoint()).await?
+        };
+
+        self.endpoints
+            .get(index)
+            .ok_or_else(|| ProtoClientError::EndpointNotFound.into())
+    }
+
+    async fn find_best_endpoint(&self) -> Result<usize> {
+        for i in 1..=self.endpoint_selection_retry_count {
+            let mut requests = FuturesUnordered::new();
+
+            for (i, endpoint) in self.endpoints.iter().enumerate() {
+                requests.push(async move { (i, self.check_latency(endpoint).await) });
+            }
+
+            let mut best_latency: Option<(usize, u32)> = None;
+            while let Some((i, response)) = requests.next().await {
+                match response {
+                    Ok(latency) if latency <= self.max_latency => return Ok(i),
+                    Ok(latency) => {
+                        if !matches!(best_latency, Some((_, l)) if l <= latency) {
+                            best_latency = Some((i, latency));
+                        }
+                    }
+                    Err(e) => {
+                        log::debug!("Proto endpoint selection error: {:?}", e);
+                    }
+                }
+            }
+
+            if let Some((i, _)) = best_latency {
+                return Ok(i);
+            }
+
+            let interval = std::cmp::min(i * 100, 5000);
+            tokio::time::sleep(Duration::from_millis(interval as u64)).await;
+        }
+
+        Err(ProtoClientError::NoEndpointFound.into())
+    }
+
+    async fn check_latency(&self, endpoint: &Url) -> Result<u32> {
+        let response = self
+            .client
+            .post(endpoint.clone())
+            .body(make_proto_request(Call::GetTimings(())))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let timings = match parse_proto_response(&response.bytes().await?)? {
+            CallResult::GetTimings(timings) => timings,
+            _ => return Err(ProtoClientError::InvalidTimings.into()),
+        };
+
+        // Compare the latest masterchain block time with the local time
+        let latency_ms = now_sec_u64()
+            .saturating_sub(timings.last_mc_utime as u64)
+            .saturating_mul(1000);
+        Ok(std::cmp::min(latency_ms, u32::MAX as u64) as u32)
+    }
+
+    async fn send(&self, url: &Url, data: Vec<u8>) -> Result<Vec<u8>> {
+        let response = self.client.post(url.clone()).body(data).send().await?;
+        read_response_bytes(response).await
+    }
+}
+
+#[async_trait::async_trait]
+impl nekoton::external::ProtoConnection for ProtoClient {
+    async fn post(&self, req: nekoton::external::ProtoRequest) -> Result<Vec<u8>> {
+        let kind = if req.broadcast {
+            RequestKind::Broadcast
+        } else {
+            RequestKind::Read
+        };
+
+        let data = &req.data;
+        self.runner
+            .run(kind, move || async move {
+                let endpoint = self.select_querying_endpoint().await?;
+                let result = self.send(endpoint, data.clone()).await;
+                if let Err(e) = &result {
+                    if self.endpoints.len() > 1 {
+                        // Select another endpoint for the next attempt
+                        log::debug!("Proto request failed, resetting endpoint: {:?}", e);
+                        self.selection.reset();
+                    }
+                }
+                result
+            })
+            .await
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+enum ProtoClientError {
+    #[error("no endpoints specified")]
+    NoEndpointsSpecified,
+    #[error("no valid proto endpoint found")]
+    NoEndpointFound,
+    #[error("endpoint not found")]
+    EndpointNotFound,
+    #[error("invalid timings response")]
+    InvalidTimings,
+}
+
+#[cfg(test)]
+mod tests {
+    use nekoton::transport::models::RawContractState;
+    use nekoton::transport::proto::{ProtoTransport, ProtoTransportError};
+    use nekoton::transport::Transport;
+    use nekoton_mock_server::{Fixtures, MockServer};
+    use ton_block::Serializable;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn proto_client_works() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+        let server = MockServer::start(fixtures).await?;
+
+        // The first attempt fails with 503 and must be retried
+        server.fail_next_requests(1, 503);
+
+        let client = ProtoClient::with_settings(ProtoNetworkSettings {
+            endpoints: vec![server.proto_endpoint()],
+            policy: RequestPolicy {
+                reads: crate::policy::RetryRules {
+                    min_backoff: Duration::from_millis(1),
+                    max_backoff: Duration::from_millis(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        let transport = ProtoTransport::new(client);
+
+        let state = transport.get_contract_state(&address).await?;
+        assert!(matches!(state, RawContractState::Exists(_)));
+        assert_eq!(server.request_count(), 2);
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 10).await?;
+        assert_eq!(transactions.len(), 3);
+        assert!(transactions.windows(2).all(|w| w[0].data.lt > w[1].data.lt));
+
+        let older = transport
+            .get_transactions(&address, transactions[1].data.lt, 10)
+            .await?;
+        assert_eq!(older.len(), 2);
+        assert_eq!(older[0].hash, transactions[1].hash);
+
+        let transaction = transport.get_transaction(&transactions[0].hash).await?;
+        assert_eq!(transaction.map(|t| t.hash), Some(transactions[0].hash));
+        let unknown = ton_types::UInt256::from([1u8; 32]);
+        assert!(transport.get_transaction(&unknown).await?.is_none());
+
+        let message = transactions[0]
+            .data
+            .read_in_msg()?
+            .expect("sample transactions have inbound messages");
+        let message_hash = message.serialize()?.repr_hash();
+        let transaction = transport.get_dst_transaction(&message_hash).await?;
+        assert_eq!(transaction.map(|t| t.hash), Some(transactions[0].hash));
+
+        transport.send_message(&message).await?;
+        let sent = server.sent_messages();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].serialize()?.repr_hash(), message_hash);
+
+        // Sample fixtures have no key block, so the RPC error must be returned as is
+        let err = transport.get_latest_key_block().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProtoTransportError>(),
+            Some(ProtoTransportError::RequestFailed { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proto_client_skips_lagging_endpoints() -> Result<()> {
+        let (fixtures, address) = Fixtures::sample().await?;
+
+        let lagging = MockServer::start(Fixtures::default()).await?;
+        lagging.set_sync_lag(Duration::from_secs(600));
+        let fresh = MockServer::start(fixtures).await?;
+
+        let client = ProtoClient::with_settings(ProtoNetworkSettings {
+            endpoints: vec![lagging.proto_endpoint(), fresh.proto_endpoint()],
+            endpoint_selection_retry_count: 1,
+            ..Default::default()
+        })?;
+        let transport = ProtoTransport::new(client);
+
+        let state = transport.get_contract_state(&address).await?;
+        assert!(matches!(state, RawContractState::Exists(_)));
+
+        // Only the latency probe could reach the lagging node
+        assert!(lagging.request_count() <= 1);
+        Ok(())
+    }
+}
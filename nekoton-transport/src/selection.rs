@@ -0,0 +1,178 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use nekoton_utils::*;
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+
+/// Periodic endpoint selection state, shared between concurrent requests
+pub(crate) struct EndpointSelection {
+    latency_detection_interval: u64,
+    // High 4 bytes - next detection time or state marker, low 4 bytes - endpoint index
+    flags: AtomicU64,
+    notify: Notify,
+}
+
+impl EndpointSelection {
+    pub fn new(latency_detection_interval: Duration) -> Self {
+        Self {
+            latency_detection_interval: latency_detection_interval.as_secs(),
+            flags: Default::default(),
+            notify: Default::default(),
+        }
+    }
+
+    /// Returns the index of the selected endpoint.
+    ///
+    /// When the previous selection has expired, `find_best_endpoint` is executed
+    /// and all other requests wait for it to finish.
+    pub async fn select<F, R>(&self, find_best_endpoint: F) -> Result<usize>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = Result<usize>>,
+    {
+        struct Guard<'a> {
+            selection: &'a EndpointSelection,
+            result: Option<u64>,
+        }
+
+        impl<'a> Guard<'a> {
+            fn new(selection: &'a EndpointSelection) -> Self {
+                Self {
+                    selection,
+                    result: None,
+                }
+            }
+
+            fn set_result(&mut self, index: usize) {
+                self.result = Some((index as u64) & INDEX_MASK);
+            }
+        }
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                let state = match self.result {
+                    Some(result) => {
+                        let detection_time =
+                            now_sec_u64() + self.selection.latency_detection_interval;
+                        (detection_time << 32) | result
+                    }
+                    None => 0,
+                };
+
+                // Lock the loop
+                self.selection
+                    .flags
+                    .store(INTERMEDIATE << 32, Ordering::Release);
+                // Notify all `notify_fut`
+                self.selection.notify.notify_waiters();
+                // Update state
+                self.selection.flags.store(state, Ordering::Release);
+            }
+        }
+
+        let now = now_sec_u64();
+
+        let mut notify_fut: Option<Notified<'_>> = None;
+        loop {
+            let state = self.flags.load(Ordering::Acquire);
+            match state >> 32 {
+                // Waiting flags change
+                INTERMEDIATE => continue,
+
+                // Already searching endpoint
+                IN_PROCESS => match notify_fut.take() {
+                    Some(notify_fut) => notify_fut.await,
+                    None => notify_fut = Some(self.notify.notified()),
+                },
+
+                // Not detecting yet
+                detection_time if now < detection_time => break Ok((state & INDEX_MASK) as usize),
+
+                _ => {
+                    match self.flags.compare_exchange(
+                        state,
+                        IN_PROCESS << 32,
+                        Ordering::Release,
+                        Ordering::Relaxed,
+                    ) {
+                        // Start searching best endpoint
+                        Ok(_) => {
+                            // This guard will reset the state back in case of error
+                            // or unlock other waiters on success
+                            let mut guard = Guard::new(self);
+
+                            let index = find_best_endpoint().await?;
+                            guard.set_result(index);
+
+                            break Ok(index);
+                        }
+                        // State has already been changed
+                        Err(_) => continue,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forces endpoint selection on the next request
+    pub fn reset(&self) {
+        let state = self.flags.load(Ordering::Acquire);
+        if !matches!(state >> 32, INTERMEDIATE | IN_PROCESS) {
+            // NOTE: ignore the result because the state could have already been changed
+            let _ = self
+                .flags
+                .compare_exchange(state, 0, Ordering::Release, Ordering::Relaxed);
+        }
+    }
+}
+
+// Low 4 bytes which are used as endpoint index
+const INDEX_MASK: u64 = 0x0000_0000_ffff_ffff;
+
+// State markers in high 4 bytes
+const INTERMEDIATE: u64 = 0xffff_fffe;
+const IN_PROCESS: u64 = 0xffff_ffff;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn selection_is_cached() -> Result<()> {
+        let selection = EndpointSelection::new(Duration::from_secs(60));
+        let searches = &AtomicUsize::new(0);
+
+        let find = move || async move {
+            searches.fetch_add(1, Ordering::Relaxed);
+            Ok(1)
+        };
+
+        assert_eq!(selection.select(find).await?, 1);
+        assert_eq!(selection.select(find).await?, 1);
+        assert_eq!(searches.load(Ordering::Relaxed), 1);
+
+        selection.reset();
+        assert_eq!(selection.select(find).await?, 1);
+        assert_eq!(searches.load(Ordering::Relaxed), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_selection_is_retried() -> Result<()> {
+        let selection = EndpointSelection::new(Duration::from_secs(60));
+
+        assert!(selection
+            .select(|| async { Err(anyhow::anyhow!("no endpoints")) })
+            .await
+            .is_err());
+        assert_eq!(selection.select(|| async { Ok(2) }).await?, 2);
+
+        Ok(())
+    }
+}
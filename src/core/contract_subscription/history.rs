@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use nekoton_utils::*;
+
+use crate::transport::models::RawTransaction;
+
+/// Continuous chain of transactions, linked via `prev_trans_lt`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LtRange {
+    /// Lt of the oldest transaction in the chain
+    #[serde(with = "serde_u64")]
+    pub min_lt: u64,
+    /// Lt of the newest transaction in the chain
+    #[serde(with = "serde_u64")]
+    pub max_lt: u64,
+    /// Lt of the transaction before the oldest one. Zero for the first transaction of the account
+    #[serde(with = "serde_u64")]
+    pub prev_trans_lt: u64,
+}
+
+/// Missing transactions between two known ranges
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryGap {
+    /// Lt of the newest missing transaction
+    #[serde(with = "serde_u64")]
+    pub upper_lt: u64,
+    /// Lt of the newest known transaction below the gap
+    #[serde(with = "serde_u64")]
+    pub lower_lt: u64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryWatermark {
+    /// All transactions from the newest known one down to this lt were received,
+    /// except for the skipped gaps, see [`HistoryTracker::skip_gap`]
+    #[serde(with = "serde_u64")]
+    pub lt: u64,
+    /// Whether this lt is the first transaction of the account
+    pub complete: bool,
+}
+
+/// Keeps track of received transactions to find gaps in the history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryTracker {
+    /// Known ranges by their `min_lt`
+    ranges: BTreeMap<u64, LtRange>,
+}
+
+impl HistoryTracker {
+    pub fn from_ranges<I: IntoIterator<Item = LtRange>>(ranges: I) -> Self {
+        let mut result = Self::default();
+        for range in ranges {
+            result.insert(range);
+        }
+        result
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = &LtRange> + '_ {
+        self.ranges.values()
+    }
+
+    /// Records received transactions in any order
+    pub fn add_transactions(&mut self, transactions: &[RawTransaction]) {
+        for transaction in transactions {
+            self.insert(LtRange {
+                min_lt: transaction.data.lt,
+                max_lt: transaction.data.lt,
+                prev_trans_lt: transaction.data.prev_trans_lt,
+            });
+        }
+    }
+
+    /// Returns gaps between known ranges, from the oldest to the newest.
+    ///
+    /// NOTE: transactions older than the oldest known range are not considered as a gap
+    pub fn gaps(&self) -> Vec<HistoryGap> {
+        let mut gaps = Vec::new();
+        let mut lower_lt = None;
+        for range in self.ranges.values() {
+            if let Some(lower_lt) = lower_lt {
+                gaps.push(HistoryGap {
+                    upper_lt: range.prev_trans_lt,
+                    lower_lt,
+                });
+            }
+            lower_lt = Some(range.max_lt);
+        }
+        gaps
+    }
+
+    /// Merges the ranges around the gap, e.g. when its transactions were pruned
+    /// by the backend, so that the gap is no longer reported
+    pub fn skip_gap(&mut self, gap: &HistoryGap) {
+        let lower = match self.ranges.range(..=gap.lower_lt).next_back() {
+            Some((_, lower)) if lower.max_lt == gap.lower_lt => *lower,
+            _ => return,
+        };
+        let upper = match self.ranges.range(gap.lower_lt + 1..).next() {
+            Some((_, upper)) if upper.prev_trans_lt == gap.upper_lt => *upper,
+            _ => return,
+        };
+
+        self.ranges.remove(&upper.min_lt);
+        self.ranges.insert(
+            lower.min_lt,
+            LtRange {
+                max_lt: upper.max_lt,
+                ..lower
+            },
+        );
+    }
+
+    /// Returns the lowest lt down to which the newest range is continuous,
+    /// not counting the skipped gaps
+    pub fn watermark(&self) -> Option<HistoryWatermark> {
+        let (_, range) = self.ranges.iter().next_back()?;
+        Some(HistoryWatermark {
+            lt: range.min_lt,
+            complete: range.prev_trans_lt == 0,
+        })
+    }
+
+    fn insert(&mut self, mut range: LtRange) {
+        // Skip already known transactions
+        if let Some((_, known)) = self.ranges.range(..=range.max_lt).next_back() {
+            if known.max_lt >= range.min_lt {
+                return;
+            }
+        }
+
+        // Merge with the range which ends right before this one
+        if range.prev_trans_lt != 0 {
+            if let Some((&min_lt, lower)) = self.ranges.range(..=range.prev_trans_lt).next_back() {
+                if lower.max_lt == range.prev_trans_lt {
+                    range.min_lt = lower.min_lt;
+                    range.prev_trans_lt = lower.prev_trans_lt;
+                    self.ranges.remove(&min_lt);
+                }
+            }
+        }
+
+        // Merge with the range which starts right after this one
+        if let Some((&min_lt, upper)) = self.ranges.range(range.max_lt + 1..).next() {
+            if upper.prev_trans_lt == range.max_lt {
+                range.max_lt = upper.max_lt;
+                self.ranges.remove(&min_lt);
+            }
+        }
+
+        self.ranges.insert(range.min_lt, range);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(min_lt: u64, max_lt: u64, prev_trans_lt: u64) -> LtRange {
+        LtRange {
+            min_lt,
+            max_lt,
+            prev_trans_lt,
+        }
+    }
+
+    #[test]
+    fn gaps_are_found() {
+        // Chain: 10 <- 20 <- 30 <- 40 <- 50
+        let mut history =
+            HistoryTracker::from_ranges([range(50, 50, 40), range(20, 20, 10), range(10, 10, 0)]);
+
+        assert_eq!(
+            history.ranges().copied().collect::<Vec<_>>(),
+            [range(10, 20, 0), range(50, 50, 40)]
+        );
+        assert_eq!(
+            history.gaps(),
+            [HistoryGap {
+                upper_lt: 40,
+                lower_lt: 20
+            }]
+        );
+        assert_eq!(
+            history.watermark(),
+            Some(HistoryWatermark {
+                lt: 50,
+                complete: false
+            })
+        );
+
+        history.insert(range(40, 40, 30));
+        history.insert(range(40, 40, 30));
+        assert_eq!(
+            history.gaps(),
+            [HistoryGap {
+                upper_lt: 30,
+                lower_lt: 20
+            }]
+        );
+
+        history.insert(range(30, 30, 20));
+        assert!(history.gaps().is_empty());
+        assert_eq!(
+            history.watermark(),
+            Some(HistoryWatermark {
+                lt: 10,
+                complete: true
+            })
+        );
+    }
+
+    #[test]
+    fn gaps_are_skipped() {
+        // Chain: 10 <- 20 <- (30 is pruned) <- 40 <- 50
+        let mut history =
+            HistoryTracker::from_ranges([range(40, 50, 30), range(20, 20, 10), range(10, 10, 0)]);
+        let gaps = history.gaps();
+        assert_eq!(gaps.len(), 1);
+
+        history.skip_gap(&gaps[0]);
+        assert!(history.gaps().is_empty());
+        assert_eq!(
+            history.ranges().copied().collect::<Vec<_>>(),
+            [range(10, 50, 0)]
+        );
+        assert_eq!(
+            history.watermark(),
+            Some(HistoryWatermark {
+                lt: 10,
+                complete: true
+            })
+        );
+
+        // Unknown gaps are ignored
+        history.skip_gap(&gaps[0]);
+        assert_eq!(
+            history.ranges().copied().collect::<Vec<_>>(),
+            [range(10, 50, 0)]
+        );
+    }
+}
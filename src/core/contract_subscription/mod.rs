@@ -1,22 +1,28 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use async_trait::async_trait;
 use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use ton_block::MsgAddressInt;
 
 use nekoton_abi::{Executor, LastTransactionId};
 use nekoton_utils::*;
 
+use self::history::{HistoryTracker, HistoryWatermark, LtRange};
 use super::models::{
     ContractState, PendingTransaction, ReliableBehavior, TransactionsBatchInfo,
     TransactionsBatchType,
 };
 use super::{utils, PollingMethod};
 use crate::core::utils::{MessageContext, PendingTransactionsExt};
+use crate::external::Storage;
 use crate::transport::models::{RawContractState, RawTransaction};
 use crate::transport::Transport;
 
+pub mod history;
+
 /// Used as a base object for different listeners implementation
 pub struct ContractSubscription {
     clock: Arc<dyn Clock>,
@@ -26,6 +32,8 @@ pub struct ContractSubscription {
     latest_known_lt: Option<u64>,
     pending_transactions: Vec<PendingTransaction>,
     transactions_synced: bool,
+    history: HistoryTracker,
+    reported_watermark: Option<HistoryWatermark>,
 }
 
 impl ContractSubscription {
@@ -44,6 +52,8 @@ impl ContractSubscription {
             latest_known_lt: None,
             pending_transactions: Vec::new(),
             transactions_synced: false,
+            history: Default::default(),
+            reported_watermark: None,
         };
 
         result.transactions_synced = !result
@@ -74,6 +84,51 @@ impl ContractSubscription {
         Ok(result)
     }
 
+    /// Restores the subscription from the snapshot.
+    ///
+    /// The actual state is always passed to `on_contract_state`, so that wrappers
+    /// could rebuild their data. Restored pending transactions are resolved
+    /// during the next refresh
+    pub async fn resume(
+        clock: Arc<dyn Clock>,
+        transport: Arc<dyn Transport>,
+        snapshot: ContractSubscriptionSnapshot,
+        on_contract_state: OnContractState<'_>,
+    ) -> Result<Self> {
+        let contract_state = transport.get_contract_state(&snapshot.address).await?;
+        on_contract_state(&contract_state);
+
+        let contract_state = contract_state.brief();
+        let updated = contract_state.last_lt > snapshot.last_lt;
+
+        Ok(Self {
+            clock,
+            transport,
+            address: snapshot.address,
+            contract_state,
+            latest_known_lt: snapshot.latest_known_lt,
+            pending_transactions: snapshot.pending_transactions,
+            transactions_synced: snapshot.transactions_synced && !updated,
+            history: HistoryTracker::from_ranges(snapshot.history),
+            reported_watermark: None,
+        })
+    }
+
+    pub fn snapshot(&self) -> ContractSubscriptionSnapshot {
+        ContractSubscriptionSnapshot {
+            address: self.address.clone(),
+            last_lt: self.contract_state.last_lt,
+            latest_known_lt: self.latest_known_lt,
+            pending_transactions: self.pending_transactions.clone(),
+            transactions_synced: self.transactions_synced,
+            history: self.history.ranges().copied().collect(),
+        }
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
     pub fn transport(&self) -> &Arc<dyn Transport> {
         &self.transport
     }
@@ -114,6 +169,20 @@ impl ContractSubscription {
         }
     }
 
+    pub fn history(&self) -> &HistoryTracker {
+        &self.history
+    }
+
+    /// Returns the history watermark if it has changed since the previous call
+    pub fn take_history_watermark(&mut self) -> Option<HistoryWatermark> {
+        let watermark = self.history.watermark();
+        if watermark == self.reported_watermark {
+            return None;
+        }
+        self.reported_watermark = watermark;
+        watermark
+    }
+
     pub fn add_pending_transaction(&mut self, pending_transaction: PendingTransaction) {
         self.pending_transactions.push(pending_transaction);
     }
@@ -205,6 +274,7 @@ impl ContractSubscription {
 
             if let Some((mut new_transactions, batch_info)) = new_transactions {
                 new_transactions.reverse();
+                self.history.add_transactions(&new_transactions);
                 self.check_executed_transactions(&new_transactions, on_message_sent);
 
                 if let Some(first) = new_transactions.first() {
@@ -329,6 +399,7 @@ impl ContractSubscription {
             };
 
             // requires `&mut self`, so `request_transactions` must use outer objects
+            self.history.add_transactions(&new_transactions);
             self.check_executed_transactions(&new_transactions, on_message_sent);
 
             if new_latest_known_transaction.is_none() {
@@ -376,12 +447,56 @@ impl ContractSubscription {
                 batch_type: TransactionsBatchType::Old,
             };
 
+            self.history.add_transactions(&transactions);
             on_transactions_found(transactions, batch_info);
         }
 
         Ok(())
     }
 
+    /// Fetches missing transactions of the newest gap in the history,
+    /// see [`HistoryTracker::gaps`]. Returns whether there are gaps left.
+    ///
+    /// Gaps which the transport has no transactions for (e.g. pruned history)
+    /// are skipped, see [`HistoryTracker::skip_gap`]
+    pub async fn backfill_history(
+        &mut self,
+        on_transactions_found: OnTransactionsFound<'_>,
+    ) -> Result<bool> {
+        let gap = match self.history.gaps().pop() {
+            Some(gap) => gap,
+            None => return Ok(false),
+        };
+
+        let transactions = self
+            .transport
+            .get_transactions(
+                &self.address,
+                gap.upper_lt,
+                self.transport.info().max_transactions_per_fetch,
+            )
+            .await?
+            .into_iter()
+            .filter(|transaction| transaction.data.lt > gap.lower_lt)
+            .collect::<Vec<_>>();
+
+        match (transactions.first(), transactions.last()) {
+            (Some(first), Some(last)) => {
+                let batch_info = TransactionsBatchInfo {
+                    min_lt: last.data.lt,
+                    max_lt: first.data.lt,
+                    batch_type: TransactionsBatchType::Old,
+                };
+
+                self.history.add_transactions(&transactions);
+                on_transactions_found(transactions, batch_info);
+            }
+            _ => self.history.skip_gap(&gap),
+        }
+
+        Ok(!self.history.gaps().is_empty())
+    }
+
     async fn refresh_contract_state_impl(
         &mut self,
         prev_trans_lt: Option<u64>,
@@ -455,10 +570,256 @@ pub struct TransactionExecutionOptions {
     pub override_balance: Option<u64>,
 }
 
+/// Serializable state of [`ContractSubscription`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractSubscriptionSnapshot {
+    #[serde(with = "serde_address")]
+    pub address: MsgAddressInt,
+    /// Latest lt of the contract state.
+    ///
+    /// The state itself is not stored, because it is always refetched on resume
+    #[serde(with = "serde_u64")]
+    pub last_lt: u64,
+    #[serde(with = "serde_optional_u64")]
+    pub latest_known_lt: Option<u64>,
+    pub pending_transactions: Vec<PendingTransaction>,
+    pub transactions_synced: bool,
+    /// Known ranges of the transactions history
+    #[serde(default)]
+    pub history: Vec<LtRange>,
+}
+
+/// Snapshot of a subscription which can be persisted via [`Storage`]
+#[async_trait]
+pub trait SubscriptionSnapshot: Serialize + DeserializeOwned + Send + Sync {
+    const STORAGE_KEY_PREFIX: &'static str;
+
+    fn address(&self) -> &MsgAddressInt;
+
+    fn storage_key(address: &MsgAddressInt) -> String {
+        format!("{}{}", Self::STORAGE_KEY_PREFIX, address)
+    }
+
+    async fn save(&self, storage: &dyn Storage) -> Result<()> {
+        let data = serde_json::to_string(self)?;
+        storage.set(&Self::storage_key(self.address()), &data).await
+    }
+
+    async fn load(storage: &dyn Storage, address: &MsgAddressInt) -> Result<Option<Self>> {
+        match storage.get(&Self::storage_key(address)).await? {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn remove(storage: &dyn Storage, address: &MsgAddressInt) -> Result<()> {
+        storage.remove(&Self::storage_key(address)).await
+    }
+}
+
+impl SubscriptionSnapshot for ContractSubscriptionSnapshot {
+    const STORAGE_KEY_PREFIX: &'static str = "__core__contract_subscription_";
+
+    fn address(&self) -> &MsgAddressInt {
+        &self.address
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use ton_block::Serializable;
+
+    use crate::external::TestStorage;
+    use crate::transport::test_utils::{
+        emulated_transport, funded_account, polling_info, sender_address, test_address, test_clock,
+        transfer, StubTransport, NOW_SEC,
+    };
+
     use super::*;
 
+    #[tokio::test]
+    async fn pending_transactions_are_restored() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let address = funded_account(&transport).await?;
+
+        let mut subscription = ContractSubscription::subscribe(
+            clock.clone(),
+            transport.clone(),
+            address.clone(),
+            &mut |_| {},
+            None,
+        )
+        .await?;
+
+        let message = transfer(&address, 2_000_000_000);
+        let pending_transaction = PendingTransaction {
+            message_hash: message.serialize()?.repr_hash(),
+            src: Some(sender_address()),
+            latest_lt: subscription.contract_state().last_lt,
+            created_at: NOW_SEC as u32,
+            expire_at: NOW_SEC as u32 + 60,
+        };
+        subscription.add_pending_transaction(pending_transaction.clone());
+
+        let storage = TestStorage::default();
+        subscription.snapshot().save(&storage).await?;
+        drop(subscription);
+
+        // The message is delivered while the app is not running
+        transport.send_message(&message).await?;
+
+        let snapshot = ContractSubscriptionSnapshot::load(&storage, &address)
+            .await?
+            .expect("snapshot must exist");
+        let mut subscription =
+            ContractSubscription::resume(clock, transport, snapshot, &mut |_| {}).await?;
+        assert_eq!(
+            subscription.pending_transactions(),
+            &[pending_transaction.clone()]
+        );
+
+        let mut sent = Vec::new();
+        subscription
+            .refresh(
+                &mut |_| {},
+                &mut |_, _| {},
+                &mut |pending, _| sent.push(pending),
+                &mut |_| {},
+            )
+            .await?;
+        assert_eq!(sent, vec![pending_transaction]);
+        assert!(subscription.pending_transactions().is_empty());
+
+        ContractSubscriptionSnapshot::remove(&storage, &address).await?;
+        assert!(ContractSubscriptionSnapshot::load(&storage, &address)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn history_gaps_are_backfilled() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let address = test_address();
+        for value in [1_000_000_000, 2_000_000_000, 3_000_000_000] {
+            transport.send_message(&transfer(&address, value)).await?;
+        }
+
+        let mut preloaded = Vec::new();
+        let on_transactions_found: OnTransactionsFound<'_> =
+            &mut |transactions, _| preloaded.extend(transactions);
+        let subscription = ContractSubscription::subscribe(
+            clock.clone(),
+            transport.clone(),
+            address,
+            &mut |_| {},
+            Some(on_transactions_found),
+        )
+        .await?;
+        assert_eq!(preloaded.len(), 3);
+        assert!(subscription.history().gaps().is_empty());
+
+        // Forget the middle transaction
+        let (first, second, third) = (&preloaded[2], &preloaded[1], &preloaded[0]);
+        let mut snapshot = subscription.snapshot();
+        snapshot.history = [first, third]
+            .into_iter()
+            .map(|transaction| LtRange {
+                min_lt: transaction.data.lt,
+                max_lt: transaction.data.lt,
+                prev_trans_lt: transaction.data.prev_trans_lt,
+            })
+            .collect();
+
+        let mut subscription =
+            ContractSubscription::resume(clock, transport, snapshot, &mut |_| {}).await?;
+        assert_eq!(subscription.history().gaps().len(), 1);
+        assert_eq!(
+            subscription.take_history_watermark(),
+            Some(HistoryWatermark {
+                lt: third.data.lt,
+                complete: false
+            })
+        );
+
+        let mut found = Vec::new();
+        let has_gaps = subscription
+            .backfill_history(&mut |transactions, batch_info| {
+                assert_eq!(batch_info.batch_type, TransactionsBatchType::Old);
+                found.extend(transactions.into_iter().map(|transaction| transaction.hash));
+            })
+            .await?;
+        assert!(!has_gaps);
+        assert_eq!(found, vec![second.hash]);
+
+        assert_eq!(
+            subscription.take_history_watermark(),
+            Some(HistoryWatermark {
+                lt: first.data.lt,
+                complete: true
+            })
+        );
+        assert_eq!(subscription.take_history_watermark(), None);
+        assert!(!subscription.backfill_history(&mut |_, _| {}).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pruned_history_gaps_are_skipped() -> Result<()> {
+        // NOTE: the stub transport has no transactions, as if they were pruned
+        let transport = Arc::new(StubTransport::new(polling_info()));
+        let snapshot = ContractSubscriptionSnapshot {
+            address: test_address(),
+            last_lt: 0,
+            latest_known_lt: None,
+            pending_transactions: Vec::new(),
+            transactions_synced: true,
+            history: vec![
+                LtRange {
+                    min_lt: 10,
+                    max_lt: 20,
+                    prev_trans_lt: 0,
+                },
+                LtRange {
+                    min_lt: 40,
+                    max_lt: 50,
+                    prev_trans_lt: 30,
+                },
+            ],
+        };
+
+        let mut subscription =
+            ContractSubscription::resume(test_clock(), transport.clone(), snapshot, &mut |_| {})
+                .await?;
+        assert_eq!(
+            subscription.take_history_watermark(),
+            Some(HistoryWatermark {
+                lt: 40,
+                complete: false
+            })
+        );
+
+        assert!(!subscription.backfill_history(&mut |_, _| {}).await?);
+        assert_eq!(
+            subscription.take_history_watermark(),
+            Some(HistoryWatermark {
+                lt: 10,
+                complete: true
+            })
+        );
+
+        // The skipped gap is not requested again
+        assert!(!subscription.backfill_history(&mut |_, _| {}).await?);
+        assert_eq!(transport.requests("get_transactions"), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn executor_params_serialization() {
         assert_eq!(
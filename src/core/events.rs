@@ -0,0 +1,160 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+/// Subscription handler which forwards all callbacks as events into the [`EventStream`].
+///
+/// It implements the handler trait of each subscription kind for its event type,
+/// e.g. [`TonWalletSubscriptionHandler`] for [`TonWalletEvent`]. Subscriptions use the
+/// same code paths as with any other handler, so both styles can be mixed.
+///
+/// [`TonWalletSubscriptionHandler`]: super::ton_wallet::TonWalletSubscriptionHandler
+/// [`TonWalletEvent`]: super::ton_wallet::TonWalletEvent
+pub struct EventsHandler<E> {
+    tx: EventSender<E>,
+    dropped: AtomicU64,
+}
+
+impl<E: Send + 'static> EventsHandler<E> {
+    /// Creates a handler for the subscription and a stream of its events.
+    ///
+    /// Events are buffered until consumed and the buffer is unbounded, so a stream
+    /// which is not polled grows with every event. Use [`bounded_channel`] to limit it.
+    /// The stream ends when the subscription with this handler is dropped
+    ///
+    /// [`bounded_channel`]: Self::bounded_channel
+    pub fn channel() -> (Arc<Self>, EventStream<E>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Arc::new(Self::new(EventSender::Unbounded(tx))),
+            EventStream {
+                rx: EventReceiver::Unbounded(rx),
+            },
+        )
+    }
+
+    /// Creates a handler with the stream which buffers at most `capacity` events.
+    ///
+    /// Subscriptions are never blocked by a slow consumer: new events are dropped
+    /// while the buffer is full and counted in [`dropped`]. Refresh the subscription
+    /// state after a drop, because the stream is no longer complete
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is zero
+    ///
+    /// [`dropped`]: Self::dropped
+    pub fn bounded_channel(capacity: usize) -> (Arc<Self>, EventStream<E>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (
+            Arc::new(Self::new(EventSender::Bounded(tx))),
+            EventStream {
+                rx: EventReceiver::Bounded(rx),
+            },
+        )
+    }
+
+    /// Number of events dropped due to the full buffer
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    fn new(tx: EventSender<E>) -> Self {
+        Self {
+            tx,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn emit(&self, event: E) {
+        // NOTE: events are silently dropped when nobody listens to them
+        match &self.tx {
+            EventSender::Unbounded(tx) => {
+                tx.send(event).ok();
+            }
+            EventSender::Bounded(tx) => {
+                if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(event) {
+                    self.dropped.fetch_add(1, Ordering::Release);
+                }
+            }
+        }
+    }
+}
+
+enum EventSender<E> {
+    Unbounded(mpsc::UnboundedSender<E>),
+    Bounded(mpsc::Sender<E>),
+}
+
+pub struct EventStream<E> {
+    rx: EventReceiver<E>,
+}
+
+enum EventReceiver<E> {
+    Unbounded(mpsc::UnboundedReceiver<E>),
+    Bounded(mpsc::Receiver<E>),
+}
+
+impl<E> Stream for EventStream<E> {
+    type Item = E;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match &mut self.rx {
+            EventReceiver::Unbounded(rx) => rx.poll_recv(cx),
+            EventReceiver::Bounded(rx) => rx.poll_recv(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use futures_util::StreamExt;
+
+    use crate::core::generic_contract::{GenericContract, GenericContractEvent};
+    use crate::transport::test_utils::{emulated_transport, funded_account, test_clock};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn generic_contract_events() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let address = funded_account(&transport).await?;
+
+        let (handler, events) = EventsHandler::<GenericContractEvent>::channel();
+        let contract = GenericContract::subscribe(clock, transport, address, handler, true).await?;
+
+        // The stream ends with the subscription
+        drop(contract);
+        let events = events.collect::<Vec<_>>().await;
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0],
+            GenericContractEvent::StateChanged { .. }
+        ));
+        assert!(matches!(
+            &events[1],
+            GenericContractEvent::TransactionsFound { transactions, .. } if transactions.len() == 1
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bounded_channel_drops_new_events() {
+        let (handler, events) = EventsHandler::<u32>::bounded_channel(2);
+        for i in 0..5 {
+            handler.emit(i);
+        }
+        assert_eq!(handler.dropped(), 3);
+
+        drop(handler);
+        assert_eq!(events.collect::<Vec<_>>().await, [0, 1]);
+    }
+}
@@ -6,15 +6,20 @@ use ton_block::{GetRepresentationHash, MsgAddressInt};
 
 use nekoton_utils::Clock;
 
+use super::contract_subscription::history::HistoryWatermark;
+use super::events::EventsHandler;
 use super::models::{ContractState, PendingTransaction, Transaction, TransactionsBatchInfo};
+use super::rebroadcast::{MessageSigner, RebroadcastPolicy, Rebroadcaster};
 use super::{ContractSubscription, PollingMethod, TransactionExecutionOptions};
 use crate::core::utils;
+use crate::crypto::{SignedMessage, UnsignedMessage};
 use crate::transport::models::{RawContractState, RawTransaction};
 use crate::transport::Transport;
 
 pub struct GenericContract {
     contract_subscription: ContractSubscription,
     handler: Arc<dyn GenericContractSubscriptionHandler>,
+    rebroadcaster: Option<Rebroadcaster>,
 }
 
 impl GenericContract {
@@ -56,6 +61,7 @@ impl GenericContract {
         Ok(Self {
             contract_subscription,
             handler,
+            rebroadcaster: None,
         })
     }
 
@@ -83,41 +89,127 @@ impl GenericContract {
         self.contract_subscription.send(message, expire_at).await
     }
 
+    /// Rebroadcasts messages sent with [`GenericContract::send_with_rebroadcast`] and
+    /// re-signs them after expiration, until they are delivered or the deadline is reached
+    pub fn enable_rebroadcast(
+        &mut self,
+        policy: RebroadcastPolicy,
+        signer: Arc<dyn MessageSigner>,
+    ) {
+        self.rebroadcaster = Some(Rebroadcaster::new(policy, signer));
+    }
+
+    pub fn disable_rebroadcast(&mut self) {
+        self.rebroadcaster = None;
+    }
+
+    /// Sends the message and tracks it if rebroadcast is enabled.
+    /// Otherwise works the same as [`GenericContract::send`]
+    pub async fn send_with_rebroadcast(
+        &mut self,
+        unsigned_message: Box<dyn UnsignedMessage>,
+        signed_message: &SignedMessage,
+    ) -> Result<PendingTransaction> {
+        let pending_transaction = self
+            .contract_subscription
+            .send(&signed_message.message, signed_message.expire_at)
+            .await?;
+
+        if let Some(rebroadcaster) = &mut self.rebroadcaster {
+            rebroadcaster.track(
+                self.contract_subscription.clock().as_ref(),
+                pending_transaction.clone(),
+                unsigned_message,
+                signed_message.message.clone(),
+            );
+        }
+
+        Ok(pending_transaction)
+    }
+
     pub async fn refresh(&mut self) -> Result<()> {
         let handler = self.handler.as_ref();
+        let mut expired = Vec::new();
         self.contract_subscription
             .refresh(
                 &mut make_contract_state_handler(handler),
                 &mut make_transactions_handler(handler),
                 &mut make_message_sent_handler(handler),
-                &mut make_message_expired_handler(handler),
+                &mut |pending_transaction| expired.push(pending_transaction),
             )
             .await?;
 
+        self.handle_expired_messages(expired).await;
+        self.report_history_watermark();
         Ok(())
     }
 
     pub async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
         let handler = self.handler.as_ref();
+        let mut expired = Vec::new();
         let new_account_state = self.contract_subscription.handle_block(
             block,
             &mut make_transactions_handler(handler),
             &mut make_message_sent_handler(handler),
-            &mut make_message_expired_handler(handler),
+            &mut |pending_transaction| expired.push(pending_transaction),
         )?;
 
         if let Some(account_state) = new_account_state {
             handler.on_state_changed(account_state);
         }
 
+        self.handle_expired_messages(expired).await;
+        self.report_history_watermark();
         Ok(())
     }
 
+    async fn handle_expired_messages(&mut self, expired: Vec<PendingTransaction>) {
+        let handler = self.handler.as_ref();
+        let expired = match &mut self.rebroadcaster {
+            Some(rebroadcaster) => {
+                rebroadcaster
+                    .process(
+                        &mut self.contract_subscription,
+                        expired,
+                        &mut make_message_resigned_handler(handler),
+                    )
+                    .await
+            }
+            None => expired,
+        };
+
+        for pending_transaction in expired {
+            handler.on_message_expired(pending_transaction);
+        }
+    }
+
     pub async fn preload_transactions(&mut self, from_lt: u64) -> Result<()> {
         let handler = self.handler.as_ref();
         self.contract_subscription
             .preload_transactions(from_lt, &mut make_transactions_handler(handler))
-            .await
+            .await?;
+
+        self.report_history_watermark();
+        Ok(())
+    }
+
+    /// Fetches transactions of the newest gap in the known history.
+    /// Returns whether there are gaps left
+    pub async fn backfill_history(&mut self) -> Result<bool> {
+        let handler = self.handler.as_ref();
+        let has_gaps = self
+            .contract_subscription
+            .backfill_history(&mut make_transactions_handler(handler))
+            .await?;
+
+        self.report_history_watermark();
+        Ok(has_gaps)
+    }
+
+    fn report_history_watermark(&mut self) {
+        if let Some(watermark) = self.contract_subscription.take_history_watermark() {
+            self.handler.on_history_watermark_changed(watermark);
+        }
     }
 
     pub async fn estimate_fees(&mut self, message: &ton_block::Message) -> Result<u128> {
@@ -163,10 +255,12 @@ fn make_message_sent_handler(
     }
 }
 
-fn make_message_expired_handler(
+fn make_message_resigned_handler(
     handler: &'_ dyn GenericContractSubscriptionHandler,
-) -> impl FnMut(PendingTransaction) + '_ {
-    move |pending_transaction| handler.on_message_expired(pending_transaction)
+) -> impl FnMut(PendingTransaction, PendingTransaction) + '_ {
+    move |old_pending_transaction, new_pending_transaction| {
+        handler.on_message_resigned(old_pending_transaction, new_pending_transaction)
+    }
 }
 
 pub trait GenericContractSubscriptionHandler: Send + Sync {
@@ -180,6 +274,18 @@ pub trait GenericContractSubscriptionHandler: Send + Sync {
     /// Called when no transactions produced for the specific message before some expiration time
     fn on_message_expired(&self, pending_transaction: PendingTransaction);
 
+    /// Called when the expired message was re-signed and sent again.
+    /// Both pending transactions belong to the same transfer.
+    /// See [`GenericContract::enable_rebroadcast`]
+    fn on_message_resigned(
+        &self,
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    ) {
+        let _ = old_pending_transaction;
+        let _ = new_pending_transaction;
+    }
+
     /// Called every time a new state is detected
     fn on_state_changed(&self, new_state: ContractState);
 
@@ -192,4 +298,84 @@ pub trait GenericContractSubscriptionHandler: Send + Sync {
         transactions: Vec<Transaction>,
         batch_info: TransactionsBatchInfo,
     );
+
+    /// Called when the known history becomes continuous down to the new lt
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        let _ = watermark;
+    }
+}
+
+/// Event of [`GenericContract`], see [`EventsHandler`]
+#[derive(Debug, Clone)]
+pub enum GenericContractEvent {
+    MessageSent {
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    },
+    MessageExpired {
+        pending_transaction: PendingTransaction,
+    },
+    MessageResigned {
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    },
+    StateChanged {
+        new_state: ContractState,
+    },
+    TransactionsFound {
+        transactions: Vec<Transaction>,
+        batch_info: TransactionsBatchInfo,
+    },
+    HistoryWatermarkChanged {
+        watermark: HistoryWatermark,
+    },
+}
+
+impl GenericContractSubscriptionHandler for EventsHandler<GenericContractEvent> {
+    fn on_message_sent(
+        &self,
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    ) {
+        self.emit(GenericContractEvent::MessageSent {
+            pending_transaction,
+            transaction,
+        });
+    }
+
+    fn on_message_expired(&self, pending_transaction: PendingTransaction) {
+        self.emit(GenericContractEvent::MessageExpired {
+            pending_transaction,
+        });
+    }
+
+    fn on_message_resigned(
+        &self,
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    ) {
+        self.emit(GenericContractEvent::MessageResigned {
+            old_pending_transaction,
+            new_pending_transaction,
+        });
+    }
+
+    fn on_state_changed(&self, new_state: ContractState) {
+        self.emit(GenericContractEvent::StateChanged { new_state });
+    }
+
+    fn on_transactions_found(
+        &self,
+        transactions: Vec<Transaction>,
+        batch_info: TransactionsBatchInfo,
+    ) {
+        self.emit(GenericContractEvent::TransactionsFound {
+            transactions,
+            batch_info,
+        });
+    }
+
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        self.emit(GenericContractEvent::HistoryWatermarkChanged { watermark });
+    }
 }
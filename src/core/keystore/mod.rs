@@ -576,38 +576,10 @@ mod tests {
         DerivedKeyCreateInput, DerivedKeySignParams, DerivedKeySigner, EncryptedKeyCreateInput,
         EncryptedKeyPassword, EncryptedKeySigner, MnemonicType, Password, PasswordCacheBehavior,
     };
-    use std::collections::HashMap;
+    use crate::external::TestStorage;
 
     use super::*;
 
-    #[derive(Default)]
-    struct TestStorage(parking_lot::Mutex<HashMap<String, String>>);
-
-    #[async_trait::async_trait]
-    impl Storage for TestStorage {
-        async fn get(&self, key: &str) -> Result<Option<String>> {
-            Ok(self.0.lock().get(key).cloned())
-        }
-
-        async fn set(&self, key: &str, value: &str) -> Result<()> {
-            self.set_unchecked(key, value);
-            Ok(())
-        }
-
-        fn set_unchecked(&self, key: &str, value: &str) {
-            self.0.lock().insert(key.to_string(), value.to_string());
-        }
-
-        async fn remove(&self, key: &str) -> Result<()> {
-            self.remove_unchecked(key);
-            Ok(())
-        }
-
-        fn remove_unchecked(&self, key: &str) {
-            self.0.lock().remove(key);
-        }
-    }
-
     const TEST_MNEMONICS: [&str; 2] = [
         "admit cheap engage ancient audit drink mammal mobile fashion aspect rapid else",
         "stuff chuckle dirt pig health refuse foam liquid around cream undo forum",
@@ -1,20 +1,31 @@
+use std::future::Future;
+use std::time::Duration;
+
 use anyhow::Result;
 use nekoton_utils::*;
 use serde::{Deserialize, Serialize};
 
-pub use self::contract_subscription::{ContractSubscription, TransactionExecutionOptions};
+pub use self::contract_subscription::{
+    ContractSubscription, ContractSubscriptionSnapshot, SubscriptionSnapshot,
+    TransactionExecutionOptions,
+};
 use self::models::PollingMethod;
+use crate::crypto::SignedMessage;
+use crate::transport::models::RawTransaction;
 use crate::transport::Transport;
 
 pub mod accounts_storage;
 pub mod contract_subscription;
 pub mod dens;
+pub mod events;
 pub mod generic_contract;
 pub mod keystore;
 pub mod models;
 pub mod nft_wallet;
 pub mod owners_cache;
 pub mod parsing;
+pub mod rebroadcast;
+pub mod scheduler;
 pub mod token_wallet;
 pub mod ton_wallet;
 pub mod transactions_tree;
@@ -33,6 +44,30 @@ impl TonInterface {
         self.transport.send_message(message).await
     }
 
+    /// Sends the message and waits for its transaction on the destination account.
+    ///
+    /// See [`utils::send_message_and_wait`]
+    pub async fn send_message_and_wait<S, F>(
+        &self,
+        clock: &dyn Clock,
+        message: &SignedMessage,
+        poll_interval: Duration,
+        sleep: S,
+    ) -> Result<RawTransaction>
+    where
+        S: Fn(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        utils::send_message_and_wait(
+            clock,
+            self.transport.as_ref(),
+            message,
+            poll_interval,
+            sleep,
+        )
+        .await
+    }
+
     pub fn set_transport(&mut self, transport: Box<dyn Transport>) {
         self.transport = transport;
     }
@@ -10,6 +10,8 @@ use nekoton_utils::Clock;
 use ton_block::{MsgAddressInt, Serializable};
 use ton_types::{BuilderData, Cell, UInt256};
 
+use crate::core::contract_subscription::history::HistoryWatermark;
+use crate::core::events::EventsHandler;
 use crate::core::models::{
     NftTransaction, NftVersion, PendingTransaction, Transaction, TransactionWithData,
     TransactionsBatchInfo,
@@ -309,14 +311,39 @@ impl Nft {
                 &mut make_message_sent_handler(handler),
                 &mut make_message_expired_handler(handler),
             )
-            .await
+            .await?;
+
+        self.report_history_watermark();
+        Ok(())
     }
 
     pub async fn preload_transactions(&mut self, from_lt: u64) -> Result<()> {
         let handler = self.handler.as_ref();
         self.contract_subscription
             .preload_transactions(from_lt, &mut make_transactions_handler(handler))
-            .await
+            .await?;
+
+        self.report_history_watermark();
+        Ok(())
+    }
+
+    /// Fetches transactions of the newest gap in the known history.
+    /// Returns whether there are gaps left
+    pub async fn backfill_history(&mut self) -> Result<bool> {
+        let handler = self.handler.as_ref();
+        let has_gaps = self
+            .contract_subscription
+            .backfill_history(&mut make_transactions_handler(handler))
+            .await?;
+
+        self.report_history_watermark();
+        Ok(has_gaps)
+    }
+
+    fn report_history_watermark(&mut self) {
+        if let Some(watermark) = self.contract_subscription.take_history_watermark() {
+            self.handler.on_history_watermark_changed(watermark);
+        }
     }
 }
 
@@ -351,6 +378,78 @@ pub trait NftSubscriptionHandler: Send + Sync {
         let _ = transactions;
         let _ = batch_info;
     }
+
+    /// Called when the known history becomes continuous down to the new lt
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        let _ = watermark;
+    }
+}
+
+/// Event of [`Nft`], see [`EventsHandler`]
+#[derive(Debug, Clone)]
+pub enum NftEvent {
+    MessageSent {
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    },
+    MessageExpired {
+        pending_transaction: PendingTransaction,
+    },
+    ManagerChanged {
+        manager: MsgAddressInt,
+    },
+    OwnerChanged {
+        owner: MsgAddressInt,
+    },
+    TransactionsFound {
+        transactions: Vec<TransactionWithData<NftTransaction>>,
+        batch_info: TransactionsBatchInfo,
+    },
+    HistoryWatermarkChanged {
+        watermark: HistoryWatermark,
+    },
+}
+
+impl NftSubscriptionHandler for EventsHandler<NftEvent> {
+    fn on_message_sent(
+        &self,
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    ) {
+        self.emit(NftEvent::MessageSent {
+            pending_transaction,
+            transaction,
+        });
+    }
+
+    fn on_message_expired(&self, pending_transaction: PendingTransaction) {
+        self.emit(NftEvent::MessageExpired {
+            pending_transaction,
+        });
+    }
+
+    fn on_manager_changed(&self, manager: MsgAddressInt) {
+        self.emit(NftEvent::ManagerChanged { manager });
+    }
+
+    fn on_owner_changed(&self, owner: MsgAddressInt) {
+        self.emit(NftEvent::OwnerChanged { owner });
+    }
+
+    fn on_transactions_found(
+        &self,
+        transactions: Vec<TransactionWithData<NftTransaction>>,
+        batch_info: TransactionsBatchInfo,
+    ) {
+        self.emit(NftEvent::TransactionsFound {
+            transactions,
+            batch_info,
+        });
+    }
+
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        self.emit(NftEvent::HistoryWatermarkChanged { watermark });
+    }
 }
 
 fn make_contract_state_handler<'a>(
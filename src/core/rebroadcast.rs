@@ -0,0 +1,497 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use nekoton_utils::*;
+
+use super::models::PendingTransaction;
+use super::ContractSubscription;
+use crate::crypto::{Signature, UnsignedMessage};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RebroadcastPolicy {
+    /// Interval between broadcasts of the same message while it is still valid.
+    /// Default: `15000`
+    #[serde(with = "serde_duration_ms")]
+    pub rebroadcast_interval: Duration,
+    /// Expired messages are re-signed only within this time after the first broadcast.
+    /// Default: `300000`
+    #[serde(with = "serde_duration_ms")]
+    pub deadline: Duration,
+}
+
+impl Default for RebroadcastPolicy {
+    fn default() -> Self {
+        Self {
+            rebroadcast_interval: Duration::from_secs(15),
+            deadline: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Signs messages whose timeout was refreshed after expiration
+#[async_trait]
+pub trait MessageSigner: Send + Sync {
+    /// Returns a signature of [`UnsignedMessage::hash`]
+    async fn sign(&self, message: &dyn UnsignedMessage) -> Result<Signature>;
+}
+
+/// Keeps external messages alive until they are delivered or the deadline is reached
+pub(crate) struct Rebroadcaster {
+    policy: RebroadcastPolicy,
+    signer: Arc<dyn MessageSigner>,
+    messages: Vec<TrackedMessage>,
+}
+
+impl Rebroadcaster {
+    pub fn new(policy: RebroadcastPolicy, signer: Arc<dyn MessageSigner>) -> Self {
+        Self {
+            policy,
+            signer,
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn track(
+        &mut self,
+        clock: &dyn Clock,
+        pending_transaction: PendingTransaction,
+        unsigned_message: Box<dyn UnsignedMessage>,
+        message: ton_block::Message,
+    ) {
+        let now_ms = clock.now_ms_u64();
+        self.messages.push(TrackedMessage {
+            pending_transaction,
+            unsigned_message,
+            message,
+            deadline_ms: now_ms + self.policy.deadline.as_millis() as u64,
+            last_broadcast_ms: now_ms,
+        });
+    }
+
+    /// Re-signs expired messages and rebroadcasts the pending ones.
+    /// Returns messages which must be reported as expired
+    pub async fn process(
+        &mut self,
+        subscription: &mut ContractSubscription,
+        expired: Vec<PendingTransaction>,
+        on_message_resigned: OnMessageResigned<'_>,
+    ) -> Vec<PendingTransaction> {
+        let clock = subscription.clock().clone();
+
+        let mut result = Vec::new();
+        for pending_transaction in expired {
+            let index = match self
+                .messages
+                .iter()
+                .position(|item| item.pending_transaction == pending_transaction)
+            {
+                Some(index) => index,
+                None => {
+                    result.push(pending_transaction);
+                    continue;
+                }
+            };
+
+            let mut item = self.messages.swap_remove(index);
+            if clock.now_ms_u64() >= item.deadline_ms {
+                result.push(pending_transaction);
+                continue;
+            }
+
+            match self.resign(clock.as_ref(), subscription, &mut item).await {
+                Ok(new_pending_transaction) => {
+                    on_message_resigned(pending_transaction, new_pending_transaction.clone());
+                    item.pending_transaction = new_pending_transaction;
+                    self.messages.push(item);
+                }
+                Err(e) => {
+                    log::warn!("Failed to re-sign expired message: {e:?}");
+                    result.push(pending_transaction);
+                }
+            }
+        }
+
+        // Forget delivered messages
+        let pending_transactions = subscription.pending_transactions();
+        self.messages
+            .retain(|item| pending_transactions.contains(&item.pending_transaction));
+
+        let interval_ms = self.policy.rebroadcast_interval.as_millis() as u64;
+        for item in &mut self.messages {
+            let now_ms = clock.now_ms_u64();
+            if now_ms < item.last_broadcast_ms + interval_ms
+                || now_ms / 1000 >= item.pending_transaction.expire_at as u64
+            {
+                continue;
+            }
+
+            if let Err(e) = subscription.transport().send_message(&item.message).await {
+                log::warn!("Failed to rebroadcast message: {e:?}");
+            }
+            item.last_broadcast_ms = now_ms;
+        }
+
+        result
+    }
+
+    async fn resign(
+        &self,
+        clock: &dyn Clock,
+        subscription: &mut ContractSubscription,
+        item: &mut TrackedMessage,
+    ) -> Result<PendingTransaction> {
+        item.unsigned_message.refresh_timeout(clock);
+        let signature = self.signer.sign(item.unsigned_message.as_ref()).await?;
+        let signed_message = item.unsigned_message.sign(&signature)?;
+
+        let pending_transaction = subscription
+            .send(&signed_message.message, signed_message.expire_at)
+            .await?;
+
+        item.message = signed_message.message;
+        item.last_broadcast_ms = clock.now_ms_u64();
+        Ok(pending_transaction)
+    }
+}
+
+struct TrackedMessage {
+    pending_transaction: PendingTransaction,
+    unsigned_message: Box<dyn UnsignedMessage>,
+    message: ton_block::Message,
+    deadline_ms: u64,
+    last_broadcast_ms: u64,
+}
+
+pub(crate) type OnMessageResigned<'a> =
+    &'a mut (dyn FnMut(PendingTransaction, PendingTransaction) + Send + Sync);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use futures_util::StreamExt;
+    use ton_block::MsgAddressInt;
+    use ton_types::BuilderData;
+
+    use crate::core::events::EventsHandler;
+    use crate::core::generic_contract::{GenericContract, GenericContractEvent};
+    use crate::core::ton_wallet::{compute_address, TonWallet, TonWalletEvent, WalletType};
+    use crate::crypto::SignedMessage;
+    use crate::transport::middleware::{InstrumentedTransport, TransportCounters};
+    use crate::transport::test_utils::{
+        emulated_transport, funded_account, test_clock, transfer, NOW_SEC,
+    };
+    use crate::transport::Transport;
+
+    use super::*;
+
+    /// Clock which is only moved by the test
+    struct ManualClock(AtomicU64);
+
+    impl ManualClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(AtomicU64::new(NOW_SEC * 1000)))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0
+                .fetch_add(duration.as_millis() as u64, Ordering::AcqRel);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now_sec_u64(&self) -> u64 {
+            self.now_ms_u64() / 1000
+        }
+
+        fn now_ms_f64(&self) -> f64 {
+            self.now_ms_u64() as f64
+        }
+
+        fn now_ms_u64(&self) -> u64 {
+            self.0.load(Ordering::Acquire)
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestMessage {
+        address: MsgAddressInt,
+        expire_at: u32,
+    }
+
+    impl UnsignedMessage for TestMessage {
+        fn refresh_timeout(&mut self, clock: &dyn Clock) {
+            self.expire_at = clock.now_sec_u64() as u32 + 60;
+        }
+
+        fn expire_at(&self) -> u32 {
+            self.expire_at
+        }
+
+        fn hash(&self) -> &[u8] {
+            &[]
+        }
+
+        fn sign(&self, _: &Signature) -> Result<SignedMessage> {
+            let mut payload = BuilderData::new();
+            payload.append_u32(self.expire_at)?;
+
+            let mut message =
+                ton_block::Message::with_ext_in_header(ton_block::ExternalInboundMessageHeader {
+                    dst: self.address.clone(),
+                    ..Default::default()
+                });
+            message.set_body(payload.into());
+
+            Ok(SignedMessage {
+                message,
+                expire_at: self.expire_at,
+            })
+        }
+
+        fn sign_with_pruned_payload(&self, signature: &Signature, _: u16) -> Result<SignedMessage> {
+            self.sign(signature)
+        }
+    }
+
+    struct TestSigner;
+
+    #[async_trait]
+    impl MessageSigner for TestSigner {
+        async fn sign(&self, _: &dyn UnsignedMessage) -> Result<Signature> {
+            Ok([0; 64])
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_resigned() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let address = funded_account(&transport).await?;
+
+        let (handler, events) = EventsHandler::<GenericContractEvent>::channel();
+        let mut contract =
+            GenericContract::subscribe(clock, transport, address.clone(), handler, false).await?;
+        contract.enable_rebroadcast(Default::default(), Arc::new(TestSigner));
+
+        // Emulated transport silently drops external messages which were not accepted
+        let unsigned_message = TestMessage {
+            address,
+            expire_at: 1649999990,
+        };
+        let signed_message = unsigned_message.sign(&[0; 64])?;
+        let old_pending_transaction = contract
+            .send_with_rebroadcast(Box::new(unsigned_message), &signed_message)
+            .await?;
+
+        contract.refresh().await?;
+
+        let new_pending_transaction = match contract.pending_transactions() {
+            [pending_transaction] => pending_transaction.clone(),
+            _ => panic!("expected exactly one pending transaction"),
+        };
+        assert_eq!(new_pending_transaction.expire_at, 1650000060);
+        assert_ne!(
+            new_pending_transaction.message_hash,
+            old_pending_transaction.message_hash
+        );
+
+        drop(contract);
+        let events = events.collect::<Vec<_>>().await;
+        assert!(matches!(
+            events.as_slice(),
+            [
+                GenericContractEvent::StateChanged { .. },
+                GenericContractEvent::MessageResigned {
+                    old_pending_transaction: old,
+                    new_pending_transaction: new,
+                },
+            ] if old == &old_pending_transaction && new == &new_pending_transaction
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn valid_messages_are_rebroadcast() -> Result<()> {
+        let clock = ManualClock::new();
+        let transport = emulated_transport(clock.clone());
+        let address = funded_account(&transport).await?;
+
+        let counters = Arc::new(TransportCounters::default());
+        let transport = Arc::new(InstrumentedTransport::new(transport, counters.clone()));
+        let sent = || counters.snapshot()["send_message"].requests;
+
+        let (handler, events) = EventsHandler::<GenericContractEvent>::channel();
+        let mut contract =
+            GenericContract::subscribe(clock.clone(), transport, address.clone(), handler, false)
+                .await?;
+        contract.enable_rebroadcast(
+            RebroadcastPolicy {
+                rebroadcast_interval: Duration::from_secs(15),
+                ..Default::default()
+            },
+            Arc::new(TestSigner),
+        );
+
+        let unsigned_message = TestMessage {
+            address,
+            expire_at: NOW_SEC as u32 + 60,
+        };
+        let signed_message = unsigned_message.sign(&[0; 64])?;
+        let pending_transaction = contract
+            .send_with_rebroadcast(Box::new(unsigned_message), &signed_message)
+            .await?;
+        assert_eq!(sent(), 1);
+
+        // (seconds since the first broadcast, total broadcasts).
+        // Messages are not rebroadcast after `expire_at`
+        let mut elapsed = 0;
+        for (at, expected) in [(10, 1), (16, 2), (21, 2), (31, 3), (46, 4), (61, 4)] {
+            clock.advance(Duration::from_secs(at - elapsed));
+            elapsed = at;
+            contract.refresh().await?;
+            assert_eq!(sent(), expected, "at {at}s");
+        }
+        assert_eq!(contract.pending_transactions(), &[pending_transaction]);
+
+        drop(contract);
+        let events = events.collect::<Vec<_>>().await;
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            GenericContractEvent::MessageExpired { .. }
+                | GenericContractEvent::MessageResigned { .. }
+        )));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_expire_after_deadline() -> Result<()> {
+        let clock = ManualClock::new();
+        let transport = emulated_transport(clock.clone());
+        let address = funded_account(&transport).await?;
+
+        let (handler, events) = EventsHandler::<GenericContractEvent>::channel();
+        let mut contract = GenericContract::subscribe(
+            clock.clone(),
+            transport.clone(),
+            address.clone(),
+            handler,
+            false,
+        )
+        .await?;
+        contract.enable_rebroadcast(Default::default(), Arc::new(TestSigner));
+
+        let unsigned_message = TestMessage {
+            address: address.clone(),
+            expire_at: NOW_SEC as u32 + 60,
+        };
+        let signed_message = unsigned_message.sign(&[0; 64])?;
+        let pending_transaction = contract
+            .send_with_rebroadcast(Box::new(unsigned_message), &signed_message)
+            .await?;
+
+        // Emulated state timings are only updated with new transactions
+        clock.advance(RebroadcastPolicy::default().deadline);
+        transport
+            .send_message(&transfer(&address, 1_000_000))
+            .await?;
+        contract.refresh().await?;
+        assert!(contract.pending_transactions().is_empty());
+
+        drop(contract);
+        let events = events.collect::<Vec<_>>().await;
+        let expired = events
+            .iter()
+            .filter_map(|event| match event {
+                GenericContractEvent::MessageExpired {
+                    pending_transaction,
+                } => Some(pending_transaction),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expired, [&pending_transaction]);
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, GenericContractEvent::MessageResigned { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumed_wallet_expires_tracked_messages() -> Result<()> {
+        let clock = ManualClock::new();
+        let transport = emulated_transport(clock.clone());
+
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32])?;
+        let public_key = ed25519_dalek::PublicKey::from(&secret);
+        let address = compute_address(&public_key, WalletType::WalletV3, 0);
+        transport
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+
+        let (handler, _events) = EventsHandler::<TonWalletEvent>::channel();
+        let mut wallet = TonWallet::subscribe(
+            clock.clone(),
+            transport.clone(),
+            0,
+            public_key,
+            WalletType::WalletV3,
+            handler,
+        )
+        .await?;
+        wallet.enable_rebroadcast(Default::default(), Arc::new(TestSigner));
+
+        let unsigned_message = TestMessage {
+            address: address.clone(),
+            expire_at: NOW_SEC as u32 + 60,
+        };
+        let signed_message = unsigned_message.sign(&[0; 64])?;
+        let pending_transaction = wallet
+            .send_with_rebroadcast(Box::new(unsigned_message), &signed_message)
+            .await?;
+
+        let snapshot = wallet.snapshot();
+        assert_eq!(
+            snapshot.subscription.pending_transactions,
+            [pending_transaction.clone()]
+        );
+        drop(wallet);
+
+        // Emulated state timings are only updated with new transactions
+        clock.advance(Duration::from_secs(61));
+        transport
+            .send_message(&transfer(&address, 1_000_000))
+            .await?;
+
+        let (handler, events) = EventsHandler::<TonWalletEvent>::channel();
+        let mut wallet = TonWallet::resume(clock, transport, snapshot, handler).await?;
+        wallet.refresh().await?;
+        assert!(wallet.pending_transactions().is_empty());
+        drop(wallet);
+
+        // The message is not re-signed without the signer
+        let events = events.collect::<Vec<_>>().await;
+        let expired = events
+            .iter()
+            .filter_map(|event| match event {
+                TonWalletEvent::MessageExpired {
+                    pending_transaction,
+                } => Some(pending_transaction),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expired, [&pending_transaction]);
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, TonWalletEvent::MessageResigned { .. })));
+
+        Ok(())
+    }
+}
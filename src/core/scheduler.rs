@@ -0,0 +1,731 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use super::generic_contract::GenericContract;
+use super::models::{PollingMethod, ReliableBehavior};
+use super::nft_wallet::Nft;
+use super::token_wallet::TokenWallet;
+use super::ton_wallet::TonWallet;
+use crate::transport::models::BlockRef;
+use crate::transport::Transport;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SchedulerConfig {
+    /// Refresh interval for subscriptions without pending transactions. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub manual_interval: Duration,
+    /// Refresh interval for subscriptions with pending transactions. Default: `5000`
+    #[serde(with = "serde_duration_ms")]
+    pub reliable_interval: Duration,
+    /// Max number of concurrent refreshes and shard walks. Default: `8`
+    pub max_concurrency: usize,
+    /// Timeout of waiting for the next shard block in block-walking mode. Default: `10000`
+    #[serde(with = "serde_duration_ms")]
+    pub block_wait_timeout: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            manual_interval: Duration::from_secs(60),
+            reliable_interval: Duration::from_secs(5),
+            max_concurrency: 8,
+            block_wait_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Subscription which can be driven by the [`SubscriptionScheduler`]
+#[async_trait]
+pub trait ScheduledSubscription: Send {
+    fn address(&self) -> &MsgAddressInt;
+
+    fn polling_method(&self) -> PollingMethod;
+
+    async fn refresh(&mut self) -> Result<()>;
+
+    async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()>;
+
+    /// Fetches transactions of the newest gap in the known history.
+    /// Returns whether there are gaps left
+    async fn backfill_history(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl ScheduledSubscription for TonWallet {
+    fn address(&self) -> &MsgAddressInt {
+        TonWallet::address(self)
+    }
+
+    fn polling_method(&self) -> PollingMethod {
+        TonWallet::polling_method(self)
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        TonWallet::refresh(self).await
+    }
+
+    async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
+        TonWallet::handle_block(self, block).await
+    }
+
+    async fn backfill_history(&mut self) -> Result<bool> {
+        TonWallet::backfill_history(self).await
+    }
+}
+
+#[async_trait]
+impl ScheduledSubscription for TokenWallet {
+    fn address(&self) -> &MsgAddressInt {
+        TokenWallet::address(self)
+    }
+
+    fn polling_method(&self) -> PollingMethod {
+        self.contract_subscription().polling_method()
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        TokenWallet::refresh(self).await
+    }
+
+    async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
+        TokenWallet::handle_block(self, block).await
+    }
+
+    async fn backfill_history(&mut self) -> Result<bool> {
+        TokenWallet::backfill_history(self).await
+    }
+}
+
+#[async_trait]
+impl ScheduledSubscription for GenericContract {
+    fn address(&self) -> &MsgAddressInt {
+        GenericContract::address(self)
+    }
+
+    fn polling_method(&self) -> PollingMethod {
+        GenericContract::polling_method(self)
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        GenericContract::refresh(self).await
+    }
+
+    async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
+        GenericContract::handle_block(self, block).await
+    }
+
+    async fn backfill_history(&mut self) -> Result<bool> {
+        GenericContract::backfill_history(self).await
+    }
+}
+
+#[async_trait]
+impl ScheduledSubscription for Nft {
+    fn address(&self) -> &MsgAddressInt {
+        Nft::address(self)
+    }
+
+    fn polling_method(&self) -> PollingMethod {
+        self.contract_subscription().polling_method()
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        Nft::refresh(self).await
+    }
+
+    async fn handle_block(&mut self, _: &ton_block::Block) -> Result<()> {
+        // NOTE: owner and manager can't be updated from the block yet
+        Nft::refresh(self).await
+    }
+
+    async fn backfill_history(&mut self) -> Result<bool> {
+        Nft::backfill_history(self).await
+    }
+}
+
+pub type SharedSubscription = Arc<Mutex<dyn ScheduledSubscription>>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Drives many subscriptions with a single loop.
+///
+/// Subscriptions without pending transactions are refreshed once per
+/// [`SchedulerConfig::manual_interval`]. Subscriptions with pending transactions are
+/// either refreshed once per [`SchedulerConfig::reliable_interval`], or, if the transport
+/// supports block walking, receive every block of their shard. Each shard block is
+/// fetched once and dispatched to all subscribed accounts in that shard.
+///
+/// Gaps in the transactions history are backfilled after each refresh,
+/// see [`ScheduledSubscription::backfill_history`].
+pub struct SubscriptionScheduler {
+    clock: Arc<dyn Clock>,
+    transport: Arc<dyn Transport>,
+    config: SchedulerConfig,
+    entries: parking_lot::Mutex<HashMap<SubscriptionId, Entry>>,
+    next_id: AtomicU64,
+    wake: Notify,
+}
+
+impl SubscriptionScheduler {
+    pub fn new(
+        clock: Arc<dyn Clock>,
+        transport: Arc<dyn Transport>,
+        config: SchedulerConfig,
+    ) -> Self {
+        Self {
+            clock,
+            transport,
+            config,
+            entries: Default::default(),
+            next_id: Default::default(),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Adds the subscription. It will be refreshed during the next round.
+    ///
+    /// The subscription remains shared, so it can still be used to send messages
+    pub async fn add(&self, subscription: SharedSubscription) -> SubscriptionId {
+        let address = subscription.lock().await.address().clone();
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        self.entries.lock().insert(
+            id,
+            Entry {
+                subscription,
+                address,
+                refresh_interval: 0,
+                last_refresh_at: None,
+                walking: false,
+                current_block: None,
+            },
+        );
+        self.wake();
+        id
+    }
+
+    /// Removes the subscription. Returns `false` if it was not found.
+    ///
+    /// NOTE: refresh which is already in progress is not interrupted
+    pub fn remove(&self, id: SubscriptionId) -> bool {
+        self.entries.lock().remove(&id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+
+    /// Interrupts the delay between rounds.
+    ///
+    /// Should be called after sending a message, because polling method
+    /// of the subscription changes only after that
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Drives all subscriptions until the future is dropped
+    pub async fn run<S, F>(&self, sleep: S)
+    where
+        S: Fn(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        loop {
+            let delay = self.tick().await;
+            if delay.is_zero() {
+                continue;
+            }
+
+            let sleep = sleep(delay);
+            let wake = self.wake.notified();
+            futures_util::pin_mut!(sleep, wake);
+            futures_util::future::select(sleep, wake).await;
+        }
+    }
+
+    /// Refreshes all due subscriptions and walks one block forward in each shard
+    /// with reliable subscriptions. Returns the delay before the next round
+    pub async fn tick(&self) -> Duration {
+        let info = self.transport.info();
+        let block_walking =
+            info.has_blocks && info.reliable_behavior == ReliableBehavior::BlockWalking;
+
+        let now = self.clock.now_ms_u64();
+        let mut refresh = Vec::new();
+        let mut walk = Vec::new();
+        {
+            let mut entries = self.entries.lock();
+            for (id, entry) in entries.iter_mut() {
+                // Busy subscriptions are checked during the next round
+                let polling_method = match entry.subscription.try_lock() {
+                    Ok(subscription) => subscription.polling_method(),
+                    Err(_) => continue,
+                };
+
+                entry.walking = block_walking && polling_method == PollingMethod::Reliable;
+                if entry.walking {
+                    walk.push(WalkItem {
+                        id: *id,
+                        subscription: entry.subscription.clone(),
+                        address: entry.address.clone(),
+                        current_block: entry.current_block,
+                    });
+                    continue;
+                }
+
+                entry.current_block = None;
+                entry.refresh_interval = self.interval(polling_method);
+                if !matches!(entry.last_refresh_at, Some(last) if now < last + entry.refresh_interval)
+                {
+                    refresh.push((*id, entry.subscription.clone()));
+                }
+            }
+        }
+
+        let mut walk_failed = false;
+        if walk.iter().any(|item| item.current_block.is_none()) {
+            match self.transport.get_latest_masterchain_block().await {
+                Ok(latest) => {
+                    let mut entries = self.entries.lock();
+                    for item in walk.iter_mut().filter(|item| item.current_block.is_none()) {
+                        item.current_block = latest.find_block(&item.address).map(|block| block.id);
+                        if let Some(entry) = entries.get_mut(&item.id) {
+                            entry.current_block = item.current_block;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Failed to get the latest masterchain block: {e:?}"),
+            }
+        }
+
+        let mut shards = HashMap::<UInt256, Vec<WalkItem>>::new();
+        for item in walk {
+            match item.current_block {
+                Some(block_id) => shards.entry(block_id).or_default().push(item),
+                None => walk_failed = true,
+            }
+        }
+        let walking = !shards.is_empty();
+
+        let mut tasks = Vec::<BoxFuture<'_, Outcome>>::with_capacity(refresh.len() + shards.len());
+        for (id, subscription) in refresh {
+            tasks.push(
+                async move {
+                    let mut subscription = subscription.lock().await;
+                    let result = subscription.refresh().await;
+
+                    // Missing history is fetched by one page per refresh
+                    if result.is_ok() {
+                        if let Err(e) = subscription.backfill_history().await {
+                            log::warn!("Failed to backfill history: {e:?}");
+                        }
+                    }
+
+                    Outcome::Refreshed(id, result)
+                }
+                .boxed(),
+            );
+        }
+        for (block_id, items) in shards {
+            tasks.push(
+                self.walk_shard(block_id, items)
+                    .map(Outcome::Walked)
+                    .boxed(),
+            );
+        }
+
+        let outcomes = futures_util::stream::iter(tasks)
+            .buffer_unordered(std::cmp::max(self.config.max_concurrency, 1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let now = self.clock.now_ms_u64();
+        let mut entries = self.entries.lock();
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Refreshed(id, result) => {
+                    if let Err(e) = result {
+                        log::warn!("Failed to refresh subscription: {e:?}");
+                    }
+                    if let Some(entry) = entries.get_mut(&id) {
+                        entry.last_refresh_at = Some(now);
+                    }
+                }
+                Outcome::Walked(Ok(advanced)) => {
+                    for (id, block_id) in advanced {
+                        if let Some(entry) = entries.get_mut(&id) {
+                            entry.current_block = Some(block_id);
+                        }
+                    }
+                }
+                Outcome::Walked(Err(e)) => {
+                    log::warn!("Failed to walk the shard: {e:?}");
+                    walk_failed = true;
+                }
+            }
+        }
+
+        // Walking is limited by the block wait timeout, so the next round starts immediately
+        let mut delay = match (walking, walk_failed) {
+            (_, true) => self.config.reliable_interval,
+            (true, false) => return Duration::ZERO,
+            (false, false) => self.config.manual_interval,
+        };
+
+        for entry in entries.values() {
+            if entry.walking {
+                continue;
+            }
+            let next_refresh_at = match entry.last_refresh_at {
+                Some(last) => last + entry.refresh_interval,
+                None => now,
+            };
+            delay = std::cmp::min(
+                delay,
+                Duration::from_millis(next_refresh_at.saturating_sub(now)),
+            );
+        }
+        delay
+    }
+
+    /// Waits for the next block after `current` and dispatches it to all subscriptions
+    /// from its shard. Returns the subscriptions which handled the block
+    async fn walk_shard(
+        &self,
+        current: UInt256,
+        items: Vec<WalkItem>,
+    ) -> Result<Vec<(SubscriptionId, UInt256)>> {
+        let address = match items.first() {
+            Some(item) => &item.address,
+            None => return Ok(Vec::new()),
+        };
+
+        let next = match self
+            .transport
+            .wait_for_next_block(&current, address, self.config.block_wait_timeout)
+            .await?
+        {
+            Some(next) => next,
+            None => return Ok(Vec::new()),
+        };
+
+        let block = self.transport.get_block(&BlockRef::Id(next)).await?;
+        let shard = block.info.read_struct()?.shard().clone();
+
+        let mut advanced = Vec::with_capacity(items.len());
+        for item in items {
+            // The shard could split, so the accounts from the sibling
+            // shard are walked separately during the next round
+            let prefix = ton_block::AccountIdPrefixFull::prefix(&item.address)?;
+            if !shard.contains_full_prefix(&prefix) {
+                continue;
+            }
+
+            // Failed subscriptions stay at the current block and receive it again
+            match item.subscription.lock().await.handle_block(&block).await {
+                Ok(()) => advanced.push((item.id, next)),
+                Err(e) => log::warn!("Failed to handle block for {}: {e:?}", item.address),
+            }
+        }
+
+        Ok(advanced)
+    }
+
+    fn interval(&self, polling_method: PollingMethod) -> u64 {
+        let interval = match polling_method {
+            PollingMethod::Manual => self.config.manual_interval,
+            PollingMethod::Reliable => self.config.reliable_interval,
+        };
+        interval.as_millis() as u64
+    }
+}
+
+struct Entry {
+    subscription: SharedSubscription,
+    address: MsgAddressInt,
+    /// Refresh interval for the latest known polling method, in milliseconds
+    refresh_interval: u64,
+    last_refresh_at: Option<u64>,
+    /// Whether the subscription receives blocks instead of refreshes
+    walking: bool,
+    /// The latest handled block of the account shard. Used only in block-walking mode
+    current_block: Option<UInt256>,
+}
+
+struct WalkItem {
+    id: SubscriptionId,
+    subscription: SharedSubscription,
+    address: MsgAddressInt,
+    current_block: Option<UInt256>,
+}
+
+enum Outcome {
+    Refreshed(SubscriptionId, Result<()>),
+    Walked(Result<Vec<(SubscriptionId, UInt256)>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::core::generic_contract::GenericContractSubscriptionHandler;
+    use crate::core::models::{
+        ContractState, PendingTransaction, Transaction, TransactionsBatchInfo,
+    };
+    use crate::transport::emulated::EmulatedTransport;
+    use crate::transport::middleware::{InstrumentedTransport, TransportCounters};
+    use crate::transport::test_utils::{
+        block_walking_transport, emulated_transport, funded_account, test_clock, transfer,
+    };
+
+    use super::*;
+
+    /// Reliable subscription which records the received blocks
+    struct BlockRecorder {
+        address: MsgAddressInt,
+        /// Shards and seqnos of the handled blocks
+        blocks: Arc<parking_lot::Mutex<Vec<(u64, u32)>>>,
+        /// Number of the next blocks which fail to be handled
+        failures: usize,
+    }
+
+    impl BlockRecorder {
+        fn new(address: &str) -> Self {
+            Self {
+                address: MsgAddressInt::from_str(address).unwrap(),
+                blocks: Default::default(),
+                failures: 0,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ScheduledSubscription for BlockRecorder {
+        fn address(&self) -> &MsgAddressInt {
+            &self.address
+        }
+
+        fn polling_method(&self) -> PollingMethod {
+            PollingMethod::Reliable
+        }
+
+        async fn refresh(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                anyhow::bail!("failed to handle block");
+            }
+
+            let info = block.info.read_struct()?;
+            self.blocks
+                .lock()
+                .push((info.shard().shard_prefix_with_tag(), info.seq_no()));
+            Ok(())
+        }
+    }
+
+    fn walking_scheduler(
+        transport: Arc<EmulatedTransport>,
+    ) -> (SubscriptionScheduler, Arc<TransportCounters>) {
+        let counters = Arc::new(TransportCounters::default());
+        let scheduler = SubscriptionScheduler::new(
+            test_clock(),
+            Arc::new(InstrumentedTransport::new(transport, counters.clone())),
+            SchedulerConfig {
+                block_wait_timeout: Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+        (scheduler, counters)
+    }
+
+    fn block_requests(counters: &TransportCounters) -> u64 {
+        counters
+            .snapshot()
+            .get("get_block")
+            .map(|counters| counters.requests)
+            .unwrap_or_default()
+    }
+
+    #[derive(Default)]
+    struct TransactionsCounter(parking_lot::Mutex<usize>);
+
+    impl GenericContractSubscriptionHandler for TransactionsCounter {
+        fn on_message_sent(&self, _: PendingTransaction, _: Option<Transaction>) {}
+
+        fn on_message_expired(&self, _: PendingTransaction) {}
+
+        fn on_state_changed(&self, _: ContractState) {}
+
+        fn on_transactions_found(&self, transactions: Vec<Transaction>, _: TransactionsBatchInfo) {
+            *self.0.lock() += transactions.len();
+        }
+    }
+
+    #[tokio::test]
+    async fn manual_subscriptions_are_refreshed() -> Result<()> {
+        let transport = emulated_transport(test_clock());
+        let address = funded_account(&transport).await?;
+
+        let clock = Arc::new(ClockWithOffset::new(0));
+        let handler = Arc::new(TransactionsCounter::default());
+        let contract = GenericContract::subscribe(
+            clock.clone(),
+            transport.clone(),
+            address.clone(),
+            handler.clone(),
+            false,
+        )
+        .await?;
+
+        let scheduler = SubscriptionScheduler::new(
+            clock.clone(),
+            transport.clone(),
+            SchedulerConfig::default(),
+        );
+        let id = scheduler.add(Arc::new(Mutex::new(contract))).await;
+
+        // The first round refreshes all subscriptions
+        let delay = scheduler.tick().await;
+        assert_eq!(delay, SchedulerConfig::default().manual_interval);
+        assert_eq!(*handler.0.lock(), 0);
+
+        transport
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+        scheduler.tick().await;
+        assert_eq!(*handler.0.lock(), 0);
+
+        // Manual subscriptions are refreshed only after the interval
+        clock.update_offset(61_000);
+        scheduler.tick().await;
+        assert_eq!(*handler.0.lock(), 1);
+
+        assert!(scheduler.remove(id));
+        assert!(scheduler.is_empty());
+
+        transport
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+        clock.update_offset(122_000);
+        scheduler.tick().await;
+        assert_eq!(*handler.0.lock(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shard_blocks_are_dispatched_once() -> Result<()> {
+        const FULL: u64 = 0x8000_0000_0000_0000;
+        const LEFT: u64 = 0x4000_0000_0000_0000;
+        const RIGHT: u64 = 0xc000_0000_0000_0000;
+
+        let transport = block_walking_transport(test_clock());
+        let (scheduler, counters) = walking_scheduler(transport.clone());
+
+        // The first two accounts are in the left half of the shard after the split
+        let mut recorded = Vec::new();
+        for address in [
+            "0:1111111111111111111111111111111111111111111111111111111111111111",
+            "0:2222222222222222222222222222222222222222222222222222222222222222",
+            "0:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ] {
+            let recorder = BlockRecorder::new(address);
+            recorded.push(recorder.blocks.clone());
+            scheduler.add(Arc::new(Mutex::new(recorder))).await;
+        }
+        let recorded = |i: usize| recorded[i].lock().clone();
+
+        // Subscriptions start from the latest block, so nothing is received
+        assert_eq!(scheduler.tick().await, Duration::ZERO);
+        assert_eq!(block_requests(&counters), 0);
+        assert!(recorded(0).is_empty());
+
+        transport.produce_blocks()?;
+        scheduler.tick().await;
+        assert_eq!(block_requests(&counters), 1);
+        for i in 0..3 {
+            assert_eq!(recorded(i), [(FULL, 2)]);
+        }
+
+        // Each half of the split shard is walked separately
+        transport.split_shard(&MsgAddressInt::from_str(
+            "0:1111111111111111111111111111111111111111111111111111111111111111",
+        )?)?;
+        scheduler.tick().await;
+        scheduler.tick().await;
+        assert_eq!(block_requests(&counters), 3);
+        assert_eq!(recorded(0), [(FULL, 2), (LEFT, 3)]);
+        assert_eq!(recorded(1), [(FULL, 2), (LEFT, 3)]);
+        assert_eq!(recorded(2), [(FULL, 2), (RIGHT, 3)]);
+
+        transport.produce_blocks()?;
+        scheduler.tick().await;
+        assert_eq!(block_requests(&counters), 5);
+        assert_eq!(recorded(0).last(), Some(&(LEFT, 4)));
+        assert_eq!(recorded(1).last(), Some(&(LEFT, 4)));
+        assert_eq!(recorded(2).last(), Some(&(RIGHT, 4)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_blocks_are_retried() -> Result<()> {
+        let transport = block_walking_transport(test_clock());
+        let (scheduler, counters) = walking_scheduler(transport.clone());
+
+        let healthy = BlockRecorder::new(
+            "0:1111111111111111111111111111111111111111111111111111111111111111",
+        );
+        let healthy_blocks = healthy.blocks.clone();
+        scheduler.add(Arc::new(Mutex::new(healthy))).await;
+
+        let failing = BlockRecorder {
+            failures: 1,
+            ..BlockRecorder::new(
+                "0:2222222222222222222222222222222222222222222222222222222222222222",
+            )
+        };
+        let failing_blocks = failing.blocks.clone();
+        scheduler.add(Arc::new(Mutex::new(failing))).await;
+
+        scheduler.tick().await;
+        transport.produce_blocks()?;
+        scheduler.tick().await;
+        assert_eq!(healthy_blocks.lock().len(), 1);
+        assert!(failing_blocks.lock().is_empty());
+
+        // Only the failed subscription stays at the previous block
+        scheduler.tick().await;
+        assert_eq!(block_requests(&counters), 2);
+        assert_eq!(healthy_blocks.lock().len(), 1);
+        assert_eq!(*failing_blocks.lock(), *healthy_blocks.lock());
+
+        Ok(())
+    }
+}
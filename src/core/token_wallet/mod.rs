@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use num_bigint::{BigInt, BigUint, ToBigInt};
+use serde::{Deserialize, Serialize};
 use ton_block::MsgAddressInt;
 
 use nekoton_abi::*;
@@ -15,7 +16,11 @@ use crate::core::transactions_tree::*;
 use crate::transport::models::{ExistingContract, RawContractState, RawTransaction};
 use crate::transport::Transport;
 
-use super::{ContractSubscription, InternalMessage};
+use super::contract_subscription::history::HistoryWatermark;
+use super::events::EventsHandler;
+use super::{
+    ContractSubscription, ContractSubscriptionSnapshot, InternalMessage, SubscriptionSnapshot,
+};
 
 pub struct TokenWallet {
     clock: Arc<dyn Clock>,
@@ -82,6 +87,46 @@ impl TokenWallet {
         })
     }
 
+    /// Restores the wallet from the snapshot, see [`ContractSubscription::resume`]
+    pub async fn resume(
+        clock: Arc<dyn Clock>,
+        transport: Arc<dyn Transport>,
+        snapshot: TokenWalletSnapshot,
+        handler: Arc<dyn TokenWalletSubscriptionHandler>,
+    ) -> Result<TokenWallet> {
+        let version = snapshot.version;
+
+        let mut balance = Default::default();
+        let contract_subscription = ContractSubscription::resume(
+            clock.clone(),
+            transport,
+            snapshot.subscription,
+            &mut make_contract_state_handler(clock.clone(), version, &mut balance),
+        )
+        .await?;
+
+        handler.on_balance_changed(balance.clone());
+
+        Ok(Self {
+            clock,
+            contract_subscription,
+            handler,
+            owner: snapshot.owner,
+            symbol: snapshot.symbol,
+            version,
+            balance,
+        })
+    }
+
+    pub fn snapshot(&self) -> TokenWalletSnapshot {
+        TokenWalletSnapshot {
+            owner: self.owner.clone(),
+            symbol: self.symbol.clone(),
+            version: self.version,
+            subscription: self.contract_subscription.snapshot(),
+        }
+    }
+
     pub fn contract_subscription(&self) -> &ContractSubscription {
         &self.contract_subscription
     }
@@ -276,6 +321,7 @@ impl TokenWallet {
             handler.on_balance_changed(self.balance.clone());
         }
 
+        self.report_history_watermark();
         Ok(())
     }
 
@@ -338,6 +384,7 @@ impl TokenWallet {
             handler.on_balance_changed(self.balance.clone());
         }
 
+        self.report_history_watermark();
         Ok(())
     }
 
@@ -348,7 +395,48 @@ impl TokenWallet {
                 from_lt,
                 &mut make_transactions_handler(handler, self.version),
             )
-            .await
+            .await?;
+
+        self.report_history_watermark();
+        Ok(())
+    }
+
+    /// Fetches transactions of the newest gap in the known history.
+    /// Returns whether there are gaps left
+    pub async fn backfill_history(&mut self) -> Result<bool> {
+        let handler = self.handler.as_ref();
+        let has_gaps = self
+            .contract_subscription
+            .backfill_history(&mut make_transactions_handler(handler, self.version))
+            .await?;
+
+        self.report_history_watermark();
+        Ok(has_gaps)
+    }
+
+    fn report_history_watermark(&mut self) {
+        if let Some(watermark) = self.contract_subscription.take_history_watermark() {
+            self.handler.on_history_watermark_changed(watermark);
+        }
+    }
+}
+
+/// Serializable state of [`TokenWallet`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenWalletSnapshot {
+    #[serde(with = "serde_address")]
+    pub owner: MsgAddressInt,
+    pub symbol: Symbol,
+    pub version: TokenWalletVersion,
+    pub subscription: ContractSubscriptionSnapshot,
+}
+
+impl SubscriptionSnapshot for TokenWalletSnapshot {
+    const STORAGE_KEY_PREFIX: &'static str = "__core__token_wallet_";
+
+    fn address(&self) -> &MsgAddressInt {
+        &self.subscription.address
     }
 }
 
@@ -364,6 +452,47 @@ pub trait TokenWalletSubscriptionHandler: Send + Sync {
         transactions: Vec<TransactionWithData<TokenWalletTransaction>>,
         batch_info: TransactionsBatchInfo,
     );
+
+    /// Called when the known history becomes continuous down to the new lt
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        let _ = watermark;
+    }
+}
+
+/// Event of [`TokenWallet`], see [`EventsHandler`]
+#[derive(Debug, Clone)]
+pub enum TokenWalletEvent {
+    BalanceChanged {
+        balance: BigUint,
+    },
+    TransactionsFound {
+        transactions: Vec<TransactionWithData<TokenWalletTransaction>>,
+        batch_info: TransactionsBatchInfo,
+    },
+    HistoryWatermarkChanged {
+        watermark: HistoryWatermark,
+    },
+}
+
+impl TokenWalletSubscriptionHandler for EventsHandler<TokenWalletEvent> {
+    fn on_balance_changed(&self, balance: BigUint) {
+        self.emit(TokenWalletEvent::BalanceChanged { balance });
+    }
+
+    fn on_transactions_found(
+        &self,
+        transactions: Vec<TransactionWithData<TokenWalletTransaction>>,
+        batch_info: TransactionsBatchInfo,
+    ) {
+        self.emit(TokenWalletEvent::TransactionsFound {
+            transactions,
+            batch_info,
+        });
+    }
+
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        self.emit(TokenWalletEvent::HistoryWatermarkChanged { watermark });
+    }
 }
 
 pub async fn get_token_root_details(
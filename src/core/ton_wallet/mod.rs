@@ -14,15 +14,20 @@ use nekoton_abi::*;
 use nekoton_utils::*;
 
 pub use self::multisig::MultisigType;
+use super::contract_subscription::history::HistoryWatermark;
+use super::events::EventsHandler;
 use super::models::{
     ContractState, Expiration, MessageFlags, MultisigPendingTransaction, MultisigPendingUpdate,
     PendingTransaction, Transaction, TransactionAdditionalInfo, TransactionWithData,
     TransactionsBatchInfo,
 };
-use super::{ContractSubscription, PollingMethod};
+use super::rebroadcast::{MessageSigner, RebroadcastPolicy, Rebroadcaster};
+use super::{
+    ContractSubscription, ContractSubscriptionSnapshot, PollingMethod, SubscriptionSnapshot,
+};
 use crate::core::parsing::*;
 use crate::core::InternalMessage;
-use crate::crypto::UnsignedMessage;
+use crate::crypto::{SignedMessage, UnsignedMessage};
 use crate::transport::models::{ExistingContract, RawContractState, RawTransaction};
 use crate::transport::Transport;
 
@@ -40,6 +45,7 @@ pub struct TonWallet {
     contract_subscription: ContractSubscription,
     handler: Arc<dyn TonWalletSubscriptionHandler>,
     wallet_data: WalletData,
+    rebroadcaster: Option<Rebroadcaster>,
 }
 
 impl TonWallet {
@@ -80,6 +86,7 @@ impl TonWallet {
             contract_subscription,
             handler,
             wallet_data,
+            rebroadcaster: None,
         })
     }
 
@@ -121,6 +128,7 @@ impl TonWallet {
             contract_subscription,
             handler,
             wallet_data,
+            rebroadcaster: None,
         })
     }
 
@@ -157,9 +165,55 @@ impl TonWallet {
             contract_subscription,
             handler,
             wallet_data,
+            rebroadcaster: None,
+        })
+    }
+
+    /// Restores the wallet from the snapshot, see [`ContractSubscription::resume`]
+    pub async fn resume(
+        clock: Arc<dyn Clock>,
+        transport: Arc<dyn Transport>,
+        snapshot: TonWalletSnapshot,
+        handler: Arc<dyn TonWalletSubscriptionHandler>,
+    ) -> Result<Self> {
+        let mut wallet_data = WalletData::default();
+
+        let contract_subscription = ContractSubscription::resume(
+            clock.clone(),
+            transport,
+            snapshot.subscription,
+            &mut make_contract_state_handler(
+                clock.as_ref(),
+                handler.as_ref(),
+                &snapshot.public_key,
+                snapshot.wallet_type,
+                &mut wallet_data,
+            ),
+        )
+        .await?;
+
+        Ok(Self {
+            clock,
+            public_key: snapshot.public_key,
+            wallet_type: snapshot.wallet_type,
+            contract_subscription,
+            handler,
+            wallet_data,
+            rebroadcaster: None,
         })
     }
 
+    /// Messages sent with [`TonWallet::send_with_rebroadcast`] are stored as plain
+    /// pending transactions, because their signers can't be persisted. A resumed wallet
+    /// doesn't rebroadcast or re-sign them and reports them with `on_message_expired`
+    pub fn snapshot(&self) -> TonWalletSnapshot {
+        TonWalletSnapshot {
+            public_key: self.public_key,
+            wallet_type: self.wallet_type,
+            subscription: self.contract_subscription.snapshot(),
+        }
+    }
+
     pub fn contract_subscription(&self) -> &ContractSubscription {
         &self.contract_subscription
     }
@@ -466,8 +520,49 @@ impl TonWallet {
         self.contract_subscription.send(message, expire_at).await
     }
 
+    /// Rebroadcasts messages sent with [`TonWallet::send_with_rebroadcast`] and
+    /// re-signs them after expiration, until they are delivered or the deadline is reached.
+    ///
+    /// NOTE: tracked messages are included into the snapshot only as pending transactions
+    pub fn enable_rebroadcast(
+        &mut self,
+        policy: RebroadcastPolicy,
+        signer: Arc<dyn MessageSigner>,
+    ) {
+        self.rebroadcaster = Some(Rebroadcaster::new(policy, signer));
+    }
+
+    pub fn disable_rebroadcast(&mut self) {
+        self.rebroadcaster = None;
+    }
+
+    /// Sends the message and tracks it if rebroadcast is enabled.
+    /// Otherwise works the same as [`TonWallet::send`]
+    pub async fn send_with_rebroadcast(
+        &mut self,
+        unsigned_message: Box<dyn UnsignedMessage>,
+        signed_message: &SignedMessage,
+    ) -> Result<PendingTransaction> {
+        let pending_transaction = self
+            .contract_subscription
+            .send(&signed_message.message, signed_message.expire_at)
+            .await?;
+
+        if let Some(rebroadcaster) = &mut self.rebroadcaster {
+            rebroadcaster.track(
+                self.clock.as_ref(),
+                pending_transaction.clone(),
+                unsigned_message,
+                signed_message.message.clone(),
+            );
+        }
+
+        Ok(pending_transaction)
+    }
+
     pub async fn refresh(&mut self) -> Result<()> {
         let handler = self.handler.as_ref();
+        let mut expired = Vec::new();
         self.contract_subscription
             .refresh(
                 &mut make_contract_state_handler(
@@ -479,29 +574,56 @@ impl TonWallet {
                 ),
                 &mut make_transactions_handler(handler, self.wallet_type),
                 &mut make_message_sent_handler(handler),
-                &mut make_message_expired_handler(handler),
+                &mut |pending_transaction| expired.push(pending_transaction),
             )
-            .await
+            .await?;
+
+        self.handle_expired_messages(expired).await;
+        self.report_history_watermark();
+        Ok(())
     }
 
     pub async fn handle_block(&mut self, block: &ton_block::Block) -> Result<()> {
         // TODO: update wallet data here
 
         let handler = self.handler.as_ref();
+        let mut expired = Vec::new();
         let new_account_state = self.contract_subscription.handle_block(
             block,
             &mut make_transactions_handler(handler, self.wallet_type),
             &mut make_message_sent_handler(handler),
-            &mut make_message_expired_handler(handler),
+            &mut |pending_transaction| expired.push(pending_transaction),
         )?;
 
         if let Some(account_state) = new_account_state {
             handler.on_state_changed(account_state);
         }
 
+        self.handle_expired_messages(expired).await;
+        self.report_history_watermark();
         Ok(())
     }
 
+    async fn handle_expired_messages(&mut self, expired: Vec<PendingTransaction>) {
+        let handler = self.handler.as_ref();
+        let expired = match &mut self.rebroadcaster {
+            Some(rebroadcaster) => {
+                rebroadcaster
+                    .process(
+                        &mut self.contract_subscription,
+                        expired,
+                        &mut make_message_resigned_handler(handler),
+                    )
+                    .await
+            }
+            None => expired,
+        };
+
+        for pending_transaction in expired {
+            handler.on_message_expired(pending_transaction);
+        }
+    }
+
     pub async fn preload_transactions(&mut self, from_lt: u64) -> Result<()> {
         let handler = self.handler.as_ref();
         self.contract_subscription
@@ -509,7 +631,29 @@ impl TonWallet {
                 from_lt,
                 &mut make_transactions_handler(handler, self.wallet_type),
             )
-            .await
+            .await?;
+
+        self.report_history_watermark();
+        Ok(())
+    }
+
+    /// Fetches transactions of the newest gap in the known history.
+    /// Returns whether there are gaps left
+    pub async fn backfill_history(&mut self) -> Result<bool> {
+        let handler = self.handler.as_ref();
+        let has_gaps = self
+            .contract_subscription
+            .backfill_history(&mut make_transactions_handler(handler, self.wallet_type))
+            .await?;
+
+        self.report_history_watermark();
+        Ok(has_gaps)
+    }
+
+    fn report_history_watermark(&mut self) {
+        if let Some(watermark) = self.contract_subscription.take_history_watermark() {
+            self.handler.on_history_watermark_changed(watermark);
+        }
     }
 
     pub async fn estimate_fees(&mut self, message: &ton_block::Message) -> Result<u128> {
@@ -710,6 +854,24 @@ pub struct ExistingWalletInfo {
     pub contract_state: ContractState,
 }
 
+/// Serializable state of [`TonWallet`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TonWalletSnapshot {
+    #[serde(with = "serde_public_key")]
+    pub public_key: PublicKey,
+    pub wallet_type: WalletType,
+    pub subscription: ContractSubscriptionSnapshot,
+}
+
+impl SubscriptionSnapshot for TonWalletSnapshot {
+    const STORAGE_KEY_PREFIX: &'static str = "__core__ton_wallet_";
+
+    fn address(&self) -> &MsgAddressInt {
+        &self.subscription.address
+    }
+}
+
 pub trait InternalMessageSender {
     fn prepare_transfer(
         &mut self,
@@ -826,10 +988,12 @@ fn make_message_sent_handler(
     }
 }
 
-fn make_message_expired_handler(
+fn make_message_resigned_handler(
     handler: &'_ dyn TonWalletSubscriptionHandler,
-) -> impl FnMut(PendingTransaction) + '_ {
-    move |pending_transaction| handler.on_message_expired(pending_transaction)
+) -> impl FnMut(PendingTransaction, PendingTransaction) + '_ {
+    move |old_pending_transaction, new_pending_transaction| {
+        handler.on_message_resigned(old_pending_transaction, new_pending_transaction)
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -983,6 +1147,18 @@ pub trait TonWalletSubscriptionHandler: Send + Sync {
     /// Called when no transactions produced for the specific message before some expiration time
     fn on_message_expired(&self, pending_transaction: PendingTransaction);
 
+    /// Called when the expired message was re-signed and sent again.
+    /// Both pending transactions belong to the same transfer.
+    /// See [`TonWallet::enable_rebroadcast`]
+    fn on_message_resigned(
+        &self,
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    ) {
+        let _ = old_pending_transaction;
+        let _ = new_pending_transaction;
+    }
+
     /// Called every time a new state is detected
     fn on_state_changed(&self, new_state: ContractState) {
         let _ = new_state;
@@ -1023,4 +1199,205 @@ pub trait TonWalletSubscriptionHandler: Send + Sync {
     fn on_unconfirmed_updates_changed(&self, unconfirmed_updates: &[MultisigPendingUpdate]) {
         let _ = unconfirmed_updates;
     }
+
+    /// Called when the known history becomes continuous down to the new lt
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        let _ = watermark;
+    }
+}
+
+/// Event of [`TonWallet`], see [`EventsHandler`]
+#[derive(Debug, Clone)]
+pub enum TonWalletEvent {
+    MessageSent {
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    },
+    MessageExpired {
+        pending_transaction: PendingTransaction,
+    },
+    MessageResigned {
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    },
+    StateChanged {
+        new_state: ContractState,
+    },
+    TransactionsFound {
+        transactions: Vec<TransactionWithData<TransactionAdditionalInfo>>,
+        batch_info: TransactionsBatchInfo,
+    },
+    DetailsChanged {
+        details: TonWalletDetails,
+    },
+    CustodiansChanged {
+        custodians: Vec<UInt256>,
+    },
+    UnconfirmedTransactionsChanged {
+        unconfirmed_transactions: Vec<MultisigPendingTransaction>,
+    },
+    UnconfirmedUpdatesChanged {
+        unconfirmed_updates: Vec<MultisigPendingUpdate>,
+    },
+    HistoryWatermarkChanged {
+        watermark: HistoryWatermark,
+    },
+}
+
+impl TonWalletSubscriptionHandler for EventsHandler<TonWalletEvent> {
+    fn on_message_sent(
+        &self,
+        pending_transaction: PendingTransaction,
+        transaction: Option<Transaction>,
+    ) {
+        self.emit(TonWalletEvent::MessageSent {
+            pending_transaction,
+            transaction,
+        });
+    }
+
+    fn on_message_expired(&self, pending_transaction: PendingTransaction) {
+        self.emit(TonWalletEvent::MessageExpired {
+            pending_transaction,
+        });
+    }
+
+    fn on_message_resigned(
+        &self,
+        old_pending_transaction: PendingTransaction,
+        new_pending_transaction: PendingTransaction,
+    ) {
+        self.emit(TonWalletEvent::MessageResigned {
+            old_pending_transaction,
+            new_pending_transaction,
+        });
+    }
+
+    fn on_state_changed(&self, new_state: ContractState) {
+        self.emit(TonWalletEvent::StateChanged { new_state });
+    }
+
+    fn on_transactions_found(
+        &self,
+        transactions: Vec<TransactionWithData<TransactionAdditionalInfo>>,
+        batch_info: TransactionsBatchInfo,
+    ) {
+        self.emit(TonWalletEvent::TransactionsFound {
+            transactions,
+            batch_info,
+        });
+    }
+
+    fn on_details_changed(&self, details: TonWalletDetails) {
+        self.emit(TonWalletEvent::DetailsChanged { details });
+    }
+
+    fn on_custodians_changed(&self, custodians: &[UInt256]) {
+        self.emit(TonWalletEvent::CustodiansChanged {
+            custodians: custodians.to_vec(),
+        });
+    }
+
+    fn on_unconfirmed_transactions_changed(
+        &self,
+        unconfirmed_transactions: &[MultisigPendingTransaction],
+    ) {
+        self.emit(TonWalletEvent::UnconfirmedTransactionsChanged {
+            unconfirmed_transactions: unconfirmed_transactions.to_vec(),
+        });
+    }
+
+    fn on_unconfirmed_updates_changed(&self, unconfirmed_updates: &[MultisigPendingUpdate]) {
+        self.emit(TonWalletEvent::UnconfirmedUpdatesChanged {
+            unconfirmed_updates: unconfirmed_updates.to_vec(),
+        });
+    }
+
+    fn on_history_watermark_changed(&self, watermark: HistoryWatermark) {
+        self.emit(TonWalletEvent::HistoryWatermarkChanged { watermark });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use crate::external::TestStorage;
+    use crate::transport::test_utils::{emulated_transport, test_clock, transfer};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wallet_is_resumed_from_snapshot() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32])?;
+        let public_key = PublicKey::from(&secret);
+        let address = compute_address(&public_key, WalletType::WalletV3, 0);
+        transport
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+
+        let (handler, _events) = EventsHandler::<TonWalletEvent>::channel();
+        let wallet = TonWallet::subscribe(
+            clock.clone(),
+            transport.clone(),
+            0,
+            public_key,
+            WalletType::WalletV3,
+            handler,
+        )
+        .await?;
+
+        let storage = TestStorage::default();
+        wallet.snapshot().save(&storage).await?;
+        let last_lt = wallet.contract_state().last_lt;
+        drop(wallet);
+
+        // Funds arrive while the app is not running
+        transport
+            .send_message(&transfer(&address, 2_000_000_000))
+            .await?;
+
+        let snapshot = TonWalletSnapshot::load(&storage, &address)
+            .await?
+            .expect("snapshot must exist");
+        assert_eq!(snapshot.public_key, public_key);
+        assert_eq!(snapshot.subscription.last_lt, last_lt);
+
+        let (handler, events) = EventsHandler::<TonWalletEvent>::channel();
+        let mut wallet = TonWallet::resume(clock, transport.clone(), snapshot, handler).await?;
+        assert_eq!(wallet.address(), &address);
+        assert!(wallet.contract_state().last_lt > last_lt);
+
+        // Wallet data is rebuilt from the actual state
+        let custodians = vec![UInt256::from(public_key.to_bytes())];
+        assert_eq!(wallet.get_custodians(), &Some(custodians.clone()));
+        assert_eq!(wallet.details(), WalletType::WalletV3.details());
+
+        wallet.refresh().await?;
+        drop(wallet);
+
+        let events = events.collect::<Vec<_>>().await;
+        assert!(events.iter().any(|event| matches!(
+            event,
+            TonWalletEvent::CustodiansChanged { custodians: c } if c == &custodians
+        )));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, TonWalletEvent::StateChanged { .. })));
+
+        // Only the missed transaction is found after resume
+        let found = events
+            .iter()
+            .filter_map(|event| match event {
+                TonWalletEvent::TransactionsFound { transactions, .. } => Some(transactions.len()),
+                _ => None,
+            })
+            .sum::<usize>();
+        assert_eq!(found, 1);
+
+        Ok(())
+    }
 }
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::Result;
 use ed25519_dalek::PublicKey;
@@ -14,7 +15,7 @@ use nekoton_utils::*;
 
 use crate::core::models::*;
 use crate::crypto::{SignedMessage, UnsignedMessage};
-use crate::transport::models::RawTransaction;
+use crate::transport::models::{BlockRef, RawTransaction};
 use crate::transport::Transport;
 
 pub fn convert_transactions(
@@ -169,6 +170,178 @@ pub fn parse_block(
     ))
 }
 
+/// Searches the transaction of the specified account which was produced by the message
+pub fn find_dst_transaction(
+    address: &MsgAddressInt,
+    message_hash: &ton_types::UInt256,
+    block: &ton_block::Block,
+) -> Result<Option<RawTransaction>> {
+    use ton_block::{Deserializable, HashmapAugType};
+    use ton_types::HashmapType;
+
+    let account_block = match block
+        .extra
+        .read_struct()
+        .and_then(|extra| extra.read_account_blocks())
+        .and_then(|account_blocks| {
+            account_blocks.get_with_aug(&ton_types::UInt256::from_be_bytes(
+                &address.address().get_bytestring(0),
+            ))
+        }) {
+        Ok(Some((extra, _))) => extra,
+        Ok(None) => return Ok(None),
+        Err(_) => return Err(BlockParsingError::InvalidBlockStructure.into()),
+    };
+
+    for item in account_block.transactions().iter() {
+        let (_, value) = item.map_err(|_| BlockParsingError::InvalidBlockStructure)?;
+        let cell = value
+            .into_cell()
+            .reference(0)
+            .map_err(|_| BlockParsingError::InvalidBlockStructure)?;
+        let hash = cell.repr_hash();
+        let data = ton_block::Transaction::construct_from_cell(cell)
+            .map_err(|_| BlockParsingError::InvalidBlockStructure)?;
+
+        let in_msg_hash = data.in_msg.as_ref().map(|msg| msg.cell().repr_hash());
+        if in_msg_hash.as_ref() == Some(message_hash) {
+            return Ok(Some(RawTransaction { hash, data }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Sends the message and polls its transaction on the destination account
+/// until it is found or the message expires.
+///
+/// `sleep` is used to wait `poll_interval` between the requests. When the transport
+/// supports block queries and reliable block walking, blocks of the destination shard
+/// are walked instead and `poll_interval` is used as the timeout of waiting for the next block
+/// and as the delay before waiting again after that timeout
+pub async fn send_message_and_wait<S, F>(
+    clock: &dyn Clock,
+    transport: &dyn Transport,
+    message: &SignedMessage,
+    poll_interval: Duration,
+    sleep: S,
+) -> Result<RawTransaction>
+where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    let message_hash = message.message.serialize()?.repr_hash();
+    let info = transport.info();
+    if info.has_blocks && info.reliable_behavior == ReliableBehavior::BlockWalking {
+        return walk_blocks_until_transaction(
+            clock,
+            transport,
+            message,
+            &message_hash,
+            poll_interval,
+            sleep,
+        )
+        .await;
+    }
+
+    transport.send_message(&message.message).await?;
+
+    loop {
+        // NOTE: expiration is checked before the request to not miss the transaction
+        let expired = clock.now_sec_u64() > message.expire_at as u64;
+
+        if let Some(transaction) = transport.get_dst_transaction(&message_hash).await? {
+            return Ok(transaction);
+        }
+
+        if expired {
+            return Err(MessageWaitError::MessageExpired {
+                expire_at: message.expire_at,
+            }
+            .into());
+        }
+
+        sleep(poll_interval).await;
+    }
+}
+
+async fn walk_blocks_until_transaction<S, F>(
+    clock: &dyn Clock,
+    transport: &dyn Transport,
+    message: &SignedMessage,
+    message_hash: &ton_types::UInt256,
+    poll_interval: Duration,
+    sleep: S,
+) -> Result<RawTransaction>
+where
+    S: Fn(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    let dst = message
+        .message
+        .dst()
+        .ok_or(MessageWaitError::InvalidMessageDestination)?;
+
+    // NOTE: remember the current block before sending the message
+    let mut current_block_id = transport
+        .get_latest_masterchain_block()
+        .await?
+        .find_block(&dst)
+        .ok_or(MessageWaitError::ShardNotFound)?
+        .id;
+    transport.send_message(&message.message).await?;
+
+    let expired = || -> Result<RawTransaction> {
+        Err(MessageWaitError::MessageExpired {
+            expire_at: message.expire_at,
+        }
+        .into())
+    };
+
+    loop {
+        let next_block_id = match transport
+            .wait_for_next_block(&current_block_id, &dst, poll_interval)
+            .await?
+        {
+            Some(block_id) => block_id,
+            // NOTE: fallback to the local time if the shard has stalled
+            None if clock.now_sec_u64() > message.expire_at as u64 => return expired(),
+            None => {
+                // NOTE: some transports return without waiting for the whole timeout
+                sleep(poll_interval).await;
+                continue;
+            }
+        };
+
+        let block = transport.get_block(&BlockRef::Id(next_block_id)).await?;
+        if let Some(transaction) = find_dst_transaction(&dst, message_hash, &block)? {
+            return Ok(transaction);
+        }
+
+        let gen_utime = block
+            .info
+            .read_struct()
+            .map_err(|_| BlockParsingError::InvalidBlockStructure)?
+            .gen_utime()
+            .0;
+        if gen_utime > message.expire_at {
+            return expired();
+        }
+
+        current_block_id = next_block_id;
+    }
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum MessageWaitError {
+    #[error("Message expired at {expire_at}")]
+    MessageExpired { expire_at: u32 },
+    #[error("Invalid message destination")]
+    InvalidMessageDestination,
+    #[error("Destination shard not found")]
+    ShardNotFound,
+}
+
 #[derive(thiserror::Error, Debug, Copy, Clone)]
 pub enum BlockParsingError {
     #[error("Invalid block structure")]
@@ -486,3 +659,108 @@ pub fn default_headers(
 }
 
 type HeadersMap = HashMap<String, ton_abi::TokenValue>;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::test_utils::{
+        block_walking_transport, emulated_transport, test_address, transfer, NOW_SEC,
+    };
+
+    #[tokio::test]
+    async fn send_message_and_wait_finds_transaction() -> Result<()> {
+        let clock = ConstClock::from_secs(NOW_SEC);
+        let transport = emulated_transport(Arc::new(clock));
+
+        let dst = test_address();
+        let message = SignedMessage {
+            message: transfer(&dst, 1_000_000_000),
+            expire_at: NOW_SEC as u32 + 60,
+        };
+
+        let transaction = send_message_and_wait(
+            &clock,
+            transport.as_ref(),
+            &message,
+            Duration::from_secs(1),
+            |_| futures_util::future::ready(()),
+        )
+        .await?;
+        let transactions = transport.get_transactions(&dst, u64::MAX, 1).await?;
+        assert_eq!(transactions, vec![transaction]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_and_wait_expires() -> Result<()> {
+        let clock = ConstClock::from_secs(NOW_SEC);
+        let transport = emulated_transport(Arc::new(clock));
+
+        let message =
+            ton_block::Message::with_ext_in_header(ton_block::ExternalInboundMessageHeader {
+                dst: test_address(),
+                ..Default::default()
+            });
+        let message = SignedMessage {
+            message,
+            expire_at: NOW_SEC as u32 - 1,
+        };
+
+        let err = send_message_and_wait(
+            &clock,
+            transport.as_ref(),
+            &message,
+            Duration::from_secs(1),
+            |_| futures_util::future::ready(()),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MessageWaitError>(),
+            Some(MessageWaitError::MessageExpired { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_and_wait_walks_blocks() -> Result<()> {
+        let clock = ConstClock::from_secs(NOW_SEC);
+        let transport = block_walking_transport(Arc::new(clock));
+
+        let dst = test_address();
+        let message = SignedMessage {
+            message: transfer(&dst, 1_000_000_000),
+            expire_at: NOW_SEC as u32 + 60,
+        };
+
+        let sleeps = AtomicUsize::new(0);
+        let wait = send_message_and_wait(
+            &clock,
+            transport.as_ref(),
+            &message,
+            Duration::from_millis(10),
+            |duration| {
+                sleeps.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(duration)
+            },
+        );
+        let produce = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            transport.produce_blocks()
+        };
+        let (transaction, produced) = futures_util::future::join(wait, produce).await;
+        produced?;
+
+        let transactions = transport.get_transactions(&dst, u64::MAX, 1).await?;
+        assert_eq!(transactions, vec![transaction?]);
+        // Every timeout without a new block is followed by a delay
+        assert!(sleeps.load(Ordering::Relaxed) > 0);
+
+        Ok(())
+    }
+}
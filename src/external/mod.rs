@@ -22,11 +22,62 @@ pub trait Storage: Sync + Send {
     fn remove_unchecked(&self, key: &str);
 }
 
+/// In-memory storage for tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct TestStorage(parking_lot::Mutex<std::collections::HashMap<String, String>>);
+
+#[cfg(test)]
+#[async_trait]
+impl Storage for TestStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.0.lock().get(key).cloned())
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.set_unchecked(key, value);
+        Ok(())
+    }
+
+    fn set_unchecked(&self, key: &str, value: &str) {
+        self.0.lock().insert(key.to_string(), value.to_string());
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.remove_unchecked(key);
+        Ok(())
+    }
+
+    fn remove_unchecked(&self, key: &str) {
+        self.0.lock().remove(key);
+    }
+}
+
 #[cfg(feature = "gql_transport")]
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct GqlRequest {
     pub data: String,
     pub long_query: bool,
+    /// Whether the request broadcasts an external message
+    pub broadcast: bool,
+}
+
+#[cfg(feature = "gql_transport")]
+impl GqlRequest {
+    pub fn new(data: String, long_query: bool) -> Self {
+        Self {
+            data,
+            long_query,
+            broadcast: false,
+        }
+    }
+
+    /// Marks the request as the one which broadcasts an external message
+    pub fn with_broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self
+    }
 }
 
 #[cfg(feature = "gql_transport")]
@@ -37,17 +88,97 @@ pub trait GqlConnection: Send + Sync {
     async fn post(&self, req: GqlRequest) -> Result<String>;
 }
 
+#[cfg(feature = "gql_transport")]
+pub type GqlSubscriptionStream = futures_util::stream::BoxStream<'static, Result<String>>;
+
+#[cfg(feature = "gql_transport")]
+#[async_trait]
+pub trait GqlSubscriptionConnection: Send + Sync {
+    /// Start a new subscription. Each item of the stream is a raw
+    /// response with the `data` field, same as the one returned by `post`.
+    /// Subscription is cancelled when the stream is dropped
+    async fn subscribe(&self, req: GqlRequest) -> Result<GqlSubscriptionStream>;
+}
+
 #[cfg(feature = "jrpc_transport")]
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct JrpcRequest {
     pub data: String,
     pub requires_db: bool,
+    /// Whether the request broadcasts an external message
+    pub broadcast: bool,
+}
+
+#[cfg(feature = "jrpc_transport")]
+impl JrpcRequest {
+    pub fn new(data: String, requires_db: bool) -> Self {
+        Self {
+            data,
+            requires_db,
+            broadcast: false,
+        }
+    }
+
+    /// Marks the request as the one which broadcasts an external message
+    pub fn with_broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self
+    }
 }
 
 #[cfg(feature = "jrpc_transport")]
 #[async_trait]
 pub trait JrpcConnection: Send + Sync {
     async fn post(&self, req: JrpcRequest) -> Result<String>;
+
+    /// Whether the endpoint accepts JSON-RPC 2.0 batch arrays
+    fn supports_batch(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "proto_transport")]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ProtoRequest {
+    /// Encoded protobuf request
+    pub data: Vec<u8>,
+    pub requires_db: bool,
+    /// Whether the request broadcasts an external message
+    pub broadcast: bool,
+}
+
+#[cfg(feature = "proto_transport")]
+impl ProtoRequest {
+    pub fn new(data: Vec<u8>, requires_db: bool) -> Self {
+        Self {
+            data,
+            requires_db,
+            broadcast: false,
+        }
+    }
+
+    /// Marks the request as the one which broadcasts an external message
+    pub fn with_broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self
+    }
+}
+
+#[cfg(feature = "proto_transport")]
+#[async_trait]
+pub trait ProtoConnection: Send + Sync {
+    /// Returns the encoded protobuf response
+    async fn post(&self, req: ProtoRequest) -> Result<Vec<u8>>;
+}
+
+#[cfg(feature = "adnl_transport")]
+#[async_trait]
+pub trait AdnlConnection: Send + Sync {
+    /// Sends the serialized liteserver function wrapped into `liteServer.query`
+    /// and returns the serialized answer
+    async fn query(&self, request: Vec<u8>) -> Result<Vec<u8>>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,822 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use anyhow::Result;
+use lru::LruCache;
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
+use parking_lot::Mutex;
+use ton_block::{Account, Deserializable, MsgAddressInt, Serializable};
+use ton_types::{Cell, UInt256};
+
+use nekoton_utils::*;
+
+use crate::core::models::{NetworkCapabilities, ReliableBehavior};
+use crate::external::AdnlConnection;
+
+use super::models::{ExistingContract, RawContractState, RawContractStateProof, RawTransaction};
+use super::{Transport, TransportInfo};
+
+use self::tl::{LiteQuery, LiteResponse};
+
+pub mod tl;
+
+/// Transport which talks to the liteserver directly.
+///
+/// Liteserver can't search transactions by hash or accounts by code hash,
+/// so these requests are not supported.
+///
+/// NOTE: responses are not verified, use only trusted liteservers
+/// or wrap the transport into [`VerifyingTransport`](super::proofs::VerifyingTransport)
+pub struct AdnlTransport {
+    connection: Arc<dyn AdnlConnection>,
+    config: tokio::sync::Mutex<Option<CachedConfig>>,
+    /// Hashes of the transactions by account and lt,
+    /// used to continue the history without fetching it from the latest transaction
+    transaction_hashes: Mutex<LruCache<(MsgAddressInt, u64), UInt256>>,
+}
+
+impl AdnlTransport {
+    pub fn new(connection: Arc<dyn AdnlConnection>) -> Self {
+        Self {
+            connection,
+            config: Default::default(),
+            transaction_hashes: Mutex::new(LruCache::new(
+                NonZeroUsize::new(TRANSACTION_HASHES_CAPACITY).trust_me(),
+            )),
+        }
+    }
+
+    async fn query<T: LiteResponse>(&self, query: LiteQuery<'_>) -> Result<T> {
+        let response = self.connection.query(query.to_bytes()).await?;
+        Ok(tl::parse_response(&response)?)
+    }
+
+    fn remember_transaction(&self, address: &MsgAddressInt, lt: u64, hash: UInt256) {
+        if lt != 0 {
+            self.transaction_hashes
+                .lock()
+                .put((address.clone(), lt), hash);
+        }
+    }
+
+    fn find_transaction_hash(&self, address: &MsgAddressInt, lt: u64) -> Option<UInt256> {
+        self.transaction_hashes
+            .lock()
+            .get(&(address.clone(), lt))
+            .copied()
+    }
+
+    async fn fetch_config(&self) -> Result<CachedConfig> {
+        let info: tl::MasterchainInfo = self.query(LiteQuery::GetMasterchainInfo).await?;
+        let config: tl::ConfigInfo = self
+            .query(LiteQuery::GetConfigAll {
+                mode: 0,
+                id: &info.last,
+            })
+            .await?;
+
+        let state = ton_types::deserialize_tree_of_cells(&mut config.config_proof.as_slice())
+            .and_then(read_merkle_proof)
+            .and_then(ton_block::ShardStateUnsplit::construct_from_cell)
+            .map_err(|_| AdnlTransportError::InvalidConfig)?;
+        let params = state
+            .read_custom()
+            .ok()
+            .flatten()
+            .ok_or(AdnlTransportError::InvalidConfig)?
+            .config;
+
+        let config = ton_executor::BlockchainConfig::with_config(params)
+            .map_err(|_| AdnlTransportError::InvalidConfig)?;
+        let capabilities = NetworkCapabilities {
+            global_id: state.global_id(),
+            raw: config.capabilites(),
+        };
+
+        Ok(CachedConfig {
+            capabilities,
+            config,
+            updated_at: 0,
+        })
+    }
+
+    async fn find_masterchain_block(&self, seqno: u32) -> Result<tl::BlockIdExt> {
+        let header: tl::BlockHeader = self
+            .query(LiteQuery::LookupBlock {
+                workchain: ton_block::MASTERCHAIN_ID,
+                shard: ton_block::SHARD_FULL,
+                seqno,
+            })
+            .await?;
+        if header.id.workchain != ton_block::MASTERCHAIN_ID || header.id.seqno != seqno {
+            return Err(AdnlTransportError::InvalidBlock.into());
+        }
+        Ok(header.id)
+    }
+
+    async fn get_config(&self, clock: &dyn Clock, force: bool) -> Result<CachedConfig> {
+        let mut cache = self.config.lock().await;
+        let now = clock.now_sec_u64();
+
+        match &*cache {
+            Some(cached) if !force && now < cached.updated_at + CONFIG_TTL_SEC => {
+                Ok(cached.clone())
+            }
+            _ => {
+                let mut config = self.fetch_config().await?;
+                config.updated_at = now;
+                *cache = Some(config.clone());
+                Ok(config)
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for AdnlTransport {
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            max_transactions_per_fetch: tl::MAX_TRANSACTIONS_PER_QUERY,
+            reliable_behavior: ReliableBehavior::IntensivePolling,
+            has_key_blocks: true,
+            has_blocks: false,
+        }
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        let body = ton_types::serialize_toc(&message.serialize()?)?;
+        let _status: i32 = self.query(LiteQuery::SendMessage { body: &body }).await?;
+        Ok(())
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let info: tl::MasterchainInfo = self.query(LiteQuery::GetMasterchainInfo).await?;
+        let account = account_id(address)?;
+        let state: tl::AccountState = self
+            .query(LiteQuery::GetAccountState {
+                id: &info.last,
+                workchain: address.workchain_id(),
+                account: &account,
+            })
+            .await?;
+
+        let state = parse_account_state(address, &state)?;
+        if let RawContractState::Exists(ExistingContract {
+            last_transaction_id: LastTransactionId::Exact(id),
+            ..
+        }) = &state
+        {
+            self.remember_transaction(address, id.lt, id.hash);
+        }
+        Ok(state)
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        let info: tl::MasterchainInfo = self.query(LiteQuery::GetMasterchainInfo).await?;
+        let account = account_id(address)?;
+        let state: tl::AccountState = self
+            .query(LiteQuery::GetAccountState {
+                id: &info.last,
+                workchain: address.workchain_id(),
+                account: &account,
+            })
+            .await?;
+
+        // Proof contains the shard block and the shard state with the account
+        let (shard_block_proof, state_proof) = parse_proof_pair(&state.proof)?;
+
+        // Shard proof contains the masterchain block and its state with the shard hashes,
+        // it is empty for masterchain accounts
+        let (mc_block_proof, mc_state_proof) = if state.shardblk == state.id {
+            (shard_block_proof.clone(), None)
+        } else {
+            let (mc_block_proof, mc_state_proof) = parse_proof_pair(&state.shard_proof)?;
+            (mc_block_proof, Some(mc_state_proof))
+        };
+
+        // Block is signed by the validators of the previous key block
+        let mc_block_info = read_merkle_proof(mc_block_proof.clone())
+            .and_then(ton_block::Block::construct_from_cell)
+            .and_then(|block| block.info.read_struct())
+            .map_err(|_| AdnlTransportError::InvalidProof)?;
+        let key_block_id = self
+            .find_masterchain_block(mc_block_info.prev_key_block_seqno())
+            .await?;
+
+        let block_proof: tl::PartialBlockProof = self
+            .query(LiteQuery::GetBlockProof {
+                known_block: &key_block_id,
+                target_block: &state.id,
+            })
+            .await?;
+        let signatures = block_proof
+            .steps
+            .into_iter()
+            .find_map(|step| match step {
+                tl::BlockLink::Forward { to, signatures, .. } if to == state.id => Some(signatures),
+                _ => None,
+            })
+            .ok_or(AdnlTransportError::InvalidProof)?;
+
+        let account = if state.state.is_empty() {
+            None
+        } else {
+            Some(
+                ton_types::deserialize_tree_of_cells(&mut state.state.as_slice())
+                    .map_err(|_| AdnlTransportError::InvalidAccountState)?,
+            )
+        };
+
+        Ok(RawContractStateProof {
+            mc_block_id: convert_block_id(&state.id)?,
+            mc_block_signatures: convert_signatures(&signatures)?,
+            mc_block_proof,
+            mc_state_proof,
+            shard_block_id: convert_block_id(&state.shardblk)?,
+            shard_block_proof,
+            state_proof,
+            account,
+        })
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        _: &UInt256,
+        _: u8,
+        _: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        Err(AdnlTransportError::NotSupported.into())
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        let count = std::cmp::min(count, tl::MAX_TRANSACTIONS_PER_QUERY) as usize;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Liteserver requires both lt and hash of the first transaction,
+        // so the history is walked from the latest one if the hash is unknown
+        let (mut lt, mut hash) = match self.find_transaction_hash(address, from_lt) {
+            Some(hash) => (from_lt, hash),
+            None => match self.get_contract_state(address).await? {
+                RawContractState::Exists(ExistingContract {
+                    last_transaction_id: LastTransactionId::Exact(id),
+                    ..
+                }) => (id.lt, id.hash),
+                _ => return Ok(Vec::new()),
+            },
+        };
+
+        let account = account_id(address)?;
+        let mut result = Vec::with_capacity(count);
+        while lt != 0 && result.len() < count {
+            let list: tl::TransactionList = self
+                .query(LiteQuery::GetTransactions {
+                    count: if lt <= from_lt {
+                        (count - result.len()) as u32
+                    } else {
+                        tl::MAX_TRANSACTIONS_PER_QUERY as u32
+                    },
+                    workchain: address.workchain_id(),
+                    account: &account,
+                    lt,
+                    hash: &hash,
+                })
+                .await?;
+
+            let transactions = parse_transactions(&list.transactions)?;
+            if transactions.is_empty() {
+                break;
+            }
+
+            for transaction in transactions {
+                if transaction.data.lt != lt || transaction.hash != hash {
+                    return Err(AdnlTransportError::InvalidTransactionList.into());
+                }
+                lt = transaction.data.prev_trans_lt;
+                hash = transaction.data.prev_trans_hash;
+                self.remember_transaction(address, lt, hash);
+
+                if transaction.data.lt <= from_lt && result.len() < count {
+                    result.push(transaction);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn get_transaction(&self, _: &UInt256) -> Result<Option<RawTransaction>> {
+        Err(AdnlTransportError::NotSupported.into())
+    }
+
+    async fn get_dst_transaction(&self, _: &UInt256) -> Result<Option<RawTransaction>> {
+        Err(AdnlTransportError::NotSupported.into())
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        let info: tl::MasterchainInfo = self.query(LiteQuery::GetMasterchainInfo).await?;
+        let header: tl::BlockHeader = self
+            .query(LiteQuery::GetBlockHeader {
+                id: &info.last,
+                mode: 0,
+            })
+            .await?;
+
+        let block_info = ton_types::deserialize_tree_of_cells(&mut header.header_proof.as_slice())
+            .and_then(read_merkle_proof)
+            .and_then(ton_block::Block::construct_from_cell)
+            .and_then(|block| block.info.read_struct())
+            .map_err(|_| AdnlTransportError::InvalidBlock)?;
+        let key_block_seqno = if block_info.key_block() {
+            block_info.seq_no()
+        } else {
+            block_info.prev_key_block_seqno()
+        };
+
+        let id = self.find_masterchain_block(key_block_seqno).await?;
+        let block: tl::BlockData = self.query(LiteQuery::GetBlock { id: &id }).await?;
+        parse_block(&id, &block.data)
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        Ok(self.get_config(clock, false).await?.capabilities)
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        Ok(self.get_config(clock, force).await?.config)
+    }
+}
+
+#[derive(Clone)]
+struct CachedConfig {
+    capabilities: NetworkCapabilities,
+    config: ton_executor::BlockchainConfig,
+    updated_at: u64,
+}
+
+fn account_id(address: &MsgAddressInt) -> Result<UInt256> {
+    let account = address.address();
+    if account.remaining_bits() != 256 {
+        return Err(AdnlTransportError::UnsupportedAddress.into());
+    }
+    Ok(UInt256::from_slice(&account.get_bytestring(0)))
+}
+
+fn parse_account_state(
+    address: &MsgAddressInt,
+    state: &tl::AccountState,
+) -> Result<RawContractState> {
+    if state.state.is_empty() {
+        return Ok(RawContractState::NotExists);
+    }
+
+    let account = match Account::construct_from_bytes(&state.state) {
+        Ok(Account::Account(account)) => account,
+        Ok(Account::AccountNone) => return Ok(RawContractState::NotExists),
+        Err(_) => return Err(AdnlTransportError::InvalidAccountState.into()),
+    };
+
+    // Proof contains the shard block and the shard state with the account
+    let roots = ton_types::deserialize_cells_tree(&mut state.proof.as_slice())
+        .map_err(|_| AdnlTransportError::InvalidAccountState)?;
+    let (shard_block, shard_state) = match roots.as_slice() {
+        [shard_block, shard_state] => (shard_block.clone(), shard_state.clone()),
+        _ => return Err(AdnlTransportError::InvalidAccountState.into()),
+    };
+
+    let block_info = read_merkle_proof(shard_block)
+        .and_then(ton_block::Block::construct_from_cell)
+        .and_then(|block| block.info.read_struct())
+        .map_err(|_| AdnlTransportError::InvalidAccountState)?;
+
+    let shard_account = read_merkle_proof(shard_state)
+        .and_then(ton_block::ShardStateUnsplit::construct_from_cell)
+        .and_then(|state| state.read_accounts())
+        .and_then(|accounts| accounts.account(&address.address()))
+        .map_err(|_| AdnlTransportError::InvalidAccountState)?
+        .ok_or(AdnlTransportError::InvalidAccountState)?;
+
+    Ok(RawContractState::Exists(ExistingContract {
+        account,
+        timings: GenTimings::Known {
+            gen_lt: block_info.end_lt(),
+            gen_utime: block_info.gen_utime().0,
+        },
+        last_transaction_id: LastTransactionId::Exact(TransactionId {
+            lt: shard_account.last_trans_lt(),
+            hash: *shard_account.last_trans_hash(),
+        }),
+    }))
+}
+
+/// Parses the block and checks its hashes
+fn parse_block(id: &tl::BlockIdExt, data: &[u8]) -> Result<ton_block::Block> {
+    use sha2::Digest;
+
+    let file_hash = UInt256::from_slice(&sha2::Sha256::digest(data));
+    let cell = ton_types::deserialize_tree_of_cells(&mut &*data)
+        .map_err(|_| AdnlTransportError::InvalidBlock)?;
+    if file_hash != id.file_hash || cell.repr_hash() != id.root_hash {
+        return Err(AdnlTransportError::InvalidBlock.into());
+    }
+    ton_block::Block::construct_from_cell(cell).map_err(|_| AdnlTransportError::InvalidBlock.into())
+}
+
+/// Parses the BOC with the block proof and the state proof
+fn parse_proof_pair(boc: &[u8]) -> Result<(Cell, Cell)> {
+    let roots = ton_types::deserialize_cells_tree(&mut &*boc)
+        .map_err(|_| AdnlTransportError::InvalidProof)?;
+    match roots.as_slice() {
+        [block_proof, state_proof] => Ok((block_proof.clone(), state_proof.clone())),
+        _ => Err(AdnlTransportError::InvalidProof.into()),
+    }
+}
+
+fn convert_block_id(id: &tl::BlockIdExt) -> Result<ton_block::BlockIdExt> {
+    Ok(ton_block::BlockIdExt {
+        shard_id: ton_block::ShardIdent::with_tagged_prefix(id.workchain, id.shard)
+            .map_err(|_| AdnlTransportError::InvalidBlock)?,
+        seq_no: id.seqno,
+        root_hash: id.root_hash,
+        file_hash: id.file_hash,
+    })
+}
+
+fn convert_signatures(signatures: &tl::SignatureSet) -> Result<ton_block::BlockSignatures> {
+    let mut pure_signatures = ton_block::BlockSignaturesPure::default();
+    for item in &signatures.signatures {
+        let signature = ton_block::CryptoSignature::from_bytes(&item.signature)
+            .map_err(|_| AdnlTransportError::InvalidProof)?;
+        pure_signatures.add_sigpair(ton_block::CryptoSignaturePair::with_params(
+            item.node_id_short,
+            signature,
+        ));
+    }
+
+    Ok(ton_block::BlockSignatures {
+        validator_info: ton_block::ValidatorBaseInfo {
+            validator_list_hash_short: signatures.validator_set_hash,
+            catchain_seqno: signatures.catchain_seqno,
+        },
+        pure_signatures,
+    })
+}
+
+/// Parses the BOC with multiple transactions in descending order
+fn parse_transactions(boc: &[u8]) -> Result<Vec<RawTransaction>> {
+    if boc.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    ton_types::deserialize_cells_tree(&mut &*boc)
+        .map_err(|_| AdnlTransportError::InvalidTransactionList)?
+        .into_iter()
+        .map(|cell| {
+            let hash = cell.repr_hash();
+            let data = ton_block::Transaction::construct_from_cell(cell)
+                .map_err(|_| AdnlTransportError::InvalidTransactionList)?;
+            Ok(RawTransaction { hash, data })
+        })
+        .collect()
+}
+
+/// Returns the virtualized root of the pruned tree
+fn read_merkle_proof(cell: Cell) -> ton_types::Result<Cell> {
+    let proof = ton_block::MerkleProof::construct_from_cell(cell)?;
+    Ok(proof.proof.virtualize(1))
+}
+
+const CONFIG_TTL_SEC: u64 = 600;
+const TRANSACTION_HASHES_CAPACITY: usize = 1000;
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum AdnlTransportError {
+    #[error("Request is not supported by liteserver")]
+    NotSupported,
+    #[error("Only standard addresses are supported")]
+    UnsupportedAddress,
+    #[error("Invalid account state")]
+    InvalidAccountState,
+    #[error("Invalid transaction list")]
+    InvalidTransactionList,
+    #[error("Invalid config")]
+    InvalidConfig,
+    #[error("Invalid block")]
+    InvalidBlock,
+    #[error("Invalid proof")]
+    InvalidProof,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tl::*;
+    use super::*;
+    use crate::transport::proofs::VerifyingTransport;
+    use crate::transport::test_utils::{
+        emulated_transport, test_address, test_clock, transfer, TestChain, TestChainData,
+    };
+
+    /// Liteserver which knows only the history of a single account
+    struct MockLiteServer {
+        /// Transactions in descending order
+        transactions: Vec<RawTransaction>,
+        messages: Mutex<Vec<Vec<u8>>>,
+        /// Blocks and proofs of the account state
+        chain: Option<TestChainData>,
+    }
+
+    impl MockLiteServer {
+        const GLOBAL_ID: i32 = 42;
+
+        fn chain(&self) -> Result<&TestChainData> {
+            self.chain
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no blocks"))
+        }
+    }
+
+    fn tl_block_id(id: &ton_block::BlockIdExt) -> BlockIdExt {
+        BlockIdExt {
+            workchain: id.shard_id.workchain_id(),
+            shard: id.shard_id.shard_prefix_with_tag(),
+            seqno: id.seq_no,
+            root_hash: id.root_hash,
+            file_hash: id.file_hash,
+        }
+    }
+
+    fn serialize_roots(roots: &[&Cell]) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        ton_types::BagOfCells::with_roots(roots.to_vec()).write_to(&mut data, false)?;
+        Ok(data)
+    }
+
+    #[async_trait::async_trait]
+    impl AdnlConnection for MockLiteServer {
+        async fn query(&self, request: Vec<u8>) -> Result<Vec<u8>> {
+            let mut reader = TlReader::new(&request);
+            let mut response = Vec::new();
+            match reader.read_u32()? {
+                id::SEND_MESSAGE => {
+                    self.messages.lock().push(reader.read_bytes()?);
+                    write_u32(&mut response, id::SEND_MSG_STATUS);
+                    write_i32(&mut response, 1);
+                }
+                id::GET_TRANSACTIONS => {
+                    let _count = reader.read_u32()?;
+                    let _workchain = reader.read_i32()?;
+                    let _account = reader.read_int256()?;
+                    let lt = reader.read_u64()?;
+                    let hash = reader.read_int256()?;
+
+                    // NOTE: one transaction per response is enough to check paging
+                    let transaction = self
+                        .transactions
+                        .iter()
+                        .find(|tx| tx.data.lt == lt && tx.hash == hash);
+                    write_u32(&mut response, id::TRANSACTION_LIST);
+                    write_u32(&mut response, 0);
+                    match transaction {
+                        Some(tx) => {
+                            let cell = tx.data.serialize()?;
+                            write_bytes(&mut response, &ton_types::serialize_toc(&cell)?);
+                        }
+                        None => write_bytes(&mut response, &[]),
+                    }
+                }
+                id::GET_MASTERCHAIN_INFO => {
+                    let chain = self.chain()?;
+                    write_u32(&mut response, id::MASTERCHAIN_INFO);
+                    tl_block_id(&chain.proof.mc_block_id).write_to(&mut response);
+                    write_int256(&mut response, &UInt256::default());
+                    write_i32(&mut response, ton_block::MASTERCHAIN_ID);
+                    write_int256(&mut response, &UInt256::default());
+                    write_int256(&mut response, &UInt256::default());
+                }
+                id::GET_CONFIG_ALL => {
+                    let chain = self.chain()?;
+                    let _mode = reader.read_u32()?;
+
+                    // Masterchain state with the default config
+                    let mut mc_state_extra = ton_block::McStateExtra::default();
+                    mc_state_extra.config = ton_executor::BlockchainConfig::default()
+                        .raw_config()
+                        .clone();
+                    let mut mc_state = ton_block::ShardStateUnsplit::with_ident(
+                        ton_block::ShardIdent::masterchain(),
+                    );
+                    mc_state.set_global_id(Self::GLOBAL_ID);
+                    mc_state.write_custom(Some(&mc_state_extra))?;
+                    let config_proof =
+                        ton_block::MerkleProof::create(&mc_state.serialize()?, |_| true)?
+                            .serialize()?;
+
+                    write_u32(&mut response, id::CONFIG_INFO);
+                    write_u32(&mut response, 0);
+                    tl_block_id(&chain.proof.mc_block_id).write_to(&mut response);
+                    write_bytes(&mut response, &[]);
+                    write_bytes(&mut response, &ton_types::serialize_toc(&config_proof)?);
+                }
+                id::GET_ACCOUNT_STATE => {
+                    let proof = &self.chain()?.proof;
+                    write_u32(&mut response, id::ACCOUNT_STATE);
+                    tl_block_id(&proof.mc_block_id).write_to(&mut response);
+                    tl_block_id(&proof.shard_block_id).write_to(&mut response);
+                    write_bytes(
+                        &mut response,
+                        &serialize_roots(&[
+                            &proof.mc_block_proof,
+                            proof.mc_state_proof.as_ref().trust_me(),
+                        ])?,
+                    );
+                    write_bytes(
+                        &mut response,
+                        &serialize_roots(&[&proof.shard_block_proof, &proof.state_proof])?,
+                    );
+                    write_bytes(
+                        &mut response,
+                        &ton_types::serialize_toc(proof.account.as_ref().trust_me())?,
+                    );
+                }
+                id::GET_BLOCK_HEADER => {
+                    let proof = &self.chain()?.proof;
+                    write_u32(&mut response, id::BLOCK_HEADER);
+                    tl_block_id(&proof.mc_block_id).write_to(&mut response);
+                    write_u32(&mut response, 0);
+                    write_bytes(
+                        &mut response,
+                        &ton_types::serialize_toc(&proof.mc_block_proof)?,
+                    );
+                }
+                id::LOOKUP_BLOCK => {
+                    let chain = self.chain()?;
+                    let _mode = reader.read_u32()?;
+                    let _workchain = reader.read_i32()?;
+                    let _shard = reader.read_u64()?;
+                    anyhow::ensure!(reader.read_u32()? == TestChain::KEY_BLOCK_SEQNO);
+
+                    write_u32(&mut response, id::BLOCK_HEADER);
+                    tl_block_id(&chain.key_block_id).write_to(&mut response);
+                    write_u32(&mut response, 0);
+                    write_bytes(&mut response, &[]);
+                }
+                id::GET_BLOCK => {
+                    let chain = self.chain()?;
+                    write_u32(&mut response, id::BLOCK_DATA);
+                    tl_block_id(&chain.key_block_id).write_to(&mut response);
+                    write_bytes(&mut response, &chain.key_block);
+                }
+                id::GET_BLOCK_PROOF => {
+                    let chain = self.chain()?;
+                    let from = tl_block_id(&chain.key_block_id);
+                    let to = tl_block_id(&chain.proof.mc_block_id);
+                    let validator_info = &chain.proof.mc_block_signatures.validator_info;
+
+                    write_u32(&mut response, id::PARTIAL_BLOCK_PROOF);
+                    write_bool(&mut response, true);
+                    from.write_to(&mut response);
+                    to.write_to(&mut response);
+                    write_u32(&mut response, 1);
+                    BlockLink::Forward {
+                        to_key_block: false,
+                        from,
+                        to,
+                        dest_proof: Vec::new(),
+                        config_proof: Vec::new(),
+                        signatures: SignatureSet {
+                            validator_set_hash: validator_info.validator_list_hash_short,
+                            catchain_seqno: validator_info.catchain_seqno,
+                            signatures: chain
+                                .signatures
+                                .iter()
+                                .map(|(node_id_short, signature)| Signature {
+                                    node_id_short: *node_id_short,
+                                    signature: signature.clone(),
+                                })
+                                .collect(),
+                        },
+                    }
+                    .write_to(&mut response);
+                }
+                _ => {
+                    write_u32(&mut response, id::LITE_SERVER_ERROR);
+                    write_i32(&mut response, 0);
+                    write_bytes(&mut response, b"unknown query");
+                }
+            }
+            Ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn history_is_fetched_by_pages() -> Result<()> {
+        let emulated = emulated_transport(test_clock());
+        let dst = test_address();
+        for i in 1..=3 {
+            emulated
+                .send_message(&transfer(&dst, i * 1_000_000_000))
+                .await?;
+        }
+        let transactions = emulated.get_transactions(&dst, u64::MAX, 10).await?;
+
+        let server = Arc::new(MockLiteServer {
+            transactions: transactions.clone(),
+            messages: Default::default(),
+            chain: None,
+        });
+        let transport = AdnlTransport::new(server.clone());
+
+        // Hash of the latest transaction is usually known from the account state
+        let latest = &transactions[0];
+        transport.remember_transaction(&dst, latest.data.lt, latest.hash);
+
+        let fetched = transport.get_transactions(&dst, latest.data.lt, 2).await?;
+        assert_eq!(fetched, transactions[..2]);
+
+        // Continues from the known prev transaction
+        let rest = transport
+            .get_transactions(&dst, fetched[1].data.prev_trans_lt, 16)
+            .await?;
+        assert_eq!(rest, transactions[2..]);
+
+        transport
+            .send_message(&ton_block::Message::default())
+            .await?;
+        assert_eq!(server.messages.lock().len(), 1);
+
+        assert!(transport.get_transaction(&latest.hash).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn state_proofs_are_verified() -> Result<()> {
+        let server = Arc::new(MockLiteServer {
+            transactions: Vec::new(),
+            messages: Default::default(),
+            chain: Some(TestChain::default().build()?),
+        });
+        let transport = VerifyingTransport::new(Arc::new(AdnlTransport::new(server)));
+
+        match transport.get_contract_state(&test_address()).await? {
+            RawContractState::Exists(state) => assert_eq!(state.account.addr, test_address()),
+            RawContractState::NotExists => panic!("account not found"),
+        }
+
+        // Signatures of other validators don't match the key block
+        let server = Arc::new(MockLiteServer {
+            transactions: Vec::new(),
+            messages: Default::default(),
+            chain: Some(
+                TestChain {
+                    signers: vec![4, 5, 6],
+                    ..Default::default()
+                }
+                .build()?,
+            ),
+        });
+        let transport = VerifyingTransport::new(Arc::new(AdnlTransport::new(server)));
+        assert!(transport.get_contract_state(&test_address()).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn config_is_read_from_the_masterchain_state() -> Result<()> {
+        let server = Arc::new(MockLiteServer {
+            transactions: Vec::new(),
+            messages: Default::default(),
+            chain: Some(TestChain::default().build()?),
+        });
+        let transport = AdnlTransport::new(server);
+        let clock = test_clock();
+        let default_config = ton_executor::BlockchainConfig::default();
+
+        let capabilities = transport.get_capabilities(clock.as_ref()).await?;
+        assert_eq!(capabilities.global_id, MockLiteServer::GLOBAL_ID);
+        assert_eq!(capabilities.raw, default_config.capabilites());
+
+        let config = transport
+            .get_blockchain_config(clock.as_ref(), true)
+            .await?;
+        assert_eq!(
+            config.raw_config().serialize()?.repr_hash(),
+            default_config.raw_config().serialize()?.repr_hash()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,723 @@
+//! Minimal TL serialization for the liteserver API.
+//!
+//! Only the functions used by [`AdnlTransport`](super::AdnlTransport) are declared here.
+//! See `lite_api.tl` for the full scheme.
+
+use ton_types::UInt256;
+
+pub mod id {
+    pub const LITE_SERVER_QUERY: u32 = 0x798c06df;
+    pub const LITE_SERVER_ERROR: u32 = 0xbba9e148;
+
+    pub const GET_MASTERCHAIN_INFO: u32 = 0x89b5e62e;
+    pub const MASTERCHAIN_INFO: u32 = 0x85832881;
+
+    pub const SEND_MESSAGE: u32 = 0x690ad482;
+    pub const SEND_MSG_STATUS: u32 = 0x3950e597;
+
+    pub const GET_ACCOUNT_STATE: u32 = 0x6b890e25;
+    pub const ACCOUNT_STATE: u32 = 0x7079c751;
+
+    pub const GET_TRANSACTIONS: u32 = 0x1c40e7a1;
+    pub const TRANSACTION_LIST: u32 = 0x6f26c60b;
+
+    pub const GET_CONFIG_ALL: u32 = 0x911b26b7;
+    pub const CONFIG_INFO: u32 = 0xae7b272f;
+
+    pub const GET_BLOCK: u32 = 0x6377cf0d;
+    pub const BLOCK_DATA: u32 = 0xa574ed6c;
+
+    pub const GET_BLOCK_HEADER: u32 = 0x21ec069e;
+    pub const LOOKUP_BLOCK: u32 = 0xfac8f71e;
+    pub const BLOCK_HEADER: u32 = 0x752d8219;
+
+    pub const GET_BLOCK_PROOF: u32 = 0x8aea9c44;
+    pub const PARTIAL_BLOCK_PROOF: u32 = 0x8ed0d2c1;
+    pub const BLOCK_LINK_BACK: u32 = 0xef7e1bef;
+    pub const BLOCK_LINK_FORWARD: u32 = 0x520fce1c;
+    pub const SIGNATURE_SET: u32 = 0xf644a6e6;
+
+    pub const BOOL_TRUE: u32 = 0x997275b5;
+    pub const BOOL_FALSE: u32 = 0xbc799737;
+}
+
+/// Max number of transactions in `liteServer.getTransactions` response
+pub const MAX_TRANSACTIONS_PER_QUERY: u8 = 16;
+
+/// `tonNode.blockIdExt`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BlockIdExt {
+    pub workchain: i32,
+    pub shard: u64,
+    pub seqno: u32,
+    pub root_hash: UInt256,
+    pub file_hash: UInt256,
+}
+
+impl BlockIdExt {
+    pub fn write_to(&self, packet: &mut Vec<u8>) {
+        write_i32(packet, self.workchain);
+        write_u64(packet, self.shard);
+        write_u32(packet, self.seqno);
+        write_int256(packet, &self.root_hash);
+        write_int256(packet, &self.file_hash);
+    }
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        Ok(Self {
+            workchain: reader.read_i32()?,
+            shard: reader.read_u64()?,
+            seqno: reader.read_u32()?,
+            root_hash: reader.read_int256()?,
+            file_hash: reader.read_int256()?,
+        })
+    }
+}
+
+/// Liteserver functions
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LiteQuery<'a> {
+    GetMasterchainInfo,
+    SendMessage {
+        body: &'a [u8],
+    },
+    GetAccountState {
+        id: &'a BlockIdExt,
+        workchain: i32,
+        account: &'a UInt256,
+    },
+    GetTransactions {
+        count: u32,
+        workchain: i32,
+        account: &'a UInt256,
+        lt: u64,
+        hash: &'a UInt256,
+    },
+    GetConfigAll {
+        mode: u32,
+        id: &'a BlockIdExt,
+    },
+    GetBlock {
+        id: &'a BlockIdExt,
+    },
+    GetBlockHeader {
+        id: &'a BlockIdExt,
+        mode: u32,
+    },
+    /// Looks up the block by its seqno
+    LookupBlock {
+        workchain: i32,
+        shard: u64,
+        seqno: u32,
+    },
+    /// Requests the proof chain from the known block to the target block
+    GetBlockProof {
+        known_block: &'a BlockIdExt,
+        target_block: &'a BlockIdExt,
+    },
+}
+
+impl LiteQuery<'_> {
+    /// Serializes the boxed function
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(128);
+        match self {
+            Self::GetMasterchainInfo => write_u32(&mut packet, id::GET_MASTERCHAIN_INFO),
+            Self::SendMessage { body } => {
+                write_u32(&mut packet, id::SEND_MESSAGE);
+                write_bytes(&mut packet, body);
+            }
+            Self::GetAccountState {
+                id,
+                workchain,
+                account,
+            } => {
+                write_u32(&mut packet, id::GET_ACCOUNT_STATE);
+                id.write_to(&mut packet);
+                write_i32(&mut packet, *workchain);
+                write_int256(&mut packet, account);
+            }
+            Self::GetTransactions {
+                count,
+                workchain,
+                account,
+                lt,
+                hash,
+            } => {
+                write_u32(&mut packet, id::GET_TRANSACTIONS);
+                write_u32(&mut packet, *count);
+                write_i32(&mut packet, *workchain);
+                write_int256(&mut packet, account);
+                write_u64(&mut packet, *lt);
+                write_int256(&mut packet, hash);
+            }
+            Self::GetConfigAll { mode, id } => {
+                write_u32(&mut packet, id::GET_CONFIG_ALL);
+                write_u32(&mut packet, *mode);
+                id.write_to(&mut packet);
+            }
+            Self::GetBlock { id } => {
+                write_u32(&mut packet, id::GET_BLOCK);
+                id.write_to(&mut packet);
+            }
+            Self::GetBlockHeader { id, mode } => {
+                write_u32(&mut packet, id::GET_BLOCK_HEADER);
+                id.write_to(&mut packet);
+                write_u32(&mut packet, *mode);
+            }
+            Self::LookupBlock {
+                workchain,
+                shard,
+                seqno,
+            } => {
+                write_u32(&mut packet, id::LOOKUP_BLOCK);
+                // NOTE: mode 1 means lookup by seqno, so `lt` and `utime` are omitted
+                write_u32(&mut packet, 1);
+                write_i32(&mut packet, *workchain);
+                write_u64(&mut packet, *shard);
+                write_u32(&mut packet, *seqno);
+            }
+            Self::GetBlockProof {
+                known_block,
+                target_block,
+            } => {
+                write_u32(&mut packet, id::GET_BLOCK_PROOF);
+                // NOTE: mode 1 means that the target block is specified
+                write_u32(&mut packet, 1);
+                known_block.write_to(&mut packet);
+                target_block.write_to(&mut packet);
+            }
+        }
+        packet
+    }
+}
+
+/// `liteServer.masterchainInfo`
+#[derive(Debug, Clone)]
+pub struct MasterchainInfo {
+    pub last: BlockIdExt,
+}
+
+/// `liteServer.accountState`
+#[derive(Debug, Clone)]
+pub struct AccountState {
+    pub id: BlockIdExt,
+    pub shardblk: BlockIdExt,
+    pub shard_proof: Vec<u8>,
+    pub proof: Vec<u8>,
+    pub state: Vec<u8>,
+}
+
+/// `liteServer.transactionList`
+#[derive(Debug, Clone)]
+pub struct TransactionList {
+    pub ids: Vec<BlockIdExt>,
+    pub transactions: Vec<u8>,
+}
+
+/// `liteServer.configInfo`
+#[derive(Debug, Clone)]
+pub struct ConfigInfo {
+    pub mode: u32,
+    pub id: BlockIdExt,
+    pub state_proof: Vec<u8>,
+    pub config_proof: Vec<u8>,
+}
+
+/// `liteServer.blockData`
+#[derive(Debug, Clone)]
+pub struct BlockData {
+    pub id: BlockIdExt,
+    pub data: Vec<u8>,
+}
+
+/// `liteServer.blockHeader`
+#[derive(Debug, Clone)]
+pub struct BlockHeader {
+    pub id: BlockIdExt,
+    pub mode: u32,
+    pub header_proof: Vec<u8>,
+}
+
+/// `liteServer.partialBlockProof`
+#[derive(Debug, Clone)]
+pub struct PartialBlockProof {
+    pub complete: bool,
+    pub from: BlockIdExt,
+    pub to: BlockIdExt,
+    pub steps: Vec<BlockLink>,
+}
+
+/// `liteServer.BlockLink`
+#[derive(Debug, Clone)]
+pub enum BlockLink {
+    Back {
+        to_key_block: bool,
+        from: BlockIdExt,
+        to: BlockIdExt,
+        dest_proof: Vec<u8>,
+        proof: Vec<u8>,
+        state_proof: Vec<u8>,
+    },
+    Forward {
+        to_key_block: bool,
+        from: BlockIdExt,
+        to: BlockIdExt,
+        dest_proof: Vec<u8>,
+        config_proof: Vec<u8>,
+        signatures: SignatureSet,
+    },
+}
+
+/// `liteServer.signatureSet`
+#[derive(Debug, Clone)]
+pub struct SignatureSet {
+    pub validator_set_hash: u32,
+    pub catchain_seqno: u32,
+    pub signatures: Vec<Signature>,
+}
+
+/// `liteServer.signature`
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub node_id_short: UInt256,
+    pub signature: Vec<u8>,
+}
+
+impl BlockLink {
+    pub fn write_to(&self, packet: &mut Vec<u8>) {
+        match self {
+            Self::Back {
+                to_key_block,
+                from,
+                to,
+                dest_proof,
+                proof,
+                state_proof,
+            } => {
+                write_u32(packet, id::BLOCK_LINK_BACK);
+                write_bool(packet, *to_key_block);
+                from.write_to(packet);
+                to.write_to(packet);
+                write_bytes(packet, dest_proof);
+                write_bytes(packet, proof);
+                write_bytes(packet, state_proof);
+            }
+            Self::Forward {
+                to_key_block,
+                from,
+                to,
+                dest_proof,
+                config_proof,
+                signatures,
+            } => {
+                write_u32(packet, id::BLOCK_LINK_FORWARD);
+                write_bool(packet, *to_key_block);
+                from.write_to(packet);
+                to.write_to(packet);
+                write_bytes(packet, dest_proof);
+                write_bytes(packet, config_proof);
+                signatures.write_to(packet);
+            }
+        }
+    }
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        match reader.read_u32()? {
+            id::BLOCK_LINK_BACK => Ok(Self::Back {
+                to_key_block: reader.read_bool()?,
+                from: BlockIdExt::read_from(reader)?,
+                to: BlockIdExt::read_from(reader)?,
+                dest_proof: reader.read_bytes()?,
+                proof: reader.read_bytes()?,
+                state_proof: reader.read_bytes()?,
+            }),
+            id::BLOCK_LINK_FORWARD => Ok(Self::Forward {
+                to_key_block: reader.read_bool()?,
+                from: BlockIdExt::read_from(reader)?,
+                to: BlockIdExt::read_from(reader)?,
+                dest_proof: reader.read_bytes()?,
+                config_proof: reader.read_bytes()?,
+                signatures: SignatureSet::read_from(reader)?,
+            }),
+            id => Err(TlError::UnknownConstructor(id)),
+        }
+    }
+}
+
+impl SignatureSet {
+    /// Writes the boxed signature set
+    pub fn write_to(&self, packet: &mut Vec<u8>) {
+        write_u32(packet, id::SIGNATURE_SET);
+        write_u32(packet, self.validator_set_hash);
+        write_u32(packet, self.catchain_seqno);
+        write_u32(packet, self.signatures.len() as u32);
+        for signature in &self.signatures {
+            write_int256(packet, &signature.node_id_short);
+            write_bytes(packet, &signature.signature);
+        }
+    }
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        match reader.read_u32()? {
+            id::SIGNATURE_SET => {}
+            id => return Err(TlError::UnknownConstructor(id)),
+        }
+
+        let validator_set_hash = reader.read_u32()?;
+        let catchain_seqno = reader.read_u32()?;
+
+        // NOTE: elements of the vector are bare `liteServer.signature`
+        let len = reader.read_u32()? as usize;
+        let mut signatures = Vec::with_capacity(std::cmp::min(len, 256));
+        for _ in 0..len {
+            signatures.push(Signature {
+                node_id_short: reader.read_int256()?,
+                signature: reader.read_bytes()?,
+            });
+        }
+
+        Ok(Self {
+            validator_set_hash,
+            catchain_seqno,
+            signatures,
+        })
+    }
+}
+
+/// Boxed liteserver response
+pub trait LiteResponse: Sized {
+    const ID: u32;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError>;
+}
+
+impl LiteResponse for MasterchainInfo {
+    const ID: u32 = id::MASTERCHAIN_INFO;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        let last = BlockIdExt::read_from(reader)?;
+        let _state_root_hash = reader.read_int256()?;
+        // init:tonNode.zeroStateIdExt
+        let _workchain = reader.read_i32()?;
+        let _root_hash = reader.read_int256()?;
+        let _file_hash = reader.read_int256()?;
+        Ok(Self { last })
+    }
+}
+
+impl LiteResponse for AccountState {
+    const ID: u32 = id::ACCOUNT_STATE;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        Ok(Self {
+            id: BlockIdExt::read_from(reader)?,
+            shardblk: BlockIdExt::read_from(reader)?,
+            shard_proof: reader.read_bytes()?,
+            proof: reader.read_bytes()?,
+            state: reader.read_bytes()?,
+        })
+    }
+}
+
+impl LiteResponse for TransactionList {
+    const ID: u32 = id::TRANSACTION_LIST;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        let len = reader.read_u32()? as usize;
+        let mut ids = Vec::with_capacity(std::cmp::min(len, 256));
+        for _ in 0..len {
+            ids.push(BlockIdExt::read_from(reader)?);
+        }
+        Ok(Self {
+            ids,
+            transactions: reader.read_bytes()?,
+        })
+    }
+}
+
+impl LiteResponse for ConfigInfo {
+    const ID: u32 = id::CONFIG_INFO;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        Ok(Self {
+            mode: reader.read_u32()?,
+            id: BlockIdExt::read_from(reader)?,
+            state_proof: reader.read_bytes()?,
+            config_proof: reader.read_bytes()?,
+        })
+    }
+}
+
+impl LiteResponse for BlockData {
+    const ID: u32 = id::BLOCK_DATA;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        Ok(Self {
+            id: BlockIdExt::read_from(reader)?,
+            data: reader.read_bytes()?,
+        })
+    }
+}
+
+impl LiteResponse for BlockHeader {
+    const ID: u32 = id::BLOCK_HEADER;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        Ok(Self {
+            id: BlockIdExt::read_from(reader)?,
+            mode: reader.read_u32()?,
+            header_proof: reader.read_bytes()?,
+        })
+    }
+}
+
+impl LiteResponse for PartialBlockProof {
+    const ID: u32 = id::PARTIAL_BLOCK_PROOF;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        let complete = reader.read_bool()?;
+        let from = BlockIdExt::read_from(reader)?;
+        let to = BlockIdExt::read_from(reader)?;
+
+        let len = reader.read_u32()? as usize;
+        let mut steps = Vec::with_capacity(std::cmp::min(len, 16));
+        for _ in 0..len {
+            steps.push(BlockLink::read_from(reader)?);
+        }
+
+        Ok(Self {
+            complete,
+            from,
+            to,
+            steps,
+        })
+    }
+}
+
+/// `liteServer.sendMsgStatus`
+impl LiteResponse for i32 {
+    const ID: u32 = id::SEND_MSG_STATUS;
+
+    fn read_from(reader: &mut TlReader<'_>) -> Result<Self, TlError> {
+        reader.read_i32()
+    }
+}
+
+/// Parses the boxed response or `liteServer.error`
+pub fn parse_response<T: LiteResponse>(data: &[u8]) -> Result<T, TlError> {
+    let mut reader = TlReader::new(data);
+    match reader.read_u32()? {
+        id if id == T::ID => T::read_from(&mut reader),
+        id::LITE_SERVER_ERROR => {
+            let code = reader.read_i32()?;
+            let message = String::from_utf8_lossy(&reader.read_bytes()?).into_owned();
+            Err(TlError::LiteServer { code, message })
+        }
+        id => Err(TlError::UnknownConstructor(id)),
+    }
+}
+
+pub fn write_u32(packet: &mut Vec<u8>, value: u32) {
+    packet.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_i32(packet: &mut Vec<u8>, value: i32) {
+    packet.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_bool(packet: &mut Vec<u8>, value: bool) {
+    write_u32(packet, if value { id::BOOL_TRUE } else { id::BOOL_FALSE });
+}
+
+pub fn write_u64(packet: &mut Vec<u8>, value: u64) {
+    packet.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_int256(packet: &mut Vec<u8>, value: &UInt256) {
+    packet.extend_from_slice(value.as_slice());
+}
+
+/// Writes `bytes` with the length prefix and the padding to 4 bytes
+pub fn write_bytes(packet: &mut Vec<u8>, bytes: &[u8]) {
+    let len = bytes.len();
+    let header_len = if len < 254 {
+        packet.push(len as u8);
+        1
+    } else {
+        packet.push(254);
+        packet.extend_from_slice(&(len as u32).to_le_bytes()[..3]);
+        4
+    };
+    packet.extend_from_slice(bytes);
+
+    let padding = (4 - (header_len + len) % 4) % 4;
+    packet.extend(std::iter::repeat(0).take(padding));
+}
+
+pub struct TlReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> TlReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], TlError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(TlError::UnexpectedEof)?;
+        let slice = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], TlError> {
+        let mut result = [0; N];
+        result.copy_from_slice(self.read_slice(N)?);
+        Ok(result)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, TlError> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, TlError> {
+        self.read_array().map(i32::from_le_bytes)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, TlError> {
+        match self.read_u32()? {
+            id::BOOL_TRUE => Ok(true),
+            id::BOOL_FALSE => Ok(false),
+            id => Err(TlError::UnknownConstructor(id)),
+        }
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, TlError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    pub fn read_int256(&mut self) -> Result<UInt256, TlError> {
+        self.read_array::<32>().map(UInt256::from)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, TlError> {
+        let (header_len, len) = match self.read_slice(1)?[0] {
+            254 => {
+                let len = self.read_slice(3)?;
+                (4, u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize)
+            }
+            255 => return Err(TlError::InvalidBytes),
+            len => (1, len as usize),
+        };
+        let bytes = self.read_slice(len)?.to_vec();
+
+        let padding = (4 - (header_len + len) % 4) % 4;
+        self.read_slice(padding)?;
+        Ok(bytes)
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum TlError {
+    #[error("Unexpected end of TL data")]
+    UnexpectedEof,
+    #[error("Invalid TL bytes")]
+    InvalidBytes,
+    #[error("Unknown TL constructor: {0:08x}")]
+    UnknownConstructor(u32),
+    #[error("Liteserver error {code}: {message}")]
+    LiteServer { code: i32, message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_roundtrip() -> anyhow::Result<()> {
+        for len in [0, 1, 3, 253, 254, 255, 1000] {
+            let data = vec![0xab; len];
+
+            let mut packet = Vec::new();
+            write_bytes(&mut packet, &data);
+            assert_eq!(packet.len() % 4, 0);
+
+            let mut reader = TlReader::new(&packet);
+            assert_eq!(reader.read_bytes()?, data);
+            assert!(reader.read_slice(1).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_parsed() {
+        let mut packet = Vec::new();
+        write_u32(&mut packet, id::LITE_SERVER_ERROR);
+        write_i32(&mut packet, 651);
+        write_bytes(&mut packet, b"block not found");
+
+        let err = parse_response::<MasterchainInfo>(&packet).unwrap_err();
+        assert!(matches!(err, TlError::LiteServer { code: 651, .. }));
+    }
+
+    #[test]
+    fn block_proof_is_parsed() -> anyhow::Result<()> {
+        let block_id = |seqno| BlockIdExt {
+            workchain: -1,
+            shard: 0x8000_0000_0000_0000,
+            seqno,
+            root_hash: UInt256::from([seqno as u8; 32]),
+            file_hash: UInt256::default(),
+        };
+
+        let mut packet = Vec::new();
+        write_u32(&mut packet, id::PARTIAL_BLOCK_PROOF);
+        write_bool(&mut packet, true);
+        block_id(1).write_to(&mut packet);
+        block_id(2).write_to(&mut packet);
+        write_u32(&mut packet, 1);
+        BlockLink::Forward {
+            to_key_block: false,
+            from: block_id(1),
+            to: block_id(2),
+            dest_proof: vec![1; 10],
+            config_proof: vec![2; 300],
+            signatures: SignatureSet {
+                validator_set_hash: 123,
+                catchain_seqno: 5,
+                signatures: vec![Signature {
+                    node_id_short: UInt256::from([3; 32]),
+                    signature: vec![4; 64],
+                }],
+            },
+        }
+        .write_to(&mut packet);
+
+        let proof = parse_response::<PartialBlockProof>(&packet)?;
+        assert!(proof.complete);
+        assert_eq!(proof.to, block_id(2));
+        match proof.steps.as_slice() {
+            [BlockLink::Forward {
+                to_key_block: false,
+                to,
+                config_proof,
+                signatures,
+                ..
+            }] => {
+                assert_eq!(to, &block_id(2));
+                assert_eq!(config_proof.len(), 300);
+                assert_eq!(signatures.validator_set_hash, 123);
+                assert_eq!(signatures.catchain_seqno, 5);
+                assert_eq!(signatures.signatures[0].signature, vec![4; 64]);
+            }
+            _ => panic!("unexpected steps"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::NetworkCapabilities;
+
+use super::models::*;
+use super::{Transport, TransportInfo};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CachingTransportConfig {
+    /// Max number of transactions cached by hash. `0` disables the cache. Default: `1000`
+    pub transactions_capacity: usize,
+    /// Max number of transactions cached by incoming message hash.
+    /// `0` disables the cache. Default: `1000`
+    pub dst_transactions_capacity: usize,
+    /// Max number of cached contract states. `0` disables the cache. Default: `100`
+    pub contract_states_capacity: usize,
+    /// How long the contract state is considered fresh. Default: `1000`
+    #[serde(with = "serde_duration_ms")]
+    pub contract_state_ttl: Duration,
+    /// How long the latest key block is considered fresh. Default: `60000`
+    #[serde(with = "serde_duration_ms")]
+    pub key_block_ttl: Duration,
+}
+
+impl Default for CachingTransportConfig {
+    fn default() -> Self {
+        Self {
+            transactions_capacity: 1000,
+            dst_transactions_capacity: 1000,
+            contract_states_capacity: 100,
+            contract_state_ttl: Duration::from_secs(1),
+            key_block_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Transport decorator which caches transactions and keeps
+/// recently requested contract states for a short period of time
+pub struct CachingTransport {
+    inner: Arc<dyn Transport>,
+    clock: Arc<dyn Clock>,
+    contract_state_ttl: u64,
+    key_block_ttl: u64,
+    transactions: Option<Mutex<LruCache<UInt256, RawTransaction>>>,
+    dst_transactions: Option<Mutex<LruCache<UInt256, RawTransaction>>>,
+    contract_states: Option<Mutex<LruCache<MsgAddressInt, Timed<RawContractState>>>>,
+    latest_key_block: Mutex<Option<Timed<ton_block::Block>>>,
+}
+
+impl CachingTransport {
+    pub fn new(
+        inner: Arc<dyn Transport>,
+        clock: Arc<dyn Clock>,
+        config: CachingTransportConfig,
+    ) -> Self {
+        fn make_cache<K, V>(capacity: usize) -> Option<Mutex<LruCache<K, V>>>
+        where
+            K: std::hash::Hash + Eq,
+        {
+            NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity)))
+        }
+
+        Self {
+            inner,
+            clock,
+            contract_state_ttl: config.contract_state_ttl.as_millis() as u64,
+            key_block_ttl: config.key_block_ttl.as_millis() as u64,
+            transactions: make_cache(config.transactions_capacity),
+            dst_transactions: make_cache(config.dst_transactions_capacity),
+            contract_states: make_cache(config.contract_states_capacity),
+            latest_key_block: Default::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &Arc<dyn Transport> {
+        &self.inner
+    }
+
+    /// Removes all cached items
+    pub fn clear(&self) {
+        if let Some(cache) = &self.transactions {
+            cache.lock().clear();
+        }
+        if let Some(cache) = &self.dst_transactions {
+            cache.lock().clear();
+        }
+        if let Some(cache) = &self.contract_states {
+            cache.lock().clear();
+        }
+        *self.latest_key_block.lock() = None;
+    }
+
+    fn store_transaction(&self, transaction: &RawTransaction) {
+        if let Some(cache) = &self.transactions {
+            cache.lock().put(transaction.hash, transaction.clone());
+        }
+
+        if let Some(cache) = &self.dst_transactions {
+            if let Some(in_msg) = &transaction.data.in_msg {
+                cache
+                    .lock()
+                    .put(in_msg.cell().repr_hash(), transaction.clone());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for CachingTransport {
+    fn info(&self) -> TransportInfo {
+        self.inner.info()
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        // Account state will definitely change soon
+        if let (Some(cache), Some(dst)) = (&self.contract_states, message.dst()) {
+            cache.lock().pop(&dst);
+        }
+        self.inner.send_message(message).await
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let cache = match &self.contract_states {
+            Some(cache) => cache,
+            None => return self.inner.get_contract_state(address).await,
+        };
+
+        let now = self.clock.now_ms_u64();
+        if let Some(item) = cache.lock().get(address) {
+            if item.is_fresh(now, self.contract_state_ttl) {
+                return Ok(item.value.clone());
+            }
+        }
+
+        let state = self.inner.get_contract_state(address).await?;
+        cache.lock().put(
+            address.clone(),
+            Timed {
+                value: state.clone(),
+                updated_at: now,
+            },
+        );
+        Ok(state)
+    }
+
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        let cache = match &self.contract_states {
+            Some(cache) => cache,
+            None => return self.inner.get_contract_states(addresses).await,
+        };
+
+        let now = self.clock.now_ms_u64();
+        let mut states = Vec::with_capacity(addresses.len());
+        let mut missing = Vec::new();
+        {
+            let mut cache = cache.lock();
+            for (i, address) in addresses.iter().enumerate() {
+                match cache.get(address) {
+                    Some(item) if item.is_fresh(now, self.contract_state_ttl) => {
+                        states.push(Some(item.value.clone()));
+                    }
+                    _ => {
+                        states.push(None);
+                        missing.push(i);
+                    }
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let missing_addresses = missing
+                .iter()
+                .map(|&i| addresses[i].clone())
+                .collect::<Vec<_>>();
+            let fetched = self.inner.get_contract_states(&missing_addresses).await?;
+
+            let mut cache = cache.lock();
+            for ((i, address), state) in missing.into_iter().zip(missing_addresses).zip(fetched) {
+                cache.put(
+                    address,
+                    Timed {
+                        value: state.clone(),
+                        updated_at: now,
+                    },
+                );
+                states[i] = Some(state);
+            }
+        }
+
+        states
+            .into_iter()
+            .map(|state| state.ok_or_else(|| CachingTransportError::InvalidResponse.into()))
+            .collect()
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        self.inner.get_contract_state_proof(address).await
+    }
+
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        self.inner
+            .get_contract_state_at(clock, address, transaction)
+            .await
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.inner
+            .get_accounts_by_code_hash(code_hash, limit, continuation)
+            .await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        let transactions = self.inner.get_transactions(address, from_lt, count).await?;
+        for transaction in &transactions {
+            self.store_transaction(transaction);
+        }
+        Ok(transactions)
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        if let Some(cache) = &self.transactions {
+            if let Some(transaction) = cache.lock().get(id) {
+                return Ok(Some(transaction.clone()));
+            }
+        }
+
+        let transaction = self.inner.get_transaction(id).await?;
+        if let Some(transaction) = &transaction {
+            self.store_transaction(transaction);
+        }
+        Ok(transaction)
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        if let Some(cache) = &self.dst_transactions {
+            if let Some(transaction) = cache.lock().get(message_hash) {
+                return Ok(Some(transaction.clone()));
+            }
+        }
+
+        // NOTE: missing transactions are not cached because they can appear later
+        let transaction = self.inner.get_dst_transaction(message_hash).await?;
+        if let Some(transaction) = &transaction {
+            self.store_transaction(transaction);
+        }
+        Ok(transaction)
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        let now = self.clock.now_ms_u64();
+        if let Some(item) = &*self.latest_key_block.lock() {
+            if item.is_fresh(now, self.key_block_ttl) {
+                return Ok(item.value.clone());
+            }
+        }
+
+        let block = self.inner.get_latest_key_block().await?;
+        *self.latest_key_block.lock() = Some(Timed {
+            value: block.clone(),
+            updated_at: now,
+        });
+        Ok(block)
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        self.inner.get_block(block).await
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.inner.get_latest_masterchain_block().await
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.inner
+            .wait_for_next_block(current, address, timeout)
+            .await
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.inner.get_capabilities(clock).await
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.inner.get_blockchain_config(clock, force).await
+    }
+}
+
+struct Timed<T> {
+    value: T,
+    updated_at: u64,
+}
+
+impl<T> Timed<T> {
+    fn is_fresh(&self, now_ms: u64, ttl_ms: u64) -> bool {
+        now_ms < self.updated_at.saturating_add(ttl_ms)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+enum CachingTransportError {
+    #[error("Invalid response")]
+    InvalidResponse,
+}
+
+#[cfg(test)]
+mod tests {
+    use ton_block::Serializable;
+
+    use super::*;
+    use crate::transport::middleware::{InstrumentedTransport, TransportCounters};
+    use crate::transport::test_utils::{
+        emulated_transport, polling_info, test_address, test_clock, transfer, StubTransport,
+    };
+
+    fn requests(counters: &TransportCounters, method: &str) -> u64 {
+        counters
+            .snapshot()
+            .get(method)
+            .map(|counters| counters.requests)
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn contract_state_ttl() -> Result<()> {
+        let inner = Arc::new(StubTransport::new(polling_info()));
+        let clock = Arc::new(ClockWithOffset::new(0));
+        let transport = CachingTransport::new(
+            inner.clone(),
+            clock.clone(),
+            CachingTransportConfig::default(),
+        );
+
+        let address = test_address();
+
+        transport.get_contract_state(&address).await?;
+        transport.get_contract_state(&address).await?;
+        assert_eq!(inner.requests("get_contract_state"), 1);
+
+        clock.update_offset(2000);
+        transport.get_contract_state(&address).await?;
+        assert_eq!(inner.requests("get_contract_state"), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_transactions_are_not_cached() -> Result<()> {
+        let inner = Arc::new(StubTransport::new(polling_info()));
+        let transport = CachingTransport::new(
+            inner.clone(),
+            Arc::new(SimpleClock),
+            CachingTransportConfig::default(),
+        );
+
+        let hash = UInt256::default();
+        assert!(transport.get_dst_transaction(&hash).await?.is_none());
+        assert!(transport.get_dst_transaction(&hash).await?.is_none());
+        assert_eq!(inner.requests("get_dst_transaction"), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetched_transactions_are_cached() -> Result<()> {
+        let emulated = emulated_transport(test_clock());
+        let address = test_address();
+        let message = transfer(&address, 1_000_000_000);
+        emulated.send_message(&message).await?;
+
+        let counters = Arc::new(TransportCounters::default());
+        let transport = CachingTransport::new(
+            Arc::new(InstrumentedTransport::new(emulated, counters.clone())),
+            test_clock(),
+            CachingTransportConfig::default(),
+        );
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 10).await?;
+        assert_eq!(transactions.len(), 1);
+        let hash = transactions[0].hash;
+
+        let transaction = transport.get_transaction(&hash).await?;
+        assert_eq!(transaction.map(|t| t.hash), Some(hash));
+
+        let message_hash = message.serialize()?.repr_hash();
+        let transaction = transport.get_dst_transaction(&message_hash).await?;
+        assert_eq!(transaction.map(|t| t.hash), Some(hash));
+
+        assert_eq!(requests(&counters, "get_transaction"), 0);
+        assert_eq!(requests(&counters, "get_dst_transaction"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sent_messages_evict_destination_state() -> Result<()> {
+        let emulated = emulated_transport(test_clock());
+        let address = test_address();
+        emulated
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+
+        let counters = Arc::new(TransportCounters::default());
+        let transport = CachingTransport::new(
+            Arc::new(InstrumentedTransport::new(emulated, counters.clone())),
+            test_clock(),
+            CachingTransportConfig::default(),
+        );
+
+        let before = transport.get_contract_state(&address).await?.brief();
+        transport.get_contract_state(&address).await?;
+        assert_eq!(requests(&counters, "get_contract_state"), 1);
+
+        transport
+            .send_message(&transfer(&address, 1_000_000_000))
+            .await?;
+        let after = transport.get_contract_state(&address).await?.brief();
+        assert_eq!(requests(&counters, "get_contract_state"), 2);
+        assert!(after.last_lt > before.last_lt);
+
+        Ok(())
+    }
+}
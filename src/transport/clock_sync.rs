@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use nekoton_abi::GenTimings;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::NetworkCapabilities;
+
+use super::models::*;
+use super::{Transport, TransportInfo};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ClockSyncConfig {
+    /// Number of the latest samples used for the estimation. Default: `15`
+    pub window_size: usize,
+    /// Min number of samples before the clock is updated. Default: `3`
+    pub min_samples: usize,
+    /// Estimated offset changes below this value are ignored. Default: `1000`
+    #[serde(with = "serde_duration_ms")]
+    pub min_correction: Duration,
+    /// Skew after which the handler is notified. Default: `30000`
+    #[serde(with = "serde_duration_ms")]
+    pub skew_threshold: Duration,
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 15,
+            min_samples: 3,
+            min_correction: Duration::from_secs(1),
+            skew_threshold: Duration::from_secs(30),
+        }
+    }
+}
+
+pub trait ClockSyncHandler: Send + Sync {
+    /// Called when the estimated skew crosses the threshold in either direction
+    fn on_clock_skew(&self, report: &ClockSkewReport);
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockSkewReport {
+    /// Network time minus local time
+    pub offset_ms: i64,
+    /// Whether the offset exceeds [`ClockSyncConfig::skew_threshold`]
+    pub exceeds_threshold: bool,
+    /// Number of samples used for the estimation
+    pub samples: usize,
+}
+
+/// Estimates the local clock skew from block generation times
+/// and keeps the [`ClockWithOffset`] in sync with the network.
+///
+/// Block generation time is always slightly behind the network time, so each
+/// sample is a lower bound of the offset. Samples which are too far from the
+/// median (e.g. from misbehaving nodes) are rejected, and the highest of the
+/// remaining ones is used as the estimation
+pub struct ClockSync {
+    clock: Arc<ClockWithOffset>,
+    config: ClockSyncConfig,
+    handler: Option<Arc<dyn ClockSyncHandler>>,
+    state: Mutex<ClockSyncState>,
+}
+
+impl ClockSync {
+    pub fn new(clock: Arc<ClockWithOffset>, config: ClockSyncConfig) -> Self {
+        Self {
+            clock,
+            config,
+            handler: None,
+            state: Default::default(),
+        }
+    }
+
+    pub fn with_handler(mut self, handler: Arc<dyn ClockSyncHandler>) -> Self {
+        self.handler = Some(handler);
+        self
+    }
+
+    pub fn clock(&self) -> &Arc<ClockWithOffset> {
+        &self.clock
+    }
+
+    /// Returns the latest estimation, if enough samples were collected
+    pub fn report(&self) -> Option<ClockSkewReport> {
+        self.state.lock().report
+    }
+
+    /// Fetches the latest masterchain block and uses its generation time as a sample.
+    /// Requires [`TransportInfo::has_blocks`]
+    pub async fn sync(&self, transport: &dyn Transport) -> Result<Option<ClockSkewReport>> {
+        let block = transport.get_latest_masterchain_block().await?;
+        self.add_sample(block.block.gen_utime);
+        Ok(self.report())
+    }
+
+    /// Adds the block generation time which was received just now
+    pub fn add_sample(&self, gen_utime: u32) {
+        self.add_sample_at(gen_utime, now_ms_u64());
+    }
+
+    /// Adds the block generation time which was received at the specified local time
+    pub fn add_sample_at(&self, gen_utime: u32, local_time_ms: u64) {
+        let sample = gen_utime as i64 * 1000 - local_time_ms as i64;
+
+        let (report, notify) = {
+            let mut state = self.state.lock();
+            state.samples.push_back(sample);
+            while state.samples.len() > std::cmp::max(self.config.window_size, 1) {
+                state.samples.pop_front();
+            }
+
+            if state.samples.len() < self.config.min_samples {
+                return;
+            }
+            let (offset_ms, samples) = match estimate_offset(state.samples.make_contiguous()) {
+                Some(estimation) => estimation,
+                None => return,
+            };
+
+            let report = ClockSkewReport {
+                offset_ms,
+                exceeds_threshold: offset_ms.unsigned_abs()
+                    >= self.config.skew_threshold.as_millis() as u64,
+                samples,
+            };
+            let notify = match &state.report {
+                Some(prev) => prev.exceeds_threshold != report.exceeds_threshold,
+                None => report.exceeds_threshold,
+            };
+            state.report = Some(report);
+            (report, notify)
+        };
+
+        let correction = report.offset_ms.abs_diff(self.clock.offset_ms());
+        if correction >= self.config.min_correction.as_millis() as u64 {
+            self.clock.update_offset(report.offset_ms);
+        }
+
+        if notify {
+            if let Some(handler) = &self.handler {
+                handler.on_clock_skew(&report);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClockSyncState {
+    samples: VecDeque<i64>,
+    report: Option<ClockSkewReport>,
+}
+
+/// Returns the estimated offset and the number of accepted samples
+fn estimate_offset(samples: &[i64]) -> Option<(i64, usize)> {
+    let center = median(samples.to_vec())?;
+    let deviation = median(samples.iter().map(|x| (x - center).abs()).collect())?;
+    let max_distance = std::cmp::max(deviation * 3, MIN_OUTLIER_DISTANCE_MS);
+
+    let mut accepted = 0;
+    let mut offset = None;
+    for &sample in samples {
+        if (sample - center).abs() <= max_distance {
+            accepted += 1;
+            offset = std::cmp::max(offset, Some(sample));
+        }
+    }
+    offset.map(|offset| (offset, accepted))
+}
+
+fn median(mut values: Vec<i64>) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    Some(values[values.len() / 2])
+}
+
+/// Blocks are produced every few seconds, so the samples are
+/// naturally spread by this value even for the correct clock
+const MIN_OUTLIER_DISTANCE_MS: i64 = 5000;
+
+/// Transport decorator which feeds [`ClockSync`] with the generation time
+/// of the fetched contract states and blocks.
+///
+/// Must wrap the network transport directly, because cached
+/// states would produce outdated samples
+pub struct ClockSyncTransport {
+    inner: Arc<dyn Transport>,
+    sync: Arc<ClockSync>,
+}
+
+impl ClockSyncTransport {
+    pub fn new(inner: Arc<dyn Transport>, sync: Arc<ClockSync>) -> Self {
+        Self { inner, sync }
+    }
+
+    pub fn sync(&self) -> &Arc<ClockSync> {
+        &self.sync
+    }
+
+    fn observe_state(&self, state: &RawContractState) {
+        if let RawContractState::Exists(ExistingContract {
+            timings: GenTimings::Known { gen_utime, .. },
+            ..
+        }) = state
+        {
+            self.sync.add_sample(*gen_utime);
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for ClockSyncTransport {
+    fn info(&self) -> TransportInfo {
+        self.inner.info()
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        self.inner.send_message(message).await
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let state = self.inner.get_contract_state(address).await?;
+        self.observe_state(&state);
+        Ok(state)
+    }
+
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        let states = self.inner.get_contract_states(addresses).await?;
+        // All states are usually from the same block, so one sample is enough
+        if let Some(state) = states
+            .iter()
+            .find(|state| matches!(state, RawContractState::Exists(_)))
+        {
+            self.observe_state(state);
+        }
+        Ok(states)
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        self.inner.get_contract_state_proof(address).await
+    }
+
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        self.inner
+            .get_contract_state_at(clock, address, transaction)
+            .await
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.inner
+            .get_accounts_by_code_hash(code_hash, limit, continuation)
+            .await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        self.inner.get_transactions(address, from_lt, count).await
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        self.inner.get_transaction(id).await
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        self.inner.get_dst_transaction(message_hash).await
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        self.inner.get_latest_key_block().await
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        self.inner.get_block(block).await
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        let block = self.inner.get_latest_masterchain_block().await?;
+        self.sync.add_sample(block.block.gen_utime);
+        Ok(block)
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.inner
+            .wait_for_next_block(current, address, timeout)
+            .await
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.inner.get_capabilities(clock).await
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.inner.get_blockchain_config(clock, force).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{emulated_transport, funded_account, test_clock, NOW_SEC};
+
+    #[derive(Default)]
+    struct ReportsCollector(Mutex<Vec<ClockSkewReport>>);
+
+    impl ClockSyncHandler for ReportsCollector {
+        fn on_clock_skew(&self, report: &ClockSkewReport) {
+            self.0.lock().push(*report);
+        }
+    }
+
+    #[test]
+    fn outliers_are_rejected() {
+        let clock = Arc::new(ClockWithOffset::new(0));
+        let handler = Arc::new(ReportsCollector::default());
+        let sync = ClockSync::new(clock.clone(), Default::default()).with_handler(handler.clone());
+
+        let local_time_ms = 1650000000000;
+        // Network is 60 seconds ahead, blocks are 0-3 seconds old
+        for (age, gen_utime_offset) in [(0, 60), (3, 60), (1, 60), (0, 3600), (2, 60)] {
+            sync.add_sample_at(1650000000 + gen_utime_offset - age, local_time_ms);
+        }
+
+        let report = sync.report().unwrap();
+        assert_eq!(report.offset_ms, 60000);
+        assert_eq!(report.samples, 4);
+        assert!(report.exceeds_threshold);
+        assert_eq!(clock.offset_ms(), 60000);
+
+        // Skew hasn't changed, so it was reported only once
+        assert_eq!(handler.0.lock().len(), 1);
+
+        // Local clock was fixed by the user
+        for _ in 0..15 {
+            sync.add_sample_at(1650000000, local_time_ms - 500);
+        }
+        let report = sync.report().unwrap();
+        assert_eq!(report.offset_ms, 500);
+        assert!(!report.exceeds_threshold);
+        assert_eq!(clock.offset_ms(), 500);
+        assert_eq!(handler.0.lock().len(), 2);
+
+        // Small changes are ignored
+        sync.add_sample_at(1650000000, local_time_ms - 800);
+        assert_eq!(sync.report().unwrap().offset_ms, 800);
+        assert_eq!(clock.offset_ms(), 500);
+    }
+
+    #[tokio::test]
+    async fn clock_is_synced_from_states() -> Result<()> {
+        let emulated = emulated_transport(test_clock());
+        let address = funded_account(&emulated).await?;
+
+        let clock = Arc::new(ClockWithOffset::new(0));
+        let sync = Arc::new(ClockSync::new(clock.clone(), Default::default()));
+        let transport = ClockSyncTransport::new(emulated, sync.clone());
+
+        for _ in 0..3 {
+            transport.get_contract_state(&address).await?;
+        }
+
+        assert!(sync.report().unwrap().exceeds_threshold);
+        assert!(clock.now_sec_u64().abs_diff(NOW_SEC) <= 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,758 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::future::{BoxFuture, Either};
+use futures_util::FutureExt;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use ton_block::{
+    Account, CurrencyCollection, Deserializable, GetRepresentationHash, MsgAddressInt, Serializable,
+};
+use ton_types::UInt256;
+
+use nekoton_abi::{Executor, GenTimings, LastTransactionId, TransactionId};
+use nekoton_utils::*;
+
+use crate::core::models::{NetworkCapabilities, ReliableBehavior};
+
+use super::models::*;
+use super::{Transport, TransportError, TransportInfo};
+
+/// In-memory blockchain which executes all incoming messages locally.
+///
+/// Useful for offline tests of subscriptions and wallets.
+pub struct EmulatedTransport {
+    clock: Arc<dyn Clock>,
+    config: ton_executor::BlockchainConfig,
+    global_id: i32,
+    state: Mutex<EmulatedState>,
+    chain: Option<EmulatedChain>,
+}
+
+impl EmulatedTransport {
+    pub fn new(clock: Arc<dyn Clock>, config: ton_executor::BlockchainConfig) -> Self {
+        Self {
+            clock,
+            config,
+            global_id: 0,
+            state: Mutex::new(EmulatedState {
+                accounts: Default::default(),
+                transactions: Default::default(),
+                dst_transactions: Default::default(),
+                lt: INITIAL_LT,
+            }),
+            chain: None,
+        }
+    }
+
+    pub fn with_global_id(mut self, global_id: i32) -> Self {
+        self.global_id = global_id;
+        self
+    }
+
+    /// Enables block walking with a single workchain shard.
+    ///
+    /// Blocks are produced only by [`produce_blocks`](Self::produce_blocks) and contain
+    /// all transactions executed since the previous block of the shard.
+    /// `sleep` is used to limit [`Transport::wait_for_next_block`] by its timeout
+    pub fn with_blocks<S, F>(mut self, sleep: S) -> Self
+    where
+        S: Fn(Duration) -> F + Send + Sync + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut blocks = EmulatedBlocks::default();
+        let utime = self.clock.now_sec_u64() as u32;
+        for shard in [
+            ton_block::ShardIdent::masterchain(),
+            ton_block::ShardIdent::full(0),
+        ] {
+            let block = blocks
+                .make_block(None, shard, 1, utime, INITIAL_LT, &[])
+                .trust_me();
+            blocks.latest.push(block);
+        }
+        self.chain = Some(EmulatedChain {
+            blocks: Mutex::new(blocks),
+            produced: Notify::new(),
+            sleep: Box::new(move |timeout| sleep(timeout).boxed()),
+        });
+        self
+    }
+
+    /// Produces the next block in the masterchain and in every shard
+    pub fn produce_blocks(&self) -> Result<()> {
+        let chain = self.chain()?;
+        let state = self.state.lock();
+        let utime = self.clock.now_sec_u64() as u32;
+
+        let mut blocks = chain.blocks.lock();
+        for i in 0..blocks.latest.len() {
+            let prev = blocks.latest[i].clone();
+            let shard = ton_block::ShardIdent::with_tagged_prefix(prev.workchain_id, prev.shard)?;
+            let transactions = state.shard_transactions(&shard, prev.end_lt, state.lt);
+            let block = blocks.make_block(
+                Some(prev.id),
+                shard,
+                prev.seqno + 1,
+                utime,
+                state.lt,
+                &transactions,
+            )?;
+            blocks.latest[i] = block;
+        }
+
+        chain.produced.notify_waiters();
+        Ok(())
+    }
+
+    /// Splits the shard with the account and produces the first blocks of its halves
+    pub fn split_shard(&self, address: &MsgAddressInt) -> Result<()> {
+        let chain = self.chain()?;
+        let state = self.state.lock();
+        let utime = self.clock.now_sec_u64() as u32;
+
+        let mut blocks = chain.blocks.lock();
+        let index = blocks
+            .latest
+            .iter()
+            .position(|block| block.contains_account(address))
+            .ok_or(EmulatedTransportError::BlockNotFound)?;
+        let prev = blocks.latest.remove(index);
+
+        let shard = ton_block::ShardIdent::with_tagged_prefix(prev.workchain_id, prev.shard)?;
+        let (left, right) = shard.split()?;
+        for shard in [left, right] {
+            let transactions = state.shard_transactions(&shard, prev.end_lt, state.lt);
+            let block = blocks.make_block(
+                Some(prev.id),
+                shard,
+                prev.seqno + 1,
+                utime,
+                state.lt,
+                &transactions,
+            )?;
+            blocks.latest.push(block);
+        }
+
+        chain.produced.notify_waiters();
+        Ok(())
+    }
+
+    fn chain(&self) -> Result<&EmulatedChain> {
+        match &self.chain {
+            Some(chain) => Ok(chain),
+            None => Err(TransportError::BlocksNotSupported.into()),
+        }
+    }
+
+    /// Inserts or replaces an account state
+    pub fn add_account(&self, account: Account) -> Result<()> {
+        let address = account
+            .get_addr()
+            .cloned()
+            .ok_or(EmulatedTransportError::AccountNotExists)?;
+
+        let mut state = self.state.lock();
+        state.bump_lt(account.last_tr_time().unwrap_or_default());
+        state.accounts.entry(address).or_default().account = account;
+        Ok(())
+    }
+
+    /// Inserts or replaces an account state from the base64 encoded boc
+    pub fn add_account_from_boc(&self, boc: &str) -> Result<MsgAddressInt> {
+        let account = Account::construct_from_base64(boc)
+            .map_err(|_| EmulatedTransportError::InvalidAccountState)?;
+        let address = account
+            .get_addr()
+            .cloned()
+            .ok_or(EmulatedTransportError::AccountNotExists)?;
+        self.add_account(account)?;
+        Ok(address)
+    }
+
+    /// Deploys an account with the state init from the TVC file
+    pub fn add_account_from_tvc(
+        &self,
+        address: MsgAddressInt,
+        tvc: &[u8],
+        balance: u64,
+    ) -> Result<()> {
+        let state_init = ton_block::StateInit::construct_from_bytes(tvc)
+            .map_err(|_| EmulatedTransportError::InvalidStateInit)?;
+
+        let mut account = Account::active_by_init_code_hash(
+            address,
+            CurrencyCollection::with_grams(balance),
+            self.clock.now_sec_u64() as u32,
+            state_init,
+            false,
+        )?;
+        account.update_storage_stat()?;
+
+        self.add_account(account)
+    }
+
+    /// Removes all accounts and transactions
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.accounts.clear();
+        state.transactions.clear();
+        state.dst_transactions.clear();
+        state.lt = INITIAL_LT;
+    }
+
+    /// Executes the message and all internal messages produced by it.
+    /// The state is updated only if the whole chain succeeds
+    fn execute(&self, message: &ton_block::Message) -> Result<()> {
+        let mut state = self.state.lock();
+
+        let mut lt = state.lt;
+        let mut accounts = HashMap::<MsgAddressInt, Account>::new();
+        let mut transactions = Vec::new();
+
+        let mut messages = VecDeque::from([message.clone()]);
+        while let Some(message) = messages.pop_front() {
+            if transactions.len() >= MAX_TRANSACTIONS_PER_MESSAGE {
+                return Err(EmulatedTransportError::TooManyTransactions.into());
+            }
+
+            let dst = match message.dst() {
+                Some(dst) => dst,
+                None => continue,
+            };
+
+            let account = match accounts.get(&dst) {
+                Some(account) => account.clone(),
+                None => state
+                    .accounts
+                    .get(&dst)
+                    .map(|item| item.account.clone())
+                    .unwrap_or(Account::AccountNone),
+            };
+            let last_trans_lt = account.last_tr_time().unwrap_or_default();
+
+            let utime = self.clock.now_sec_u64() as u32;
+            let mut executor =
+                Executor::with_params(self.config.clone(), account, last_trans_lt, utime, lt);
+
+            let transaction = match executor.run_mut(&message) {
+                Ok(transaction) => transaction,
+                // External messages which were not accepted are silently dropped,
+                // the same way as the real network does
+                Err(e) if message.is_inbound_external() => {
+                    log::debug!("External message was not accepted: {e:?}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            transaction.iterate_out_msgs(|message| {
+                if message.is_internal() {
+                    messages.push_back(message);
+                }
+                Ok(true)
+            })?;
+
+            lt = std::cmp::max(lt, executor.last_transaction_lt()) + LT_STEP;
+
+            let hash = transaction.hash()?;
+            let message_hash = message.serialize()?.repr_hash();
+
+            accounts.insert(dst.clone(), executor.into_account());
+            transactions.push((
+                dst,
+                message_hash,
+                RawTransaction {
+                    hash,
+                    data: transaction,
+                },
+            ));
+        }
+
+        state.lt = lt;
+        for (address, account) in accounts {
+            state.accounts.entry(address).or_default().account = account;
+        }
+        for (dst, message_hash, transaction) in transactions {
+            let item = state.accounts.entry(dst).or_default();
+            item.transactions
+                .insert(transaction.data.lt, transaction.hash);
+
+            state
+                .dst_transactions
+                .insert(message_hash, transaction.hash);
+            state.transactions.insert(transaction.hash, transaction);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for EmulatedTransport {
+    fn info(&self) -> TransportInfo {
+        let has_blocks = self.chain.is_some();
+        TransportInfo {
+            max_transactions_per_fetch: 50,
+            reliable_behavior: if has_blocks {
+                ReliableBehavior::BlockWalking
+            } else {
+                ReliableBehavior::IntensivePolling
+            },
+            has_key_blocks: false,
+            has_blocks,
+        }
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        self.execute(message)
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let state = self.state.lock();
+
+        let item = match state.accounts.get(address) {
+            Some(item) => item,
+            None => return Ok(RawContractState::NotExists),
+        };
+
+        let account = match &item.account {
+            Account::Account(account) => account.clone(),
+            Account::AccountNone => return Ok(RawContractState::NotExists),
+        };
+
+        let last_transaction_id = match item.transactions.iter().next_back() {
+            Some((&lt, &hash)) => LastTransactionId::Exact(TransactionId { lt, hash }),
+            None => LastTransactionId::Inexact {
+                latest_lt: account.storage.last_trans_lt,
+            },
+        };
+
+        Ok(RawContractState::Exists(ExistingContract {
+            account,
+            timings: GenTimings::Known {
+                gen_lt: state.lt,
+                gen_utime: self.clock.now_sec_u64() as u32,
+            },
+            last_transaction_id,
+        }))
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        let state = self.state.lock();
+
+        let continuation = continuation.as_ref().map(ToString::to_string);
+
+        let mut accounts = state
+            .accounts
+            .iter()
+            .filter(|(_, item)| matches!(item.code_hash(), Some(hash) if &hash == code_hash))
+            .map(|(address, _)| (address.to_string(), address))
+            .filter(|(id, _)| matches!(&continuation, Some(c) if id > c) || continuation.is_none())
+            .collect::<Vec<_>>();
+        accounts.sort_unstable_by(|(left, _), (right, _)| left.cmp(right));
+
+        Ok(accounts
+            .into_iter()
+            .take(limit as usize)
+            .map(|(_, address)| address.clone())
+            .collect())
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        let state = self.state.lock();
+
+        let item = match state.accounts.get(address) {
+            Some(item) => item,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(item
+            .transactions
+            .range(..=from_lt)
+            .rev()
+            .take(count as usize)
+            .filter_map(|(_, hash)| state.transactions.get(hash).cloned())
+            .collect())
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        Ok(self.state.lock().transactions.get(id).cloned())
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        let state = self.state.lock();
+        Ok(state
+            .dst_transactions
+            .get(message_hash)
+            .and_then(|hash| state.transactions.get(hash))
+            .cloned())
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        Err(EmulatedTransportError::NoKeyBlocks.into())
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        let blocks = self.chain()?.blocks.lock();
+        let block = match block {
+            BlockRef::Id(id) => blocks.blocks.get(id),
+            BlockRef::Seqno {
+                workchain_id,
+                shard,
+                seqno,
+            } => blocks.blocks.values().find(|block| {
+                block.summary.workchain_id == *workchain_id
+                    && block.summary.shard == *shard
+                    && block.summary.seqno == *seqno
+            }),
+        };
+        match block {
+            Some(block) => Ok(block.block.clone()),
+            None => Err(EmulatedTransportError::BlockNotFound.into()),
+        }
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        let blocks = self.chain()?.blocks.lock();
+        let (block, shards) = blocks
+            .latest
+            .split_first()
+            .ok_or(EmulatedTransportError::BlockNotFound)?;
+        Ok(LatestMasterchainBlock {
+            block: block.clone(),
+            shards: shards.to_vec(),
+        })
+    }
+
+    /// Waits until the next block is produced by [`EmulatedTransport::produce_blocks`]
+    /// or [`EmulatedTransport::split_shard`]
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        let chain = self.chain()?;
+        let find_next = || -> Result<Option<UInt256>> {
+            let blocks = chain.blocks.lock();
+            let current = blocks
+                .blocks
+                .get(current)
+                .ok_or(EmulatedTransportError::BlockNotFound)?;
+            Ok(current.next.iter().copied().find(|id| {
+                matches!(blocks.blocks.get(id), Some(block) if block.summary.contains_account(address))
+            }))
+        };
+
+        let mut sleep = (chain.sleep)(timeout);
+        loop {
+            // NOTE: subscribe before the check to not miss the block
+            let produced = chain.produced.notified();
+            futures_util::pin_mut!(produced);
+
+            if let Some(next) = find_next()? {
+                return Ok(Some(next));
+            }
+
+            match futures_util::future::select(produced, sleep.as_mut()).await {
+                Either::Left(_) => continue,
+                Either::Right(_) => return Ok(None),
+            }
+        }
+    }
+
+    async fn get_capabilities(&self, _: &dyn Clock) -> Result<NetworkCapabilities> {
+        Ok(NetworkCapabilities {
+            global_id: self.global_id,
+            raw: self.config.capabilites(),
+        })
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        _: &dyn Clock,
+        _: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        Ok(self.config.clone())
+    }
+}
+
+struct EmulatedState {
+    accounts: HashMap<MsgAddressInt, EmulatedAccount>,
+    transactions: HashMap<UInt256, RawTransaction>,
+    /// Transaction hashes by incoming message hash
+    dst_transactions: HashMap<UInt256, UInt256>,
+    /// Logical time of the next emulated "block"
+    lt: u64,
+}
+
+impl EmulatedState {
+    fn bump_lt(&mut self, lt: u64) {
+        self.lt = std::cmp::max(self.lt, lt) + LT_STEP;
+    }
+
+    /// Returns transactions of the shard accounts with `from_lt <= lt < to_lt`
+    fn shard_transactions(
+        &self,
+        shard: &ton_block::ShardIdent,
+        from_lt: u64,
+        to_lt: u64,
+    ) -> Vec<ton_block::Transaction> {
+        self.accounts
+            .iter()
+            .filter(|(address, _)| {
+                matches!(
+                    ton_block::AccountIdPrefixFull::prefix(address),
+                    Ok(prefix) if shard.contains_full_prefix(&prefix)
+                )
+            })
+            .flat_map(|(_, item)| item.transactions.range(from_lt..to_lt))
+            .filter_map(|(_, hash)| self.transactions.get(hash))
+            .map(|transaction| transaction.data.clone())
+            .collect()
+    }
+}
+
+struct EmulatedChain {
+    blocks: Mutex<EmulatedBlocks>,
+    /// Notified after new blocks are produced
+    produced: Notify,
+    sleep: Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>,
+}
+
+/// Chains of the emulated blocks
+#[derive(Default)]
+struct EmulatedBlocks {
+    /// Latest blocks of the masterchain (first) and all shards
+    latest: Vec<BlockSummary>,
+    blocks: HashMap<UInt256, EmulatedBlock>,
+}
+
+impl EmulatedBlocks {
+    fn make_block(
+        &mut self,
+        prev: Option<UInt256>,
+        shard: ton_block::ShardIdent,
+        seqno: u32,
+        utime: u32,
+        lt: u64,
+        transactions: &[ton_block::Transaction],
+    ) -> Result<BlockSummary> {
+        let mut info = ton_block::BlockInfo::new();
+        info.set_shard(shard.clone());
+        info.set_seq_no(seqno)?;
+        info.set_gen_utime(ton_block::UnixTime32(utime));
+        info.set_end_lt(lt);
+
+        let mut account_blocks = ton_block::ShardAccountBlocks::default();
+        for transaction in transactions {
+            account_blocks.add_serialized_transaction(transaction, &transaction.serialize()?)?;
+        }
+        let mut extra = ton_block::BlockExtra::default();
+        extra.write_account_blocks(&account_blocks)?;
+
+        let block =
+            ton_block::Block::with_params(0, info, Default::default(), Default::default(), extra)?;
+
+        let summary = BlockSummary {
+            workchain_id: shard.workchain_id(),
+            shard: shard.shard_prefix_with_tag(),
+            seqno,
+            id: block.serialize()?.repr_hash(),
+            end_lt: lt,
+            gen_utime: utime,
+        };
+
+        if let Some(prev) = prev.and_then(|prev| self.blocks.get_mut(&prev)) {
+            prev.next.push(summary.id);
+        }
+        self.blocks.insert(
+            summary.id,
+            EmulatedBlock {
+                block,
+                summary: summary.clone(),
+                next: Vec::new(),
+            },
+        );
+        Ok(summary)
+    }
+}
+
+struct EmulatedBlock {
+    block: ton_block::Block,
+    summary: BlockSummary,
+    /// Ids of the next blocks, two of them after the split
+    next: Vec<UInt256>,
+}
+
+struct EmulatedAccount {
+    account: Account,
+    /// Transaction hashes by lt
+    transactions: BTreeMap<u64, UInt256>,
+}
+
+impl Default for EmulatedAccount {
+    fn default() -> Self {
+        Self {
+            account: Account::AccountNone,
+            transactions: Default::default(),
+        }
+    }
+}
+
+impl EmulatedAccount {
+    fn code_hash(&self) -> Option<UInt256> {
+        match &self.account {
+            Account::Account(account) => match &account.storage.state {
+                ton_block::AccountState::AccountActive { state_init, .. } => {
+                    state_init.code.as_ref().map(ton_types::Cell::repr_hash)
+                }
+                _ => None,
+            },
+            Account::AccountNone => None,
+        }
+    }
+}
+
+const INITIAL_LT: u64 = 1_000_000;
+const LT_STEP: u64 = 1_000;
+const MAX_TRANSACTIONS_PER_MESSAGE: usize = 1_000;
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum EmulatedTransportError {
+    #[error("Account not exists")]
+    AccountNotExists,
+    #[error("Invalid account state")]
+    InvalidAccountState,
+    #[error("Invalid state init")]
+    InvalidStateInit,
+    #[error("Too many transactions produced by a single message")]
+    TooManyTransactions,
+    #[error("Key blocks are not supported")]
+    NoKeyBlocks,
+    #[error("Block not found")]
+    BlockNotFound,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{
+        block_walking_transport, emulated_transport, test_address, test_clock, transfer,
+    };
+
+    #[tokio::test]
+    async fn empty_state() -> Result<()> {
+        let transport = emulated_transport(test_clock());
+        let address = test_address();
+
+        assert!(matches!(
+            transport.get_contract_state(&address).await?,
+            RawContractState::NotExists
+        ));
+        assert!(transport
+            .get_transactions(&address, u64::MAX, 10)
+            .await?
+            .is_empty());
+        assert!(transport.get_latest_key_block().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn internal_transfer_creates_account() -> Result<()> {
+        let transport = emulated_transport(test_clock());
+        let dst = test_address();
+
+        let message = transfer(&dst, 1_000_000_000);
+        let message_hash = message.serialize()?.repr_hash();
+
+        transport.send_message(&message).await?;
+
+        let state = transport.get_contract_state(&dst).await?;
+        let transaction = transport
+            .get_dst_transaction(&message_hash)
+            .await?
+            .expect("transaction must exist");
+
+        assert!(state.brief().last_lt > transaction.data.lt);
+
+        let transactions = transport.get_transactions(&dst, u64::MAX, 10).await?;
+        assert_eq!(transactions, vec![transaction]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocks_contain_transactions() -> Result<()> {
+        const TIMEOUT: Duration = Duration::from_millis(10);
+
+        let transport = block_walking_transport(test_clock());
+        let address = test_address();
+
+        let latest = transport.get_latest_masterchain_block().await?;
+        let current = latest.find_block(&address).expect("shard must exist").id;
+        assert_eq!(
+            transport
+                .wait_for_next_block(&current, &address, TIMEOUT)
+                .await?,
+            None
+        );
+
+        let message = transfer(&address, 1_000_000_000);
+        transport.send_message(&message).await?;
+        transport.produce_blocks()?;
+
+        let next = transport
+            .wait_for_next_block(&current, &address, TIMEOUT)
+            .await?
+            .expect("block must be produced");
+        let block = transport.get_block(&BlockRef::Id(next)).await?;
+        let parsed = crate::core::utils::parse_block(&address, &Default::default(), &block)?;
+
+        let (_, new_transactions) = parsed.data.expect("account must be in the block");
+        let (transactions, _) = new_transactions.expect("transaction must be in the block");
+        let transaction = transport
+            .get_dst_transaction(&message.serialize()?.repr_hash())
+            .await?
+            .expect("transaction must exist");
+        assert_eq!(transactions, vec![transaction]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiting_ends_with_the_produced_block() -> Result<()> {
+        let transport = block_walking_transport(test_clock());
+        let address = test_address();
+
+        let latest = transport.get_latest_masterchain_block().await?;
+        let current = latest.find_block(&address).expect("shard must exist").id;
+
+        let wait = transport.wait_for_next_block(&current, &address, Duration::from_secs(60));
+        let produce = async {
+            tokio::task::yield_now().await;
+            transport.produce_blocks()
+        };
+        let (next, produced) = futures_util::future::join(wait, produce).await;
+        produced?;
+        assert!(next?.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::{NetworkCapabilities, ReliableBehavior};
+
+use super::models::*;
+use super::{Transport, TransportError, TransportInfo};
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FailoverTransportConfig {
+    /// How many backends must accept an external message for `send_message` to succeed.
+    /// The message is always broadcast to all backends. Default: `1`
+    pub send_quorum: usize,
+    /// Smoothing factor of the latency and error rate averages. Default: `0.2`
+    pub smoothing: f64,
+    /// How many milliseconds of latency a backend with 100% error rate is worth.
+    /// Default: `5000`
+    pub error_penalty_ms: f64,
+}
+
+impl Default for FailoverTransportConfig {
+    fn default() -> Self {
+        Self {
+            send_quorum: 1,
+            smoothing: 0.2,
+            error_penalty_ms: 5000.0,
+        }
+    }
+}
+
+/// Transport which routes each request to the healthiest of the backends
+/// and falls back to the others on failure.
+///
+/// Capability-dependent requests (blocks, key blocks) are routed only to the backends
+/// which support them, and "not supported" errors don't affect the backend health
+pub struct FailoverTransport {
+    backends: Vec<Backend>,
+    config: FailoverTransportConfig,
+}
+
+impl FailoverTransport {
+    pub fn new(backends: Vec<Arc<dyn Transport>>, config: FailoverTransportConfig) -> Result<Self> {
+        if backends.is_empty() {
+            return Err(FailoverTransportError::NoBackends.into());
+        }
+
+        Ok(Self {
+            backends: backends
+                .into_iter()
+                .map(|transport| Backend {
+                    transport,
+                    stats: Default::default(),
+                })
+                .collect(),
+            config,
+        })
+    }
+
+    /// Returns health stats of all backends in the order they were passed
+    pub fn backend_stats(&self) -> Vec<BackendStats> {
+        self.backends
+            .iter()
+            .map(|backend| *backend.stats.lock())
+            .collect()
+    }
+
+    /// Index of the backend which will be used for the next request
+    pub fn active_backend(&self) -> usize {
+        self.ordered_backends()[0]
+    }
+
+    fn ordered_backends(&self) -> Vec<usize> {
+        let penalty = self.config.error_penalty_ms;
+        let mut scores = self
+            .backends
+            .iter()
+            .enumerate()
+            .map(|(i, backend)| (i, backend.stats.lock().score(penalty)))
+            .collect::<Vec<_>>();
+        // NOTE: stable sort keeps the initial order for equally healthy backends
+        scores.sort_by(|(_, left), (_, right)| left.total_cmp(right));
+        scores.into_iter().map(|(i, _)| i).collect()
+    }
+
+    async fn call<'a, T, F, R>(&'a self, f: F) -> Result<T>
+    where
+        F: Fn(&'a dyn Transport) -> R,
+        R: Future<Output = Result<T>> + 'a,
+    {
+        self.call_backends(self.ordered_backends(), f).await
+    }
+
+    /// Same as [`FailoverTransport::call`], but skips the backends without the capability
+    async fn call_supported<'a, T, F, R>(
+        &'a self,
+        supported: fn(&TransportInfo) -> bool,
+        unsupported: TransportError,
+        f: F,
+    ) -> Result<T>
+    where
+        F: Fn(&'a dyn Transport) -> R,
+        R: Future<Output = Result<T>> + 'a,
+    {
+        let backends = self
+            .ordered_backends()
+            .into_iter()
+            .filter(|&i| supported(&self.backends[i].transport.info()))
+            .collect::<Vec<_>>();
+        if backends.is_empty() {
+            return Err(unsupported.into());
+        }
+
+        self.call_backends(backends, f).await
+    }
+
+    async fn call_backends<'a, T, F, R>(&'a self, backends: Vec<usize>, f: F) -> Result<T>
+    where
+        F: Fn(&'a dyn Transport) -> R,
+        R: Future<Output = Result<T>> + 'a,
+    {
+        let mut last_error = None;
+        for i in backends {
+            let backend = &self.backends[i];
+            match backend
+                .measure(f(backend.transport.as_ref()), &self.config)
+                .await
+            {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    log::warn!("Failover transport backend {i} failed: {e:?}");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        // NOTE: there is always at least one backend
+        Err(last_error.unwrap_or_else(|| FailoverTransportError::NoBackends.into()))
+    }
+}
+
+#[async_trait]
+impl Transport for FailoverTransport {
+    fn info(&self) -> TransportInfo {
+        let infos = self
+            .backends
+            .iter()
+            .map(|backend| backend.transport.info())
+            .collect::<Vec<_>>();
+
+        // NOTE: block queries are routed only to the backends which support them,
+        // so block walking is possible if at least one of them can do it
+        let reliable_behavior = if infos
+            .iter()
+            .any(|info| info.has_blocks && info.reliable_behavior == ReliableBehavior::BlockWalking)
+        {
+            ReliableBehavior::BlockWalking
+        } else {
+            ReliableBehavior::IntensivePolling
+        };
+
+        TransportInfo {
+            // NOTE: there is always at least one backend
+            max_transactions_per_fetch: infos
+                .iter()
+                .map(|info| info.max_transactions_per_fetch)
+                .min()
+                .unwrap_or_default(),
+            reliable_behavior,
+            has_key_blocks: infos.iter().any(|info| info.has_key_blocks),
+            has_blocks: infos.iter().any(|info| info.has_blocks),
+        }
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        let results =
+            join_all(self.backends.iter().map(|backend| {
+                backend.measure(backend.transport.send_message(message), &self.config)
+            }))
+            .await;
+
+        let required = self.config.send_quorum.clamp(1, self.backends.len());
+        let mut accepted = 0;
+        let mut last_error = None;
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(()) => accepted += 1,
+                Err(e) => {
+                    log::warn!("Failover transport backend {i} failed to send message: {e:?}");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if accepted >= required {
+            Ok(())
+        } else {
+            match last_error {
+                Some(e) if accepted == 0 => Err(e),
+                _ => Err(FailoverTransportError::QuorumNotReached { accepted, required }.into()),
+            }
+        }
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        self.call(|transport| transport.get_contract_state(address))
+            .await
+    }
+
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        self.call(|transport| transport.get_contract_states(addresses))
+            .await
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        self.call(|transport| transport.get_contract_state_proof(address))
+            .await
+    }
+
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        self.call(|transport| transport.get_contract_state_at(clock, address, transaction))
+            .await
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.call(|transport| transport.get_accounts_by_code_hash(code_hash, limit, continuation))
+            .await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        self.call(|transport| transport.get_transactions(address, from_lt, count))
+            .await
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        self.call(|transport| transport.get_transaction(id)).await
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        self.call(|transport| transport.get_dst_transaction(message_hash))
+            .await
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        self.call_supported(
+            |info| info.has_key_blocks,
+            TransportError::KeyBlocksNotSupported,
+            |transport| transport.get_latest_key_block(),
+        )
+        .await
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        self.call_supported(
+            |info| info.has_blocks,
+            TransportError::BlocksNotSupported,
+            |transport| transport.get_block(block),
+        )
+        .await
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.call_supported(
+            |info| info.has_blocks,
+            TransportError::BlocksNotSupported,
+            |transport| transport.get_latest_masterchain_block(),
+        )
+        .await
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.call_supported(
+            |info| info.has_blocks,
+            TransportError::BlocksNotSupported,
+            |transport| transport.wait_for_next_block(current, address, timeout),
+        )
+        .await
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.call(|transport| transport.get_capabilities(clock))
+            .await
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.call(|transport| transport.get_blockchain_config(clock, force))
+            .await
+    }
+}
+
+struct Backend {
+    transport: Arc<dyn Transport>,
+    stats: Mutex<BackendStats>,
+}
+
+impl Backend {
+    async fn measure<T, R>(&self, request: R, config: &FailoverTransportConfig) -> Result<T>
+    where
+        R: Future<Output = Result<T>>,
+    {
+        let started_at = now_ms_f64();
+        let result = request.await;
+        let elapsed = now_ms_f64() - started_at;
+
+        // NOTE: missing capabilities say nothing about the backend health
+        if !matches!(&result, Err(e) if is_capability_error(e)) {
+            self.stats
+                .lock()
+                .update(result.is_ok(), elapsed, config.smoothing);
+        }
+        result
+    }
+}
+
+fn is_capability_error(error: &anyhow::Error) -> bool {
+    if error.is::<TransportError>() {
+        return true;
+    }
+
+    #[cfg(feature = "adnl_transport")]
+    if let Some(super::adnl::AdnlTransportError::NotSupported) = error.downcast_ref() {
+        return true;
+    }
+
+    false
+}
+
+#[derive(Debug, Default, Copy, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackendStats {
+    /// Smoothed latency of the successful requests in milliseconds
+    pub latency_ms: f64,
+    /// Smoothed ratio of the failed requests, from `0.0` to `1.0`
+    pub error_rate: f64,
+    pub total_requests: u64,
+    pub failed_requests: u64,
+}
+
+impl BackendStats {
+    fn update(&mut self, success: bool, elapsed_ms: f64, smoothing: f64) {
+        let smoothing = smoothing.clamp(0.0, 1.0);
+        let first = self.total_requests == 0;
+
+        self.total_requests += 1;
+        if success {
+            self.latency_ms = if first || self.latency_ms == 0.0 {
+                elapsed_ms
+            } else {
+                smoothing.mul_add(elapsed_ms - self.latency_ms, self.latency_ms)
+            };
+        } else {
+            self.failed_requests += 1;
+        }
+
+        let outcome = if success { 0.0 } else { 1.0 };
+        self.error_rate = if first {
+            outcome
+        } else {
+            smoothing.mul_add(outcome - self.error_rate, self.error_rate)
+        };
+    }
+
+    fn score(&self, error_penalty_ms: f64) -> f64 {
+        self.error_rate.mul_add(error_penalty_ms, self.latency_ms)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FailoverTransportError {
+    #[error("No backends specified")]
+    NoBackends,
+    #[error("Message was accepted by {accepted} of {required} required backends")]
+    QuorumNotReached { accepted: usize, required: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{
+        emulated_transport, polling_info, test_address, StubTransport,
+    };
+
+    fn make_transport(config: FailoverTransportConfig) -> FailoverTransport {
+        let failing = StubTransport::unavailable(TransportInfo {
+            max_transactions_per_fetch: 16,
+            reliable_behavior: ReliableBehavior::BlockWalking,
+            has_key_blocks: true,
+            has_blocks: false,
+        });
+        let backends: Vec<Arc<dyn Transport>> =
+            vec![Arc::new(failing), emulated_transport(Arc::new(SimpleClock))];
+        FailoverTransport::new(backends, config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn switches_to_healthy_backend() -> Result<()> {
+        let transport = make_transport(Default::default());
+        assert_eq!(transport.active_backend(), 0);
+
+        // NOTE: the failing backend can't walk blocks without block queries
+        let info = transport.info();
+        assert_eq!(info.max_transactions_per_fetch, 16);
+        assert_eq!(info.reliable_behavior, ReliableBehavior::IntensivePolling);
+        assert!(info.has_key_blocks);
+
+        let address = test_address();
+        let state = transport.get_contract_state(&address).await?;
+        assert!(matches!(state, RawContractState::NotExists));
+
+        assert_eq!(transport.active_backend(), 1);
+        let info = transport.info();
+        assert_eq!(info.max_transactions_per_fetch, 16);
+        assert_eq!(info.reliable_behavior, ReliableBehavior::IntensivePolling);
+        assert!(info.has_key_blocks);
+
+        let stats = transport.backend_stats();
+        assert_eq!(stats[0].failed_requests, 1);
+        assert_eq!(stats[1].failed_requests, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn routes_block_queries_to_capable_backends() -> Result<()> {
+        let walker = Arc::new(StubTransport::new(TransportInfo {
+            reliable_behavior: ReliableBehavior::BlockWalking,
+            has_blocks: true,
+            ..polling_info()
+        }));
+        let backends: Vec<Arc<dyn Transport>> =
+            vec![emulated_transport(Arc::new(SimpleClock)), walker.clone()];
+        let transport = FailoverTransport::new(backends, Default::default())?;
+
+        let info = transport.info();
+        assert_eq!(info.reliable_behavior, ReliableBehavior::BlockWalking);
+        assert!(info.has_blocks);
+        assert!(!info.has_key_blocks);
+
+        transport.get_latest_masterchain_block().await?;
+        assert_eq!(walker.requests("get_latest_masterchain_block"), 1);
+
+        let error = transport.get_latest_key_block().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(TransportError::KeyBlocksNotSupported)
+        ));
+
+        // NOTE: neither backend supports proofs, which must not affect their health
+        assert!(transport
+            .get_contract_state_proof(&test_address())
+            .await
+            .is_err());
+
+        let stats = transport.backend_stats();
+        assert_eq!(stats[0].total_requests, 0);
+        assert_eq!(stats[1].total_requests, 1);
+        assert_eq!(stats[1].failed_requests, 0);
+        assert_eq!(transport.active_backend(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_backends() {
+        assert!(FailoverTransport::new(Vec::new(), Default::default()).is_err());
+    }
+}
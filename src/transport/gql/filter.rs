@@ -0,0 +1,247 @@
+use ton_block::MsgAddressInt;
+
+use super::queries::query_transactions::*;
+use crate::transport::models::RawTransaction;
+
+/// Transactions search filter for [`GqlTransport::search_transactions`].
+///
+/// All conditions are combined with AND. E.g. every transfer from `x` to `y`
+/// during the last week:
+///
+/// ```ignore
+/// let filter = TransactionsFilter::default()
+///     .account(&y)
+///     .from(&x)
+///     .since(now - 7 * 86400);
+/// ```
+///
+/// [`GqlTransport::search_transactions`]: super::GqlTransport::search_transactions
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct TransactionsFilter {
+    account: Option<MsgAddressInt>,
+    src: Option<MsgAddressInt>,
+    dst: Option<MsgAddressInt>,
+    since: Option<u32>,
+    until: Option<u32>,
+    aborted: Option<bool>,
+    exit_code: Option<i32>,
+    min_balance_delta: Option<i128>,
+    max_balance_delta: Option<i128>,
+    min_incoming_value: Option<u128>,
+    order: SortDirection,
+}
+
+impl TransactionsFilter {
+    /// Transactions of the specified account
+    pub fn account(mut self, address: &MsgAddressInt) -> Self {
+        self.account = Some(address.clone());
+        self
+    }
+
+    /// Transactions with an incoming message from the specified address
+    pub fn from(mut self, address: &MsgAddressInt) -> Self {
+        self.src = Some(address.clone());
+        self
+    }
+
+    /// Transactions with at least one outgoing message to the specified address
+    pub fn to(mut self, address: &MsgAddressInt) -> Self {
+        self.dst = Some(address.clone());
+        self
+    }
+
+    /// Transactions created at or after the specified unix time
+    pub fn since(mut self, utime: u32) -> Self {
+        self.since = Some(utime);
+        self
+    }
+
+    /// Transactions created at or before the specified unix time
+    pub fn until(mut self, utime: u32) -> Self {
+        self.until = Some(utime);
+        self
+    }
+
+    pub fn aborted(mut self, aborted: bool) -> Self {
+        self.aborted = Some(aborted);
+        self
+    }
+
+    /// Transactions with the specified compute phase exit code
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = Some(exit_code);
+        self
+    }
+
+    /// Transactions which changed the account balance by at least the specified amount
+    pub fn min_balance_delta(mut self, delta: i128) -> Self {
+        self.min_balance_delta = Some(delta);
+        self
+    }
+
+    /// Transactions which changed the account balance by at most the specified amount
+    pub fn max_balance_delta(mut self, delta: i128) -> Self {
+        self.max_balance_delta = Some(delta);
+        self
+    }
+
+    /// Transactions with an incoming message of at least the specified value
+    pub fn min_incoming_value(mut self, value: u128) -> Self {
+        self.min_incoming_value = Some(value);
+        self
+    }
+
+    /// Order of the results. Newest transactions are returned first by default
+    pub fn order(mut self, order: SortDirection) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub(super) fn build(&self, continuation: Option<&str>, limit: u8) -> Variables {
+        let mut in_message = MessageFilter::default();
+        if let Some(src) = &self.src {
+            in_message.src = Some(ScalarFilter::eq(src.to_string()));
+        }
+        if let Some(value) = self.min_incoming_value {
+            in_message.value = Some(ScalarFilter {
+                ge: Some(value.to_string()),
+                ..Default::default()
+            });
+        }
+
+        let now = ScalarFilter {
+            ge: self.since,
+            le: self.until,
+            ..Default::default()
+        };
+
+        let balance_delta = ScalarFilter {
+            ge: self.min_balance_delta.map(|delta| delta.to_string()),
+            le: self.max_balance_delta.map(|delta| delta.to_string()),
+            ..Default::default()
+        };
+
+        let chain_order = continuation.map(|continuation| match self.order {
+            SortDirection::Ascending => ScalarFilter {
+                gt: Some(continuation.to_owned()),
+                ..Default::default()
+            },
+            SortDirection::Descending => ScalarFilter {
+                lt: Some(continuation.to_owned()),
+                ..Default::default()
+            },
+        });
+
+        let filter = TransactionFilter {
+            account_addr: self
+                .account
+                .as_ref()
+                .map(|address| ScalarFilter::eq(address.to_string())),
+            now: (self.since.is_some() || self.until.is_some()).then_some(now),
+            aborted: self.aborted.map(ScalarFilter::eq),
+            compute: self.exit_code.map(|exit_code| ComputeFilter {
+                exit_code: ScalarFilter::eq(exit_code),
+            }),
+            balance_delta: (self.min_balance_delta.is_some() || self.max_balance_delta.is_some())
+                .then_some(balance_delta),
+            in_message: (in_message.src.is_some() || in_message.value.is_some())
+                .then_some(in_message),
+            out_messages: self.dst.as_ref().map(|dst| ArrayFilter {
+                any: MessageFilter {
+                    dst: Some(ScalarFilter::eq(dst.to_string())),
+                    ..Default::default()
+                },
+            }),
+            chain_order,
+        };
+
+        Variables {
+            filter,
+            order_by: vec![QueryOrderBy {
+                path: "chain_order",
+                direction: match self.order {
+                    SortDirection::Ascending => "ASC",
+                    SortDirection::Descending => "DESC",
+                },
+            }],
+            limit,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum SortDirection {
+    Ascending,
+    #[default]
+    Descending,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionsPage {
+    pub transactions: Vec<RawTransaction>,
+    /// Position after the last transaction in this page.
+    /// `None` if there are no more transactions
+    pub continuation: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn filter_is_serialized() -> anyhow::Result<()> {
+        let x = MsgAddressInt::from_str(
+            "0:1111111111111111111111111111111111111111111111111111111111111111",
+        )?;
+        let y = MsgAddressInt::from_str(
+            "0:2222222222222222222222222222222222222222222222222222222222222222",
+        )?;
+
+        let filter = TransactionsFilter::default()
+            .account(&y)
+            .from(&x)
+            .since(1650000000)
+            .aborted(false)
+            .min_incoming_value(1_000_000_000);
+
+        let variables = serde_json::to_value(filter.build(None, 50))?;
+        assert_eq!(
+            variables,
+            serde_json::json!({
+                "f": {
+                    "account_addr": { "eq": y.to_string() },
+                    "now": { "ge": 1650000000 },
+                    "aborted": { "eq": false },
+                    "in_message": {
+                        "src": { "eq": x.to_string() },
+                        "value": { "ge": "1000000000" },
+                    },
+                },
+                "o": [{ "path": "chain_order", "direction": "DESC" }],
+                "l": 50,
+            })
+        );
+
+        let filter = TransactionsFilter::default()
+            .to(&x)
+            .exit_code(0)
+            .min_balance_delta(-5)
+            .order(SortDirection::Ascending);
+
+        let variables = serde_json::to_value(filter.build(Some("abc"), 10))?;
+        assert_eq!(
+            variables["f"],
+            serde_json::json!({
+                "compute": { "exit_code": { "eq": 0 } },
+                "balance_delta": { "ge": "-5" },
+                "out_messages": { "any": { "dst": { "eq": x.to_string() } } },
+                "chain_order": { "gt": "abc" },
+            })
+        );
+        assert_eq!(variables["o"][0]["direction"], "ASC");
+
+        Ok(())
+    }
+}
@@ -4,21 +4,27 @@ use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
 use serde::Deserialize;
 use ton_block::{Account, Deserializable, Message, MsgAddressInt, Serializable};
 
-use nekoton_abi::{GenTimings, LastTransactionId};
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
 use nekoton_utils::*;
 
 use crate::core::models::{NetworkCapabilities, ReliableBehavior};
-use crate::external::{GqlConnection, GqlRequest};
+use crate::external::{GqlConnection, GqlRequest, GqlSubscriptionConnection};
 
 use self::queries::*;
 use super::models::*;
+use super::replay::{self, ReplayError};
 use super::utils::ConfigCache;
 use super::{Transport, TransportInfo};
 
-mod queries;
+pub use self::filter::{SortDirection, TransactionsFilter, TransactionsPage};
+
+mod filter;
+pub mod queries;
 
 pub struct GqlTransport {
     connection: Arc<dyn GqlConnection>,
@@ -35,6 +41,17 @@ impl GqlTransport {
         }
     }
 
+    /// Creates a transport with the custom config cache (e.g. persistent or bundled)
+    pub fn with_config_cache(
+        connection: Arc<dyn GqlConnection>,
+        config_cache: ConfigCache,
+    ) -> Self {
+        Self {
+            connection,
+            config_cache,
+        }
+    }
+
     async fn fetch<T>(&self, params: T::Variables) -> Result<T::ResponseData>
     where
         T: GqlQuery,
@@ -45,57 +62,54 @@ impl GqlTransport {
             .post(GqlRequest {
                 data: request_body,
                 long_query: T::LONG_QUERY,
+                broadcast: T::BROADCAST,
             })
             .await
             .map_err(api_failure)?;
 
-        #[derive(Deserialize)]
-        pub struct Response<T> {
-            pub data: Option<T>,
-        }
+        parse_response(&response)
+    }
 
-        match serde_json::from_str::<Response<T::ResponseData>>(&response) {
-            Ok(response) => response.data.ok_or_else(|| invalid_response().into()),
-            Err(e) => Err(api_failure(format!(
-                "Failed parsing api response: {e}. Response data: {response}"
-            ))
-            .into()),
-        }
+    /// Searches transactions matching the filter.
+    /// Pass the `continuation` from the previous page to fetch the next one
+    pub async fn search_transactions(
+        &self,
+        filter: &TransactionsFilter,
+        continuation: Option<&str>,
+        limit: u8,
+    ) -> Result<TransactionsPage> {
+        let limit = std::cmp::max(limit, 1);
+        let items = self
+            .fetch::<QueryTransactions>(filter.build(continuation, limit))
+            .await?
+            .transactions;
+
+        let continuation = match items.last() {
+            Some(last) if items.len() >= limit as usize => Some(last.chain_order.clone()),
+            _ => None,
+        };
+        let transactions = items
+            .iter()
+            .map(|transaction| parse_transaction(&transaction.boc))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(TransactionsPage {
+            transactions,
+            continuation,
+        })
     }
 
     pub async fn get_latest_block(&self, addr: &MsgAddressInt) -> Result<LatestBlock> {
         let workchain_id = addr.get_workchain_id();
 
-        let block = self
-            .fetch::<QueryLatestMasterchainBlock>(())
-            .await?
-            .blocks
-            .into_iter()
-            .next();
-
-        match block {
+        match self.fetch_latest_masterchain_block().await? {
             Some(block) => {
-                // Handle simple case when searched account is in masterchain
-                if workchain_id == -1 {
-                    return Ok(LatestBlock {
-                        id: block.id,
-                        end_lt: parse_lt(&block.end_lt)?,
-                        gen_utime: block.gen_utime as u32,
-                    });
-                }
-
-                // Find matching shard
-                for item in block.master.shard_hashes {
-                    if check_shard_match(item.workchain_id, &item.shard, addr)? {
-                        return Ok(LatestBlock {
-                            id: item.descr.root_hash,
-                            end_lt: parse_lt(&item.descr.end_lt)?,
-                            gen_utime: item.descr.gen_utime as u32,
-                        });
-                    }
-                }
-
-                Err(no_blocks_found().into())
+                let block = block.find_block(addr).ok_or_else(no_blocks_found)?;
+                Ok(LatestBlock {
+                    id: block.id.to_hex_string(),
+                    end_lt: block.end_lt,
+                    gen_utime: block.gen_utime,
+                })
             }
             // Node SE case (without masterchain and sharding)
             None => {
@@ -129,50 +143,148 @@ impl GqlTransport {
         }
     }
 
+    #[deprecated(note = "use `Transport::get_block` instead")]
     pub async fn get_block(&self, id: &str) -> Result<ton_block::Block> {
-        let blocks = self
-            .fetch::<QueryBlock>(query_block::Variables { id: id.to_owned() })
-            .await?
-            .blocks;
-        let boc = blocks.into_iter().next().ok_or_else(no_blocks_found)?.boc;
-
-        ton_block::Block::construct_from_base64(&boc)
-            .map_err(|_| NodeClientError::InvalidBlock.into())
+        let id = parse_block_id(id)?;
+        Transport::get_block(self, &BlockRef::Id(id)).await
     }
 
+    #[deprecated(note = "use `Transport::wait_for_next_block` instead")]
     pub async fn wait_for_next_block(
         &self,
         current: &str,
         addr: &MsgAddressInt,
         timeout: Duration,
     ) -> Result<String> {
-        let timeout_ms = timeout.as_secs_f64() * 1000.0;
+        let current = parse_block_id(current)?;
+        match Transport::wait_for_next_block(self, &current, addr, timeout).await? {
+            Some(block_id) => Ok(block_id.to_hex_string()),
+            None => Err(no_blocks_found().into()),
+        }
+    }
 
-        let blocks = self
-            .fetch::<QueryNextBlock>(query_next_block::Variables {
-                id: current.to_owned(),
-                timeout: timeout_ms,
-            })
+    async fn fetch_latest_masterchain_block(&self) -> Result<Option<LatestMasterchainBlock>> {
+        let block = match self
+            .fetch::<QueryLatestMasterchainBlock>(())
             .await?
-            .blocks;
-        let block = blocks.into_iter().next().ok_or_else(no_blocks_found)?;
+            .blocks
+            .into_iter()
+            .next()
+        {
+            Some(block) => block,
+            None => return Ok(None),
+        };
 
-        let block_id =
-            if block.after_split && !check_shard_match(block.workchain_id, &block.shard, addr)? {
-                let blocks = self
-                    .fetch::<QueryBlockAfterSplit>(query_block_after_split::Variables {
-                        block_id: block.id,
-                        prev_id: current.to_owned(),
-                        timeout: timeout_ms,
-                    })
-                    .await?
-                    .blocks;
-                blocks.into_iter().next().ok_or_else(no_blocks_found)?.id
-            } else {
-                block.id
-            };
+        let shards = block
+            .master
+            .shard_hashes
+            .into_iter()
+            .map(|item| -> Result<BlockSummary> {
+                Ok(BlockSummary {
+                    workchain_id: item.workchain_id,
+                    shard: u64::from_str_radix(&item.shard, 16)?,
+                    seqno: item.descr.seq_no as u32,
+                    id: parse_block_id(&item.descr.root_hash)?,
+                    end_lt: parse_lt(&item.descr.end_lt)?,
+                    gen_utime: item.descr.gen_utime as u32,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(LatestMasterchainBlock {
+            block: BlockSummary {
+                workchain_id: -1,
+                shard: ton_block::SHARD_FULL,
+                seqno: block.seq_no as u32,
+                id: parse_block_id(&block.id)?,
+                end_lt: parse_lt(&block.end_lt)?,
+                gen_utime: block.gen_utime as u32,
+            },
+            shards,
+        }))
+    }
+}
+
+/// Push-based updates for the specified accounts
+pub struct GqlSubscriptions {
+    connection: Arc<dyn GqlSubscriptionConnection>,
+}
 
-        Ok(block_id)
+impl GqlSubscriptions {
+    pub fn new(connection: Arc<dyn GqlSubscriptionConnection>) -> Self {
+        Self { connection }
+    }
+
+    /// Streams new blocks which contain transactions of the specified accounts.
+    /// Each block can be passed to `ContractSubscription::handle_block`
+    pub async fn subscribe_blocks(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<BoxStream<'static, Result<ton_block::Block>>> {
+        let stream = self
+            .subscribe::<SubscriptionAccountBlocks>(subscription_account_blocks::Variables {
+                addresses: addresses.iter().map(ToString::to_string).collect(),
+            })
+            .await?;
+
+        Ok(stream
+            .map(|data| {
+                ton_block::Block::construct_from_base64(&data?.blocks.boc)
+                    .map_err(|_| NodeClientError::InvalidBlock.into())
+            })
+            .boxed())
+    }
+
+    /// Streams new transactions of the specified accounts
+    pub async fn subscribe_transactions(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<BoxStream<'static, Result<RawTransaction>>> {
+        let stream = self
+            .subscribe::<SubscriptionAccountTransactions>(
+                subscription_account_transactions::Variables {
+                    addresses: addresses.iter().map(ToString::to_string).collect(),
+                },
+            )
+            .await?;
+
+        Ok(stream
+            .map(|data| {
+                let bytes = base64::decode(data?.transactions.boc)?;
+                let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())
+                    .map_err(|_| NodeClientError::InvalidTransaction)?;
+                let hash = cell.repr_hash();
+                Ok(RawTransaction {
+                    hash,
+                    data: ton_block::Transaction::construct_from_cell(cell)
+                        .map_err(|_| NodeClientError::InvalidTransaction)?,
+                })
+            })
+            .boxed())
+    }
+
+    async fn subscribe<T>(
+        &self,
+        params: T::Variables,
+    ) -> Result<BoxStream<'static, Result<T::ResponseData>>>
+    where
+        T: GqlQuery,
+        T::ResponseData: Send + 'static,
+    {
+        let request_body = serde_json::to_string(&T::build_query(&params)).trust_me();
+        let stream = self
+            .connection
+            .subscribe(GqlRequest {
+                data: request_body,
+                long_query: false,
+                broadcast: false,
+            })
+            .await
+            .map_err(api_failure)?;
+
+        Ok(stream
+            .map(|response| parse_response(&response.map_err(api_failure)?))
+            .boxed())
     }
 }
 
@@ -183,6 +295,8 @@ impl Transport for GqlTransport {
             max_transactions_per_fetch: 50,
             reliable_behavior: ReliableBehavior::BlockWalking,
             has_key_blocks: !self.connection.is_local(),
+            // NOTE: Node SE has no masterchain
+            has_blocks: !self.connection.is_local(),
         }
     }
 
@@ -236,6 +350,74 @@ impl Transport for GqlTransport {
         }
     }
 
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        let target = match transaction {
+            TransactionRef::Lt(lt) => self.get_transactions(address, *lt, 1).await?.pop(),
+            TransactionRef::Hash(hash) => match self.get_transaction(hash).await? {
+                Some(target) if target.data.account_addr == address.address() => Some(target),
+                _ => return Err(ReplayError::TransactionNotFound.into()),
+            },
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return Ok(RawContractState::NotExists),
+        };
+
+        // NOTE: only archive endpoints keep the accounts history, others return
+        // the current state, which is checked against the transaction state update.
+        // Endpoints without the history query are treated the same way
+        let account_state = match self
+            .fetch::<QueryAccountStateAfter>(query_account_state_after::Variables {
+                address: address.to_string(),
+                lt: target.data.lt.to_string(),
+            })
+            .await
+        {
+            Ok(data) => data.accounts.into_iter().next().and_then(|state| state.boc),
+            Err(e) => {
+                log::debug!(
+                    "Failed to fetch the account state after {}: {e:?}",
+                    target.data.lt
+                );
+                None
+            }
+        };
+
+        if let Some(boc) = account_state {
+            let cell = base64::decode(boc)
+                .ok()
+                .and_then(|bytes| ton_types::deserialize_tree_of_cells(&mut bytes.as_slice()).ok())
+                .ok_or(NodeClientError::InvalidAccountState)?;
+
+            if cell.repr_hash() == target.data.read_state_update()?.new_hash {
+                return match Account::construct_from_cell(cell) {
+                    Ok(Account::Account(account)) => {
+                        Ok(RawContractState::Exists(ExistingContract {
+                            account,
+                            timings: GenTimings::Known {
+                                gen_lt: target.data.lt,
+                                gen_utime: target.data.now,
+                            },
+                            last_transaction_id: LastTransactionId::Exact(TransactionId {
+                                lt: target.data.lt,
+                                hash: target.hash,
+                            }),
+                        }))
+                    }
+                    Ok(_) => Ok(RawContractState::NotExists),
+                    Err(_) => Err(NodeClientError::InvalidAccountState.into()),
+                };
+            }
+        }
+
+        replay::replay_contract_state(self, clock, address, transaction).await
+    }
+
     async fn get_accounts_by_code_hash(
         &self,
         code_hash: &ton_types::UInt256,
@@ -268,17 +450,7 @@ impl Transport for GqlTransport {
         .await?
         .transactions
         .into_iter()
-        .map(|transaction| {
-            let bytes = base64::decode(transaction.boc)?;
-            let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())
-                .map_err(|_| NodeClientError::InvalidTransaction)?;
-            let hash = cell.repr_hash();
-            Ok(RawTransaction {
-                hash,
-                data: ton_block::Transaction::construct_from_cell(cell)
-                    .map_err(|_| NodeClientError::InvalidTransaction)?,
-            })
-        })
+        .map(|transaction| parse_transaction(&transaction.boc))
         .collect()
     }
 
@@ -289,17 +461,7 @@ impl Transport for GqlTransport {
         .await?
         .transactions
         .into_iter()
-        .map(|transaction| {
-            let bytes = base64::decode(transaction.boc)?;
-            let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())
-                .map_err(|_| NodeClientError::InvalidTransaction)?;
-            let hash = cell.repr_hash();
-            Ok(RawTransaction {
-                hash,
-                data: ton_block::Transaction::construct_from_cell(cell)
-                    .map_err(|_| NodeClientError::InvalidTransaction)?,
-            })
-        })
+        .map(|transaction| parse_transaction(&transaction.boc))
         .next()
         .transpose()
     }
@@ -314,17 +476,7 @@ impl Transport for GqlTransport {
         .await?
         .transactions
         .into_iter()
-        .map(|transaction| {
-            let bytes = base64::decode(transaction.boc)?;
-            let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())
-                .map_err(|_| NodeClientError::InvalidTransaction)?;
-            let hash = cell.repr_hash();
-            Ok(RawTransaction {
-                hash,
-                data: ton_block::Transaction::construct_from_cell(cell)
-                    .map_err(|_| NodeClientError::InvalidTransaction)?,
-            })
-        })
+        .map(|transaction| parse_transaction(&transaction.boc))
         .next()
         .transpose()
     }
@@ -337,6 +489,83 @@ impl Transport for GqlTransport {
             .map_err(|_| NodeClientError::InvalidBlock.into())
     }
 
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        let blocks = match block {
+            BlockRef::Id(id) => {
+                self.fetch::<QueryBlock>(query_block::Variables {
+                    id: id.to_hex_string(),
+                })
+                .await?
+                .blocks
+            }
+            BlockRef::Seqno {
+                workchain_id,
+                shard,
+                seqno,
+            } => {
+                self.fetch::<QueryBlockBySeqno>(query_block_by_seqno::Variables {
+                    workchain: *workchain_id,
+                    shard: format!("{shard:016x}"),
+                    seqno: *seqno as f64,
+                })
+                .await?
+                .blocks
+            }
+        };
+        let boc = blocks.into_iter().next().ok_or_else(no_blocks_found)?.boc;
+
+        ton_block::Block::construct_from_base64(&boc)
+            .map_err(|_| NodeClientError::InvalidBlock.into())
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.fetch_latest_masterchain_block()
+            .await?
+            .ok_or_else(|| no_blocks_found().into())
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &ton_types::UInt256,
+        addr: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<ton_types::UInt256>> {
+        let timeout_ms = timeout.as_secs_f64() * 1000.0;
+        let current = current.to_hex_string();
+
+        let blocks = self
+            .fetch::<QueryNextBlock>(query_next_block::Variables {
+                id: current.clone(),
+                timeout: timeout_ms,
+            })
+            .await?
+            .blocks;
+        let block = match blocks.into_iter().next() {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
+        let block_id =
+            if block.after_split && !check_shard_match(block.workchain_id, &block.shard, addr)? {
+                let blocks = self
+                    .fetch::<QueryBlockAfterSplit>(query_block_after_split::Variables {
+                        block_id: block.id,
+                        prev_id: current,
+                        timeout: timeout_ms,
+                    })
+                    .await?
+                    .blocks;
+                match blocks.into_iter().next() {
+                    Some(block) => block.id,
+                    None => return Ok(None),
+                }
+            } else {
+                block.id
+            };
+
+        parse_block_id(&block_id).map(Some)
+    }
+
     async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
         let (capabilities, _) = self
             .config_cache
@@ -384,6 +613,36 @@ where
     }
 }
 
+fn parse_response<T>(response: &str) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    pub struct Response<T> {
+        pub data: Option<T>,
+    }
+
+    match serde_json::from_str::<Response<T>>(response) {
+        Ok(response) => response.data.ok_or_else(|| invalid_response().into()),
+        Err(e) => Err(api_failure(format!(
+            "Failed parsing api response: {e}. Response data: {response}"
+        ))
+        .into()),
+    }
+}
+
+fn parse_transaction(boc: &str) -> Result<RawTransaction> {
+    let bytes = base64::decode(boc)?;
+    let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())
+        .map_err(|_| NodeClientError::InvalidTransaction)?;
+    let hash = cell.repr_hash();
+    Ok(RawTransaction {
+        hash,
+        data: ton_block::Transaction::construct_from_cell(cell)
+            .map_err(|_| NodeClientError::InvalidTransaction)?,
+    })
+}
+
 fn parse_lt(lt: &str) -> Result<u64, std::num::ParseIntError> {
     match lt.strip_prefix("0x") {
         Some(lt) => u64::from_str_radix(lt, 16),
@@ -391,6 +650,10 @@ fn parse_lt(lt: &str) -> Result<u64, std::num::ParseIntError> {
     }
 }
 
+fn parse_block_id(id: &str) -> Result<ton_types::UInt256> {
+    ton_types::UInt256::from_str(id).map_err(|_| invalid_response().into())
+}
+
 fn invalid_response() -> NodeClientError {
     NodeClientError::InvalidResponse
 }
@@ -500,4 +763,90 @@ mod tests {
 
         transport.get_latest_key_block().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_search_transactions() -> Result<()> {
+        let transport = GqlTransport::new(Arc::new(reqwest::Client::new()));
+        let address = MsgAddressInt::from_str(
+            "-1:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+
+        let filter = TransactionsFilter::default()
+            .account(&address)
+            .aborted(false);
+        let first = transport.search_transactions(&filter, None, 5).await?;
+        assert_eq!(first.transactions.len(), 5);
+
+        let second = transport
+            .search_transactions(&filter, first.continuation.as_deref(), 5)
+            .await?;
+        assert!(second.transactions[0].data.lt < first.transactions[4].data.lt);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_historical_state() -> Result<()> {
+        let transport = GqlTransport::new(Arc::new(reqwest::Client::new()));
+        let address = MsgAddressInt::from_str(
+            "-1:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+
+        let transactions = transport.get_transactions(&address, u64::MAX, 2).await?;
+        let target = &transactions[1];
+
+        let state = transport
+            .get_contract_state_at(&SimpleClock, &address, &TransactionRef::Hash(target.hash))
+            .await?;
+        match state {
+            RawContractState::Exists(state) => {
+                assert_eq!(state.last_transaction_id.lt(), target.data.lt);
+                let account = Account::Account(state.account).serialize()?;
+                assert_eq!(
+                    account.repr_hash(),
+                    target.data.read_state_update()?.new_hash
+                );
+            }
+            RawContractState::NotExists => panic!("account not found"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_block_queries() -> Result<()> {
+        let transport = GqlTransport::new(Arc::new(reqwest::Client::new()));
+        let address = MsgAddressInt::from_str(
+            "0:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+
+        let latest = transport.get_latest_masterchain_block().await?;
+        let shard_block = latest.find_block(&address).expect("shard must exist");
+
+        let block = Transport::get_block(&transport, &BlockRef::Id(shard_block.id)).await?;
+        let info = block.info.read_struct()?;
+        assert_eq!(info.seq_no(), shard_block.seqno);
+
+        let block = Transport::get_block(
+            &transport,
+            &BlockRef::Seqno {
+                workchain_id: shard_block.workchain_id,
+                shard: shard_block.shard,
+                seqno: shard_block.seqno,
+            },
+        )
+        .await?;
+        assert_eq!(block.info.read_struct()?.seq_no(), shard_block.seqno);
+
+        let next = Transport::wait_for_next_block(
+            &transport,
+            &shard_block.id,
+            &address,
+            Duration::from_secs(30),
+        )
+        .await?;
+        assert!(next.is_some());
+
+        Ok(())
+    }
 }
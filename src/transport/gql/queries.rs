@@ -5,6 +5,7 @@ pub trait GqlQuery {
     type ResponseData: for<'de> serde::Deserialize<'de>;
 
     const LONG_QUERY: bool = false;
+    const BROADCAST: bool = false;
 
     fn build_query(variables: &'_ Self::Variables) -> QueryBody<'_>;
 }
@@ -16,14 +17,14 @@ pub struct QueryBody<'a> {
 }
 
 macro_rules! declare_queries {
-    ($($query:ident => $query_module:tt $((LONG_QUERY = $long_query:literal))?),*$(,)?) => {
+    ($($query:ident => $query_module:tt $(($const_name:ident = $const_value:literal))*),*$(,)?) => {
         $(pub struct $query;
 
         impl GqlQuery for $query {
             type Variables = $query_module::Variables;
             type ResponseData = $query_module::ResponseData;
 
-            $(const LONG_QUERY: bool = $long_query;)?
+            $(const $const_name: bool = $const_value;)*
 
             fn build_query(variables: &'_ Self::Variables) -> QueryBody<'_> {
                 QueryBody {
@@ -37,10 +38,13 @@ macro_rules! declare_queries {
 
 declare_queries! {
     QueryBlock => query_block,
+    QueryBlockBySeqno => query_block_by_seqno,
     QueryNextBlock => query_next_block (LONG_QUERY = true),
     QueryBlockAfterSplit => query_block_after_split (LONG_QUERY = true),
     QueryAccountState => query_account_state,
+    QueryAccountStateAfter => query_account_state_after,
     QueryAccountTransactions => query_account_transactions,
+    QueryTransactions => query_transactions,
     QueryTransaction => query_transaction,
     QueryDstTransaction => query_dst_transaction,
     QueryAccountsByCodeHash => query_accounts_by_code_hash,
@@ -48,7 +52,9 @@ declare_queries! {
     QueryLatestKeyBlock => query_latest_key_block,
     QueryNodeSeConditions => query_node_se_conditions,
     QueryNodeSeLatestBlock => query_node_se_latest_block,
-    MutationSendMessage => mutation_send_message,
+    MutationSendMessage => mutation_send_message (BROADCAST = true),
+    SubscriptionAccountBlocks => subscription_account_blocks,
+    SubscriptionAccountTransactions => subscription_account_transactions,
 }
 
 pub mod query_block {
@@ -72,6 +78,24 @@ pub mod query_block {
     }
 }
 
+pub mod query_block_by_seqno {
+    use super::*;
+
+    pub const QUERY: &str = "query($w:Int!,$s:String!,$n:Float!){blocks(filter:{workchain_id:{eq:$w},shard:{eq:$s},seq_no:{eq:$n}},limit:1){boc}}";
+
+    #[derive(Serialize)]
+    pub struct Variables {
+        #[serde(rename = "w")]
+        pub workchain: i32,
+        #[serde(rename = "s")]
+        pub shard: String,
+        #[serde(rename = "n")]
+        pub seqno: f64,
+    }
+
+    pub type ResponseData = super::query_block::ResponseData;
+}
+
 pub mod query_next_block {
     use super::*;
 
@@ -143,6 +167,22 @@ pub mod query_account_state {
     }
 }
 
+pub mod query_account_state_after {
+    use super::*;
+
+    pub const QUERY: &str = "query($a:String!,$lt:String!){accounts(filter:{id:{eq:$a},last_trans_lt:{gt:$lt}},orderBy:[{path:\"last_trans_lt\",direction:ASC}],limit:1){boc}}";
+
+    #[derive(Serialize)]
+    pub struct Variables {
+        #[serde(rename = "a")]
+        pub address: String,
+        #[serde(rename = "lt")]
+        pub lt: String,
+    }
+
+    pub type ResponseData = super::query_account_state::ResponseData;
+}
+
 pub mod query_account_transactions {
     use super::*;
 
@@ -169,6 +209,114 @@ pub mod query_account_transactions {
     }
 }
 
+pub mod query_transactions {
+    use super::*;
+
+    pub const QUERY: &str = "query($f:TransactionFilter,$o:[QueryOrderBy],$l:Int!){transactions(filter:$f,orderBy:$o,limit:$l){boc chain_order}}";
+
+    #[derive(Serialize)]
+    pub struct Variables {
+        #[serde(rename = "f")]
+        pub filter: TransactionFilter,
+        #[serde(rename = "o")]
+        pub order_by: Vec<QueryOrderBy>,
+        #[serde(rename = "l")]
+        pub limit: u8,
+    }
+
+    #[derive(Default, Serialize)]
+    pub struct TransactionFilter {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub account_addr: Option<ScalarFilter<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub now: Option<ScalarFilter<u32>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub aborted: Option<ScalarFilter<bool>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub compute: Option<ComputeFilter>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub balance_delta: Option<ScalarFilter<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub in_message: Option<MessageFilter>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub out_messages: Option<ArrayFilter<MessageFilter>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub chain_order: Option<ScalarFilter<String>>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ComputeFilter {
+        pub exit_code: ScalarFilter<i32>,
+    }
+
+    #[derive(Default, Serialize)]
+    pub struct MessageFilter {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub src: Option<ScalarFilter<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub dst: Option<ScalarFilter<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value: Option<ScalarFilter<String>>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ScalarFilter<T> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub eq: Option<T>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub gt: Option<T>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub lt: Option<T>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub ge: Option<T>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub le: Option<T>,
+    }
+
+    impl<T> Default for ScalarFilter<T> {
+        fn default() -> Self {
+            Self {
+                eq: None,
+                gt: None,
+                lt: None,
+                ge: None,
+                le: None,
+            }
+        }
+    }
+
+    impl<T> ScalarFilter<T> {
+        pub fn eq(value: T) -> Self {
+            Self {
+                eq: Some(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    pub struct ArrayFilter<T> {
+        pub any: T,
+    }
+
+    #[derive(Serialize)]
+    pub struct QueryOrderBy {
+        pub path: &'static str,
+        pub direction: &'static str,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ResponseData {
+        pub transactions: Vec<QueryTransactionsTransactions>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct QueryTransactionsTransactions {
+        pub boc: String,
+        pub chain_order: String,
+    }
+}
+
 pub mod query_transaction {
     use super::*;
 
@@ -242,7 +390,7 @@ pub mod query_accounts_by_code_hash {
 pub mod query_latest_masterchain_block {
     use super::*;
 
-    pub const QUERY: &str = "query{blocks(filter:{workchain_id:{eq:-1}},orderBy:[{path:\"seq_no\",direction:DESC}],limit:1){id gen_utime end_lt master{shard_hashes{workchain_id shard descr{root_hash gen_utime end_lt}}}}}";
+    pub const QUERY: &str = "query{blocks(filter:{workchain_id:{eq:-1}},orderBy:[{path:\"seq_no\",direction:DESC}],limit:1){id seq_no gen_utime end_lt master{shard_hashes{workchain_id shard descr{seq_no root_hash gen_utime end_lt}}}}}";
 
     pub type Variables = ();
 
@@ -254,6 +402,7 @@ pub mod query_latest_masterchain_block {
     #[derive(Deserialize)]
     pub struct QueryLatestMasterchainBlockBlocks {
         pub id: String,
+        pub seq_no: f64,
         pub gen_utime: f64,
         pub end_lt: String,
         pub master: QueryLatestMasterchainBlockBlocksMaster,
@@ -273,6 +422,7 @@ pub mod query_latest_masterchain_block {
 
     #[derive(Deserialize)]
     pub struct QueryLatestMasterchainBlockBlocksMasterShardHashesDescr {
+        pub seq_no: f64,
         pub root_hash: String,
         pub gen_utime: f64,
         pub end_lt: String,
@@ -359,3 +509,48 @@ pub mod mutation_send_message {
     #[derive(Deserialize)]
     pub struct ResponseData {}
 }
+
+pub mod subscription_account_blocks {
+    use super::*;
+
+    pub const QUERY: &str = "subscription($a:[String]){blocks(filter:{account_blocks:{any:{account_addr:{in:$a}}}}){boc}}";
+
+    #[derive(Serialize)]
+    pub struct Variables {
+        #[serde(rename = "a")]
+        pub addresses: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ResponseData {
+        pub blocks: SubscriptionAccountBlocksBlocks,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SubscriptionAccountBlocksBlocks {
+        pub boc: String,
+    }
+}
+
+pub mod subscription_account_transactions {
+    use super::*;
+
+    pub const QUERY: &str =
+        "subscription($a:[String]){transactions(filter:{account_addr:{in:$a}}){boc}}";
+
+    #[derive(Serialize)]
+    pub struct Variables {
+        #[serde(rename = "a")]
+        pub addresses: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ResponseData {
+        pub transactions: SubscriptionAccountTransactionsTransactions,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SubscriptionAccountTransactionsTransactions {
+        pub boc: String,
+    }
+}
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -9,7 +10,7 @@ use nekoton_utils::*;
 use crate::core::models::{NetworkCapabilities, ReliableBehavior};
 use crate::external::{self, JrpcConnection};
 
-use super::models::{RawContractState, RawTransaction};
+use super::models::{BlockRef, LatestMasterchainBlock, RawContractState, RawTransaction};
 use super::utils::*;
 use super::{Transport, TransportInfo};
 
@@ -29,6 +30,17 @@ impl JrpcTransport {
             config_cache: ConfigCache::new(false),
         }
     }
+
+    /// Creates a transport with the custom config cache (e.g. persistent or bundled)
+    pub fn with_config_cache(
+        connection: Arc<dyn JrpcConnection>,
+        config_cache: ConfigCache,
+    ) -> Self {
+        Self {
+            connection,
+            config_cache,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -38,6 +50,7 @@ impl Transport for JrpcTransport {
             max_transactions_per_fetch: 50,
             reliable_behavior: ReliableBehavior::IntensivePolling,
             has_key_blocks: true,
+            has_blocks: true,
         }
     }
 
@@ -45,6 +58,7 @@ impl Transport for JrpcTransport {
         let req = external::JrpcRequest {
             data: make_jrpc_request("sendMessage", &SendMessage { message }),
             requires_db: false,
+            broadcast: true,
         };
         self.connection.post(req).await.map(|_| ())
     }
@@ -53,12 +67,45 @@ impl Transport for JrpcTransport {
         let req = external::JrpcRequest {
             data: make_jrpc_request("getContractState", &GetContractState { address }),
             requires_db: false,
+            broadcast: false,
         };
         let data = self.connection.post(req).await?;
         let response = tiny_jsonrpc::parse_response::<RawContractState>(&data)?;
         Ok(response)
     }
 
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        if !self.connection.supports_batch() {
+            let mut states = Vec::with_capacity(addresses.len());
+            for address in addresses {
+                states.push(self.get_contract_state(address).await?);
+            }
+            return Ok(states);
+        }
+
+        let mut states = Vec::with_capacity(addresses.len());
+        for chunk in addresses.chunks(MAX_BATCH_SIZE) {
+            let params = chunk
+                .iter()
+                .map(|address| GetContractState { address })
+                .collect::<Vec<_>>();
+            let req = external::JrpcRequest {
+                data: make_jrpc_batch_request("getContractState", &params),
+                requires_db: false,
+                broadcast: false,
+            };
+            let data = self.connection.post(req).await?;
+            states.extend(parse_jrpc_batch_response::<RawContractState>(
+                &data,
+                chunk.len(),
+            )?);
+        }
+        Ok(states)
+    }
+
     async fn get_accounts_by_code_hash(
         &self,
         code_hash: &ton_types::UInt256,
@@ -75,6 +122,7 @@ impl Transport for JrpcTransport {
                 },
             ),
             requires_db: true,
+            broadcast: false,
         };
         let data = self.connection.post(req).await?;
 
@@ -103,6 +151,7 @@ impl Transport for JrpcTransport {
                 },
             ),
             requires_db: true,
+            broadcast: false,
         };
         let response = self.connection.post(req).await?;
         let data: Vec<String> = tiny_jsonrpc::parse_response(&response)?;
@@ -113,6 +162,7 @@ impl Transport for JrpcTransport {
         let req = external::JrpcRequest {
             data: make_jrpc_request("getTransaction", &GetTransaction { id }),
             requires_db: true,
+            broadcast: false,
         };
         let response = self.connection.post(req).await?;
         let data: Option<String> = tiny_jsonrpc::parse_response(&response)?;
@@ -126,6 +176,7 @@ impl Transport for JrpcTransport {
         let req = external::JrpcRequest {
             data: make_jrpc_request("getDstTransaction", &GetDstTransaction { message_hash }),
             requires_db: true,
+            broadcast: false,
         };
         let response = self.connection.post(req).await?;
         let data: Option<String> = tiny_jsonrpc::parse_response(&response)?;
@@ -136,6 +187,7 @@ impl Transport for JrpcTransport {
         let req = external::JrpcRequest {
             data: make_jrpc_request("getLatestKeyBlock", &()),
             requires_db: true,
+            broadcast: false,
         };
         self.connection
             .post(req)
@@ -144,6 +196,67 @@ impl Transport for JrpcTransport {
             .map(|block: GetBlockResponse| block.block)
     }
 
+    async fn get_block(&self, block: &BlockRef) -> Result<Block> {
+        let data = match block {
+            BlockRef::Id(id) => make_jrpc_request("getBlock", &GetBlockById { id }),
+            BlockRef::Seqno {
+                workchain_id,
+                shard,
+                seqno,
+            } => make_jrpc_request(
+                "getBlock",
+                &GetBlockBySeqno {
+                    workchain_id: *workchain_id,
+                    shard: format!("{shard:016x}"),
+                    seqno: *seqno,
+                },
+            ),
+        };
+        let req = external::JrpcRequest {
+            data,
+            requires_db: true,
+            broadcast: false,
+        };
+        let response = self.connection.post(req).await?;
+        match tiny_jsonrpc::parse_response::<Option<GetBlockResponse>>(&response)? {
+            Some(block) => Ok(block.block),
+            None => Err(JrpcTransportError::BlockNotFound.into()),
+        }
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        let req = external::JrpcRequest {
+            data: make_jrpc_request("getLatestMasterchainBlock", &()),
+            requires_db: true,
+            broadcast: false,
+        };
+        let response = self.connection.post(req).await?;
+        Ok(tiny_jsonrpc::parse_response(&response)?)
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &ton_types::UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<ton_types::UInt256>> {
+        let req = external::JrpcRequest {
+            data: make_jrpc_request(
+                "waitForNextBlock",
+                &WaitForNextBlock {
+                    current,
+                    account: address,
+                    timeout: timeout.as_millis() as u64,
+                },
+            ),
+            requires_db: true,
+            broadcast: false,
+        };
+        let response = self.connection.post(req).await?;
+        let data: Option<BlockIdResponse> = tiny_jsonrpc::parse_response(&response)?;
+        Ok(data.map(|block| block.id))
+    }
+
     async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
         let (capabilities, _) = self
             .config_cache
@@ -169,10 +282,57 @@ pub fn make_jrpc_request<S>(method: &str, params: &S) -> String
 where
     S: Serialize,
 {
-    serde_json::to_string(&JrpcRequest { method, params }).trust_me()
+    serde_json::to_string(&JrpcRequest {
+        id: 1,
+        method,
+        params,
+    })
+    .trust_me()
+}
+
+/// Builds a JSON-RPC 2.0 batch with one call per params item.
+/// Call ids are indices of the items
+pub fn make_jrpc_batch_request<S>(method: &str, params: &[S]) -> String
+where
+    S: Serialize,
+{
+    let batch = params
+        .iter()
+        .enumerate()
+        .map(|(id, params)| JrpcRequest { id, method, params })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&batch).trust_me()
+}
+
+/// Parses a response to the request built with [`make_jrpc_batch_request`]
+pub fn parse_jrpc_batch_response<T>(response: &str, len: usize) -> Result<Vec<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let items = serde_json::from_str::<Vec<serde_json::Value>>(response)?;
+
+    let mut result = std::iter::repeat_with(|| None)
+        .take(len)
+        .collect::<Vec<Option<T>>>();
+    for item in items {
+        let id = item
+            .get("id")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or(JrpcTransportError::InvalidBatchResponse)? as usize;
+        let slot = result
+            .get_mut(id)
+            .ok_or(JrpcTransportError::InvalidBatchResponse)?;
+        *slot = Some(tiny_jsonrpc::parse_response(&item.to_string())?);
+    }
+
+    result
+        .into_iter()
+        .map(|item| item.ok_or_else(|| JrpcTransportError::InvalidBatchResponse.into()))
+        .collect()
 }
 
 pub struct JrpcRequest<'a, T> {
+    id: usize,
     method: &'a str,
     params: &'a T,
 }
@@ -189,13 +349,15 @@ where
 
         let mut ser = serializer.serialize_struct("JrpcRequest", 4)?;
         ser.serialize_field("jsonrpc", "2.0")?;
-        ser.serialize_field("id", &1)?;
+        ser.serialize_field("id", &self.id)?;
         ser.serialize_field("method", self.method)?;
         ser.serialize_field("params", self.params)?;
         ser.end()
     }
 }
 
+const MAX_BATCH_SIZE: usize = 100;
+
 fn decode_raw_transaction(boc: &str) -> Result<RawTransaction> {
     let bytes = base64::decode(boc)?;
     let cell = ton_types::deserialize_tree_of_cells(&mut bytes.as_slice())?;
@@ -204,6 +366,14 @@ fn decode_raw_transaction(boc: &str) -> Result<RawTransaction> {
     Ok(RawTransaction { hash, data })
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum JrpcTransportError {
+    #[error("Invalid batch response")]
+    InvalidBatchResponse,
+    #[error("Block not found")]
+    BlockNotFound,
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -229,6 +399,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn batch_response_order() -> Result<()> {
+        let request = make_jrpc_batch_request("getTransaction", &[1u32, 2]);
+        assert_eq!(
+            request,
+            r#"[{"jsonrpc":"2.0","id":0,"method":"getTransaction","params":1},{"jsonrpc":"2.0","id":1,"method":"getTransaction","params":2}]"#
+        );
+
+        let response =
+            r#"[{"jsonrpc":"2.0","id":1,"result":20},{"jsonrpc":"2.0","id":0,"result":10}]"#;
+        assert_eq!(parse_jrpc_batch_response::<u32>(response, 2)?, vec![10, 20]);
+
+        let response = r#"[{"jsonrpc":"2.0","id":0,"result":10}]"#;
+        assert!(parse_jrpc_batch_response::<u32>(response, 2).is_err());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_transactions_stream() -> Result<()> {
         let transport = JrpcTransport::new(Arc::new(reqwest::Client::new()));
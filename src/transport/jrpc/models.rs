@@ -52,6 +52,41 @@ pub struct GetDstTransaction<'a> {
     pub message_hash: &'a ton_types::UInt256,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBlockById<'a> {
+    #[serde(with = "serde_uint256")]
+    pub id: &'a ton_types::UInt256,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBlockBySeqno {
+    pub workchain_id: i32,
+    /// Shard prefix with tag in hex
+    pub shard: String,
+    pub seqno: u32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WaitForNextBlock<'a> {
+    #[serde(with = "serde_uint256")]
+    pub current: &'a ton_types::UInt256,
+
+    #[serde(with = "serde_address")]
+    pub account: &'a ton_block::MsgAddressInt,
+
+    /// Timeout in milliseconds
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockIdResponse {
+    #[serde(with = "serde_uint256")]
+    pub id: ton_types::UInt256,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetBlockResponse {
     #[serde(with = "serde_ton_block")]
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::NetworkCapabilities;
+#[cfg(feature = "gql_transport")]
+use crate::external::{GqlConnection, GqlRequest};
+#[cfg(feature = "jrpc_transport")]
+use crate::external::{JrpcConnection, JrpcRequest};
+#[cfg(feature = "proto_transport")]
+use crate::external::{ProtoConnection, ProtoRequest};
+
+use super::models::*;
+use super::{Transport, TransportInfo};
+
+/// Transport call report
+#[derive(Debug)]
+pub struct TransportEvent<'a> {
+    /// Name of the `Transport` method, or `gql_post`, `jrpc_post` and `proto_post`
+    /// for the requests reported by [`InstrumentedConnection`]
+    pub method: &'static str,
+    /// Time spent waiting for the response
+    pub duration: Duration,
+    /// Number of decoded items in the response (e.g. transactions or addresses).
+    /// This is not a size, raw responses are always counted as a single item
+    pub response_items: usize,
+    /// Size of the raw response in bytes. Only known at the connection level,
+    /// so it is always `None` for the events of [`InstrumentedTransport`]
+    pub response_bytes: Option<usize>,
+    /// Request error, if any
+    pub error: Option<&'a anyhow::Error>,
+}
+
+pub trait TransportObserver: Send + Sync {
+    fn on_event(&self, event: &TransportEvent<'_>);
+}
+
+impl<F> TransportObserver for F
+where
+    F: Fn(&TransportEvent<'_>) + Send + Sync,
+{
+    fn on_event(&self, event: &TransportEvent<'_>) {
+        self(event)
+    }
+}
+
+/// Transport decorator which reports every call to the observer
+pub struct InstrumentedTransport {
+    inner: Arc<dyn Transport>,
+    observer: Arc<dyn TransportObserver>,
+}
+
+impl InstrumentedTransport {
+    pub fn new(inner: Arc<dyn Transport>, observer: Arc<dyn TransportObserver>) -> Self {
+        Self { inner, observer }
+    }
+
+    async fn observe<T, R>(
+        &self,
+        method: &'static str,
+        request: R,
+        response_items: fn(&T) -> usize,
+    ) -> Result<T>
+    where
+        R: Future<Output = Result<T>>,
+    {
+        observe(self.observer.as_ref(), method, request, |response| {
+            (response_items(response), None)
+        })
+        .await
+    }
+}
+
+/// Connection decorator which reports every request to the observer.
+///
+/// Unlike [`InstrumentedTransport`], it sees the raw responses and reports their size
+pub struct InstrumentedConnection<C: ?Sized> {
+    inner: Arc<C>,
+    observer: Arc<dyn TransportObserver>,
+}
+
+impl<C: ?Sized> InstrumentedConnection<C> {
+    pub fn new(inner: Arc<C>, observer: Arc<dyn TransportObserver>) -> Self {
+        Self { inner, observer }
+    }
+}
+
+#[cfg(feature = "gql_transport")]
+#[async_trait]
+impl GqlConnection for InstrumentedConnection<dyn GqlConnection> {
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+
+    async fn post(&self, req: GqlRequest) -> Result<String> {
+        observe(
+            self.observer.as_ref(),
+            "gql_post",
+            self.inner.post(req),
+            |response: &String| (1, Some(response.len())),
+        )
+        .await
+    }
+}
+
+#[cfg(feature = "jrpc_transport")]
+#[async_trait]
+impl JrpcConnection for InstrumentedConnection<dyn JrpcConnection> {
+    async fn post(&self, req: JrpcRequest) -> Result<String> {
+        observe(
+            self.observer.as_ref(),
+            "jrpc_post",
+            self.inner.post(req),
+            |response: &String| (1, Some(response.len())),
+        )
+        .await
+    }
+
+    fn supports_batch(&self) -> bool {
+        self.inner.supports_batch()
+    }
+}
+
+#[cfg(feature = "proto_transport")]
+#[async_trait]
+impl ProtoConnection for InstrumentedConnection<dyn ProtoConnection> {
+    async fn post(&self, req: ProtoRequest) -> Result<Vec<u8>> {
+        observe(
+            self.observer.as_ref(),
+            "proto_post",
+            self.inner.post(req),
+            |response: &Vec<u8>| (1, Some(response.len())),
+        )
+        .await
+    }
+}
+
+async fn observe<T, R, F>(
+    observer: &dyn TransportObserver,
+    method: &'static str,
+    request: R,
+    measure: F,
+) -> Result<T>
+where
+    R: Future<Output = Result<T>>,
+    F: FnOnce(&T) -> (usize, Option<usize>),
+{
+    let started_at = now_ms_f64();
+    let result = request.await;
+    let elapsed_ms = (now_ms_f64() - started_at).max(0.0);
+
+    let ((response_items, response_bytes), error) = match &result {
+        Ok(response) => (measure(response), None),
+        Err(e) => ((0, None), Some(e)),
+    };
+
+    observer.on_event(&TransportEvent {
+        method,
+        duration: Duration::from_secs_f64(elapsed_ms / 1000.0),
+        response_items,
+        response_bytes,
+        error,
+    });
+
+    result
+}
+
+#[async_trait]
+impl Transport for InstrumentedTransport {
+    fn info(&self) -> TransportInfo {
+        self.inner.info()
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        self.observe("send_message", self.inner.send_message(message), |_| 0)
+            .await
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        self.observe(
+            "get_contract_state",
+            self.inner.get_contract_state(address),
+            |state| matches!(state, RawContractState::Exists(_)) as usize,
+        )
+        .await
+    }
+
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        self.observe(
+            "get_contract_states",
+            self.inner.get_contract_states(addresses),
+            Vec::len,
+        )
+        .await
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        self.observe(
+            "get_contract_state_proof",
+            self.inner.get_contract_state_proof(address),
+            |_| 1,
+        )
+        .await
+    }
+
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        self.observe(
+            "get_contract_state_at",
+            self.inner
+                .get_contract_state_at(clock, address, transaction),
+            |_| 1,
+        )
+        .await
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.observe(
+            "get_accounts_by_code_hash",
+            self.inner
+                .get_accounts_by_code_hash(code_hash, limit, continuation),
+            Vec::len,
+        )
+        .await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        self.observe(
+            "get_transactions",
+            self.inner.get_transactions(address, from_lt, count),
+            Vec::len,
+        )
+        .await
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        self.observe(
+            "get_transaction",
+            self.inner.get_transaction(id),
+            |transaction| transaction.is_some() as usize,
+        )
+        .await
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        self.observe(
+            "get_dst_transaction",
+            self.inner.get_dst_transaction(message_hash),
+            |transaction| transaction.is_some() as usize,
+        )
+        .await
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        self.observe(
+            "get_latest_key_block",
+            self.inner.get_latest_key_block(),
+            |_| 1,
+        )
+        .await
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        self.observe("get_block", self.inner.get_block(block), |_| 1)
+            .await
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.observe(
+            "get_latest_masterchain_block",
+            self.inner.get_latest_masterchain_block(),
+            |_| 1,
+        )
+        .await
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.observe(
+            "wait_for_next_block",
+            self.inner.wait_for_next_block(current, address, timeout),
+            |block_id| block_id.is_some() as usize,
+        )
+        .await
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.observe(
+            "get_capabilities",
+            self.inner.get_capabilities(clock),
+            |_| 1,
+        )
+        .await
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.observe(
+            "get_blockchain_config",
+            self.inner.get_blockchain_config(clock, force),
+            |_| 1,
+        )
+        .await
+    }
+}
+
+/// Observer which writes all events into the log
+#[derive(Debug, Copy, Clone)]
+pub struct LogObserver {
+    /// Level of the successful requests. Errors are always logged as warnings
+    pub level: log::Level,
+}
+
+impl Default for LogObserver {
+    fn default() -> Self {
+        Self {
+            level: log::Level::Debug,
+        }
+    }
+}
+
+impl TransportObserver for LogObserver {
+    fn on_event(&self, event: &TransportEvent<'_>) {
+        match event.error {
+            Some(e) => log::warn!(
+                "Transport request {} failed after {:?}: {:?}",
+                event.method,
+                event.duration,
+                e
+            ),
+            None => match event.response_bytes {
+                Some(bytes) => log::log!(
+                    self.level,
+                    "Transport request {} finished in {:?} ({} bytes)",
+                    event.method,
+                    event.duration,
+                    bytes
+                ),
+                None => log::log!(
+                    self.level,
+                    "Transport request {} finished in {:?} ({} items)",
+                    event.method,
+                    event.duration,
+                    event.response_items
+                ),
+            },
+        }
+    }
+}
+
+/// Observer which accumulates per-method statistics
+#[derive(Default)]
+pub struct TransportCounters {
+    methods: Mutex<HashMap<&'static str, MethodCounters>>,
+}
+
+impl TransportCounters {
+    /// Returns accumulated counters for each called method
+    pub fn snapshot(&self) -> HashMap<&'static str, MethodCounters> {
+        self.methods.lock().clone()
+    }
+
+    pub fn reset(&self) {
+        self.methods.lock().clear();
+    }
+}
+
+impl TransportObserver for TransportCounters {
+    fn on_event(&self, event: &TransportEvent<'_>) {
+        let mut methods = self.methods.lock();
+        let counters = methods.entry(event.method).or_default();
+        counters.requests += 1;
+        if event.error.is_some() {
+            counters.errors += 1;
+        }
+        counters.response_items += event.response_items as u64;
+        counters.response_bytes += event.response_bytes.unwrap_or_default() as u64;
+        counters.total_duration += event.duration;
+        counters.max_duration = std::cmp::max(counters.max_duration, event.duration);
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct MethodCounters {
+    pub requests: u64,
+    pub errors: u64,
+    pub response_items: u64,
+    /// Total size of the raw responses, only counted by [`InstrumentedConnection`]
+    pub response_bytes: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{emulated_transport, test_address};
+
+    #[tokio::test]
+    async fn counters_are_updated() -> Result<()> {
+        let counters = Arc::new(TransportCounters::default());
+        let transport =
+            InstrumentedTransport::new(emulated_transport(Arc::new(SimpleClock)), counters.clone());
+
+        let address = test_address();
+        transport.get_contract_state(&address).await?;
+        transport.get_transactions(&address, u64::MAX, 10).await?;
+        assert!(transport.get_latest_key_block().await.is_err());
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot["get_contract_state"].requests, 1);
+        assert_eq!(snapshot["get_contract_state"].response_items, 0);
+        assert_eq!(snapshot["get_transactions"].requests, 1);
+        assert_eq!(snapshot["get_latest_key_block"].errors, 1);
+        assert_eq!(snapshot["get_transactions"].response_bytes, 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "gql_transport")]
+    #[tokio::test]
+    async fn connection_reports_response_size() -> Result<()> {
+        struct StaticConnection;
+
+        #[async_trait]
+        impl GqlConnection for StaticConnection {
+            fn is_local(&self) -> bool {
+                false
+            }
+
+            async fn post(&self, _: GqlRequest) -> Result<String> {
+                Ok(r#"{"data":{}}"#.to_owned())
+            }
+        }
+
+        let counters = Arc::new(TransportCounters::default());
+        let connection: InstrumentedConnection<dyn GqlConnection> =
+            InstrumentedConnection::new(Arc::new(StaticConnection), counters.clone());
+
+        connection
+            .post(GqlRequest::new("{}".to_owned(), false))
+            .await?;
+        connection
+            .post(GqlRequest::new("{}".to_owned(), false))
+            .await?;
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot["gql_post"].requests, 2);
+        assert_eq!(snapshot["gql_post"].response_items, 2);
+        assert_eq!(snapshot["gql_post"].response_bytes, 22);
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use async_trait::async_trait;
 use nekoton_utils::Clock;
@@ -8,14 +10,33 @@ use crate::core::models::{NetworkCapabilities, ReliableBehavior};
 
 use self::models::*;
 
+#[cfg(feature = "adnl_transport")]
+pub mod adnl;
+pub mod caching;
+pub mod clock_sync;
+pub mod emulated;
+pub mod failover;
 #[cfg(feature = "gql_transport")]
 pub mod gql;
 #[cfg(feature = "jrpc_transport")]
 pub mod jrpc;
 
+pub mod middleware;
 pub mod models;
-#[cfg(any(feature = "gql_transport", feature = "jrpc_transport",))]
-mod utils;
+pub mod paging;
+pub mod proofs;
+#[cfg(feature = "proto_transport")]
+pub mod proto;
+pub mod recording;
+pub mod replay;
+#[cfg(test)]
+pub(crate) mod test_utils;
+#[cfg(any(
+    feature = "gql_transport",
+    feature = "jrpc_transport",
+    feature = "proto_transport"
+))]
+pub mod utils;
 
 #[async_trait]
 pub trait Transport: Send + Sync {
@@ -25,6 +46,42 @@ pub trait Transport: Send + Sync {
 
     async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState>;
 
+    /// Fetches states of multiple contracts, in the same order as addresses.
+    /// Default implementation requests them one by one
+    async fn get_contract_states(
+        &self,
+        addresses: &[MsgAddressInt],
+    ) -> Result<Vec<RawContractState>> {
+        let mut states = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            states.push(self.get_contract_state(address).await?);
+        }
+        Ok(states)
+    }
+
+    /// Fetches the account state with the proofs of its inclusion into the shard state.
+    /// Only the liteserver provides them. See [`proofs::verify_contract_state_proof`]
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        let _ = address;
+        Err(TransportError::ProofsNotSupported.into())
+    }
+
+    /// Fetches the account state as it was right after the specified transaction.
+    /// Default implementation rebuilds it from the account history with the current
+    /// config, so the result is exact only while the config is unchanged since then.
+    /// See [`replay::replay_contract_state`]
+    async fn get_contract_state_at(
+        &self,
+        clock: &dyn Clock,
+        address: &MsgAddressInt,
+        transaction: &TransactionRef,
+    ) -> Result<RawContractState> {
+        replay::replay_contract_state(self, clock, address, transaction).await
+    }
+
     async fn get_accounts_by_code_hash(
         &self,
         code_hash: &ton_types::UInt256,
@@ -48,6 +105,32 @@ pub trait Transport: Send + Sync {
 
     async fn get_latest_key_block(&self) -> Result<ton_block::Block>;
 
+    /// Fetches the block by its id or seqno.
+    /// Requires [`TransportInfo::has_blocks`]
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        let _ = block;
+        Err(TransportError::BlocksNotSupported.into())
+    }
+
+    /// Fetches the latest masterchain block with the latest blocks of all shards.
+    /// Requires [`TransportInfo::has_blocks`]
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        Err(TransportError::BlocksNotSupported.into())
+    }
+
+    /// Waits for the block which follows `current` in the shard of the specified account.
+    /// Returns `None` if no block was produced within the timeout.
+    /// Requires [`TransportInfo::has_blocks`]
+    async fn wait_for_next_block(
+        &self,
+        current: &ton_types::UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<ton_types::UInt256>> {
+        let _ = (current, address, timeout);
+        Err(TransportError::BlocksNotSupported.into())
+    }
+
     async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities>;
 
     // NOTE: clock is used for caching here
@@ -63,4 +146,17 @@ pub struct TransportInfo {
     pub max_transactions_per_fetch: u8,
     pub reliable_behavior: ReliableBehavior,
     pub has_key_blocks: bool,
+    /// Whether block-level queries are supported
+    #[serde(default)]
+    pub has_blocks: bool,
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum TransportError {
+    #[error("Block queries are not supported by the transport")]
+    BlocksNotSupported,
+    #[error("State proofs are not supported by the transport")]
+    ProofsNotSupported,
+    #[error("Key blocks are not supported by the transport")]
+    KeyBlocksNotSupported,
 }
@@ -1,11 +1,14 @@
 use std::cmp::Ordering;
 
 use serde::{Deserialize, Serialize};
-use ton_block::{Account, AccountStuff, Transaction};
-use ton_types::UInt256;
+use ton_block::{Account, AccountStuff, MsgAddressInt, Transaction};
+use ton_types::{Cell, UInt256};
 
 use nekoton_abi::{ExecutionContext, GenTimings, LastTransactionId};
-use nekoton_utils::{serde_account_stuff, Clock};
+use nekoton_utils::{
+    serde_account_stuff, serde_cell, serde_optional_cell, serde_ton_block, serde_u64,
+    serde_uint256, Clock,
+};
 
 use crate::core::models::{ContractState, PendingTransaction};
 
@@ -101,6 +104,39 @@ impl PartialOrd for ExistingContract {
     }
 }
 
+/// Account state proofs which chain it to a signed masterchain block.
+///
+/// Matches the liteserver `getAccountState` response with the signatures
+/// from `getBlockProof`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawContractStateProof {
+    #[serde(with = "serde_ton_block")]
+    pub mc_block_id: ton_block::BlockIdExt,
+    /// Validator signatures of the masterchain block
+    #[serde(with = "serde_ton_block")]
+    pub mc_block_signatures: ton_block::BlockSignatures,
+    /// Merkle proof of the masterchain block with its info and state update
+    #[serde(with = "serde_cell")]
+    pub mc_block_proof: Cell,
+    /// Merkle proof of the shard hashes in the masterchain state.
+    /// Not used for masterchain accounts
+    #[serde(default, with = "serde_optional_cell")]
+    pub mc_state_proof: Option<Cell>,
+    /// Block of the account shard. Equals to the masterchain block for masterchain accounts
+    #[serde(with = "serde_ton_block")]
+    pub shard_block_id: ton_block::BlockIdExt,
+    /// Merkle proof of the shard block with its info and state update
+    #[serde(with = "serde_cell")]
+    pub shard_block_proof: Cell,
+    /// Merkle proof of the shard account in the shard state after the shard block
+    #[serde(with = "serde_cell")]
+    pub state_proof: Cell,
+    /// Account cell, which is pruned in the state proof. `None` if the account doesn't exist
+    #[serde(default, with = "serde_optional_cell")]
+    pub account: Option<Cell>,
+}
+
 #[derive(Clone, Debug)]
 pub struct RawTransaction {
     pub hash: UInt256,
@@ -139,3 +175,74 @@ impl PartialEq<RawTransaction> for PendingTransaction {
         )
     }
 }
+
+/// Point in the account history for
+/// [`Transport::get_contract_state_at`](super::Transport::get_contract_state_at)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransactionRef {
+    /// The latest transaction with lt less than or equal to the specified one
+    Lt(u64),
+    /// Transaction hash
+    Hash(UInt256),
+}
+
+/// Block reference for [`Transport::get_block`](super::Transport::get_block)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BlockRef {
+    /// Block root hash
+    Id(UInt256),
+    /// Block seqno in the specified shard
+    Seqno {
+        workchain_id: i32,
+        /// Shard prefix with tag
+        shard: u64,
+        seqno: u32,
+    },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockSummary {
+    pub workchain_id: i32,
+    /// Shard prefix with tag
+    #[serde(with = "serde_u64")]
+    pub shard: u64,
+    pub seqno: u32,
+    /// Block root hash
+    #[serde(with = "serde_uint256")]
+    pub id: UInt256,
+    #[serde(with = "serde_u64")]
+    pub end_lt: u64,
+    pub gen_utime: u32,
+}
+
+impl BlockSummary {
+    pub fn contains_account(&self, address: &MsgAddressInt) -> bool {
+        let shard = match ton_block::ShardIdent::with_tagged_prefix(self.workchain_id, self.shard) {
+            Ok(shard) => shard,
+            Err(_) => return false,
+        };
+        match ton_block::AccountIdPrefixFull::prefix(address) {
+            Ok(prefix) => shard.contains_full_prefix(&prefix),
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatestMasterchainBlock {
+    #[serde(flatten)]
+    pub block: BlockSummary,
+    /// Latest blocks of all shards at the moment of this block
+    pub shards: Vec<BlockSummary>,
+}
+
+impl LatestMasterchainBlock {
+    /// Returns the latest known block of the shard which contains the account
+    pub fn find_block(&self, address: &MsgAddressInt) -> Option<&BlockSummary> {
+        std::iter::once(&self.block)
+            .chain(self.shards.iter())
+            .find(|block| block.contains_account(address))
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use super::models::RawTransaction;
+use super::Transport;
+
+/// Position in the account transactions history
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionsCursor {
+    /// Lt of the next transaction. `u64::MAX` starts from the latest transaction
+    #[serde(with = "serde_string")]
+    pub from_lt: u64,
+    /// Transactions with lt less than or equal to this one are not fetched
+    #[serde(with = "serde_string")]
+    pub until_lt: u64,
+}
+
+impl TransactionsCursor {
+    /// Cursor for the full history, starting from the latest transaction
+    pub fn latest() -> Self {
+        Self {
+            from_lt: u64::MAX,
+            until_lt: 0,
+        }
+    }
+
+    /// Cursor which continues right after the specified transaction
+    pub fn after(&self, transaction: &RawTransaction) -> Self {
+        Self {
+            from_lt: transaction.data.prev_trans_lt,
+            until_lt: self.until_lt,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.from_lt == 0 || self.from_lt <= self.until_lt
+    }
+}
+
+impl Default for TransactionsCursor {
+    fn default() -> Self {
+        Self::latest()
+    }
+}
+
+/// Position in the list of accounts with the same code hash
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountsCursor {
+    /// Last received address
+    #[serde(with = "serde_optional_address")]
+    pub continuation: Option<MsgAddressInt>,
+}
+
+impl AccountsCursor {
+    /// Cursor which continues right after the specified address
+    pub fn after(address: &MsgAddressInt) -> Self {
+        Self {
+            continuation: Some(address.clone()),
+        }
+    }
+}
+
+/// Paginated requests as streams.
+///
+/// Pages are only requested when the stream is polled, so the consumer controls
+/// the pace. Streams stop after the first error, use cursors to resume them.
+pub trait TransportPagingExt: Transport {
+    /// Walks the account transactions history in descending order
+    fn transactions_stream(
+        &self,
+        address: &MsgAddressInt,
+        cursor: TransactionsCursor,
+    ) -> BoxStream<'_, Result<RawTransaction>>;
+
+    /// Enumerates all accounts with the specified code hash in ascending order
+    fn accounts_by_code_hash_stream(
+        &self,
+        code_hash: &UInt256,
+        page_size: u8,
+        cursor: AccountsCursor,
+    ) -> BoxStream<'_, Result<MsgAddressInt>>;
+}
+
+impl<T> TransportPagingExt for T
+where
+    T: Transport + ?Sized,
+{
+    fn transactions_stream(
+        &self,
+        address: &MsgAddressInt,
+        cursor: TransactionsCursor,
+    ) -> BoxStream<'_, Result<RawTransaction>> {
+        let state = PagingState {
+            transport: self,
+            batch: VecDeque::new(),
+            finished: cursor.is_finished(),
+            cursor,
+        };
+        let address = address.clone();
+
+        futures_util::stream::unfold(state, move |mut state| {
+            let address = address.clone();
+            async move {
+                loop {
+                    if let Some(transaction) = state.batch.pop_front() {
+                        return Some((Ok(transaction), state));
+                    }
+                    if state.finished {
+                        return None;
+                    }
+
+                    let count = state.transport.info().max_transactions_per_fetch;
+                    let transactions = match state
+                        .transport
+                        .get_transactions(&address, state.cursor.from_lt, count)
+                        .await
+                    {
+                        Ok(transactions) => transactions,
+                        Err(e) => {
+                            state.finished = true;
+                            return Some((Err(e), state));
+                        }
+                    };
+
+                    let prev_cursor = state.cursor;
+                    state.finished = transactions.is_empty();
+                    for transaction in transactions {
+                        if transaction.data.lt <= state.cursor.until_lt {
+                            state.finished = true;
+                            break;
+                        }
+                        state.cursor = state.cursor.after(&transaction);
+                        state.batch.push_back(transaction);
+                    }
+
+                    // NOTE: stop on inconsistent responses to prevent infinite loops
+                    if state.cursor.is_finished() || state.cursor.from_lt >= prev_cursor.from_lt {
+                        state.finished = true;
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+
+    fn accounts_by_code_hash_stream(
+        &self,
+        code_hash: &UInt256,
+        page_size: u8,
+        cursor: AccountsCursor,
+    ) -> BoxStream<'_, Result<MsgAddressInt>> {
+        let state = PagingState {
+            transport: self,
+            batch: VecDeque::new(),
+            finished: false,
+            cursor,
+        };
+        let code_hash = *code_hash;
+        let page_size = std::cmp::max(page_size, 1);
+
+        futures_util::stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(address) = state.batch.pop_front() {
+                    return Some((Ok(address), state));
+                }
+                if state.finished {
+                    return None;
+                }
+
+                let addresses = match state
+                    .transport
+                    .get_accounts_by_code_hash(&code_hash, page_size, &state.cursor.continuation)
+                    .await
+                {
+                    Ok(addresses) => addresses,
+                    Err(e) => {
+                        state.finished = true;
+                        return Some((Err(e), state));
+                    }
+                };
+
+                state.finished = addresses.len() < page_size as usize;
+                if let Some(last) = addresses.last() {
+                    state.cursor = AccountsCursor::after(last);
+                }
+                state.batch.extend(addresses);
+            }
+        })
+        .boxed()
+    }
+}
+
+struct PagingState<'a, T: ?Sized, I, C> {
+    transport: &'a T,
+    batch: VecDeque<I>,
+    finished: bool,
+    cursor: C,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+
+    use super::*;
+    use crate::transport::test_utils::{emulated_transport, test_address, test_clock, transfer};
+
+    #[tokio::test]
+    async fn transactions_history_is_resumable() -> Result<()> {
+        let transport = emulated_transport(test_clock());
+        let dst = test_address();
+
+        for i in 1..=3 {
+            transport
+                .send_message(&transfer(&dst, i * 1_000_000_000))
+                .await?;
+        }
+
+        let transactions = transport
+            .transactions_stream(&dst, TransactionsCursor::latest())
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(transactions.len(), 3);
+        assert!(transactions
+            .windows(2)
+            .all(|pair| pair[0].data.lt > pair[1].data.lt));
+
+        let cursor = TransactionsCursor::latest().after(&transactions[0]);
+        let rest = transport
+            .transactions_stream(&dst, cursor)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(rest, transactions[1..]);
+
+        let bounded = TransactionsCursor {
+            until_lt: transactions[1].data.lt,
+            ..TransactionsCursor::latest()
+        };
+        let bounded = transport
+            .transactions_stream(&dst, bounded)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(bounded, transactions[..1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,539 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use ton_block::{Account, Deserializable, MsgAddressInt};
+use ton_types::{Cell, SliceData, UInt256};
+
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
+use nekoton_utils::*;
+
+use crate::core::models::NetworkCapabilities;
+
+use super::models::*;
+use super::{Transport, TransportInfo};
+
+/// Transport decorator which verifies all contract states it returns.
+///
+/// States are requested with proofs and checked against the validator set
+/// of the latest key block. See [`verify_contract_state_proof`]
+pub struct VerifyingTransport {
+    inner: Arc<dyn Transport>,
+    key_block: Mutex<Option<Arc<ton_block::Block>>>,
+}
+
+impl VerifyingTransport {
+    pub fn new(inner: Arc<dyn Transport>) -> Self {
+        Self {
+            inner,
+            key_block: Default::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &Arc<dyn Transport> {
+        &self.inner
+    }
+
+    async fn key_block(&self, force: bool) -> Result<Arc<ton_block::Block>> {
+        if !force {
+            if let Some(key_block) = &*self.key_block.lock() {
+                return Ok(key_block.clone());
+            }
+        }
+
+        let key_block = Arc::new(self.inner.get_latest_key_block().await?);
+        *self.key_block.lock() = Some(key_block.clone());
+        Ok(key_block)
+    }
+}
+
+#[async_trait]
+impl Transport for VerifyingTransport {
+    fn info(&self) -> TransportInfo {
+        self.inner.info()
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        self.inner.send_message(message).await
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let proof = self.inner.get_contract_state_proof(address).await?;
+
+        let key_block = self.key_block(false).await?;
+        match verify_contract_state_proof(&key_block, address, &proof) {
+            // Validator set could have changed since the key block was fetched
+            Err(e) if matches!(e.downcast_ref(), Some(ProofError::KeyBlockMismatch)) => {
+                let key_block = self.key_block(true).await?;
+                verify_contract_state_proof(&key_block, address, &proof)
+            }
+            result => result,
+        }
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        self.inner.get_contract_state_proof(address).await
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.inner
+            .get_accounts_by_code_hash(code_hash, limit, continuation)
+            .await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        self.inner.get_transactions(address, from_lt, count).await
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        self.inner.get_transaction(id).await
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        self.inner.get_dst_transaction(message_hash).await
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        self.inner.get_latest_key_block().await
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        self.inner.get_block(block).await
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.inner.get_latest_masterchain_block().await
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.inner
+            .wait_for_next_block(current, address, timeout)
+            .await
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.inner.get_capabilities(clock).await
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.inner.get_blockchain_config(clock, force).await
+    }
+}
+
+/// Checks that the account state is included into the shard state which
+/// chains to the masterchain block signed by the validators of the key block.
+///
+/// Masterchain block must be produced after the key block.
+pub fn verify_contract_state_proof(
+    key_block: &ton_block::Block,
+    address: &MsgAddressInt,
+    proof: &RawContractStateProof,
+) -> Result<RawContractState> {
+    // Check masterchain block
+    let mc_block_id = &proof.mc_block_id;
+    if !mc_block_id.shard_id.is_masterchain() {
+        return Err(ProofError::InvalidMasterchainBlock.into());
+    }
+
+    let mc_block = read_block(&proof.mc_block_proof, &mc_block_id.root_hash)?;
+    let mc_block_info = mc_block
+        .info
+        .read_struct()
+        .map_err(|_| ProofError::InvalidMasterchainBlock)?;
+    if mc_block_info.seq_no() != mc_block_id.seq_no {
+        return Err(ProofError::InvalidMasterchainBlock.into());
+    }
+
+    check_signatures(
+        key_block,
+        &mc_block_info,
+        mc_block_id,
+        &proof.mc_block_signatures,
+    )?;
+
+    // Check shard block
+    let shard_block_id = &proof.shard_block_id;
+    if shard_block_id.shard_id.is_masterchain() {
+        if shard_block_id != mc_block_id {
+            return Err(ProofError::ShardBlockMismatch.into());
+        }
+    } else {
+        let mc_state_update = mc_block
+            .read_state_update()
+            .map_err(|_| ProofError::InvalidMasterchainBlock)?;
+        let mc_state_proof = proof
+            .mc_state_proof
+            .as_ref()
+            .ok_or(ProofError::InvalidMasterchainState)?;
+        let mc_state = read_merkle_proof(mc_state_proof, &mc_state_update.new_hash)?;
+
+        let shard = read_shard_hashes(mc_state)?
+            .get_shard(&shard_block_id.shard_id)
+            .map_err(|_| ProofError::InvalidMasterchainState)?
+            .ok_or(ProofError::ShardBlockMismatch)?;
+        if shard.block_id() != shard_block_id {
+            return Err(ProofError::ShardBlockMismatch.into());
+        }
+    }
+
+    let prefix = ton_block::AccountIdPrefixFull::prefix(address)?;
+    if !shard_block_id.shard_id.contains_full_prefix(&prefix) {
+        return Err(ProofError::ShardBlockMismatch.into());
+    }
+
+    let shard_block = read_block(&proof.shard_block_proof, &shard_block_id.root_hash)?;
+    let shard_block_info = shard_block
+        .info
+        .read_struct()
+        .map_err(|_| ProofError::InvalidShardBlock)?;
+    let state_update = shard_block
+        .read_state_update()
+        .map_err(|_| ProofError::InvalidShardBlock)?;
+
+    // Check account state
+    let state = read_merkle_proof(&proof.state_proof, &state_update.new_hash)?;
+    let shard_account = read_shard_account(state, address)?;
+
+    let shard_account = match shard_account {
+        Some(shard_account) => shard_account,
+        None => return Ok(RawContractState::NotExists),
+    };
+
+    // NOTE: account itself is pruned in the state proof
+    let account = proof
+        .account
+        .as_ref()
+        .ok_or(ProofError::InvalidStateProof)?;
+    if account.repr_hash() != shard_account.account_cell().repr_hash() {
+        return Err(ProofError::InvalidStateProof.into());
+    }
+
+    match Account::construct_from_cell(account.clone())
+        .map_err(|_| ProofError::InvalidStateProof)?
+    {
+        Account::Account(account) => Ok(RawContractState::Exists(ExistingContract {
+            account,
+            timings: GenTimings::Known {
+                gen_lt: shard_block_info.end_lt(),
+                gen_utime: shard_block_info.gen_utime().0,
+            },
+            last_transaction_id: LastTransactionId::Exact(TransactionId {
+                lt: shard_account.last_trans_lt(),
+                hash: *shard_account.last_trans_hash(),
+            }),
+        })),
+        Account::AccountNone => Ok(RawContractState::NotExists),
+    }
+}
+
+fn check_signatures(
+    key_block: &ton_block::Block,
+    block_info: &ton_block::BlockInfo,
+    block_id: &ton_block::BlockIdExt,
+    signatures: &ton_block::BlockSignatures,
+) -> Result<()> {
+    let key_block_info = key_block
+        .info
+        .read_struct()
+        .map_err(|_| ProofError::InvalidKeyBlock)?;
+
+    // NOTE: validator set is only known for the blocks after the key block
+    if block_info.prev_key_block_seqno() != key_block_info.seq_no() {
+        return Err(ProofError::KeyBlockMismatch.into());
+    }
+
+    let config = key_block
+        .read_extra()
+        .and_then(|extra| extra.read_custom())
+        .map_err(|_| ProofError::InvalidKeyBlock)?
+        .and_then(|custom| custom.config().cloned())
+        .ok_or(ProofError::InvalidKeyBlock)?;
+    let validator_set = config
+        .validator_set()
+        .map_err(|_| ProofError::InvalidKeyBlock)?;
+    let catchain_config = config
+        .catchain_config()
+        .map_err(|_| ProofError::InvalidKeyBlock)?;
+
+    let (validators, hash_short) = validator_set
+        .calc_subset(
+            &catchain_config,
+            ton_block::SHARD_FULL,
+            ton_block::MASTERCHAIN_ID,
+            block_info.gen_catchain_seqno(),
+            block_info.gen_utime(),
+        )
+        .map_err(|_| ProofError::InvalidKeyBlock)?;
+
+    if hash_short != block_info.gen_validator_list_hash_short()
+        || hash_short != signatures.validator_info.validator_list_hash_short
+        || signatures.validator_info.catchain_seqno != block_info.gen_catchain_seqno()
+    {
+        return Err(ProofError::ValidatorSetMismatch.into());
+    }
+
+    let total_weight = validators.iter().map(|item| item.weight).sum::<u64>();
+
+    let data = ton_block::Block::build_data_for_sign(&block_id.root_hash, &block_id.file_hash);
+    let weight = signatures
+        .pure_signatures
+        .check_signatures(validators, &data)
+        .map_err(|_| ProofError::InvalidSignatures)?;
+
+    // More than 2/3 of the total weight is required
+    if weight * 3 <= total_weight * 2 {
+        return Err(ProofError::NotEnoughSignatures.into());
+    }
+
+    Ok(())
+}
+
+fn read_block(proof: &Cell, root_hash: &UInt256) -> Result<ton_block::Block> {
+    let cell = read_merkle_proof(proof, root_hash)?;
+    ton_block::Block::construct_from_cell(cell).map_err(|_| ProofError::InvalidMerkleProof.into())
+}
+
+/// Reads the shard hashes from the pruned masterchain state.
+///
+/// Only the path to the shard descriptions is present in the proof,
+/// so the references are followed manually instead of reading the whole state
+fn read_shard_hashes(mc_state: Cell) -> Result<ton_block::ShardHashes> {
+    // `custom:(Maybe ^McStateExtra)` is the fourth reference of `ShardStateUnsplit`
+    let extra = mc_state
+        .reference(3)
+        .map_err(|_| ProofError::InvalidMasterchainState)?;
+
+    let mut extra = SliceData::from(extra);
+    if extra.get_next_u16().ok() != Some(MC_STATE_EXTRA_TAG) {
+        return Err(ProofError::InvalidMasterchainState.into());
+    }
+    ton_block::ShardHashes::construct_from(&mut extra)
+        .map_err(|_| ProofError::InvalidMasterchainState.into())
+}
+
+/// Reads the shard account from the pruned shard state
+fn read_shard_account(
+    state: Cell,
+    address: &MsgAddressInt,
+) -> Result<Option<ton_block::ShardAccount>> {
+    // `accounts:^ShardAccounts` is the second reference of `ShardStateUnsplit`
+    state
+        .reference(1)
+        .and_then(ton_block::ShardAccounts::construct_from_cell)
+        .and_then(|accounts| accounts.account(&address.address()))
+        .map_err(|_| ProofError::InvalidStateProof.into())
+}
+
+/// Returns the virtualized root of the pruned tree
+fn read_merkle_proof(proof: &Cell, root_hash: &UInt256) -> Result<Cell> {
+    let proof = ton_block::MerkleProof::construct_from_cell(proof.clone())
+        .map_err(|_| ProofError::InvalidMerkleProof)?;
+    if &proof.hash != root_hash || proof.proof.hash(0) != proof.hash {
+        return Err(ProofError::InvalidMerkleProof.into());
+    }
+    Ok(proof.proof.virtualize(1))
+}
+
+const MC_STATE_EXTRA_TAG: u16 = 0xcc26;
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum ProofError {
+    #[error("Invalid merkle proof")]
+    InvalidMerkleProof,
+    #[error("Invalid key block")]
+    InvalidKeyBlock,
+    #[error("Key block doesn't match the masterchain block")]
+    KeyBlockMismatch,
+    #[error("Invalid masterchain block")]
+    InvalidMasterchainBlock,
+    #[error("Invalid masterchain state")]
+    InvalidMasterchainState,
+    #[error("Validator set mismatch")]
+    ValidatorSetMismatch,
+    #[error("Invalid signatures")]
+    InvalidSignatures,
+    #[error("Not enough signatures")]
+    NotEnoughSignatures,
+    #[error("Shard block doesn't match the masterchain block")]
+    ShardBlockMismatch,
+    #[error("Invalid shard block")]
+    InvalidShardBlock,
+    #[error("Invalid state proof")]
+    InvalidStateProof,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ton_block::Serializable;
+
+    use super::*;
+    use crate::transport::emulated::EmulatedTransport;
+    use crate::transport::test_utils::{test_address, TestChain};
+
+    fn verify(chain: TestChain) -> Result<RawContractState> {
+        let data = chain.build()?;
+        let key_block = ton_block::Block::construct_from_bytes(&data.key_block)?;
+        verify_contract_state_proof(&key_block, &test_address(), &data.proof)
+    }
+
+    fn proof_error(result: Result<RawContractState>) -> ProofError {
+        *result
+            .unwrap_err()
+            .downcast_ref::<ProofError>()
+            .expect("proof error")
+    }
+
+    #[test]
+    fn merkle_proof_root_hash_is_checked() -> Result<()> {
+        let mut builder = ton_types::BuilderData::new();
+        builder.append_u32(123)?;
+        let cell = builder.into_cell()?;
+
+        let proof = ton_block::MerkleProof::create(&cell, |_| true)?.serialize()?;
+        assert_eq!(
+            read_merkle_proof(&proof, &cell.repr_hash())?.repr_hash(),
+            cell.repr_hash()
+        );
+        assert!(read_merkle_proof(&proof, &UInt256::default()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn valid_proof_is_accepted() -> Result<()> {
+        let state = match verify(TestChain::default())? {
+            RawContractState::Exists(state) => state,
+            RawContractState::NotExists => panic!("account not found"),
+        };
+        assert_eq!(state.account.addr, test_address());
+        assert_eq!(state.account.storage.balance.grams.0, 1_000_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_signatures_are_rejected() {
+        let error = proof_error(verify(TestChain {
+            tamper_signatures: true,
+            ..Default::default()
+        }));
+        assert!(matches!(
+            error,
+            ProofError::InvalidSignatures | ProofError::NotEnoughSignatures
+        ));
+    }
+
+    #[test]
+    fn minority_signatures_are_rejected() {
+        let error = proof_error(verify(TestChain {
+            signers: vec![1],
+            ..Default::default()
+        }));
+        assert!(matches!(error, ProofError::NotEnoughSignatures));
+    }
+
+    #[test]
+    fn wrong_validator_subset_is_rejected() {
+        // Block is produced and signed by validators outside of the key block set
+        let error = proof_error(verify(TestChain {
+            block_validators: vec![4, 5, 6],
+            signers: vec![4, 5, 6],
+            ..Default::default()
+        }));
+        assert!(matches!(error, ProofError::ValidatorSetMismatch));
+    }
+
+    #[test]
+    fn wrong_key_block_is_rejected() -> Result<()> {
+        let data = TestChain::default().build()?;
+        let mc_block = read_block(
+            &data.proof.mc_block_proof,
+            &data.proof.mc_block_id.root_hash,
+        )?;
+
+        // Masterchain block is not a key block of itself
+        let error = proof_error(verify_contract_state_proof(
+            &mc_block,
+            &test_address(),
+            &data.proof,
+        ));
+        assert!(matches!(error, ProofError::KeyBlockMismatch));
+        Ok(())
+    }
+
+    #[test]
+    fn substituted_account_is_rejected() -> Result<()> {
+        let mut data = TestChain::default().build()?;
+        let key_block = ton_block::Block::construct_from_bytes(&data.key_block)?;
+
+        let account = ton_block::Account::with_address_and_ballance(
+            &test_address(),
+            &ton_block::CurrencyCollection::with_grams(1_000_000_000_000),
+        );
+        data.proof.account = Some(account.serialize()?);
+
+        let error = proof_error(verify_contract_state_proof(
+            &key_block,
+            &test_address(),
+            &data.proof,
+        ));
+        assert!(matches!(error, ProofError::InvalidStateProof));
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_shard_block_is_rejected() -> Result<()> {
+        let mut data = TestChain::default().build()?;
+        let key_block = ton_block::Block::construct_from_bytes(&data.key_block)?;
+
+        data.proof.shard_block_id.seq_no += 1;
+
+        let error = proof_error(verify_contract_state_proof(
+            &key_block,
+            &test_address(),
+            &data.proof,
+        ));
+        assert!(matches!(error, ProofError::ShardBlockMismatch));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn states_without_proofs_are_rejected() -> Result<()> {
+        let transport = VerifyingTransport::new(Arc::new(EmulatedTransport::new(
+            Arc::new(SimpleClock),
+            Default::default(),
+        )));
+
+        let address = MsgAddressInt::from_str(
+            "0:3333333333333333333333333333333333333333333333333333333333333333",
+        )?;
+        assert!(transport.get_contract_state(&address).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
+use prost::Message;
+use ton_block::{Account, Block, Deserializable, MsgAddressInt, Serializable};
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::{NetworkCapabilities, ReliableBehavior};
+use crate::external::{ProtoConnection, ProtoRequest};
+
+use super::models::{ExistingContract, RawContractState, RawTransaction};
+use super::utils::ConfigCache;
+use super::{Transport, TransportInfo};
+
+use self::rpc::request::Call;
+use self::rpc::response::Result as CallResult;
+
+pub mod rpc;
+
+/// Transport over the protobuf RPC API.
+///
+/// Same requests as the JRPC transport, but with binary encoding,
+/// which is much cheaper for bulk state and transaction fetches
+pub struct ProtoTransport {
+    connection: Arc<dyn ProtoConnection>,
+    config_cache: ConfigCache,
+}
+
+impl ProtoTransport {
+    pub fn new(connection: Arc<dyn ProtoConnection>) -> Self {
+        Self {
+            connection,
+            config_cache: ConfigCache::new(false),
+        }
+    }
+
+    /// Creates a transport with the custom config cache (e.g. persistent or bundled)
+    pub fn with_config_cache(
+        connection: Arc<dyn ProtoConnection>,
+        config_cache: ConfigCache,
+    ) -> Self {
+        Self {
+            connection,
+            config_cache,
+        }
+    }
+
+    async fn call(&self, call: Call, requires_db: bool, broadcast: bool) -> Result<CallResult> {
+        let req = ProtoRequest {
+            data: make_proto_request(call),
+            requires_db,
+            broadcast,
+        };
+        let response = self.connection.post(req).await?;
+        parse_proto_response(&response)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for ProtoTransport {
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            max_transactions_per_fetch: 50,
+            reliable_behavior: ReliableBehavior::IntensivePolling,
+            has_key_blocks: true,
+            has_blocks: false,
+        }
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        let call = Call::SendMessage(rpc::request::SendMessage {
+            message: ton_types::serialize_toc(&message.serialize()?)?,
+        });
+        match self.call(call, false, true).await? {
+            CallResult::SendMessage(()) => Ok(()),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let call = Call::GetContractState(rpc::request::GetContractState {
+            address: encode_address(address)?,
+            last_transaction_lt: None,
+        });
+        match self.call(call, false, false).await? {
+            CallResult::GetContractState(state) => decode_contract_state(state),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        let call = Call::GetAccountsByCodeHash(rpc::request::GetAccountsByCodeHash {
+            code_hash: code_hash.as_slice().to_vec(),
+            continuation: continuation.as_ref().map(encode_address).transpose()?,
+            limit: limit as u32,
+        });
+        match self.call(call, true, false).await? {
+            CallResult::GetAccounts(response) => response
+                .account
+                .iter()
+                .map(|address| decode_address(address))
+                .collect(),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        let call = Call::GetTransactionsList(rpc::request::GetTransactionsList {
+            account: encode_address(address)?,
+            last_transaction_lt: (from_lt != u64::MAX).then_some(from_lt),
+            limit: count as u32,
+        });
+        match self.call(call, true, false).await? {
+            CallResult::GetTransactionsList(response) => response
+                .transactions
+                .iter()
+                .map(|boc| decode_raw_transaction(boc))
+                .collect(),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        let call = Call::GetTransaction(rpc::request::GetTransaction {
+            id: id.as_slice().to_vec(),
+        });
+        match self.call(call, true, false).await? {
+            CallResult::GetRawTransaction(response) => response
+                .transaction
+                .map(|boc| decode_raw_transaction(&boc))
+                .transpose(),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        let call = Call::GetDstTransaction(rpc::request::GetTransaction {
+            id: message_hash.as_slice().to_vec(),
+        });
+        match self.call(call, true, false).await? {
+            CallResult::GetRawTransaction(response) => response
+                .transaction
+                .map(|boc| decode_raw_transaction(&boc))
+                .transpose(),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_latest_key_block(&self) -> Result<Block> {
+        match self.call(Call::GetLatestKeyBlock(()), true, false).await? {
+            CallResult::GetLatestKeyBlock(response) => Block::construct_from_bytes(&response.block)
+                .map_err(|_| ProtoTransportError::InvalidBlock.into()),
+            _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        let (capabilities, _) = self
+            .config_cache
+            .get_blockchain_config(self, clock, false)
+            .await?;
+        Ok(capabilities)
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        let (_, config) = self
+            .config_cache
+            .get_blockchain_config(self, clock, force)
+            .await?;
+        Ok(config)
+    }
+}
+
+pub fn make_proto_request(call: Call) -> Vec<u8> {
+    rpc::Request { call: Some(call) }.encode_to_vec()
+}
+
+/// Parses the response, converting RPC errors into [`ProtoTransportError::RequestFailed`]
+pub fn parse_proto_response(response: &[u8]) -> Result<CallResult> {
+    if let Ok(rpc::Response {
+        result: Some(result),
+    }) = rpc::Response::decode(response)
+    {
+        return Ok(result);
+    }
+
+    // Failed requests are answered with a separate message
+    match rpc::Error::decode(response) {
+        Ok(e) if !e.message.is_empty() => Err(ProtoTransportError::RequestFailed {
+            code: e.code,
+            message: e.message,
+        }
+        .into()),
+        _ => Err(ProtoTransportError::UnexpectedResponse.into()),
+    }
+}
+
+pub fn encode_address(address: &MsgAddressInt) -> Result<Vec<u8>> {
+    let workchain_id = address.workchain_id();
+    let account_id = address.address();
+    if account_id.remaining_bits() != 256 || i8::try_from(workchain_id).is_err() {
+        return Err(ProtoTransportError::UnsupportedAddress.into());
+    }
+
+    let mut result = Vec::with_capacity(33);
+    result.push(workchain_id as i8 as u8);
+    result.extend_from_slice(&account_id.get_bytestring(0));
+    Ok(result)
+}
+
+pub fn decode_address(bytes: &[u8]) -> Result<MsgAddressInt> {
+    match bytes {
+        [workchain_id, account_id @ ..] if account_id.len() == 32 => {
+            let account_id = UInt256::from_slice(account_id);
+            Ok(MsgAddressInt::AddrStd(ton_block::MsgAddrStd::with_address(
+                None,
+                *workchain_id as i8,
+                account_id.into(),
+            )))
+        }
+        _ => Err(ProtoTransportError::InvalidAddress.into()),
+    }
+}
+
+fn decode_contract_state(response: rpc::response::GetContractState) -> Result<RawContractState> {
+    use rpc::response::get_contract_state::exists::last_transaction_id::LastTransactionId as Id;
+    use rpc::response::get_contract_state::State;
+
+    let state = match response.state {
+        Some(State::Exists(state)) => state,
+        Some(State::NotExists(_)) => return Ok(RawContractState::NotExists),
+        // Only returned for the requests with the known `last_transaction_lt`
+        Some(State::Unchanged(_)) | None => {
+            return Err(ProtoTransportError::UnexpectedResponse.into())
+        }
+    };
+
+    let account = match Account::construct_from_bytes(&state.account) {
+        Ok(Account::Account(account)) => account,
+        Ok(Account::AccountNone) => return Ok(RawContractState::NotExists),
+        Err(_) => return Err(ProtoTransportError::InvalidAccountState.into()),
+    };
+
+    let timings = match state.gen_timings {
+        Some(timings) => GenTimings::Known {
+            gen_lt: timings.gen_lt,
+            gen_utime: timings.gen_utime,
+        },
+        None => GenTimings::Unknown,
+    };
+
+    let last_transaction_id = match state
+        .last_transaction_id
+        .and_then(|id| id.last_transaction_id)
+    {
+        Some(Id::Exact(id)) => {
+            if id.hash.len() != 32 {
+                return Err(ProtoTransportError::InvalidAccountState.into());
+            }
+            LastTransactionId::Exact(TransactionId {
+                lt: id.lt,
+                hash: UInt256::from_slice(&id.hash),
+            })
+        }
+        Some(Id::Inexact(id)) => LastTransactionId::Inexact {
+            latest_lt: id.latest_lt,
+        },
+        None => LastTransactionId::Inexact {
+            latest_lt: account.storage.last_trans_lt,
+        },
+    };
+
+    Ok(RawContractState::Exists(ExistingContract {
+        account,
+        timings,
+        last_transaction_id,
+    }))
+}
+
+fn decode_raw_transaction(boc: &[u8]) -> Result<RawTransaction> {
+    let cell = ton_types::deserialize_tree_of_cells(&mut &*boc)?;
+    let hash = cell.repr_hash();
+    let data = ton_block::Transaction::construct_from_cell(cell)?;
+    Ok(RawTransaction { hash, data })
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProtoTransportError {
+    #[error("Request failed with code {code}: {message}")]
+    RequestFailed { code: i32, message: String },
+    #[error("Unexpected response")]
+    UnexpectedResponse,
+    #[error("Only standard addresses are supported")]
+    UnsupportedAddress,
+    #[error("Invalid address")]
+    InvalidAddress,
+    #[error("Invalid account state")]
+    InvalidAccountState,
+    #[error("Invalid block")]
+    InvalidBlock,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    struct StaticConnection(Vec<u8>);
+
+    #[async_trait::async_trait]
+    impl ProtoConnection for StaticConnection {
+        async fn post(&self, req: ProtoRequest) -> Result<Vec<u8>> {
+            let request = rpc::Request::decode(req.data.as_slice())?;
+            assert!(matches!(request.call, Some(Call::GetTransaction(_))));
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Finds the tag of the message field in the vendored schema
+    fn schema_tag(message: &str, field: &str) -> u32 {
+        const SCHEMA: &str = include_str!("rpc.proto");
+
+        let header = format!("message {message} {{");
+        let pattern = format!(" {field} = ");
+        SCHEMA
+            .lines()
+            .skip_while(|line| *line != header)
+            .find_map(|line| {
+                let (_, tag) = line.split_once(&pattern)?;
+                tag.trim_end_matches(';').parse().ok()
+            })
+            .unwrap_or_else(|| panic!("field {field} not found"))
+    }
+
+    #[test]
+    fn tags_match_schema() {
+        let calls = [
+            ("get_latest_key_block", Call::GetLatestKeyBlock(())),
+            ("get_timings", Call::GetTimings(())),
+            (
+                "get_contract_state",
+                Call::GetContractState(Default::default()),
+            ),
+            ("get_transaction", Call::GetTransaction(Default::default())),
+            (
+                "get_dst_transaction",
+                Call::GetDstTransaction(Default::default()),
+            ),
+            (
+                "get_transactions_list",
+                Call::GetTransactionsList(Default::default()),
+            ),
+            (
+                "get_accounts_by_code_hash",
+                Call::GetAccountsByCodeHash(Default::default()),
+            ),
+            ("send_message", Call::SendMessage(Default::default())),
+        ];
+        for (field, call) in calls {
+            // All tags fit into the first byte of the encoded field key
+            let data = make_proto_request(call);
+            assert_eq!(
+                (data[0] >> 3) as u32,
+                schema_tag("Request", field),
+                "{field}"
+            );
+        }
+
+        let results = [
+            (
+                "get_raw_transaction",
+                CallResult::GetRawTransaction(Default::default()),
+            ),
+            (
+                "get_transactions_list",
+                CallResult::GetTransactionsList(Default::default()),
+            ),
+            ("get_timings", CallResult::GetTimings(Default::default())),
+            (
+                "get_latest_key_block",
+                CallResult::GetLatestKeyBlock(Default::default()),
+            ),
+            ("get_accounts", CallResult::GetAccounts(Default::default())),
+            (
+                "get_contract_state",
+                CallResult::GetContractState(Default::default()),
+            ),
+            ("send_message", CallResult::SendMessage(())),
+        ];
+        for (field, result) in results {
+            let data = rpc::Response {
+                result: Some(result),
+            }
+            .encode_to_vec();
+            assert_eq!(
+                (data[0] >> 3) as u32,
+                schema_tag("Response", field),
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn address_roundtrip() -> Result<()> {
+        for address in [
+            "-1:3333333333333333333333333333333333333333333333333333333333333333",
+            "0:a921453472366b7feeec15323a96b5dcf17197c88dc0d4578dfa52900b8a33cb",
+        ] {
+            let address = MsgAddressInt::from_str(address)?;
+            let encoded = encode_address(&address)?;
+            assert_eq!(encoded.len(), 33);
+            assert_eq!(decode_address(&encoded)?, address);
+        }
+
+        assert!(decode_address(&[0; 10]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_are_returned() -> Result<()> {
+        let response = rpc::Error {
+            code: -32602,
+            message: "invalid params".to_owned(),
+        };
+        let transport = ProtoTransport::new(Arc::new(StaticConnection(response.encode_to_vec())));
+
+        let err = transport
+            .get_transaction(&UInt256::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProtoTransportError>(),
+            Some(ProtoTransportError::RequestFailed { code: -32602, .. })
+        ));
+
+        let response = rpc::Response {
+            result: Some(CallResult::GetRawTransaction(
+                rpc::response::GetRawTransaction { transaction: None },
+            )),
+        };
+        let transport = ProtoTransport::new(Arc::new(StaticConnection(response.encode_to_vec())));
+        assert!(transport
+            .get_transaction(&UInt256::default())
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+}
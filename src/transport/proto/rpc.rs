@@ -0,0 +1,257 @@
+//! Messages of the RPC protobuf API, see `rpc.proto` next to this file.
+//! Tags must be kept in sync with the schema.
+//!
+//! Addresses are encoded as 33 bytes: workchain id (as `i8`) followed by the account id.
+//! Blocks, messages and transactions are encoded as BOC.
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Request {
+    #[prost(oneof = "request::Call", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11")]
+    pub call: ::core::option::Option<request::Call>,
+}
+
+pub mod request {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetContractState {
+        #[prost(bytes = "vec", tag = "1")]
+        pub address: ::prost::alloc::vec::Vec<u8>,
+        #[prost(uint64, optional, tag = "2")]
+        pub last_transaction_lt: ::core::option::Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetTransaction {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: ::prost::alloc::vec::Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetTransactionsList {
+        #[prost(bytes = "vec", tag = "1")]
+        pub account: ::prost::alloc::vec::Vec<u8>,
+        #[prost(uint64, optional, tag = "2")]
+        pub last_transaction_lt: ::core::option::Option<u64>,
+        #[prost(uint32, tag = "3")]
+        pub limit: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetAccountsByCodeHash {
+        #[prost(bytes = "vec", tag = "1")]
+        pub code_hash: ::prost::alloc::vec::Vec<u8>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub continuation: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+        #[prost(uint32, tag = "3")]
+        pub limit: u32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SendMessage {
+        #[prost(bytes = "vec", tag = "1")]
+        pub message: ::prost::alloc::vec::Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Call {
+        #[prost(message, tag = "1")]
+        GetCapabilities(()),
+        #[prost(message, tag = "2")]
+        GetLatestKeyBlock(()),
+        #[prost(message, tag = "3")]
+        GetBlockchainConfig(()),
+        #[prost(message, tag = "4")]
+        GetStatus(()),
+        #[prost(message, tag = "5")]
+        GetTimings(()),
+        #[prost(message, tag = "6")]
+        GetContractState(GetContractState),
+        #[prost(message, tag = "7")]
+        GetTransaction(GetTransaction),
+        #[prost(message, tag = "8")]
+        GetDstTransaction(GetTransaction),
+        #[prost(message, tag = "9")]
+        GetTransactionsList(GetTransactionsList),
+        #[prost(message, tag = "10")]
+        GetAccountsByCodeHash(GetAccountsByCodeHash),
+        #[prost(message, tag = "11")]
+        SendMessage(SendMessage),
+    }
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Response {
+    #[prost(oneof = "response::Result", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10")]
+    pub result: ::core::option::Option<response::Result>,
+}
+
+pub mod response {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetRawTransaction {
+        #[prost(bytes = "vec", optional, tag = "1")]
+        pub transaction: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetTransactionsList {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub transactions: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetTimings {
+        #[prost(uint32, tag = "1")]
+        pub last_mc_block_seqno: u32,
+        #[prost(uint32, tag = "2")]
+        pub last_shard_client_mc_block_seqno: u32,
+        #[prost(uint32, tag = "3")]
+        pub last_mc_utime: u32,
+        #[prost(int64, tag = "4")]
+        pub mc_time_diff: i64,
+        #[prost(int64, tag = "5")]
+        pub shard_client_time_diff: i64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetStatus {
+        #[prost(bool, tag = "1")]
+        pub ready: bool,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetCapabilities {
+        #[prost(string, repeated, tag = "1")]
+        pub capabilities: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetLatestKeyBlock {
+        #[prost(bytes = "vec", tag = "1")]
+        pub block: ::prost::alloc::vec::Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetBlockchainConfig {
+        #[prost(int32, tag = "1")]
+        pub global_id: i32,
+        #[prost(bytes = "vec", tag = "2")]
+        pub config: ::prost::alloc::vec::Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetAccountsByCodeHash {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub account: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetContractState {
+        #[prost(oneof = "get_contract_state::State", tags = "1, 2, 3")]
+        pub state: ::core::option::Option<get_contract_state::State>,
+    }
+
+    pub mod get_contract_state {
+        #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+        pub struct NotExist {}
+
+        #[derive(Clone, PartialEq, ::prost::Message)]
+        pub struct Exists {
+            /// Serialized `ton_block::Account`
+            #[prost(bytes = "vec", tag = "1")]
+            pub account: ::prost::alloc::vec::Vec<u8>,
+            #[prost(message, optional, tag = "2")]
+            pub gen_timings: ::core::option::Option<exists::GenTimings>,
+            #[prost(message, optional, tag = "3")]
+            pub last_transaction_id: ::core::option::Option<exists::LastTransactionId>,
+        }
+
+        pub mod exists {
+            #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+            pub struct GenTimings {
+                #[prost(uint64, tag = "1")]
+                pub gen_lt: u64,
+                #[prost(uint32, tag = "2")]
+                pub gen_utime: u32,
+            }
+
+            #[derive(Clone, PartialEq, ::prost::Message)]
+            pub struct LastTransactionId {
+                #[prost(oneof = "last_transaction_id::LastTransactionId", tags = "1, 2")]
+                pub last_transaction_id:
+                    ::core::option::Option<last_transaction_id::LastTransactionId>,
+            }
+
+            pub mod last_transaction_id {
+                #[derive(Clone, PartialEq, ::prost::Message)]
+                pub struct Exact {
+                    #[prost(uint64, tag = "1")]
+                    pub lt: u64,
+                    #[prost(bytes = "vec", tag = "2")]
+                    pub hash: ::prost::alloc::vec::Vec<u8>,
+                }
+
+                #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+                pub struct Inexact {
+                    #[prost(uint64, tag = "1")]
+                    pub latest_lt: u64,
+                }
+
+                #[derive(Clone, PartialEq, ::prost::Oneof)]
+                pub enum LastTransactionId {
+                    #[prost(message, tag = "1")]
+                    Exact(Exact),
+                    #[prost(message, tag = "2")]
+                    Inexact(Inexact),
+                }
+            }
+        }
+
+        #[derive(Clone, Copy, PartialEq, ::prost::Message)]
+        pub struct Unchanged {
+            #[prost(message, optional, tag = "1")]
+            pub gen_timings: ::core::option::Option<exists::GenTimings>,
+        }
+
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum State {
+            #[prost(message, tag = "1")]
+            NotExists(NotExist),
+            #[prost(message, tag = "2")]
+            Exists(Exists),
+            #[prost(message, tag = "3")]
+            Unchanged(Unchanged),
+        }
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Result {
+        #[prost(message, tag = "1")]
+        GetRawTransaction(GetRawTransaction),
+        #[prost(message, tag = "2")]
+        GetTransactionsList(GetTransactionsList),
+        #[prost(message, tag = "3")]
+        GetTimings(GetTimings),
+        #[prost(message, tag = "4")]
+        GetStatus(GetStatus),
+        #[prost(message, tag = "5")]
+        GetCapabilities(GetCapabilities),
+        #[prost(message, tag = "6")]
+        GetLatestKeyBlock(GetLatestKeyBlock),
+        #[prost(message, tag = "7")]
+        GetBlockchainConfig(GetBlockchainConfig),
+        #[prost(message, tag = "8")]
+        GetAccounts(GetAccountsByCodeHash),
+        #[prost(message, tag = "9")]
+        GetContractState(GetContractState),
+        #[prost(message, tag = "10")]
+        SendMessage(()),
+    }
+}
+
+/// Returned instead of the [`Response`] when the request fails
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Error {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: ::prost::alloc::string::String,
+}
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use ton_block::{GetRepresentationHash, MsgAddressInt, Serializable};
+use ton_types::UInt256;
+
+use nekoton_utils::*;
+
+use crate::core::models::NetworkCapabilities;
+
+use super::models::*;
+use super::{Transport, TransportInfo};
+
+/// Transport decorator which records all responses of the inner transport.
+///
+/// Recorded data can later be served by the [`ReplayTransport`] without network.
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    recording: Mutex<Recording>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Arc<dyn Transport>) -> Self {
+        let info = inner.info();
+        Self {
+            inner,
+            recording: Mutex::new(Recording {
+                info,
+                entries: Default::default(),
+            }),
+        }
+    }
+
+    /// Returns a snapshot of all recorded calls
+    pub fn recording(&self) -> Recording {
+        self.recording.lock().clone()
+    }
+
+    /// Writes all recorded calls into the JSON file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.recording.lock().save(path)
+    }
+
+    fn record<T, F>(&self, key: String, result: Result<T>, f: F) -> Result<T>
+    where
+        F: FnOnce(&T) -> Result<RecordedValue>,
+    {
+        let response = match &result {
+            Ok(value) => RecordedResponse::Ok(f(value)?),
+            Err(e) => RecordedResponse::Err(e.to_string()),
+        };
+
+        self.recording
+            .lock()
+            .entries
+            .entry(key)
+            .or_default()
+            .push(response);
+
+        result
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    fn info(&self) -> TransportInfo {
+        self.inner.info()
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        let key = keys::send_message(message)?;
+        let result = self.inner.send_message(message).await;
+        self.record(key, result, |_| Ok(RecordedValue::Unit))
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        let result = self.inner.get_contract_state(address).await;
+        self.record(keys::get_contract_state(address), result, |state| {
+            Ok(RecordedValue::ContractState(state.clone()))
+        })
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        let result = self.inner.get_contract_state_proof(address).await;
+        self.record(keys::get_contract_state_proof(address), result, |proof| {
+            Ok(RecordedValue::ContractStateProof(proof.clone()))
+        })
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        let result = self
+            .inner
+            .get_accounts_by_code_hash(code_hash, limit, continuation)
+            .await;
+        self.record(
+            keys::get_accounts_by_code_hash(code_hash, limit, continuation),
+            result,
+            |addresses| Ok(RecordedValue::Addresses(addresses.clone())),
+        )
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        let result = self.inner.get_transactions(address, from_lt, count).await;
+        self.record(
+            keys::get_transactions(address, from_lt, count),
+            result,
+            |transactions| {
+                Ok(RecordedValue::Transactions(
+                    transactions
+                        .iter()
+                        .map(|item| RecordedTransaction(item.data.clone()))
+                        .collect(),
+                ))
+            },
+        )
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        let result = self.inner.get_transaction(id).await;
+        self.record(keys::get_transaction(id), result, |transaction| {
+            Ok(RecordedValue::Transaction(
+                transaction
+                    .as_ref()
+                    .map(|item| RecordedTransaction(item.data.clone())),
+            ))
+        })
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        let result = self.inner.get_dst_transaction(message_hash).await;
+        self.record(
+            keys::get_dst_transaction(message_hash),
+            result,
+            |transaction| {
+                Ok(RecordedValue::Transaction(
+                    transaction
+                        .as_ref()
+                        .map(|item| RecordedTransaction(item.data.clone())),
+                ))
+            },
+        )
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        let result = self.inner.get_latest_key_block().await;
+        self.record(keys::get_latest_key_block(), result, |block| {
+            Ok(RecordedValue::Block(block.clone()))
+        })
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        let result = self.inner.get_block(block).await;
+        self.record(keys::get_block(block), result, |block| {
+            Ok(RecordedValue::Block(block.clone()))
+        })
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        let result = self.inner.get_latest_masterchain_block().await;
+        self.record(keys::get_latest_masterchain_block(), result, |block| {
+            Ok(RecordedValue::LatestMasterchainBlock(block.clone()))
+        })
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        timeout: Duration,
+    ) -> Result<Option<UInt256>> {
+        let result = self
+            .inner
+            .wait_for_next_block(current, address, timeout)
+            .await;
+        self.record(
+            keys::wait_for_next_block(current, address),
+            result,
+            |block_id| Ok(RecordedValue::BlockId(*block_id)),
+        )
+    }
+
+    async fn get_capabilities(&self, clock: &dyn Clock) -> Result<NetworkCapabilities> {
+        let result = self.inner.get_capabilities(clock).await;
+        self.record(keys::get_capabilities(), result, |capabilities| {
+            Ok(RecordedValue::Capabilities(*capabilities))
+        })
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        clock: &dyn Clock,
+        force: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        let result = self.inner.get_blockchain_config(clock, force).await;
+        self.record(keys::get_blockchain_config(), result, |config| {
+            Ok(RecordedValue::Config(config.raw_config().clone()))
+        })
+    }
+}
+
+/// Transport which serves responses from the recording without network.
+///
+/// Responses for the same call are returned in the recorded order.
+/// The last response is repeated when all of them were consumed.
+pub struct ReplayTransport {
+    info: TransportInfo,
+    entries: Mutex<HashMap<String, ReplayQueue>>,
+}
+
+impl ReplayTransport {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            info: recording.info,
+            entries: Mutex::new(
+                recording
+                    .entries
+                    .into_iter()
+                    .map(|(key, responses)| {
+                        let queue = ReplayQueue {
+                            responses,
+                            position: 0,
+                        };
+                        (key, queue)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Loads recording from the JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Recording::load(path).map(Self::new)
+    }
+
+    fn replay(&self, key: String) -> Result<RecordedValue> {
+        let mut entries = self.entries.lock();
+        let queue = match entries.get_mut(&key) {
+            Some(queue) => queue,
+            None => return Err(ReplayTransportError::NotRecorded { key }.into()),
+        };
+
+        match queue.next() {
+            Some(RecordedResponse::Ok(value)) => Ok(value),
+            Some(RecordedResponse::Err(reason)) => {
+                Err(ReplayTransportError::RecordedError { reason }.into())
+            }
+            None => Err(ReplayTransportError::NotRecorded { key }.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    fn info(&self) -> TransportInfo {
+        self.info
+    }
+
+    async fn send_message(&self, message: &ton_block::Message) -> Result<()> {
+        match self.replay(keys::send_message(message)?)? {
+            RecordedValue::Unit => Ok(()),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_contract_state(&self, address: &MsgAddressInt) -> Result<RawContractState> {
+        match self.replay(keys::get_contract_state(address))? {
+            RecordedValue::ContractState(state) => Ok(state),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_contract_state_proof(
+        &self,
+        address: &MsgAddressInt,
+    ) -> Result<RawContractStateProof> {
+        match self.replay(keys::get_contract_state_proof(address))? {
+            RecordedValue::ContractStateProof(proof) => Ok(proof),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        match self.replay(keys::get_accounts_by_code_hash(
+            code_hash,
+            limit,
+            continuation,
+        ))? {
+            RecordedValue::Addresses(addresses) => Ok(addresses),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &MsgAddressInt,
+        from_lt: u64,
+        count: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        match self.replay(keys::get_transactions(address, from_lt, count))? {
+            RecordedValue::Transactions(transactions) => transactions
+                .into_iter()
+                .map(RecordedTransaction::into_raw)
+                .collect(),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_transaction(&self, id: &UInt256) -> Result<Option<RawTransaction>> {
+        match self.replay(keys::get_transaction(id))? {
+            RecordedValue::Transaction(transaction) => {
+                transaction.map(RecordedTransaction::into_raw).transpose()
+            }
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_dst_transaction(&self, message_hash: &UInt256) -> Result<Option<RawTransaction>> {
+        match self.replay(keys::get_dst_transaction(message_hash))? {
+            RecordedValue::Transaction(transaction) => {
+                transaction.map(RecordedTransaction::into_raw).transpose()
+            }
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        match self.replay(keys::get_latest_key_block())? {
+            RecordedValue::Block(block) => Ok(block),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_block(&self, block: &BlockRef) -> Result<ton_block::Block> {
+        match self.replay(keys::get_block(block))? {
+            RecordedValue::Block(block) => Ok(block),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        match self.replay(keys::get_latest_masterchain_block())? {
+            RecordedValue::LatestMasterchainBlock(block) => Ok(block),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        current: &UInt256,
+        address: &MsgAddressInt,
+        _: Duration,
+    ) -> Result<Option<UInt256>> {
+        match self.replay(keys::wait_for_next_block(current, address))? {
+            RecordedValue::BlockId(block_id) => Ok(block_id),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_capabilities(&self, _: &dyn Clock) -> Result<NetworkCapabilities> {
+        match self.replay(keys::get_capabilities())? {
+            RecordedValue::Capabilities(capabilities) => Ok(capabilities),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        _: &dyn Clock,
+        _: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        match self.replay(keys::get_blockchain_config())? {
+            RecordedValue::Config(params) => ton_executor::BlockchainConfig::with_config(params)
+                .map_err(|_| ReplayTransportError::InvalidConfig.into()),
+            _ => Err(ReplayTransportError::UnexpectedResponse.into()),
+        }
+    }
+}
+
+/// Recorded transport calls, keyed by method and arguments
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recording {
+    pub info: TransportInfo,
+    pub entries: HashMap<String, Vec<RecordedResponse>>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum RecordedResponse {
+    Ok(RecordedValue),
+    Err(String),
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum RecordedValue {
+    Unit,
+    ContractState(RawContractState),
+    ContractStateProof(RawContractStateProof),
+    Addresses(#[serde(with = "serde_vec_address")] Vec<MsgAddressInt>),
+    Transactions(Vec<RecordedTransaction>),
+    Transaction(Option<RecordedTransaction>),
+    Block(#[serde(with = "serde_ton_block")] ton_block::Block),
+    BlockId(#[serde(with = "serde_optional_uint256")] Option<UInt256>),
+    LatestMasterchainBlock(LatestMasterchainBlock),
+    Capabilities(NetworkCapabilities),
+    Config(#[serde(with = "serde_ton_block")] ton_block::ConfigParams),
+}
+
+/// Transaction stored as BOC
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecordedTransaction(#[serde(with = "serde_ton_block")] pub ton_block::Transaction);
+
+impl RecordedTransaction {
+    fn into_raw(self) -> Result<RawTransaction> {
+        Ok(RawTransaction {
+            hash: self.0.hash()?,
+            data: self.0,
+        })
+    }
+}
+
+struct ReplayQueue {
+    responses: Vec<RecordedResponse>,
+    position: usize,
+}
+
+impl ReplayQueue {
+    fn next(&mut self) -> Option<RecordedResponse> {
+        let response = self
+            .responses
+            .get(self.position)
+            .or_else(|| self.responses.last())
+            .cloned();
+        self.position = std::cmp::min(self.position + 1, self.responses.len());
+        response
+    }
+}
+
+mod keys {
+    use super::*;
+
+    pub fn send_message(message: &ton_block::Message) -> Result<String> {
+        let hash = message.serialize()?.repr_hash();
+        Ok(format!("send_message:{}", hash.to_hex_string()))
+    }
+
+    pub fn get_contract_state(address: &MsgAddressInt) -> String {
+        format!("get_contract_state:{address}")
+    }
+
+    pub fn get_contract_state_proof(address: &MsgAddressInt) -> String {
+        format!("get_contract_state_proof:{address}")
+    }
+
+    pub fn get_accounts_by_code_hash(
+        code_hash: &UInt256,
+        limit: u8,
+        continuation: &Option<MsgAddressInt>,
+    ) -> String {
+        let continuation = continuation
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        format!(
+            "get_accounts_by_code_hash:{}:{limit}:{continuation}",
+            code_hash.to_hex_string()
+        )
+    }
+
+    pub fn get_transactions(address: &MsgAddressInt, from_lt: u64, count: u8) -> String {
+        format!("get_transactions:{address}:{from_lt}:{count}")
+    }
+
+    pub fn get_transaction(id: &UInt256) -> String {
+        format!("get_transaction:{}", id.to_hex_string())
+    }
+
+    pub fn get_dst_transaction(message_hash: &UInt256) -> String {
+        format!("get_dst_transaction:{}", message_hash.to_hex_string())
+    }
+
+    pub fn get_latest_key_block() -> String {
+        "get_latest_key_block".to_owned()
+    }
+
+    pub fn get_block(block: &BlockRef) -> String {
+        match block {
+            BlockRef::Id(id) => format!("get_block:{}", id.to_hex_string()),
+            BlockRef::Seqno {
+                workchain_id,
+                shard,
+                seqno,
+            } => format!("get_block:{workchain_id}:{shard:016x}:{seqno}"),
+        }
+    }
+
+    pub fn get_latest_masterchain_block() -> String {
+        "get_latest_masterchain_block".to_owned()
+    }
+
+    // NOTE: timeout is not a part of the key
+    pub fn wait_for_next_block(current: &UInt256, address: &MsgAddressInt) -> String {
+        format!("wait_for_next_block:{}:{address}", current.to_hex_string())
+    }
+
+    pub fn get_capabilities() -> String {
+        "get_capabilities".to_owned()
+    }
+
+    pub fn get_blockchain_config() -> String {
+        "get_blockchain_config".to_owned()
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum ReplayTransportError {
+    #[error("Call was not recorded: {key}")]
+    NotRecorded { key: String },
+    #[error("Recorded error: {reason}")]
+    RecordedError { reason: String },
+    #[error("Unexpected response type")]
+    UnexpectedResponse,
+    #[error("Invalid config")]
+    InvalidConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{emulated_transport, test_address, test_clock, transfer};
+
+    #[tokio::test]
+    async fn record_and_replay() -> Result<()> {
+        let emulated = emulated_transport(test_clock());
+        let dst = test_address();
+        let message = transfer(&dst, 1_000_000_000);
+
+        let recorder = RecordingTransport::new(emulated);
+        let state_before = recorder.get_contract_state(&dst).await?;
+        recorder.send_message(&message).await?;
+        let state_after = recorder.get_contract_state(&dst).await?;
+        let transactions = recorder.get_transactions(&dst, u64::MAX, 10).await?;
+
+        let data = serde_json::to_string(&recorder.recording())?;
+        let replay = ReplayTransport::new(serde_json::from_str(&data)?);
+
+        assert!(matches!(
+            (replay.get_contract_state(&dst).await?, state_before),
+            (RawContractState::NotExists, RawContractState::NotExists)
+        ));
+        replay.send_message(&message).await?;
+        assert_eq!(
+            replay.get_contract_state(&dst).await?.brief().last_lt,
+            state_after.brief().last_lt
+        );
+        // The last response is repeated
+        assert_eq!(
+            replay.get_contract_state(&dst).await?.brief().last_lt,
+            state_after.brief().last_lt
+        );
+        assert_eq!(
+            replay.get_transactions(&dst, u64::MAX, 10).await?,
+            transactions
+        );
+        assert!(replay.get_transactions(&dst, 0, 10).await.is_err());
+
+        Ok(())
+    }
+}
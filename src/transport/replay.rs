@@ -0,0 +1,327 @@
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use nekoton_abi::{Executor, GenTimings, LastTransactionId, TransactionId};
+use nekoton_utils::Clock;
+use ton_block::{Account, MsgAddressInt, Serializable};
+
+use super::models::{ExistingContract, RawContractState, RawTransaction, TransactionRef};
+use super::paging::{TransactionsCursor, TransportPagingExt};
+use super::Transport;
+
+/// Rebuilds the account state right after the specified transaction.
+///
+/// Returns the current state if there were no transactions after the specified one.
+/// Otherwise the whole account history is fetched and replayed starting from
+/// the empty account, so it can be slow for accounts with many transactions.
+/// Use [`replay_contract_state_from`] to start from an already known state.
+///
+/// NOTE: transactions are executed with the current blockchain config, so the result
+/// is exact only while the config is unchanged since them. Transactions executed
+/// with a different config fail the replay with [`ReplayError::StateMismatch`]
+pub async fn replay_contract_state<T>(
+    transport: &T,
+    clock: &dyn Clock,
+    address: &MsgAddressInt,
+    transaction: &TransactionRef,
+) -> Result<RawContractState>
+where
+    T: Transport + ?Sized,
+{
+    replay_contract_state_from(transport, clock, address, None, transaction).await
+}
+
+/// Rebuilds the account state right after the specified transaction,
+/// starting from the known earlier state of the account.
+///
+/// Only the transactions after the known state are fetched and replayed.
+/// Starts from the empty account if the state is not known.
+/// See [`replay_contract_state`] for the details
+pub async fn replay_contract_state_from<T>(
+    transport: &T,
+    clock: &dyn Clock,
+    address: &MsgAddressInt,
+    known: Option<&ExistingContract>,
+    transaction: &TransactionRef,
+) -> Result<RawContractState>
+where
+    T: Transport + ?Sized,
+{
+    let until_lt = match transaction {
+        TransactionRef::Lt(lt) => *lt,
+        TransactionRef::Hash(hash) => match transport.get_transaction(hash).await? {
+            Some(transaction) => transaction.data.lt,
+            None => return Err(ReplayError::TransactionNotFound.into()),
+        },
+    };
+
+    // NOTE: `last_trans_lt` is the logical time at the end of the last transaction,
+    // so it can only be less than or equal to `until_lt` if there were no later ones
+    let current = transport.get_contract_state(address).await?;
+    if let RawContractState::Exists(state) = &current {
+        if state.account.storage.last_trans_lt <= until_lt {
+            return Ok(current);
+        }
+    }
+
+    // Transactions with lt less than or equal to `since_lt` are already applied
+    let (initial, since_lt) = match known {
+        Some(known) => {
+            let last_trans_lt = known.account.storage.last_trans_lt;
+            if last_trans_lt > until_lt {
+                // NOTE: the next transaction can't start before `last_trans_lt`,
+                // so the known state is the requested one if its own transaction fits
+                return match &known.last_transaction_id {
+                    LastTransactionId::Exact(id) if id.lt <= until_lt => {
+                        Ok(RawContractState::Exists(known.clone()))
+                    }
+                    _ => Err(ReplayError::KnownStateIsNewer.into()),
+                };
+            }
+            (
+                Account::Account(known.account.clone()),
+                last_trans_lt.saturating_sub(1),
+            )
+        }
+        None => (Account::AccountNone, 0),
+    };
+
+    let mut transactions = transport
+        .transactions_stream(
+            address,
+            TransactionsCursor {
+                from_lt: until_lt,
+                until_lt: since_lt,
+            },
+        )
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let (hash, lt, utime) = match transactions.first() {
+        Some(last) => {
+            if let TransactionRef::Hash(hash) = transaction {
+                if &last.hash != hash {
+                    return Err(ReplayError::TransactionNotFound.into());
+                }
+            }
+            (last.hash, last.data.lt, last.data.now)
+        }
+        None => {
+            return Ok(match known {
+                Some(known) => RawContractState::Exists(known.clone()),
+                None => RawContractState::NotExists,
+            })
+        }
+    };
+
+    if !matches!(transactions.last(), Some(first) if first.data.prev_trans_lt <= since_lt) {
+        return Err(ReplayError::IncompleteHistory.into());
+    }
+    transactions.reverse();
+
+    let config = transport.get_blockchain_config(clock, false).await?;
+    let account = replay_transactions(&config, initial, &transactions)?;
+
+    Ok(match account {
+        Account::Account(account) => RawContractState::Exists(ExistingContract {
+            account,
+            timings: GenTimings::Known {
+                gen_lt: lt,
+                gen_utime: utime,
+            },
+            last_transaction_id: LastTransactionId::Exact(TransactionId { lt, hash }),
+        }),
+        Account::AccountNone => RawContractState::NotExists,
+    })
+}
+
+/// Applies transactions to the account state.
+///
+/// Transactions must be in ascending order and directly follow the state.
+/// Each step is checked against the state hashes stored in the transaction,
+/// so a wrong initial state or any difference in the execution
+/// (e.g. due to the changed config) is an error.
+pub fn replay_transactions(
+    config: &ton_executor::BlockchainConfig,
+    mut account: Account,
+    transactions: &[RawTransaction],
+) -> Result<Account> {
+    for transaction in transactions {
+        let lt = transaction.data.lt;
+        let state_update = transaction.data.read_state_update()?;
+        if account.serialize()?.repr_hash() != state_update.old_hash {
+            return Err(ReplayError::StateMismatch { lt }.into());
+        }
+
+        let message = match &transaction.data.in_msg {
+            Some(message) => message.read_struct()?,
+            None => return Err(ReplayError::UnsupportedTransaction { lt }.into()),
+        };
+
+        let last_trans_lt = account.last_tr_time().unwrap_or_default();
+        let mut executor = Executor::with_params(
+            config.clone(),
+            account,
+            last_trans_lt,
+            transaction.data.now,
+            lt,
+        );
+        executor.run_mut(&message)?;
+        account = executor.into_account();
+
+        if account.serialize()?.repr_hash() != state_update.new_hash {
+            return Err(ReplayError::StateMismatch { lt }.into());
+        }
+    }
+    Ok(account)
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone)]
+pub enum ReplayError {
+    #[error("Transaction not found")]
+    TransactionNotFound,
+    #[error("Account history is incomplete")]
+    IncompleteHistory,
+    #[error("Known state is newer than the requested one")]
+    KnownStateIsNewer,
+    #[error("Transaction without incoming message can't be replayed (lt: {lt})")]
+    UnsupportedTransaction { lt: u64 },
+    #[error("Replayed state differs from the original one (lt: {lt})")]
+    StateMismatch { lt: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::test_utils::{emulated_transport, test_address, test_clock, transfer};
+
+    fn account_hash(state: &RawContractState) -> Result<ton_types::UInt256> {
+        Ok(match state {
+            RawContractState::Exists(state) => Account::Account(state.account.clone()),
+            RawContractState::NotExists => Account::AccountNone,
+        }
+        .serialize()?
+        .repr_hash())
+    }
+
+    #[tokio::test]
+    async fn historical_state_is_replayed() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let dst = test_address();
+
+        let mut states = Vec::new();
+        for i in 1..=3 {
+            transport
+                .send_message(&transfer(&dst, i * 1_000_000_000))
+                .await?;
+            states.push(transport.get_contract_state(&dst).await?);
+        }
+
+        let transactions = transport.get_transactions(&dst, u64::MAX, 10).await?;
+        assert_eq!(transactions.len(), 3);
+
+        let middle = &transactions[1];
+        let by_hash = transport
+            .get_contract_state_at(clock.as_ref(), &dst, &TransactionRef::Hash(middle.hash))
+            .await?;
+        assert_eq!(account_hash(&by_hash)?, account_hash(&states[1])?);
+
+        let by_lt = transport
+            .get_contract_state_at(clock.as_ref(), &dst, &TransactionRef::Lt(middle.data.lt))
+            .await?;
+        assert_eq!(account_hash(&by_lt)?, account_hash(&states[1])?);
+
+        let latest = transport
+            .get_contract_state_at(clock.as_ref(), &dst, &TransactionRef::Lt(u64::MAX))
+            .await?;
+        assert_eq!(account_hash(&latest)?, account_hash(&states[2])?);
+
+        let before = transport
+            .get_contract_state_at(clock.as_ref(), &dst, &TransactionRef::Lt(1))
+            .await?;
+        assert!(matches!(before, RawContractState::NotExists));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_starts_from_known_state() -> Result<()> {
+        let clock = test_clock();
+        let transport = emulated_transport(clock.clone());
+        let dst = test_address();
+
+        let mut states = Vec::new();
+        for i in 1..=3 {
+            transport
+                .send_message(&transfer(&dst, i * 1_000_000_000))
+                .await?;
+            match transport.get_contract_state(&dst).await? {
+                RawContractState::Exists(state) => states.push(state),
+                RawContractState::NotExists => panic!("account not found"),
+            }
+        }
+
+        let transactions = transport.get_transactions(&dst, u64::MAX, 10).await?;
+        let middle = TransactionRef::Hash(transactions[1].hash);
+
+        let replayed = replay_contract_state_from(
+            transport.as_ref(),
+            clock.as_ref(),
+            &dst,
+            Some(&states[0]),
+            &middle,
+        )
+        .await?;
+        assert_eq!(
+            account_hash(&replayed)?,
+            account_hash(&RawContractState::Exists(states[1].clone()))?
+        );
+
+        // Known state is the requested one
+        let replayed = replay_contract_state_from(
+            transport.as_ref(),
+            clock.as_ref(),
+            &dst,
+            Some(&states[1]),
+            &middle,
+        )
+        .await?;
+        assert_eq!(
+            account_hash(&replayed)?,
+            account_hash(&RawContractState::Exists(states[1].clone()))?
+        );
+
+        let err = replay_contract_state_from(
+            transport.as_ref(),
+            clock.as_ref(),
+            &dst,
+            Some(&states[2]),
+            &middle,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ReplayError::KnownStateIsNewer)
+        ));
+
+        // Known state doesn't match the history
+        let mut wrong = states[0].clone();
+        wrong.account.storage.balance.grams = ton_block::Grams(1);
+        let err = replay_contract_state_from(
+            transport.as_ref(),
+            clock.as_ref(),
+            &dst,
+            Some(&wrong),
+            &middle,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ReplayError::StateMismatch { .. })
+        ));
+
+        Ok(())
+    }
+}
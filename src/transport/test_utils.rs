@@ -0,0 +1,445 @@
+//! Fixtures shared by the transport and subscription tests
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ed25519_dalek::Signer;
+use parking_lot::Mutex;
+use sha2::Digest;
+use ton_block::{MsgAddressInt, Serializable};
+use ton_types::{Cell, UInt256};
+
+use nekoton_utils::*;
+
+use crate::core::models::{NetworkCapabilities, ReliableBehavior};
+
+use super::emulated::EmulatedTransport;
+use super::models::*;
+use super::{Transport, TransportError, TransportInfo};
+
+/// Unix time of the emulated network in tests
+pub const NOW_SEC: u64 = 1650000000;
+
+pub fn test_clock() -> Arc<ConstClock> {
+    Arc::new(ConstClock::from_secs(NOW_SEC))
+}
+
+pub fn emulated_transport(clock: Arc<dyn Clock>) -> Arc<EmulatedTransport> {
+    Arc::new(EmulatedTransport::new(clock, Default::default()))
+}
+
+/// Emulated transport with blocks, which waits for them in real time
+pub fn block_walking_transport(clock: Arc<dyn Clock>) -> Arc<EmulatedTransport> {
+    Arc::new(EmulatedTransport::new(clock, Default::default()).with_blocks(tokio::time::sleep))
+}
+
+/// Masterchain account which sends all test transfers
+pub fn sender_address() -> MsgAddressInt {
+    MsgAddressInt::from_str("-1:3333333333333333333333333333333333333333333333333333333333333333")
+        .unwrap()
+}
+
+pub fn test_address() -> MsgAddressInt {
+    MsgAddressInt::from_str("0:3333333333333333333333333333333333333333333333333333333333333333")
+        .unwrap()
+}
+
+/// Non-bounceable internal transfer from the [`sender_address`]
+pub fn transfer(dst: &MsgAddressInt, value: u64) -> ton_block::Message {
+    ton_block::Message::with_int_header(ton_block::InternalMessageHeader {
+        src: ton_block::MsgAddressIntOrNone::Some(sender_address()),
+        dst: dst.clone(),
+        value: ton_block::CurrencyCollection::with_grams(value),
+        bounce: false,
+        ..Default::default()
+    })
+}
+
+/// Creates an uninit account at the [`test_address`] with 1 EVER on its balance
+pub async fn funded_account(transport: &EmulatedTransport) -> Result<MsgAddressInt> {
+    let address = test_address();
+    transport
+        .send_message(&transfer(&address, 1_000_000_000))
+        .await?;
+    Ok(address)
+}
+
+/// Chain of a key block, a masterchain block and a shard block with the funded account
+/// at the [`test_address`], signed by the generated validators.
+///
+/// Validators are identified by the seeds of their keys
+pub struct TestChain {
+    /// Validators from the key block config
+    pub validators: Vec<u8>,
+    /// Validators which the masterchain block claims to be produced by
+    pub block_validators: Vec<u8>,
+    /// Validators which actually signed the masterchain block
+    pub signers: Vec<u8>,
+    /// Whether to corrupt the signatures
+    pub tamper_signatures: bool,
+}
+
+impl Default for TestChain {
+    fn default() -> Self {
+        Self {
+            validators: vec![1, 2, 3],
+            block_validators: vec![1, 2, 3],
+            signers: vec![1, 2, 3],
+            tamper_signatures: false,
+        }
+    }
+}
+
+pub struct TestChainData {
+    pub key_block_id: ton_block::BlockIdExt,
+    /// Serialized key block
+    pub key_block: Vec<u8>,
+    /// Node ids and signatures of the masterchain block
+    pub signatures: Vec<(UInt256, Vec<u8>)>,
+    pub proof: RawContractStateProof,
+}
+
+impl TestChain {
+    pub const KEY_BLOCK_SEQNO: u32 = 10;
+    pub const MC_BLOCK_SEQNO: u32 = 15;
+    pub const SHARD_BLOCK_SEQNO: u32 = 20;
+    pub const CATCHAIN_SEQNO: u32 = 3;
+
+    fn keypair(seed: u8) -> ed25519_dalek::Keypair {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        ed25519_dalek::Keypair { secret, public }
+    }
+
+    fn validator(seed: u8) -> Result<ton_block::ValidatorDescr> {
+        let public_key = ton_block::SigPubKey::from_bytes(Self::keypair(seed).public.as_bytes())?;
+        Ok(ton_block::ValidatorDescr::with_params(public_key, 1, None))
+    }
+
+    fn validator_set(seeds: &[u8]) -> Result<ton_block::ValidatorSet> {
+        let list = seeds
+            .iter()
+            .map(|seed| Self::validator(*seed))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ton_block::ValidatorSet::new(
+            0,
+            u32::MAX,
+            list.len() as u16,
+            list,
+        )?)
+    }
+
+    pub fn build(&self) -> Result<TestChainData> {
+        let address = test_address();
+        let gen_utime = ton_block::UnixTime32(NOW_SEC as u32);
+
+        // Shard block with the account
+        let account = ton_block::Account::with_address_and_ballance(
+            &address,
+            &ton_block::CurrencyCollection::with_grams(1_000_000_000),
+        );
+        let shard_account = ton_block::ShardAccount::with_params(&account, UInt256::default(), 0)?;
+        let account_cell = shard_account.account_cell();
+
+        let mut shard_state =
+            ton_block::ShardStateUnsplit::with_ident(ton_block::ShardIdent::full(0));
+        shard_state.insert_account(
+            &UInt256::from_slice(&address.address().get_bytestring(0)),
+            &shard_account,
+        )?;
+        let shard_state = shard_state.serialize()?;
+
+        let mut info = ton_block::BlockInfo::new();
+        info.set_shard(ton_block::ShardIdent::full(0));
+        info.set_seq_no(Self::SHARD_BLOCK_SEQNO)?;
+        info.set_gen_utime(gen_utime);
+        let (shard_block, shard_block_id, _) = make_block(info, &shard_state, None)?;
+
+        // Masterchain state with the shard block
+        let mut mc_state_extra = ton_block::McStateExtra::default();
+        let shard_descr = ton_block::ShardDescr {
+            seq_no: shard_block_id.seq_no,
+            root_hash: shard_block_id.root_hash,
+            file_hash: shard_block_id.file_hash,
+            ..Default::default()
+        };
+        mc_state_extra.shards.set(
+            &0i32,
+            &ton_block::InRefValue(ton_block::BinTree::with_item(&shard_descr)?),
+        )?;
+        let mut mc_state =
+            ton_block::ShardStateUnsplit::with_ident(ton_block::ShardIdent::masterchain());
+        mc_state.write_custom(Some(&mc_state_extra))?;
+        let mc_state = mc_state.serialize()?;
+
+        // Key block with the validator set
+        let catchain_config = ton_block::CatchainConfig::default();
+        let mut config = ton_block::ConfigParams::new();
+        config.set_config(ton_block::ConfigParamEnum::ConfigParam28(
+            catchain_config.clone(),
+        ))?;
+        config.set_config(ton_block::ConfigParamEnum::ConfigParam34(
+            ton_block::ConfigParam34 {
+                cur_validators: Self::validator_set(&self.validators)?,
+            },
+        ))?;
+        let mut mc_block_extra = ton_block::McBlockExtra::default();
+        mc_block_extra.set_config(config);
+
+        let mut info = ton_block::BlockInfo::new();
+        info.set_shard(ton_block::ShardIdent::masterchain());
+        info.set_seq_no(Self::KEY_BLOCK_SEQNO)?;
+        info.set_key_block(true);
+        info.set_gen_utime(gen_utime);
+        let (_, key_block_id, key_block) =
+            make_block(info, &Cell::default(), Some(mc_block_extra))?;
+
+        // Masterchain block signed by the validators of the key block
+        let (_, hash_short) = Self::validator_set(&self.block_validators)?.calc_subset(
+            &catchain_config,
+            ton_block::SHARD_FULL,
+            ton_block::MASTERCHAIN_ID,
+            Self::CATCHAIN_SEQNO,
+            gen_utime,
+        )?;
+
+        let mut info = ton_block::BlockInfo::new();
+        info.set_shard(ton_block::ShardIdent::masterchain());
+        info.set_seq_no(Self::MC_BLOCK_SEQNO)?;
+        info.set_prev_key_block_seqno(Self::KEY_BLOCK_SEQNO);
+        info.set_gen_catchain_seqno(Self::CATCHAIN_SEQNO);
+        info.set_gen_validator_list_hash_short(hash_short);
+        info.set_gen_utime(gen_utime);
+        let (mc_block, mc_block_id, _) = make_block(info, &mc_state, None)?;
+
+        let data =
+            ton_block::Block::build_data_for_sign(&mc_block_id.root_hash, &mc_block_id.file_hash);
+        let mut signatures = Vec::new();
+        let mut pure_signatures = ton_block::BlockSignaturesPure::default();
+        for seed in &self.signers {
+            let node_id_short = Self::validator(*seed)?.compute_node_id_short();
+            let mut signature = Self::keypair(*seed).sign(&data).to_bytes();
+            if self.tamper_signatures {
+                signature[0] ^= 0xff;
+            }
+
+            pure_signatures.add_sigpair(ton_block::CryptoSignaturePair::with_params(
+                node_id_short,
+                ton_block::CryptoSignature::from_bytes(&signature)?,
+            ));
+            signatures.push((node_id_short, signature.to_vec()));
+        }
+
+        let full_proof = |cell: &Cell| ton_block::MerkleProof::create(cell, |_| true)?.serialize();
+
+        Ok(TestChainData {
+            key_block_id,
+            key_block,
+            signatures,
+            proof: RawContractStateProof {
+                mc_block_id,
+                mc_block_signatures: ton_block::BlockSignatures {
+                    validator_info: ton_block::ValidatorBaseInfo {
+                        validator_list_hash_short: hash_short,
+                        catchain_seqno: Self::CATCHAIN_SEQNO,
+                    },
+                    pure_signatures,
+                },
+                mc_block_proof: full_proof(&mc_block)?,
+                mc_state_proof: Some(full_proof(&mc_state)?),
+                shard_block_id,
+                shard_block_proof: full_proof(&shard_block)?,
+                // NOTE: account is pruned as in the liteserver proofs
+                state_proof: ton_block::MerkleProof::create(&shard_state, |hash| {
+                    hash != &account_cell.repr_hash()
+                })?
+                .serialize()?,
+                account: Some(account_cell),
+            },
+        })
+    }
+}
+
+/// Returns the block cell, its id and the serialized block
+fn make_block(
+    info: ton_block::BlockInfo,
+    state: &Cell,
+    mc_extra: Option<ton_block::McBlockExtra>,
+) -> Result<(Cell, ton_block::BlockIdExt, Vec<u8>)> {
+    let mut extra = ton_block::BlockExtra::default();
+    if let Some(mc_extra) = &mc_extra {
+        extra.write_custom(Some(mc_extra))?;
+    }
+
+    let shard_id = info.shard().clone();
+    let seq_no = info.seq_no();
+    let state_update = ton_block::MerkleUpdate::create(&Cell::default(), state)?;
+    let block = ton_block::Block::with_params(0, info, Default::default(), state_update, extra)?;
+
+    let cell = block.serialize()?;
+    let data = ton_types::serialize_toc(&cell)?;
+    let id = ton_block::BlockIdExt {
+        shard_id,
+        seq_no,
+        root_hash: cell.repr_hash(),
+        file_hash: UInt256::from_slice(&sha2::Sha256::digest(&data)),
+    };
+    Ok((cell, id, data))
+}
+
+pub fn polling_info() -> TransportInfo {
+    TransportInfo {
+        max_transactions_per_fetch: 50,
+        reliable_behavior: ReliableBehavior::IntensivePolling,
+        has_key_blocks: false,
+        has_blocks: false,
+    }
+}
+
+/// Transport without any data, which counts requests by method.
+///
+/// Block queries are served only if [`TransportInfo::has_blocks`] is set
+pub struct StubTransport {
+    info: TransportInfo,
+    available: bool,
+    requests: Mutex<HashMap<&'static str, usize>>,
+}
+
+impl StubTransport {
+    pub fn new(info: TransportInfo) -> Self {
+        Self {
+            info,
+            available: true,
+            requests: Default::default(),
+        }
+    }
+
+    /// Transport which fails all supported requests
+    pub fn unavailable(info: TransportInfo) -> Self {
+        Self {
+            available: false,
+            ..Self::new(info)
+        }
+    }
+
+    pub fn requests(&self, method: &str) -> usize {
+        self.requests
+            .lock()
+            .get(method)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn respond<T>(&self, method: &'static str, value: T) -> Result<T> {
+        *self.requests.lock().entry(method).or_default() += 1;
+        if self.available {
+            Ok(value)
+        } else {
+            Err(anyhow::anyhow!("unavailable"))
+        }
+    }
+
+    fn respond_with_block<T>(&self, method: &'static str, value: T) -> Result<T> {
+        if !self.info.has_blocks {
+            return Err(TransportError::BlocksNotSupported.into());
+        }
+        self.respond(method, value)
+    }
+}
+
+#[async_trait]
+impl Transport for StubTransport {
+    fn info(&self) -> TransportInfo {
+        self.info
+    }
+
+    async fn send_message(&self, _: &ton_block::Message) -> Result<()> {
+        self.respond("send_message", ())
+    }
+
+    async fn get_contract_state(&self, _: &MsgAddressInt) -> Result<RawContractState> {
+        self.respond("get_contract_state", RawContractState::NotExists)
+    }
+
+    async fn get_accounts_by_code_hash(
+        &self,
+        _: &UInt256,
+        _: u8,
+        _: &Option<MsgAddressInt>,
+    ) -> Result<Vec<MsgAddressInt>> {
+        self.respond("get_accounts_by_code_hash", Vec::new())
+    }
+
+    async fn get_transactions(
+        &self,
+        _: &MsgAddressInt,
+        _: u64,
+        _: u8,
+    ) -> Result<Vec<RawTransaction>> {
+        self.respond("get_transactions", Vec::new())
+    }
+
+    async fn get_transaction(&self, _: &UInt256) -> Result<Option<RawTransaction>> {
+        self.respond("get_transaction", None)
+    }
+
+    async fn get_dst_transaction(&self, _: &UInt256) -> Result<Option<RawTransaction>> {
+        self.respond("get_dst_transaction", None)
+    }
+
+    async fn get_latest_key_block(&self) -> Result<ton_block::Block> {
+        self.respond("get_latest_key_block", Default::default())
+    }
+
+    async fn get_block(&self, _: &BlockRef) -> Result<ton_block::Block> {
+        self.respond_with_block("get_block", Default::default())
+    }
+
+    async fn get_latest_masterchain_block(&self) -> Result<LatestMasterchainBlock> {
+        self.respond_with_block(
+            "get_latest_masterchain_block",
+            LatestMasterchainBlock {
+                block: BlockSummary {
+                    workchain_id: -1,
+                    shard: 0x8000_0000_0000_0000,
+                    seqno: 1,
+                    id: Default::default(),
+                    end_lt: 0,
+                    gen_utime: NOW_SEC as u32,
+                },
+                shards: Vec::new(),
+            },
+        )
+    }
+
+    async fn wait_for_next_block(
+        &self,
+        _: &UInt256,
+        _: &MsgAddressInt,
+        _: Duration,
+    ) -> Result<Option<UInt256>> {
+        self.respond_with_block("wait_for_next_block", None)
+    }
+
+    async fn get_capabilities(&self, _: &dyn Clock) -> Result<NetworkCapabilities> {
+        self.respond(
+            "get_capabilities",
+            NetworkCapabilities {
+                global_id: 0,
+                raw: 0,
+            },
+        )
+    }
+
+    async fn get_blockchain_config(
+        &self,
+        _: &dyn Clock,
+        _: bool,
+    ) -> Result<ton_executor::BlockchainConfig> {
+        self.respond("get_blockchain_config", Default::default())
+    }
+}
@@ -1,13 +1,21 @@
+use std::sync::Arc;
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
+use ton_block::Deserializable;
 
 use nekoton_utils::*;
 
 use super::Transport;
 use crate::core::models::NetworkCapabilities;
+use crate::external::Storage;
+
+pub const CONFIG_CACHE_STORAGE_KEY: &str = "__core__config_cache";
 
 pub struct ConfigCache {
     use_default_config: bool,
+    storage: Option<ConfigStorage>,
     state: Mutex<Option<ConfigCacheState>>,
 }
 
@@ -15,6 +23,7 @@ impl ConfigCache {
     pub fn new(use_default_config: bool) -> Self {
         Self {
             use_default_config,
+            storage: None,
             state: Mutex::new(if use_default_config {
                 Some(ConfigCacheState {
                     capabilities: NetworkCapabilities {
@@ -31,6 +40,44 @@ impl ConfigCache {
         }
     }
 
+    /// Creates a cache which always returns the specified config without requests
+    pub fn with_config(global_id: i32, params: ton_block::ConfigParams) -> Result<Self> {
+        let config = ton_executor::BlockchainConfig::with_config(params)
+            .map_err(|_| QueryConfigError::InvalidConfig)?;
+        let capabilities = NetworkCapabilities {
+            global_id,
+            raw: config.capabilites(),
+        };
+
+        Ok(Self {
+            use_default_config: true,
+            storage: None,
+            state: Mutex::new(Some(ConfigCacheState {
+                capabilities,
+                config,
+                last_key_block_seqno: 0,
+                phase: ConfigCachePhase::WainingNextValidatorsSet { deadline: u32::MAX },
+            })),
+        })
+    }
+
+    /// Creates a cache with config params from the BOC (e.g. bundled into the app)
+    pub fn with_config_boc(global_id: i32, boc: &[u8]) -> Result<Self> {
+        let params = ton_block::ConfigParams::construct_from_bytes(boc)
+            .map_err(|_| QueryConfigError::InvalidConfig)?;
+        Self::with_config(global_id, params)
+    }
+
+    /// Persists the last fetched config for the specified network and preloads it
+    /// on the first request, so that the key block is not required at startup
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>, network_name: &str) -> Self {
+        self.storage = Some(ConfigStorage {
+            storage,
+            key: format!("{CONFIG_CACHE_STORAGE_KEY}_{network_name}"),
+        });
+        self
+    }
+
     pub async fn get_blockchain_config(
         &self,
         transport: &dyn Transport,
@@ -41,39 +88,112 @@ impl ConfigCache {
 
         let now = clock.now_sec_u64() as u32;
 
-        Ok(match &*cache {
-            None => {
-                let (capabilities, config, key_block_seqno) = fetch_config(transport).await?;
-                let phase = compute_next_phase(now, &config, None, key_block_seqno)?;
-                *cache = Some(ConfigCacheState {
-                    capabilities,
-                    config: config.clone(),
-                    last_key_block_seqno: key_block_seqno,
-                    phase,
-                });
-                (capabilities, config)
+        if cache.is_none() {
+            if let Some(storage) = &self.storage {
+                *cache = storage.load(now).await;
             }
-            Some(a) if force && !self.use_default_config || cache_expired(now, a.phase) => {
-                let (capabilities, config, key_block_seqno) = fetch_config(transport).await?;
-                let phase = compute_next_phase(
-                    now,
-                    &config,
-                    Some(a.last_key_block_seqno),
-                    key_block_seqno,
-                )?;
-                *cache = Some(ConfigCacheState {
-                    capabilities,
-                    config: config.clone(),
-                    last_key_block_seqno: key_block_seqno,
-                    phase,
-                });
-                (capabilities, config)
+        }
+
+        if let Some(a) = &*cache {
+            if !(force && !self.use_default_config || cache_expired(now, a.phase)) {
+                return Ok((a.capabilities, a.config.clone()));
             }
-            Some(a) => (a.capabilities, a.config.clone()),
-        })
+        }
+
+        let (capabilities, config, key_block_seqno) = match fetch_config(transport).await {
+            Ok(fetched) => fetched,
+            Err(e) => match &*cache {
+                // Use the outdated config from the storage until the new one is fetched
+                Some(a) if matches!(a.phase, ConfigCachePhase::Preloaded) => {
+                    log::warn!("Failed to fetch blockchain config: {e:?}");
+                    return Ok((a.capabilities, a.config.clone()));
+                }
+                _ => return Err(e),
+            },
+        };
+
+        let last_key_block_seqno = cache.as_ref().map(|a| a.last_key_block_seqno);
+        let phase = compute_next_phase(now, &config, last_key_block_seqno, key_block_seqno)?;
+
+        if let Some(storage) = &self.storage {
+            storage.save(&capabilities, &config, key_block_seqno);
+        }
+
+        *cache = Some(ConfigCacheState {
+            capabilities,
+            config: config.clone(),
+            last_key_block_seqno: key_block_seqno,
+            phase,
+        });
+        Ok((capabilities, config))
     }
 }
 
+struct ConfigStorage {
+    storage: Arc<dyn Storage>,
+    key: String,
+}
+
+impl ConfigStorage {
+    async fn load(&self, now: u32) -> Option<ConfigCacheState> {
+        match self.try_load(now).await {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("Failed to load stored blockchain config: {e:?}");
+                None
+            }
+        }
+    }
+
+    async fn try_load(&self, now: u32) -> Result<Option<ConfigCacheState>> {
+        let data = match self.storage.get(&self.key).await? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let stored = serde_json::from_str::<StoredConfig>(&data)?;
+
+        let config = ton_executor::BlockchainConfig::with_config(stored.config)
+            .map_err(|_| QueryConfigError::InvalidConfig)?;
+        let phase = match compute_next_phase(now, &config, None, stored.key_block_seqno) {
+            Ok(phase) if !cache_expired(now, phase) => phase,
+            _ => ConfigCachePhase::Preloaded,
+        };
+
+        Ok(Some(ConfigCacheState {
+            capabilities: stored.capabilities,
+            config,
+            last_key_block_seqno: stored.key_block_seqno,
+            phase,
+        }))
+    }
+
+    fn save(
+        &self,
+        capabilities: &NetworkCapabilities,
+        config: &ton_executor::BlockchainConfig,
+        key_block_seqno: u32,
+    ) {
+        let data = StoredConfig {
+            capabilities: *capabilities,
+            key_block_seqno,
+            config: config.raw_config().clone(),
+        };
+        match serde_json::to_string(&data) {
+            Ok(data) => self.storage.set_unchecked(&self.key, &data),
+            Err(e) => log::warn!("Failed to serialize blockchain config: {e:?}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredConfig {
+    capabilities: NetworkCapabilities,
+    key_block_seqno: u32,
+    #[serde(with = "serde_ton_block")]
+    config: ton_block::ConfigParams,
+}
+
 async fn fetch_config(
     transport: &dyn Transport,
 ) -> Result<(NetworkCapabilities, ton_executor::BlockchainConfig, u32)> {
@@ -133,7 +253,7 @@ fn compute_next_phase(
 
 fn cache_expired(now: u32, phase: ConfigCachePhase) -> bool {
     match phase {
-        ConfigCachePhase::WaitingKeyBlock => true,
+        ConfigCachePhase::WaitingKeyBlock | ConfigCachePhase::Preloaded => true,
         ConfigCachePhase::WaitingElectionsEnd { deadline }
         | ConfigCachePhase::WainingNextValidatorsSet { deadline } => now > deadline,
     }
@@ -148,6 +268,8 @@ struct ConfigCacheState {
 
 #[derive(Copy, Clone)]
 enum ConfigCachePhase {
+    /// Outdated config from the storage, which is used until the new one is fetched
+    Preloaded,
     WaitingKeyBlock,
     WaitingElectionsEnd { deadline: u32 },
     WainingNextValidatorsSet { deadline: u32 },
@@ -160,3 +282,63 @@ enum QueryConfigError {
     #[error("Invalid config")]
     InvalidConfig,
 }
+
+#[cfg(test)]
+mod tests {
+    use ton_block::Serializable;
+
+    use super::*;
+    use crate::external::TestStorage;
+    use crate::transport::emulated::EmulatedTransport;
+
+    // NOTE: emulated transport has no key blocks, so all configs are served from the cache
+    fn make_transport() -> EmulatedTransport {
+        EmulatedTransport::new(Arc::new(SimpleClock), Default::default())
+    }
+
+    #[tokio::test]
+    async fn bundled_config_is_used() -> Result<()> {
+        let boc = ton_executor::BlockchainConfig::default()
+            .raw_config()
+            .write_to_bytes()?;
+        let cache = ConfigCache::with_config_boc(42, &boc)?;
+
+        let (capabilities, _) = cache
+            .get_blockchain_config(&make_transport(), &SimpleClock, true)
+            .await?;
+        assert_eq!(capabilities.global_id, 42);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_config_is_preloaded() -> Result<()> {
+        let storage = Arc::new(TestStorage::default());
+        let transport = make_transport();
+
+        let cache = ConfigCache::new(false).with_storage(storage.clone(), "test");
+        assert!(cache
+            .get_blockchain_config(&transport, &SimpleClock, false)
+            .await
+            .is_err());
+
+        let config = ton_executor::BlockchainConfig::default();
+        let capabilities = NetworkCapabilities {
+            global_id: 42,
+            raw: config.capabilites(),
+        };
+        cache
+            .storage
+            .as_ref()
+            .unwrap()
+            .save(&capabilities, &config, 1);
+
+        let cache = ConfigCache::new(false).with_storage(storage, "test");
+        let (loaded, _) = cache
+            .get_blockchain_config(&transport, &SimpleClock, false)
+            .await?;
+        assert_eq!(loaded, capabilities);
+
+        Ok(())
+    }
+}